@@ -20,8 +20,6 @@
 // TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
 // SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use core::{f32, u32, u8};
-
 use crate::math::f32_to_i32_clamped;
 
 pub fn compress_bc2(rgba: &[[u8; 4]; 16], mask: u32, block: &mut [u8]) {
@@ -67,20 +65,41 @@ pub fn decompress_bc2(rgba: &mut [[u8; 4]; 16], bytes: &[u8]) {
     }
 }
 
-fn fix_range(min: &mut u8, max: &mut u8, steps: u8) {
+/// Returns the range of values a BC3-style alpha block can represent
+fn value_range(signed: bool) -> (i32, i32) {
+    if signed {
+        (-127, 127)
+    } else {
+        (0, 255)
+    }
+}
+
+/// Reads a channel value, interpreting it as a two's complement byte for signed blocks
+fn channel_value(pixel: &[u8; 4], channel: usize, signed: bool) -> i32 {
+    if signed {
+        // -128 and -127 both represent -1.0
+        i32::from(pixel[channel] as i8).max(-127)
+    } else {
+        i32::from(pixel[channel])
+    }
+}
+
+fn fix_range(min: &mut i32, max: &mut i32, steps: i32, signed: bool) {
+    let (lo, hi) = value_range(signed);
     if (*max - *min) < steps {
-        *max = (i32::from(*min) + i32::from(steps)).min(i32::from(u8::MAX)) as u8;
+        *max = (*min + steps).min(hi);
     }
     if (*max - *min) < steps {
-        *min = (i32::from(*max) - i32::from(steps)).max(0) as u8;
+        *min = (*max - steps).max(lo);
     }
 }
 
 fn fit_codes(
     rgba: &[[u8; 4]; 16],
     channel: usize,
+    signed: bool,
     mask: u32,
-    codes: [u8; 8],
+    codes: [i32; 8],
     indices: &mut [u8; 16],
 ) -> u32 {
     let mut err = 0;
@@ -95,12 +114,12 @@ fn fit_codes(
             continue;
         }
 
-        let value = rgba[i][channel];
+        let value = channel_value(&rgba[i], channel, signed);
         let mut least = u32::MAX;
         let mut index = 0;
         for (j, &code) in codes.iter().enumerate().take(8) {
             // get squared error from this code
-            let dist = i32::from(value) - i32::from(code);
+            let dist = value - code;
             let dist = (dist * dist) as u32;
 
             // compare with best so far
@@ -118,12 +137,12 @@ fn fit_codes(
     err
 }
 
-fn write_alpha_block(alpha0: u8, alpha1: u8, indices: &[u8; 16], block: &mut [u8]) {
+fn write_alpha_block(alpha0: i32, alpha1: i32, indices: &[u8; 16], block: &mut [u8]) {
     let mut buf = [0u8; 8];
 
-    // write endpoints
-    buf[0] = alpha0;
-    buf[1] = alpha1;
+    // write endpoints, signed values end up in two's complement
+    buf[0] = alpha0 as u8;
+    buf[1] = alpha1 as u8;
 
     // pack the indices with 3 bits each
     for i in 0..2 {
@@ -143,7 +162,7 @@ fn write_alpha_block(alpha0: u8, alpha1: u8, indices: &[u8; 16], block: &mut [u8
     block.copy_from_slice(&buf);
 }
 
-fn write_alpha_block5(alpha0: u8, alpha1: u8, indices: &[u8; 16], block: &mut [u8]) {
+fn write_alpha_block5(alpha0: i32, alpha1: i32, indices: &[u8; 16], block: &mut [u8]) {
     if alpha0 > alpha1 {
         // invert indices
         let mut swapped = *indices;
@@ -164,7 +183,7 @@ fn write_alpha_block5(alpha0: u8, alpha1: u8, indices: &[u8; 16], block: &mut [u
     }
 }
 
-fn write_alpha_block7(alpha0: u8, alpha1: u8, indices: &[u8; 16], block: &mut [u8]) {
+fn write_alpha_block7(alpha0: i32, alpha1: i32, indices: &[u8; 16], block: &mut [u8]) {
     if alpha0 < alpha1 {
         // invert indices
         let mut swapped = *indices;
@@ -184,12 +203,82 @@ fn write_alpha_block7(alpha0: u8, alpha1: u8, indices: &[u8; 16], block: &mut [u
     }
 }
 
-pub fn compress_bc3(rgba: &[[u8; 4]; 16], channel: usize, mask: u32, block: &mut [u8]) {
+/// Sets up the 7-alpha codebook, or the 5-alpha codebook that includes both extremes
+fn codebook(min: i32, max: i32, seven: bool, signed: bool) -> [i32; 8] {
+    let mut codes = [0i32; 8];
+    codes[0] = min;
+    codes[1] = max;
+    if seven {
+        for i in 1..7 {
+            codes[1 + i as usize] = ((7 - i) * min + i * max) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            codes[1 + i as usize] = ((5 - i) * min + i * max) / 5;
+        }
+        (codes[6], codes[7]) = value_range(signed);
+    }
+    codes
+}
+
+/// Compresses one channel of a block in the BC3 alpha block format with fixed endpoints
+///
+/// The endpoints must satisfy `min < max` within the value range of the block.
+#[allow(clippy::too_many_arguments)]
+pub fn compress_bc3_endpoints(
+    rgba: &[[u8; 4]; 16],
+    channel: usize,
+    signed: bool,
+    mask: u32,
+    min: i32,
+    max: i32,
+    seven: bool,
+    block: &mut [u8],
+) {
+    let mut indices = [0u8; 16];
+    fit_codes(
+        rgba,
+        channel,
+        signed,
+        mask,
+        codebook(min, max, seven, signed),
+        &mut indices,
+    );
+    if seven {
+        write_alpha_block7(min, max, &indices, block);
+    } else {
+        write_alpha_block5(min, max, &indices, block);
+    }
+}
+
+/// Returns the endpoints of a BC3 alpha block as ordered `(min, max, seven)`
+pub fn endpoints_bc3(bytes: &[u8], signed: bool) -> (i32, i32, bool) {
+    let (alpha0, alpha1) = if signed {
+        (i32::from(bytes[0] as i8), i32::from(bytes[1] as i8))
+    } else {
+        (i32::from(bytes[0]), i32::from(bytes[1]))
+    };
+    (alpha0.min(alpha1), alpha0.max(alpha1), alpha0 > alpha1)
+}
+
+/// Compresses one channel of a block in the BC3 alpha block format
+///
+/// Signed blocks (as used by BC4 and BC5 SNORM) read the channel as two's complement
+/// bytes in [-127, 127].
+pub fn compress_bc3(
+    rgba: &[[u8; 4]; 16],
+    channel: usize,
+    signed: bool,
+    mask: u32,
+    block: &mut [u8],
+) {
+    let (lo, hi) = value_range(signed);
+
     // get range for 5-alpha and 7-alpha interpolation
-    let mut min5 = u8::MAX;
-    let mut max5 = 0u8;
-    let mut min7 = u8::MAX;
-    let mut max7 = 0u8;
+    let mut min5 = hi;
+    let mut max5 = lo;
+    let mut min7 = hi;
+    let mut max7 = lo;
 
     for (i, pixel) in rgba.iter().enumerate() {
         // skip masked-out bits
@@ -199,14 +288,14 @@ pub fn compress_bc3(rgba: &[[u8; 4]; 16], channel: usize, mask: u32, block: &mut
         }
 
         // incorporate into the min/max
-        let value = pixel[channel];
+        let value = channel_value(pixel, channel, signed);
         min7 = min7.min(value);
         max7 = max7.max(value);
 
-        if value != 0 {
+        if value != lo {
             min5 = min5.min(value);
         }
-        if value != u8::MAX {
+        if value != hi {
             max5 = max5.max(value);
         }
     }
@@ -220,32 +309,16 @@ pub fn compress_bc3(rgba: &[[u8; 4]; 16], channel: usize, mask: u32, block: &mut
     }
 
     // fix range to be the minimum in both cases
-    fix_range(&mut min5, &mut max5, 5);
-    fix_range(&mut min7, &mut max7, 7);
-
-    // set up the 5-alpha codebook
-    let mut codes5 = [0u8; 8];
-    codes5[0] = min5;
-    codes5[1] = max5;
-    for i in 1..5i32 {
-        codes5[1 + i as usize] = (((5 - i) * i32::from(min5) + i * i32::from(max5)) / 5) as u8;
-    }
-    codes5[6] = 0;
-    codes5[7] = u8::MAX;
-
-    // set up the 7-alpha codebook
-    let mut codes7 = [0u8; 8];
-    codes7[0] = min5;
-    codes7[1] = max5;
-    for i in 1..7i32 {
-        codes7[1 + i as usize] = (((7 - i) * i32::from(min7) + i * i32::from(max7)) / 7) as u8;
-    }
+    fix_range(&mut min5, &mut max5, 5, signed);
+    fix_range(&mut min7, &mut max7, 7, signed);
 
     // fit the data to both codebooks
     let mut indices5 = [0u8; 16];
     let mut indices7 = [0u8; 16];
-    let err5 = fit_codes(rgba, channel, mask, codes5, &mut indices5);
-    let err7 = fit_codes(rgba, channel, mask, codes7, &mut indices7);
+    let codes5 = codebook(min5, max5, false, signed);
+    let codes7 = codebook(min7, max7, true, signed);
+    let err5 = fit_codes(rgba, channel, signed, mask, codes5, &mut indices5);
+    let err7 = fit_codes(rgba, channel, signed, mask, codes7, &mut indices7);
 
     // save the block with the least error
     if err5 <= err7 {
@@ -255,28 +328,38 @@ pub fn compress_bc3(rgba: &[[u8; 4]; 16], channel: usize, mask: u32, block: &mut
     }
 }
 
-pub fn decompress_bc3(rgba: &mut [[u8; 4]; 16], channel: usize, bytes: &[u8]) {
+/// Decompresses a BC3 alpha block into one channel of a block
+///
+/// Signed blocks write two's complement bytes in [-127, 127] to the channel.
+pub fn decompress_bc3(rgba: &mut [[u8; 4]; 16], channel: usize, signed: bool, bytes: &[u8]) {
     assert!(bytes.len() == 8);
 
     // get endpoint values
-    let alpha0 = i32::from(bytes[0]);
-    let alpha1 = i32::from(bytes[1]);
+    let (lo, hi) = value_range(signed);
+    let (alpha0, alpha1) = if signed {
+        (
+            i32::from(bytes[0] as i8).max(lo),
+            i32::from(bytes[1] as i8).max(lo),
+        )
+    } else {
+        (i32::from(bytes[0]), i32::from(bytes[1]))
+    };
 
     // build the codebook
-    let mut codes = [0u8; 8];
-    codes[0] = bytes[0];
-    codes[1] = bytes[1];
+    let mut codes = [0i32; 8];
+    codes[0] = alpha0;
+    codes[1] = alpha1;
     if alpha0 <= alpha1 {
         // use 5-alpha codebook
-        for i in 1..5i32 {
-            codes[1 + i as usize] = (((5 - i) * alpha0 + i * alpha1) / 5) as u8
+        for i in 1..5 {
+            codes[1 + i as usize] = ((5 - i) * alpha0 + i * alpha1) / 5;
         }
-        codes[6] = 0;
-        codes[7] = u8::MAX;
+        codes[6] = lo;
+        codes[7] = hi;
     } else {
         // use 7-alpha codebook
-        for i in 1..7i32 {
-            codes[1 + i as usize] = (((7 - i) * alpha0 + i * alpha1) / 7) as u8;
+        for i in 1..7 {
+            codes[1 + i as usize] = ((7 - i) * alpha0 + i * alpha1) / 7;
         }
     }
 
@@ -299,6 +382,6 @@ pub fn decompress_bc3(rgba: &mut [[u8; 4]; 16], channel: usize, bytes: &[u8]) {
 
     // write out the indexed codebook values
     for (pixel, &index) in rgba.iter_mut().zip(indices.iter()) {
-        pixel[channel] = codes[index as usize];
+        pixel[channel] = codes[index as usize] as u8;
     }
 }
@@ -0,0 +1,885 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! BC6H block encoder and decoder
+//!
+//! Pixels are passed around as the bit patterns of RGB half floats. Endpoints are fitted
+//! in the 16-bit integer domain the format interpolates in, while errors are measured on
+//! the decoded half float bit patterns, which roughly tracks the relative error of each
+//! channel. Most modes store the first endpoint at full precision and the others as
+//! deltas from it, so endpoints that are too far apart get pulled towards the first one.
+
+use core::f32;
+
+use crate::bptc::{self, BitReader, BitWriter};
+use crate::{Algorithm, Params};
+
+// endpoints in the order they are stored: both endpoints of the first subset,
+// followed by both endpoints of the second subset
+const W: usize = 0;
+const X: usize = 1;
+const Y: usize = 2;
+const Z: usize = 3;
+
+// pseudo endpoint for header fields holding the partition index
+const D: usize = 4;
+
+// colour channels
+const R: usize = 0;
+const G: usize = 1;
+const B: usize = 2;
+
+/// A run of header bits holding part of an endpoint channel or the partition index
+#[derive(Clone, Copy)]
+struct Field {
+    endpoint: usize,
+    channel: usize,
+    lsb: usize,
+    count: usize,
+}
+
+const fn bits(endpoint: usize, channel: usize, lsb: usize, count: usize) -> Field {
+    Field {
+        endpoint,
+        channel,
+        lsb,
+        count,
+    }
+}
+
+struct ModeInfo {
+    /// Mode number stored in the first two or five bits of the block
+    id: u32,
+    id_bits: usize,
+    subsets: usize,
+    /// Whether all but the first endpoint are stored as deltas
+    transformed: bool,
+    endpoint_bits: usize,
+    delta_bits: [usize; 3],
+    layout: &'static [Field],
+}
+
+macro_rules! mode {
+    ($id:expr, $idb:expr, $ns:expr, $tr:expr, $eb:expr, $db:expr, $layout:expr) => {
+        ModeInfo {
+            id: $id,
+            id_bits: $idb,
+            subsets: $ns,
+            transformed: $tr,
+            endpoint_bits: $eb,
+            delta_bits: $db,
+            layout: $layout,
+        }
+    };
+}
+
+const MODES: [ModeInfo; 14] = [
+    mode!(0b00, 2, 2, true, 10, [5, 5, 5], LAYOUT0),
+    mode!(0b01, 2, 2, true, 7, [6, 6, 6], LAYOUT1),
+    mode!(0b00010, 5, 2, true, 11, [5, 4, 4], LAYOUT2),
+    mode!(0b00110, 5, 2, true, 11, [4, 5, 4], LAYOUT3),
+    mode!(0b01010, 5, 2, true, 11, [4, 4, 5], LAYOUT4),
+    mode!(0b01110, 5, 2, true, 9, [5, 5, 5], LAYOUT5),
+    mode!(0b10010, 5, 2, true, 8, [6, 5, 5], LAYOUT6),
+    mode!(0b10110, 5, 2, true, 8, [5, 6, 5], LAYOUT7),
+    mode!(0b11010, 5, 2, true, 8, [5, 5, 6], LAYOUT8),
+    mode!(0b11110, 5, 2, false, 6, [6, 6, 6], LAYOUT9),
+    mode!(0b00011, 5, 1, false, 10, [10, 10, 10], LAYOUT10),
+    mode!(0b00111, 5, 1, true, 11, [9, 9, 9], LAYOUT11),
+    mode!(0b01011, 5, 1, true, 12, [8, 8, 8], LAYOUT12),
+    mode!(0b01111, 5, 1, true, 16, [4, 4, 4], LAYOUT13),
+];
+
+// The header fields are listed in the order they are stored, starting at the least
+// significant bit of each run. Bits that are stored in reverse order are listed
+// one at a time.
+
+/// Header fields of mode 0 following the mode bits
+#[rustfmt::skip]
+const LAYOUT0: &[Field] = &[
+    bits(Y, G, 4, 1), bits(Y, B, 4, 1), bits(Z, B, 4, 1), bits(W, R, 0, 10), bits(W, G, 0, 10),
+    bits(W, B, 0, 10), bits(X, R, 0, 5), bits(Z, G, 4, 1), bits(Y, G, 0, 4), bits(X, G, 0, 5),
+    bits(Z, B, 0, 1), bits(Z, G, 0, 4), bits(X, B, 0, 5), bits(Z, B, 1, 1), bits(Y, B, 0, 4),
+    bits(Y, R, 0, 5), bits(Z, B, 2, 1), bits(Z, R, 0, 5), bits(Z, B, 3, 1), bits(D, R, 0, 5),
+];
+
+/// Header fields of mode 1 following the mode bits
+#[rustfmt::skip]
+const LAYOUT1: &[Field] = &[
+    bits(Y, G, 5, 1), bits(Z, G, 4, 1), bits(Z, G, 5, 1), bits(W, R, 0, 7), bits(Z, B, 0, 1),
+    bits(Z, B, 1, 1), bits(Y, B, 4, 1), bits(W, G, 0, 7), bits(Y, B, 5, 1), bits(Z, B, 2, 1),
+    bits(Y, G, 4, 1), bits(W, B, 0, 7), bits(Z, B, 3, 1), bits(Z, B, 5, 1), bits(Z, B, 4, 1),
+    bits(X, R, 0, 6), bits(Y, G, 0, 4), bits(X, G, 0, 6), bits(Z, G, 0, 4), bits(X, B, 0, 6),
+    bits(Y, B, 0, 4), bits(Y, R, 0, 6), bits(Z, R, 0, 6), bits(D, R, 0, 5),
+];
+
+/// Header fields of mode 2 following the mode bits
+#[rustfmt::skip]
+const LAYOUT2: &[Field] = &[
+    bits(W, R, 0, 10), bits(W, G, 0, 10), bits(W, B, 0, 10), bits(X, R, 0, 5), bits(W, R, 10, 1),
+    bits(Y, G, 0, 4), bits(X, G, 0, 4), bits(W, G, 10, 1), bits(Z, B, 0, 1), bits(Z, G, 0, 4),
+    bits(X, B, 0, 4), bits(W, B, 10, 1), bits(Z, B, 1, 1), bits(Y, B, 0, 4), bits(Y, R, 0, 5),
+    bits(Z, B, 2, 1), bits(Z, R, 0, 5), bits(Z, B, 3, 1), bits(D, R, 0, 5),
+];
+
+/// Header fields of mode 3 following the mode bits
+#[rustfmt::skip]
+const LAYOUT3: &[Field] = &[
+    bits(W, R, 0, 10), bits(W, G, 0, 10), bits(W, B, 0, 10), bits(X, R, 0, 4), bits(W, R, 10, 1),
+    bits(Z, G, 4, 1), bits(Y, G, 0, 4), bits(X, G, 0, 5), bits(W, G, 10, 1), bits(Z, G, 0, 4),
+    bits(X, B, 0, 4), bits(W, B, 10, 1), bits(Z, B, 1, 1), bits(Y, B, 0, 4), bits(Y, R, 0, 4),
+    bits(Z, B, 0, 1), bits(Z, B, 2, 1), bits(Z, R, 0, 4), bits(Y, G, 4, 1), bits(Z, B, 3, 1),
+    bits(D, R, 0, 5),
+];
+
+/// Header fields of mode 4 following the mode bits
+#[rustfmt::skip]
+const LAYOUT4: &[Field] = &[
+    bits(W, R, 0, 10), bits(W, G, 0, 10), bits(W, B, 0, 10), bits(X, R, 0, 4), bits(W, R, 10, 1),
+    bits(Y, B, 4, 1), bits(Y, G, 0, 4), bits(X, G, 0, 4), bits(W, G, 10, 1), bits(Z, B, 0, 1),
+    bits(Z, G, 0, 4), bits(X, B, 0, 5), bits(W, B, 10, 1), bits(Y, B, 0, 4), bits(Y, R, 0, 4),
+    bits(Z, B, 1, 1), bits(Z, B, 2, 1), bits(Z, R, 0, 4), bits(Z, B, 4, 1), bits(Z, B, 3, 1),
+    bits(D, R, 0, 5),
+];
+
+/// Header fields of mode 5 following the mode bits
+#[rustfmt::skip]
+const LAYOUT5: &[Field] = &[
+    bits(W, R, 0, 9), bits(Y, B, 4, 1), bits(W, G, 0, 9), bits(Y, G, 4, 1), bits(W, B, 0, 9),
+    bits(Z, B, 4, 1), bits(X, R, 0, 5), bits(Z, G, 4, 1), bits(Y, G, 0, 4), bits(X, G, 0, 5),
+    bits(Z, B, 0, 1), bits(Z, G, 0, 4), bits(X, B, 0, 5), bits(Z, B, 1, 1), bits(Y, B, 0, 4),
+    bits(Y, R, 0, 5), bits(Z, B, 2, 1), bits(Z, R, 0, 5), bits(Z, B, 3, 1), bits(D, R, 0, 5),
+];
+
+/// Header fields of mode 6 following the mode bits
+#[rustfmt::skip]
+const LAYOUT6: &[Field] = &[
+    bits(W, R, 0, 8), bits(Z, G, 4, 1), bits(Y, B, 4, 1), bits(W, G, 0, 8), bits(Z, B, 2, 1),
+    bits(Y, G, 4, 1), bits(W, B, 0, 8), bits(Z, B, 3, 1), bits(Z, B, 4, 1), bits(X, R, 0, 6),
+    bits(Y, G, 0, 4), bits(X, G, 0, 5), bits(Z, B, 0, 1), bits(Z, G, 0, 4), bits(X, B, 0, 5),
+    bits(Z, B, 1, 1), bits(Y, B, 0, 4), bits(Y, R, 0, 6), bits(Z, R, 0, 6), bits(D, R, 0, 5),
+];
+
+/// Header fields of mode 7 following the mode bits
+#[rustfmt::skip]
+const LAYOUT7: &[Field] = &[
+    bits(W, R, 0, 8), bits(Z, B, 0, 1), bits(Y, B, 4, 1), bits(W, G, 0, 8), bits(Y, G, 5, 1),
+    bits(Y, G, 4, 1), bits(W, B, 0, 8), bits(Z, G, 5, 1), bits(Z, B, 4, 1), bits(X, R, 0, 5),
+    bits(Z, G, 4, 1), bits(Y, G, 0, 4), bits(X, G, 0, 6), bits(Z, G, 0, 4), bits(X, B, 0, 5),
+    bits(Z, B, 1, 1), bits(Y, B, 0, 4), bits(Y, R, 0, 5), bits(Z, B, 2, 1), bits(Z, R, 0, 5),
+    bits(Z, B, 3, 1), bits(D, R, 0, 5),
+];
+
+/// Header fields of mode 8 following the mode bits
+#[rustfmt::skip]
+const LAYOUT8: &[Field] = &[
+    bits(W, R, 0, 8), bits(Z, B, 1, 1), bits(Y, B, 4, 1), bits(W, G, 0, 8), bits(Y, B, 5, 1),
+    bits(Y, G, 4, 1), bits(W, B, 0, 8), bits(Z, B, 5, 1), bits(Z, B, 4, 1), bits(X, R, 0, 5),
+    bits(Z, G, 4, 1), bits(Y, G, 0, 4), bits(X, G, 0, 5), bits(Z, B, 0, 1), bits(Z, G, 0, 4),
+    bits(X, B, 0, 6), bits(Y, B, 0, 4), bits(Y, R, 0, 5), bits(Z, B, 2, 1), bits(Z, R, 0, 5),
+    bits(Z, B, 3, 1), bits(D, R, 0, 5),
+];
+
+/// Header fields of mode 9 following the mode bits
+#[rustfmt::skip]
+const LAYOUT9: &[Field] = &[
+    bits(W, R, 0, 6), bits(Z, G, 4, 1), bits(Z, B, 0, 1), bits(Z, B, 1, 1), bits(Y, B, 4, 1),
+    bits(W, G, 0, 6), bits(Y, G, 5, 1), bits(Y, B, 5, 1), bits(Z, B, 2, 1), bits(Y, G, 4, 1),
+    bits(W, B, 0, 6), bits(Z, G, 5, 1), bits(Z, B, 3, 1), bits(Z, B, 5, 1), bits(Z, B, 4, 1),
+    bits(X, R, 0, 6), bits(Y, G, 0, 4), bits(X, G, 0, 6), bits(Z, G, 0, 4), bits(X, B, 0, 6),
+    bits(Y, B, 0, 4), bits(Y, R, 0, 6), bits(Z, R, 0, 6), bits(D, R, 0, 5),
+];
+
+/// Header fields of mode 10 following the mode bits
+#[rustfmt::skip]
+const LAYOUT10: &[Field] = &[
+    bits(W, R, 0, 10), bits(W, G, 0, 10), bits(W, B, 0, 10), bits(X, R, 0, 10), bits(X, G, 0, 10),
+    bits(X, B, 0, 10),
+];
+
+/// Header fields of mode 11 following the mode bits
+#[rustfmt::skip]
+const LAYOUT11: &[Field] = &[
+    bits(W, R, 0, 10), bits(W, G, 0, 10), bits(W, B, 0, 10), bits(X, R, 0, 9), bits(W, R, 10, 1),
+    bits(X, G, 0, 9), bits(W, G, 10, 1), bits(X, B, 0, 9), bits(W, B, 10, 1),
+];
+
+/// Header fields of mode 12 following the mode bits
+#[rustfmt::skip]
+const LAYOUT12: &[Field] = &[
+    bits(W, R, 0, 10), bits(W, G, 0, 10), bits(W, B, 0, 10), bits(X, R, 0, 8), bits(W, R, 11, 1),
+    bits(W, R, 10, 1), bits(X, G, 0, 8), bits(W, G, 11, 1), bits(W, G, 10, 1), bits(X, B, 0, 8),
+    bits(W, B, 11, 1), bits(W, B, 10, 1),
+];
+
+/// Header fields of mode 13 following the mode bits
+#[rustfmt::skip]
+const LAYOUT13: &[Field] = &[
+    bits(W, R, 0, 10), bits(W, G, 0, 10), bits(W, B, 0, 10), bits(X, R, 0, 4), bits(W, R, 15, 1),
+    bits(W, R, 14, 1), bits(W, R, 13, 1), bits(W, R, 12, 1), bits(W, R, 11, 1), bits(W, R, 10, 1),
+    bits(X, G, 0, 4), bits(W, G, 15, 1), bits(W, G, 14, 1), bits(W, G, 13, 1), bits(W, G, 12, 1),
+    bits(W, G, 11, 1), bits(W, G, 10, 1), bits(X, B, 0, 4), bits(W, B, 15, 1), bits(W, B, 14, 1),
+    bits(W, B, 13, 1), bits(W, B, 12, 1), bits(W, B, 11, 1), bits(W, B, 10, 1),
+];
+
+/// Returns the number of index bits used by modes with the given number of subsets
+fn index_bits(subsets: usize) -> usize {
+    if subsets == 2 {
+        3
+    } else {
+        4
+    }
+}
+
+fn sign_extend(value: i32, bits: usize) -> i32 {
+    let shift = 32 - bits;
+    (value << shift) >> shift
+}
+
+/// Expands a quantised endpoint value to the 16-bit interpolation domain
+fn unquantise(value: i32, bits: usize, signed: bool) -> i32 {
+    if signed {
+        if bits >= 16 {
+            return value;
+        }
+        let magnitude = value.abs();
+        let expanded = if magnitude == 0 {
+            0
+        } else if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7FFF
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        if value < 0 {
+            -expanded
+        } else {
+            expanded
+        }
+    } else if bits >= 15 || value == 0 {
+        value
+    } else if value == (1 << bits) - 1 {
+        0xFFFF
+    } else {
+        ((value << 16) + 0x8000) >> bits
+    }
+}
+
+/// Scales an interpolated value to the half float range and returns its bit pattern
+fn finish_unquantise(value: i32, signed: bool) -> u16 {
+    if signed {
+        let magnitude = ((value.abs() * 31) >> 5) as u16;
+        if value < 0 && magnitude > 0 {
+            0x8000 | magnitude
+        } else {
+            magnitude
+        }
+    } else {
+        ((value * 31) >> 6) as u16
+    }
+}
+
+/// Converts a half float bit pattern to an integer that orders like the value it encodes
+fn ordinal(half: u16) -> i32 {
+    let magnitude = i32::from(half & 0x7FFF);
+    if (half & 0x8000) != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+/// A fully specified BC6H block
+#[derive(Clone, Copy)]
+struct Encoding {
+    mode: usize,
+    partition: usize,
+    /// Endpoint fields as stored in the block, indexed by endpoint and channel
+    fields: [[i32; 3]; 4],
+    indices: [u8; 16],
+    error: f32,
+}
+
+impl Encoding {
+    fn new(mode: usize) -> Self {
+        Encoding {
+            mode,
+            partition: 0,
+            fields: [[0; 3]; 4],
+            indices: [0; 16],
+            error: 0.0,
+        }
+    }
+
+    fn read(bytes: &[u8]) -> Option<Self> {
+        let mut reader = BitReader::new(bytes);
+
+        // the mode is stored in two bits if it fits, otherwise in five
+        let mut id = reader.read(2);
+        if id > 1 {
+            id |= reader.read(3) << 2;
+        }
+        let mode = MODES.iter().position(|info| info.id == id)?;
+        let info = &MODES[mode];
+
+        let mut enc = Encoding::new(mode);
+        for field in info.layout {
+            let value = reader.read(field.count) as i32;
+            if field.endpoint == D {
+                enc.partition |= (value as usize) << field.lsb;
+            } else {
+                enc.fields[field.endpoint][field.channel] |= value << field.lsb;
+            }
+        }
+
+        let index_bits = index_bits(info.subsets);
+        for (i, index) in enc.indices.iter_mut().enumerate() {
+            let anchor = bptc::is_anchor(info.subsets, enc.partition, i);
+            *index = reader.read(index_bits - anchor as usize) as u8;
+        }
+
+        Some(enc)
+    }
+
+    fn write(&self, block: &mut [u8]) {
+        let info = &MODES[self.mode];
+        let mut writer = BitWriter::new();
+
+        writer.write(info.id, info.id_bits);
+        for field in info.layout {
+            let value = if field.endpoint == D {
+                self.partition as i32
+            } else {
+                self.fields[field.endpoint][field.channel]
+            };
+            writer.write(
+                ((value >> field.lsb) & ((1 << field.count) - 1)) as u32,
+                field.count,
+            );
+        }
+
+        let index_bits = index_bits(info.subsets);
+        for (i, &index) in self.indices.iter().enumerate() {
+            let anchor = bptc::is_anchor(info.subsets, self.partition, i);
+            writer.write(u32::from(index), index_bits - anchor as usize);
+        }
+
+        writer.finish(block);
+    }
+
+    /// Reconstructs the endpoints in the 16-bit interpolation domain
+    #[allow(clippy::needless_range_loop)]
+    fn endpoints(&self, signed: bool) -> [[i32; 3]; 4] {
+        let info = &MODES[self.mode];
+        let mask = (1 << info.endpoint_bits) - 1;
+
+        let mut endpoints = [[0i32; 3]; 4];
+        for c in 0..3 {
+            let base = if signed {
+                sign_extend(self.fields[W][c], info.endpoint_bits)
+            } else {
+                self.fields[W][c]
+            };
+            endpoints[W][c] = base;
+
+            for e in 1..2 * info.subsets {
+                let mut value = self.fields[e][c];
+                if info.transformed || signed {
+                    value = sign_extend(value, info.delta_bits[c]);
+                }
+                if info.transformed {
+                    value = (value + base) & mask;
+                    if signed {
+                        value = sign_extend(value, info.endpoint_bits);
+                    }
+                }
+                endpoints[e][c] = value;
+            }
+        }
+
+        for endpoint in endpoints.iter_mut().take(2 * info.subsets) {
+            for value in endpoint.iter_mut() {
+                *value = unquantise(*value, info.endpoint_bits, signed);
+            }
+        }
+
+        endpoints
+    }
+
+    fn decode(&self, signed: bool) -> [[u16; 3]; 16] {
+        let info = &MODES[self.mode];
+        let endpoints = self.endpoints(signed);
+        let weights = bptc::weights(index_bits(info.subsets));
+
+        let mut rgb = [[0u16; 3]; 16];
+        for (i, pixel) in rgb.iter_mut().enumerate() {
+            let subset = bptc::subset(info.subsets, self.partition, i);
+            let weight = weights[self.indices[i] as usize];
+            for (c, value) in pixel.iter_mut().enumerate() {
+                let start = endpoints[2 * subset][c];
+                let end = endpoints[2 * subset + 1][c];
+                *value = finish_unquantise(bptc::interpolate(start, end, weight), signed);
+            }
+        }
+
+        rgb
+    }
+}
+
+/// Decompresses a 16 byte BC6H block to RGB half floats
+///
+/// Blocks using one of the reserved modes decode to zero.
+pub fn decompress(bytes: &[u8], signed: bool) -> [[u16; 3]; 16] {
+    assert!(bytes.len() == 16);
+
+    match Encoding::read(bytes) {
+        Some(enc) => enc.decode(signed),
+        None => [[0u16; 3]; 16],
+    }
+}
+
+/// Amount of work the encoder puts into a single block
+struct Effort {
+    /// Number of partitions encoded per two-subset mode
+    partitions: usize,
+    /// Number of least squares endpoint refinement passes
+    refinements: usize,
+}
+
+impl From<Algorithm> for Effort {
+    fn from(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::RangeFit => Effort {
+                partitions: 1,
+                refinements: 0,
+            },
+            Algorithm::ClusterFit => Effort {
+                partitions: 4,
+                refinements: 2,
+            },
+            Algorithm::IterativeClusterFit => Effort {
+                partitions: 32,
+                refinements: 8,
+            },
+        }
+    }
+}
+
+struct Compressor {
+    /// Target pixels as ordered half float bit patterns
+    targets: [[i32; 3]; 16],
+    /// Target pixels in the 16-bit interpolation domain
+    pixels: [[f32; 3]; 16],
+    weights: [f32; 3],
+    mask: u32,
+    signed: bool,
+    effort: Effort,
+}
+
+impl Compressor {
+    /// Returns the range of the 16-bit interpolation domain
+    fn range(&self) -> (f32, f32) {
+        if self.signed {
+            (-32767.0, 32767.0)
+        } else {
+            (0.0, 65535.0)
+        }
+    }
+
+    /// Returns the mask of valid pixels belonging to a subset
+    fn subset_mask(&self, subsets: usize, partition: usize, subset: usize) -> u32 {
+        (0..16)
+            .filter(|&i| bptc::subset(subsets, partition, i) == subset)
+            .fold(0, |acc, i| acc | (1 << i))
+            & self.mask
+    }
+
+    /// Computes the weighted mean and covariance of the pixels in a subset
+    fn covariance(&self, mask: u32) -> ([f32; 3], [[f32; 4]; 4]) {
+        let mut mean = [0f32; 3];
+        let mut count = 0f32;
+        for (i, pixel) in self.pixels.iter().enumerate() {
+            if (mask & (1 << i)) != 0 {
+                for c in 0..3 {
+                    mean[c] += pixel[c];
+                }
+                count += 1.0;
+            }
+        }
+        if count > 0.0 {
+            for m in mean.iter_mut() {
+                *m /= count;
+            }
+        }
+
+        let mut covariance = [[0f32; 4]; 4];
+        for (i, pixel) in self.pixels.iter().enumerate() {
+            if (mask & (1 << i)) != 0 {
+                for a in 0..3 {
+                    for b in 0..3 {
+                        let da = (pixel[a] - mean[a]) * libm::sqrtf(self.weights[a]);
+                        let db = (pixel[b] - mean[b]) * libm::sqrtf(self.weights[b]);
+                        covariance[a][b] += da * db;
+                    }
+                }
+            }
+        }
+
+        (mean, covariance)
+    }
+
+    /// Estimates the error of a partition by the residual of a line fit through each subset
+    fn estimate_partition(&self, partition: usize) -> f32 {
+        let mut error = 0.0;
+        for subset in 0..2 {
+            let mask = self.subset_mask(2, partition, subset);
+            let (_, covariance) = self.covariance(mask);
+            let (_, eigenvalue) = bptc::principal_axis(&covariance);
+            let total: f32 = (0..3).map(|c| covariance[c][c]).sum();
+            error += total - eigenvalue;
+        }
+        error
+    }
+
+    /// Finds the extent of the pixels of a subset along their principal axis
+    fn extent(&self, mask: u32) -> [[f32; 3]; 2] {
+        let (mean, covariance) = self.covariance(mask);
+        let (axis, _) = bptc::principal_axis(&covariance);
+        // the covariance is weighted, the pixels are not
+        let axis = bptc::unweight_axis(axis, &self.weights);
+
+        let mut min = 0f32;
+        let mut max = 0f32;
+        for (i, pixel) in self.pixels.iter().enumerate() {
+            if (mask & (1 << i)) != 0 {
+                let dot: f32 = (0..3).map(|c| (pixel[c] - mean[c]) * axis[c]).sum();
+                min = min.min(dot);
+                max = max.max(dot);
+            }
+        }
+
+        let (low, high) = self.range();
+        let mut extent = [[0f32; 3]; 2];
+        for c in 0..3 {
+            extent[0][c] = (mean[c] + axis[c] * min).clamp(low, high);
+            extent[1][c] = (mean[c] + axis[c] * max).clamp(low, high);
+        }
+        extent
+    }
+
+    /// Quantises a single endpoint channel to the closest representable value
+    fn quantise(&self, value: f32, bits: usize) -> i32 {
+        let (magnitude, precision, sign) = if self.signed {
+            (value.abs(), bits - 1, if value < 0.0 { -1 } else { 1 })
+        } else {
+            (value, bits, 1)
+        };
+        let max = if self.signed {
+            (1 << precision) - 1
+        } else {
+            (1 << bits) - 1
+        };
+        let scale = if self.signed { 32768.0 } else { 65536.0 };
+
+        // initial guess, then check the neighbouring values for the closest match
+        let guess = (magnitude * (1 << precision) as f32 / scale) as i32;
+
+        let mut best = 0;
+        let mut least = f32::MAX;
+        for candidate in (guess - 1).max(0)..=(guess + 1).min(max) {
+            let expanded = unquantise(sign * candidate, bits, self.signed);
+            let dist = (expanded as f32 - value).abs();
+            if dist < least {
+                least = dist;
+                best = sign * candidate;
+            }
+        }
+
+        best
+    }
+
+    /// Builds the palette of a subset as ordered half float bit patterns
+    fn palette(&self, start: &[i32; 3], end: &[i32; 3], index_bits: usize) -> [[i32; 3]; 16] {
+        let mut palette = [[0i32; 3]; 16];
+        for (entry, &weight) in palette.iter_mut().zip(bptc::weights(index_bits)) {
+            for c in 0..3 {
+                let value = bptc::interpolate(start[c], end[c], weight);
+                entry[c] = ordinal(finish_unquantise(value, self.signed));
+            }
+        }
+        palette
+    }
+
+    /// Finds the closest of the first `count` palette entries for a pixel
+    fn closest(&self, pixel: usize, palette: &[[i32; 3]; 16], count: usize) -> (u8, f32) {
+        let mut index = 0;
+        let mut least = f32::MAX;
+        for (j, entry) in palette.iter().enumerate().take(count) {
+            let mut dist = 0.0;
+            for ((value, target), weight) in
+                entry.iter().zip(&self.targets[pixel]).zip(&self.weights)
+            {
+                let d = (value - target) as f32;
+                dist += weight * d * d;
+            }
+            if dist < least {
+                least = dist;
+                index = j as u8;
+            }
+        }
+        (index, least)
+    }
+
+    /// Solves for the endpoints that best reproduce the pixels given their indices
+    fn least_squares(
+        &self,
+        mask: u32,
+        index_bits: usize,
+        indices: &[u8; 16],
+    ) -> Option<[[f32; 3]; 2]> {
+        let weights = bptc::weights(index_bits);
+        let mut aa = 0.0;
+        let mut ab = 0.0;
+        let mut bb = 0.0;
+        let mut ax = [0f32; 3];
+        let mut bx = [0f32; 3];
+
+        for (i, pixel) in self.pixels.iter().enumerate() {
+            if (mask & (1 << i)) == 0 {
+                continue;
+            }
+
+            let beta = weights[indices[i] as usize] as f32 / 64.0;
+            let alpha = 1.0 - beta;
+            aa += alpha * alpha;
+            ab += alpha * beta;
+            bb += beta * beta;
+            for c in 0..3 {
+                ax[c] += alpha * pixel[c];
+                bx[c] += beta * pixel[c];
+            }
+        }
+
+        let det = aa * bb - ab * ab;
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+
+        let (low, high) = self.range();
+        let mut endpoints = [[0f32; 3]; 2];
+        for c in 0..3 {
+            endpoints[0][c] = ((ax[c] * bb - bx[c] * ab) / det).clamp(low, high);
+            endpoints[1][c] = ((bx[c] * aa - ax[c] * ab) / det).clamp(low, high);
+        }
+
+        Some(endpoints)
+    }
+
+    /// Stores a set of quantised endpoints in a mode and computes the resulting indices
+    #[allow(clippy::needless_range_loop)]
+    fn finish(&self, mode: usize, partition: usize, mut endpoints: [[i32; 3]; 4]) -> Encoding {
+        let info = &MODES[mode];
+        let index_bits = index_bits(info.subsets);
+        let highest = (1 << index_bits) - 1;
+
+        // the most significant index bit of the anchor pixel is implicitly zero
+        for subset in 0..info.subsets {
+            let anchor = bptc::anchor(info.subsets, partition, subset);
+            if (self.mask & (1 << anchor)) == 0 {
+                continue;
+            }
+
+            let mut start = [0i32; 3];
+            let mut end = [0i32; 3];
+            for c in 0..3 {
+                start[c] = unquantise(endpoints[2 * subset][c], info.endpoint_bits, self.signed);
+                end[c] = unquantise(
+                    endpoints[2 * subset + 1][c],
+                    info.endpoint_bits,
+                    self.signed,
+                );
+            }
+            let palette = self.palette(&start, &end, index_bits);
+            let (index, _) = self.closest(anchor, &palette, highest + 1);
+            if index > (highest >> 1) as u8 {
+                endpoints.swap(2 * subset, 2 * subset + 1);
+            }
+        }
+
+        // store the endpoints, pulling them towards the first one if the delta is too large
+        let mut enc = Encoding::new(mode);
+        enc.partition = partition;
+        let mask = (1 << info.endpoint_bits) - 1;
+        for c in 0..3 {
+            enc.fields[W][c] = endpoints[W][c] & mask;
+            for e in 1..2 * info.subsets {
+                enc.fields[e][c] = if info.transformed {
+                    let limit = 1 << (info.delta_bits[c] - 1);
+                    let delta = (endpoints[e][c] - endpoints[W][c]).clamp(-limit, limit - 1);
+                    delta & ((1 << info.delta_bits[c]) - 1)
+                } else {
+                    endpoints[e][c] & mask
+                };
+            }
+        }
+
+        // assign indices against the endpoints exactly as the decoder sees them
+        let unquantised = enc.endpoints(self.signed);
+        for subset in 0..info.subsets {
+            let palette = self.palette(
+                &unquantised[2 * subset],
+                &unquantised[2 * subset + 1],
+                index_bits,
+            );
+            let anchor = bptc::anchor(info.subsets, partition, subset);
+            for i in 0..16 {
+                if bptc::subset(info.subsets, partition, i) != subset {
+                    continue;
+                }
+
+                let count = if i == anchor {
+                    1 << (index_bits - 1)
+                } else {
+                    1 << index_bits
+                };
+                let (index, error) = self.closest(i, &palette, count);
+                enc.indices[i] = index;
+                if (self.mask & (1 << i)) != 0 {
+                    enc.error += error;
+                }
+            }
+        }
+
+        enc
+    }
+
+    /// Encodes a block with a given mode and partition
+    fn compress_mode(&self, mode: usize, partition: usize) -> Encoding {
+        let info = &MODES[mode];
+        let index_bits = index_bits(info.subsets);
+
+        // start from the extent of the pixels along the principal axis
+        let mut endpoints = [[0i32; 3]; 4];
+        for subset in 0..info.subsets {
+            let extent = self.extent(self.subset_mask(info.subsets, partition, subset));
+            for e in 0..2 {
+                for c in 0..3 {
+                    endpoints[2 * subset + e][c] = self.quantise(extent[e][c], info.endpoint_bits);
+                }
+            }
+        }
+        let mut best = self.finish(mode, partition, endpoints);
+
+        // refine the endpoints for the current set of indices
+        for _ in 0..self.effort.refinements {
+            if best.error == 0.0 {
+                break;
+            }
+
+            let mut refined = endpoints;
+            for subset in 0..info.subsets {
+                let mask = self.subset_mask(info.subsets, partition, subset);
+                if let Some(fit) = self.least_squares(mask, index_bits, &best.indices) {
+                    for e in 0..2 {
+                        for c in 0..3 {
+                            refined[2 * subset + e][c] =
+                                self.quantise(fit[e][c], info.endpoint_bits);
+                        }
+                    }
+                }
+            }
+
+            let enc = self.finish(mode, partition, refined);
+            if enc.error < best.error {
+                best = enc;
+                endpoints = refined;
+            } else {
+                break;
+            }
+        }
+
+        best
+    }
+}
+
+/// Compresses a 4x4 block of RGB half floats to BC6H
+///
+/// Values outside the range of the format are clamped, i.e. negative values become zero
+/// for the unsigned format, and infinities and NaNs become the largest finite value.
+pub fn compress(rgb: &[[u16; 3]; 16], mask: u32, signed: bool, params: &Params, block: &mut [u8]) {
+    let mut targets = [[0i32; 3]; 16];
+    let mut pixels = [[0f32; 3]; 16];
+    for (i, pixel) in rgb.iter().enumerate() {
+        for c in 0..3 {
+            let magnitude = i32::from(pixel[c] & 0x7FFF).min(0x7BFF);
+            let negative = (pixel[c] & 0x8000) != 0;
+            targets[i][c] = match (negative, signed) {
+                (true, true) => -magnitude,
+                (true, false) => 0,
+                (false, _) => magnitude,
+            };
+
+            // undo the scaling applied by the decoder after interpolation
+            let scale = if signed { 32.0 } else { 64.0 };
+            pixels[i][c] = targets[i][c] as f32 * scale / 31.0;
+        }
+    }
+
+    let compressor = Compressor {
+        targets,
+        pixels,
+        weights: bptc::colour_weights(&params.weights),
+        mask,
+        signed,
+        effort: Effort::from(params.algorithm),
+    };
+
+    // rank the partitions by their estimated error, they are the same for all modes
+    let mut ranked = [(0usize, 0f32); 32];
+    for (partition, rank) in ranked.iter_mut().enumerate() {
+        *rank = (partition, 0.0);
+    }
+    let candidates = ranked.len().min(compressor.effort.partitions);
+    if candidates < ranked.len() {
+        for rank in ranked.iter_mut() {
+            rank.1 = compressor.estimate_partition(rank.0);
+        }
+        ranked.sort_unstable_by(|a, b| a.1.total_cmp(&b.1));
+    }
+
+    let mut best = Encoding::new(10);
+    best.error = f32::MAX;
+    for (mode, info) in MODES.iter().enumerate() {
+        if best.error == 0.0 {
+            break;
+        }
+
+        let partitions = if info.subsets == 2 { candidates } else { 1 };
+        for &(partition, _) in ranked.iter().take(partitions) {
+            let partition = if info.subsets == 2 { partition } else { 0 };
+            let enc = compressor.compress_mode(mode, partition);
+            if enc.error < best.error {
+                best = enc;
+            }
+        }
+    }
+
+    best.write(block);
+}
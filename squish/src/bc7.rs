@@ -0,0 +1,874 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! BC7 block encoder and decoder
+//!
+//! The encoder evaluates all eight block modes. Partitioned modes are ranked by a
+//! cheap line-fit estimate first and only the most promising partitions are encoded
+//! fully. How many partitions are tried and how much effort goes into refining
+//! the endpoints is controlled by the `Algorithm` in `Params`.
+
+use core::f32;
+use core::ops::Range;
+
+use crate::bptc::{self, BitReader, BitWriter};
+use crate::{Algorithm, Params};
+
+/// How the p-bits (shared least significant endpoint bits) of a mode are laid out
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PBits {
+    None,
+    Shared,
+    Unique,
+}
+
+struct ModeInfo {
+    subsets: usize,
+    partition_bits: usize,
+    rotation_bits: usize,
+    index_selection_bits: usize,
+    colour_bits: usize,
+    alpha_bits: usize,
+    pbits: PBits,
+    index_bits: usize,
+    index_bits2: usize,
+}
+
+macro_rules! mode {
+    ($ns:expr, $pb:expr, $rb:expr, $isb:expr, $cb:expr, $ab:expr, $pbits:expr, $ib:expr, $ib2:expr) => {
+        ModeInfo {
+            subsets: $ns,
+            partition_bits: $pb,
+            rotation_bits: $rb,
+            index_selection_bits: $isb,
+            colour_bits: $cb,
+            alpha_bits: $ab,
+            pbits: $pbits,
+            index_bits: $ib,
+            index_bits2: $ib2,
+        }
+    };
+}
+
+const MODES: [ModeInfo; 8] = [
+    mode!(3, 4, 0, 0, 4, 0, PBits::Unique, 3, 0),
+    mode!(2, 6, 0, 0, 6, 0, PBits::Shared, 3, 0),
+    mode!(3, 6, 0, 0, 5, 0, PBits::None, 2, 0),
+    mode!(2, 6, 0, 0, 7, 0, PBits::Unique, 2, 0),
+    mode!(1, 0, 2, 1, 5, 6, PBits::None, 2, 3),
+    mode!(1, 0, 2, 0, 7, 8, PBits::None, 2, 2),
+    mode!(1, 0, 0, 0, 7, 7, PBits::Unique, 4, 0),
+    mode!(2, 6, 0, 0, 5, 5, PBits::Unique, 2, 0),
+];
+
+/// Expands a quantised endpoint value to 8 bits by replicating its high bits
+fn unquantise(value: i32, bits: usize) -> i32 {
+    let value = value << (8 - bits);
+    value | (value >> bits)
+}
+
+/// A fully specified BC7 block
+#[derive(Clone, Copy)]
+struct Encoding {
+    mode: usize,
+    partition: usize,
+    rotation: usize,
+    index_selection: usize,
+    /// Quantised endpoints without p-bits, indexed by subset, endpoint and channel
+    endpoints: [[[i32; 4]; 2]; 3],
+    /// P-bits by subset and endpoint, both entries are equal for shared p-bits
+    pbits: [[i32; 2]; 3],
+    indices: [u8; 16],
+    indices2: [u8; 16],
+    error: f32,
+}
+
+impl Encoding {
+    fn new(mode: usize) -> Self {
+        Encoding {
+            mode,
+            partition: 0,
+            rotation: 0,
+            index_selection: 0,
+            endpoints: [[[0; 4]; 2]; 3],
+            pbits: [[0; 2]; 3],
+            indices: [0; 16],
+            indices2: [0; 16],
+            error: 0.0,
+        }
+    }
+
+    fn read(bytes: &[u8]) -> Option<Self> {
+        let mut reader = BitReader::new(bytes);
+
+        // the mode is stored as the number of zero bits preceding the first set bit
+        let mut mode = 0;
+        while reader.read(1) == 0 {
+            mode += 1;
+            if mode == MODES.len() {
+                return None;
+            }
+        }
+
+        let info = &MODES[mode];
+        let mut enc = Encoding::new(mode);
+        enc.partition = reader.read(info.partition_bits) as usize;
+        enc.rotation = reader.read(info.rotation_bits) as usize;
+        enc.index_selection = reader.read(info.index_selection_bits) as usize;
+
+        // endpoints are stored channel by channel
+        for channel in 0..4 {
+            let bits = if channel < 3 {
+                info.colour_bits
+            } else {
+                info.alpha_bits
+            };
+            for subset in 0..info.subsets {
+                for endpoint in 0..2 {
+                    enc.endpoints[subset][endpoint][channel] = reader.read(bits) as i32;
+                }
+            }
+        }
+
+        for subset in 0..info.subsets {
+            match info.pbits {
+                PBits::None => {}
+                PBits::Shared => {
+                    let p = reader.read(1) as i32;
+                    enc.pbits[subset] = [p, p];
+                }
+                PBits::Unique => {
+                    enc.pbits[subset][0] = reader.read(1) as i32;
+                    enc.pbits[subset][1] = reader.read(1) as i32;
+                }
+            }
+        }
+
+        for i in 0..16 {
+            let anchor = bptc::is_anchor(info.subsets, enc.partition, i);
+            enc.indices[i] = reader.read(info.index_bits - anchor as usize) as u8;
+        }
+        if info.index_bits2 > 0 {
+            for i in 0..16 {
+                enc.indices2[i] = reader.read(info.index_bits2 - (i == 0) as usize) as u8;
+            }
+        }
+
+        Some(enc)
+    }
+
+    fn write(&self, block: &mut [u8]) {
+        let info = &MODES[self.mode];
+        let mut writer = BitWriter::new();
+
+        writer.write(1 << self.mode, self.mode + 1);
+        writer.write(self.partition as u32, info.partition_bits);
+        writer.write(self.rotation as u32, info.rotation_bits);
+        writer.write(self.index_selection as u32, info.index_selection_bits);
+
+        for channel in 0..4 {
+            let bits = if channel < 3 {
+                info.colour_bits
+            } else {
+                info.alpha_bits
+            };
+            for subset in 0..info.subsets {
+                for endpoint in 0..2 {
+                    writer.write(self.endpoints[subset][endpoint][channel] as u32, bits);
+                }
+            }
+        }
+
+        for subset in 0..info.subsets {
+            match info.pbits {
+                PBits::None => {}
+                PBits::Shared => writer.write(self.pbits[subset][0] as u32, 1),
+                PBits::Unique => {
+                    writer.write(self.pbits[subset][0] as u32, 1);
+                    writer.write(self.pbits[subset][1] as u32, 1);
+                }
+            }
+        }
+
+        for i in 0..16 {
+            let anchor = bptc::is_anchor(info.subsets, self.partition, i);
+            writer.write(
+                u32::from(self.indices[i]),
+                info.index_bits - anchor as usize,
+            );
+        }
+        if info.index_bits2 > 0 {
+            for i in 0..16 {
+                writer.write(
+                    u32::from(self.indices2[i]),
+                    info.index_bits2 - (i == 0) as usize,
+                );
+            }
+        }
+
+        writer.finish(block);
+    }
+
+    /// Returns the 8-bit RGBA value of an endpoint
+    fn endpoint(&self, subset: usize, endpoint: usize) -> [i32; 4] {
+        let info = &MODES[self.mode];
+        let mut rgba = [255; 4];
+
+        for (channel, value) in rgba.iter_mut().enumerate() {
+            let bits = if channel < 3 {
+                info.colour_bits
+            } else {
+                info.alpha_bits
+            };
+            if bits == 0 {
+                continue;
+            }
+
+            let quantised = self.endpoints[subset][endpoint][channel];
+            *value = if info.pbits == PBits::None {
+                unquantise(quantised, bits)
+            } else {
+                unquantise((quantised << 1) | self.pbits[subset][endpoint], bits + 1)
+            };
+        }
+
+        rgba
+    }
+
+    fn decode(&self) -> [[u8; 4]; 16] {
+        let info = &MODES[self.mode];
+        let mut rgba = [[0u8; 4]; 16];
+
+        // look up the colour and alpha weights for each pixel
+        let (colour_indices, colour_bits, alpha_indices, alpha_bits) = if info.index_bits2 == 0 {
+            (
+                &self.indices,
+                info.index_bits,
+                &self.indices,
+                info.index_bits,
+            )
+        } else if self.index_selection == 0 {
+            (
+                &self.indices,
+                info.index_bits,
+                &self.indices2,
+                info.index_bits2,
+            )
+        } else {
+            (
+                &self.indices2,
+                info.index_bits2,
+                &self.indices,
+                info.index_bits,
+            )
+        };
+        let colour_weights = bptc::weights(colour_bits);
+        let alpha_weights = bptc::weights(alpha_bits);
+
+        for (i, pixel) in rgba.iter_mut().enumerate() {
+            let subset = bptc::subset(info.subsets, self.partition, i);
+            let start = self.endpoint(subset, 0);
+            let end = self.endpoint(subset, 1);
+
+            let colour_weight = colour_weights[colour_indices[i] as usize];
+            let alpha_weight = alpha_weights[alpha_indices[i] as usize];
+            for channel in 0..4 {
+                let weight = if channel < 3 {
+                    colour_weight
+                } else {
+                    alpha_weight
+                };
+                pixel[channel] = bptc::interpolate(start[channel], end[channel], weight) as u8;
+            }
+
+            // undo the channel rotation
+            if self.rotation > 0 {
+                pixel.swap(3, self.rotation - 1);
+            }
+        }
+
+        rgba
+    }
+}
+
+/// Decompresses a BC7 block to 4x4 RGBA pixels
+///
+/// Blocks using the reserved mode decode to transparent black.
+pub fn decompress(bytes: &[u8]) -> [[u8; 4]; 16] {
+    assert!(bytes.len() == 16);
+
+    match Encoding::read(bytes) {
+        Some(enc) => enc.decode(),
+        None => [[0u8; 4]; 16],
+    }
+}
+
+/// Amount of work the encoder puts into a single block
+struct Effort {
+    /// Number of partitions encoded per partitioned mode
+    partitions: usize,
+    /// Number of least squares endpoint refinement passes
+    refinements: usize,
+    /// Whether to try every channel rotation and index selection in modes 4 and 5
+    rotations: bool,
+    /// Whether to search the neighbourhood of the quantised endpoints
+    exhaustive: bool,
+}
+
+impl From<Algorithm> for Effort {
+    fn from(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::RangeFit => Effort {
+                partitions: 1,
+                refinements: 0,
+                rotations: false,
+                exhaustive: false,
+            },
+            Algorithm::ClusterFit => Effort {
+                partitions: 4,
+                refinements: 2,
+                rotations: true,
+                exhaustive: false,
+            },
+            Algorithm::IterativeClusterFit => Effort {
+                partitions: 16,
+                refinements: 8,
+                rotations: true,
+                exhaustive: true,
+            },
+        }
+    }
+}
+
+/// Result of fitting a single subset
+struct SubsetFit {
+    /// Quantised endpoints without p-bits
+    endpoints: [[i32; 4]; 2],
+    pbits: [i32; 2],
+    indices: [u8; 16],
+    error: f32,
+}
+
+/// Describes how the channels of a subset are quantised and indexed
+#[derive(Clone)]
+struct Layout {
+    channels: Range<usize>,
+    bits: usize,
+    pbits: PBits,
+    index_bits: usize,
+}
+
+struct Compressor {
+    pixels: [[f32; 4]; 16],
+    weights: [f32; 4],
+    mask: u32,
+    effort: Effort,
+}
+
+impl Compressor {
+    /// Returns the mask of valid pixels belonging to a subset
+    fn subset_mask(&self, subsets: usize, partition: usize, subset: usize) -> u32 {
+        (0..16)
+            .filter(|&i| bptc::subset(subsets, partition, i) == subset)
+            .fold(0, |acc, i| acc | (1 << i))
+            & self.mask
+    }
+
+    /// Computes the weighted mean and covariance of the pixels in a subset
+    fn covariance(&self, mask: u32, channels: &Range<usize>) -> ([f32; 4], [[f32; 4]; 4]) {
+        let mut mean = [0f32; 4];
+        let mut count = 0f32;
+        for (i, pixel) in self.pixels.iter().enumerate() {
+            if (mask & (1 << i)) != 0 {
+                for c in channels.clone() {
+                    mean[c] += pixel[c];
+                }
+                count += 1.0;
+            }
+        }
+        if count > 0.0 {
+            for m in mean.iter_mut() {
+                *m /= count;
+            }
+        }
+
+        let mut covariance = [[0f32; 4]; 4];
+        for (i, pixel) in self.pixels.iter().enumerate() {
+            if (mask & (1 << i)) != 0 {
+                for a in channels.clone() {
+                    for b in channels.clone() {
+                        let da = (pixel[a] - mean[a]) * libm::sqrtf(self.weights[a]);
+                        let db = (pixel[b] - mean[b]) * libm::sqrtf(self.weights[b]);
+                        covariance[a][b] += da * db;
+                    }
+                }
+            }
+        }
+
+        (mean, covariance)
+    }
+
+    /// Estimates the error of a partition by the residual of a line fit through each subset
+    fn estimate_partition(&self, subsets: usize, partition: usize, channels: &Range<usize>) -> f32 {
+        let mut error = 0.0;
+        for subset in 0..subsets {
+            let mask = self.subset_mask(subsets, partition, subset);
+            let (_, covariance) = self.covariance(mask, channels);
+            let (_, eigenvalue) = bptc::principal_axis(&covariance);
+            let total: f32 = (0..4).map(|c| covariance[c][c]).sum();
+            error += total - eigenvalue;
+        }
+        error
+    }
+
+    /// Computes the index and error of the closest palette entry for every pixel
+    fn assign_indices(
+        &self,
+        mask: u32,
+        layout: &Layout,
+        start: &[i32; 4],
+        end: &[i32; 4],
+        indices: &mut [u8; 16],
+    ) -> f32 {
+        let weights = bptc::weights(layout.index_bits);
+        let mut palette = [[0f32; 4]; 16];
+        for (entry, &weight) in palette.iter_mut().zip(weights) {
+            for c in layout.channels.clone() {
+                entry[c] = bptc::interpolate(start[c], end[c], weight) as f32;
+            }
+        }
+
+        let mut error = 0.0;
+        for (i, pixel) in self.pixels.iter().enumerate() {
+            if (mask & (1 << i)) == 0 {
+                continue;
+            }
+
+            let mut least = f32::MAX;
+            for (j, entry) in palette.iter().enumerate().take(weights.len()) {
+                let mut dist = 0.0;
+                for c in layout.channels.clone() {
+                    let d = entry[c] - pixel[c];
+                    dist += self.weights[c] * d * d;
+                }
+                if dist < least {
+                    least = dist;
+                    indices[i] = j as u8;
+                }
+            }
+            error += least;
+        }
+
+        error
+    }
+
+    /// Evaluates a pair of quantised endpoints
+    fn evaluate(
+        &self,
+        mask: u32,
+        layout: &Layout,
+        endpoints: [[i32; 4]; 2],
+        pbits: [i32; 2],
+    ) -> SubsetFit {
+        let mut expanded = [[0i32; 4]; 2];
+        for e in 0..2 {
+            for c in layout.channels.clone() {
+                expanded[e][c] = match layout.pbits {
+                    PBits::None => unquantise(endpoints[e][c], layout.bits),
+                    _ => unquantise((endpoints[e][c] << 1) | pbits[e], layout.bits + 1),
+                };
+            }
+        }
+
+        let mut indices = [0u8; 16];
+        let error = self.assign_indices(mask, layout, &expanded[0], &expanded[1], &mut indices);
+
+        SubsetFit {
+            endpoints,
+            pbits,
+            indices,
+            error,
+        }
+    }
+
+    /// Quantises a pair of unquantised endpoints, trying every p-bit combination
+    fn quantise(&self, mask: u32, layout: &Layout, start: &[f32; 4], end: &[f32; 4]) -> SubsetFit {
+        let combinations: &[[i32; 2]] = match layout.pbits {
+            PBits::None => &[[0, 0]],
+            PBits::Shared => &[[0, 0], [1, 1]],
+            PBits::Unique => &[[0, 0], [0, 1], [1, 0], [1, 1]],
+        };
+
+        let mut best: Option<SubsetFit> = None;
+        for &pbits in combinations {
+            let mut endpoints = [[0i32; 4]; 2];
+            for c in layout.channels.clone() {
+                endpoints[0][c] = quantise_channel(start[c], layout, pbits[0]);
+                endpoints[1][c] = quantise_channel(end[c], layout, pbits[1]);
+            }
+
+            let fit = self.evaluate(mask, layout, endpoints, pbits);
+            if best.as_ref().is_none_or(|b| fit.error < b.error) {
+                best = Some(fit);
+            }
+        }
+
+        best.unwrap()
+    }
+
+    /// Solves for the endpoints that best reproduce the pixels given their indices
+    fn least_squares(
+        &self,
+        mask: u32,
+        layout: &Layout,
+        indices: &[u8; 16],
+    ) -> Option<[[f32; 4]; 2]> {
+        let weights = bptc::weights(layout.index_bits);
+        let mut aa = 0.0;
+        let mut ab = 0.0;
+        let mut bb = 0.0;
+        let mut ax = [0f32; 4];
+        let mut bx = [0f32; 4];
+
+        for (i, pixel) in self.pixels.iter().enumerate() {
+            if (mask & (1 << i)) == 0 {
+                continue;
+            }
+
+            let beta = weights[indices[i] as usize] as f32 / 64.0;
+            let alpha = 1.0 - beta;
+            aa += alpha * alpha;
+            ab += alpha * beta;
+            bb += beta * beta;
+            for c in layout.channels.clone() {
+                ax[c] += alpha * pixel[c];
+                bx[c] += beta * pixel[c];
+            }
+        }
+
+        let det = aa * bb - ab * ab;
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+
+        let mut endpoints = [[0f32; 4]; 2];
+        for c in layout.channels.clone() {
+            endpoints[0][c] = ((ax[c] * bb - bx[c] * ab) / det).clamp(0.0, 255.0);
+            endpoints[1][c] = ((bx[c] * aa - ax[c] * ab) / det).clamp(0.0, 255.0);
+        }
+
+        Some(endpoints)
+    }
+
+    /// Fits a pair of endpoints and indices to the pixels of a subset
+    fn fit(&self, mask: u32, anchor: usize, layout: &Layout) -> SubsetFit {
+        // start from the extent of the pixels along the principal axis
+        let (mean, covariance) = self.covariance(mask, &layout.channels);
+        let (axis, _) = bptc::principal_axis(&covariance);
+        // the covariance is weighted, the pixels are not
+        let axis = bptc::unweight_axis(axis, &self.weights);
+
+        let mut min = 0f32;
+        let mut max = 0f32;
+        for (i, pixel) in self.pixels.iter().enumerate() {
+            if (mask & (1 << i)) != 0 {
+                let dot: f32 = layout
+                    .channels
+                    .clone()
+                    .map(|c| (pixel[c] - mean[c]) * axis[c])
+                    .sum();
+                min = min.min(dot);
+                max = max.max(dot);
+            }
+        }
+
+        let mut start = [0f32; 4];
+        let mut end = [0f32; 4];
+        for c in layout.channels.clone() {
+            start[c] = (mean[c] + axis[c] * min).clamp(0.0, 255.0);
+            end[c] = (mean[c] + axis[c] * max).clamp(0.0, 255.0);
+        }
+
+        let mut best = self.quantise(mask, layout, &start, &end);
+
+        // refine the endpoints for the current set of indices
+        for _ in 0..self.effort.refinements {
+            if best.error == 0.0 {
+                break;
+            }
+            let endpoints = match self.least_squares(mask, layout, &best.indices) {
+                Some(endpoints) => endpoints,
+                None => break,
+            };
+            let fit = self.quantise(mask, layout, &endpoints[0], &endpoints[1]);
+            if fit.error < best.error {
+                best = fit;
+            } else {
+                break;
+            }
+        }
+
+        // nudge each quantised endpoint channel until nothing improves
+        if self.effort.exhaustive {
+            let max = (1 << layout.bits) - 1;
+            let mut improved = best.error > 0.0;
+            while improved {
+                improved = false;
+                for e in 0..2 {
+                    for c in layout.channels.clone() {
+                        for delta in [-1, 1] {
+                            let value = best.endpoints[e][c] + delta;
+                            if !(0..=max).contains(&value) {
+                                continue;
+                            }
+
+                            let mut endpoints = best.endpoints;
+                            endpoints[e][c] = value;
+                            let fit = self.evaluate(mask, layout, endpoints, best.pbits);
+                            if fit.error < best.error {
+                                best = fit;
+                                improved = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // the most significant index bit of the anchor pixel is implicitly zero
+        let highest = (1 << layout.index_bits) - 1;
+        if best.indices[anchor] > highest >> 1 {
+            best.endpoints.swap(0, 1);
+            best.pbits.swap(0, 1);
+            for (i, index) in best.indices.iter_mut().enumerate() {
+                if (mask & (1 << i)) != 0 {
+                    *index = highest - *index;
+                }
+            }
+        }
+
+        best
+    }
+
+    /// Encodes a block with one of the partitioned (or single subset) modes 0-3, 6 and 7
+    fn compress_partitioned(&self, mode: usize, best: &mut Encoding) {
+        let info = &MODES[mode];
+        let channels = if info.alpha_bits > 0 { 0..4 } else { 0..3 };
+        let layout = Layout {
+            channels: channels.clone(),
+            bits: info.colour_bits,
+            pbits: info.pbits,
+            index_bits: info.index_bits,
+        };
+
+        // rank the partitions by their estimated error
+        let count = 1 << info.partition_bits;
+        let mut ranked = [(0usize, 0f32); 64];
+        for (partition, rank) in ranked.iter_mut().enumerate().take(count) {
+            *rank = (partition, 0.0);
+        }
+        let candidates = count.min(self.effort.partitions);
+        if candidates < count {
+            for rank in ranked.iter_mut().take(count) {
+                rank.1 = self.estimate_partition(info.subsets, rank.0, &channels);
+            }
+            ranked[..count].sort_unstable_by(|a, b| a.1.total_cmp(&b.1));
+        }
+
+        for &(partition, _) in ranked.iter().take(candidates) {
+            let mut enc = Encoding::new(mode);
+            enc.partition = partition;
+
+            for subset in 0..info.subsets {
+                let mask = self.subset_mask(info.subsets, partition, subset);
+                let anchor = bptc::anchor(info.subsets, partition, subset);
+                let fit = self.fit(mask, anchor, &layout);
+
+                enc.endpoints[subset] = fit.endpoints;
+                enc.pbits[subset] = fit.pbits;
+                for i in 0..16 {
+                    if (mask & (1 << i)) != 0 {
+                        enc.indices[i] = fit.indices[i];
+                    }
+                }
+                enc.error += fit.error;
+
+                if enc.error >= best.error {
+                    break;
+                }
+            }
+
+            if enc.error < best.error {
+                *best = enc;
+            }
+        }
+    }
+
+    /// Encodes a block with one of the modes 4 and 5, which store colour and alpha separately
+    fn compress_rotated(&mut self, mode: usize, best: &mut Encoding) {
+        let info = &MODES[mode];
+        let rotations = if self.effort.rotations { 4 } else { 1 };
+        let selections = if self.effort.rotations {
+            1 << info.index_selection_bits
+        } else {
+            1
+        };
+
+        for rotation in 0..rotations {
+            // move the scalar channel into the alpha position
+            if rotation > 0 {
+                self.rotate(rotation);
+            }
+
+            for index_selection in 0..selections {
+                let (colour_index_bits, alpha_index_bits) = if index_selection == 0 {
+                    (info.index_bits, info.index_bits2)
+                } else {
+                    (info.index_bits2, info.index_bits)
+                };
+
+                let colour = self.fit(
+                    self.mask,
+                    0,
+                    &Layout {
+                        channels: 0..3,
+                        bits: info.colour_bits,
+                        pbits: PBits::None,
+                        index_bits: colour_index_bits,
+                    },
+                );
+                let alpha = self.fit(
+                    self.mask,
+                    0,
+                    &Layout {
+                        channels: 3..4,
+                        bits: info.alpha_bits,
+                        pbits: PBits::None,
+                        index_bits: alpha_index_bits,
+                    },
+                );
+
+                let mut enc = Encoding::new(mode);
+                enc.rotation = rotation;
+                enc.index_selection = index_selection;
+                for e in 0..2 {
+                    enc.endpoints[0][e][..3].copy_from_slice(&colour.endpoints[e][..3]);
+                    enc.endpoints[0][e][3] = alpha.endpoints[e][3];
+                }
+                if index_selection == 0 {
+                    enc.indices = colour.indices;
+                    enc.indices2 = alpha.indices;
+                } else {
+                    enc.indices = alpha.indices;
+                    enc.indices2 = colour.indices;
+                }
+                enc.error = colour.error + alpha.error;
+
+                if enc.error < best.error {
+                    *best = enc;
+                }
+            }
+
+            // restore the original channel order
+            if rotation > 0 {
+                self.rotate(rotation);
+            }
+        }
+    }
+
+    /// Swaps the alpha channel with the colour channel selected by the rotation
+    fn rotate(&mut self, rotation: usize) {
+        for pixel in self.pixels.iter_mut() {
+            pixel.swap(3, rotation - 1);
+        }
+        self.weights.swap(3, rotation - 1);
+    }
+}
+
+/// Quantises a single channel of an endpoint to the closest representable value
+fn quantise_channel(value: f32, layout: &Layout, pbit: i32) -> i32 {
+    let max = (1 << layout.bits) - 1;
+    let has_pbit = layout.pbits != PBits::None;
+    let precision = layout.bits + has_pbit as usize;
+
+    // initial guess, then check the neighbouring values for the closest match
+    let full = libm::roundf(value * ((1 << precision) - 1) as f32 / 255.0) as i32;
+    let guess = if has_pbit { (full - pbit) >> 1 } else { full };
+
+    let mut best = 0;
+    let mut least = f32::MAX;
+    for candidate in (guess - 1).max(0)..=(guess + 1).min(max) {
+        let expanded = if has_pbit {
+            unquantise((candidate << 1) | pbit, precision)
+        } else {
+            unquantise(candidate, precision)
+        };
+        let dist = (expanded as f32 - value).abs();
+        if dist < least {
+            least = dist;
+            best = candidate;
+        }
+    }
+
+    best
+}
+
+/// Compresses a 4x4 block of pixels to BC7
+pub fn compress(rgba: &[[u8; 4]; 16], mask: u32, params: &Params, block: &mut [u8]) {
+    let mut pixels = [[0f32; 4]; 16];
+    for (pixel, source) in pixels.iter_mut().zip(rgba) {
+        for (p, &s) in pixel.iter_mut().zip(source) {
+            *p = f32::from(s);
+        }
+    }
+
+    let colour_weights = bptc::colour_weights(&params.weights);
+    let weights = [colour_weights[0], colour_weights[1], colour_weights[2], 1.0];
+
+    let mut compressor = Compressor {
+        pixels,
+        weights,
+        mask,
+        effort: Effort::from(params.algorithm),
+    };
+
+    // modes without alpha are only useful for opaque blocks
+    let opaque = (0..16).all(|i| (mask & (1 << i)) == 0 || rgba[i][3] == 255);
+
+    let mut best = Encoding::new(6);
+    best.error = f32::MAX;
+    for (mode, info) in MODES.iter().enumerate() {
+        if best.error == 0.0 {
+            break;
+        }
+        if !opaque && info.alpha_bits == 0 {
+            continue;
+        }
+
+        if info.rotation_bits > 0 {
+            compressor.compress_rotated(mode, &mut best);
+        } else {
+            compressor.compress_partitioned(mode, &mut best);
+        }
+    }
+
+    best.write(block);
+}
@@ -0,0 +1,194 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Building blocks shared by the BPTC formats (BC6H and BC7)
+
+use core::mem;
+
+use crate::ColourWeights;
+
+mod tables;
+
+pub use self::tables::*;
+
+/// Interpolation weights for 2-bit indices
+pub const WEIGHTS2: [i32; 4] = [0, 21, 43, 64];
+
+/// Interpolation weights for 3-bit indices
+pub const WEIGHTS3: [i32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+
+/// Interpolation weights for 4-bit indices
+pub const WEIGHTS4: [i32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Returns the interpolation weights for indices of the given bit width
+pub fn weights(index_bits: usize) -> &'static [i32] {
+    match index_bits {
+        2 => &WEIGHTS2,
+        3 => &WEIGHTS3,
+        4 => &WEIGHTS4,
+        _ => unreachable!(),
+    }
+}
+
+/// Interpolates between two endpoint values using a weight in [0, 64]
+pub fn interpolate(a: i32, b: i32, weight: i32) -> i32 {
+    (a * (64 - weight) + b * weight + 32) >> 6
+}
+
+/// Returns the subset a pixel belongs to in the given partition
+pub fn subset(subsets: usize, partition: usize, pixel: usize) -> usize {
+    match subsets {
+        1 => 0,
+        2 => usize::from((PARTITIONS2[partition] >> pixel) & 1),
+        3 => ((PARTITIONS3[partition] >> (2 * pixel)) & 3) as usize,
+        _ => unreachable!(),
+    }
+}
+
+/// Returns the anchor pixel of a subset in the given partition
+///
+/// The index of the anchor pixel is stored with its most significant bit omitted.
+pub fn anchor(subsets: usize, partition: usize, subset: usize) -> usize {
+    match (subsets, subset) {
+        (_, 0) => 0,
+        (2, 1) => usize::from(ANCHORS2_2[partition]),
+        (3, 1) => usize::from(ANCHORS3_2[partition]),
+        (3, 2) => usize::from(ANCHORS3_3[partition]),
+        _ => unreachable!(),
+    }
+}
+
+/// Returns true if the pixel is the anchor of its subset
+pub fn is_anchor(subsets: usize, partition: usize, pixel: usize) -> bool {
+    (0..subsets).any(|s| anchor(subsets, partition, s) == pixel)
+}
+
+/// Scales the colour weights so that uniform weights are all one
+pub fn colour_weights(weights: &ColourWeights) -> [f32; 3] {
+    let sum: f32 = weights.iter().sum();
+    let scale = if sum > f32::EPSILON { 3.0 / sum } else { 1.0 };
+    [weights[0] * scale, weights[1] * scale, weights[2] * scale]
+}
+
+/// Finds the dominant eigenvector and its eigenvalue using power iteration
+pub fn principal_axis(covariance: &[[f32; 4]; 4]) -> ([f32; 4], f32) {
+    const POWER_ITERATION_COUNT: usize = 8;
+
+    // start from the row with the largest diagonal entry
+    let mut row = 0;
+    for i in 1..4 {
+        if covariance[i][i] > covariance[row][row] {
+            row = i;
+        }
+    }
+    let mut v = covariance[row];
+
+    let mut eigenvalue = 0.0;
+    for _ in 0..POWER_ITERATION_COUNT {
+        let length = libm::sqrtf(v.iter().map(|x| x * x).sum());
+        if length < f32::EPSILON {
+            return ([0.0; 4], 0.0);
+        }
+        for x in v.iter_mut() {
+            *x /= length;
+        }
+
+        let mut w = [0f32; 4];
+        for (i, wi) in w.iter_mut().enumerate() {
+            *wi = (0..4).map(|j| covariance[i][j] * v[j]).sum();
+        }
+        eigenvalue = (0..4).map(|i| w[i] * v[i]).sum();
+        mem::swap(&mut v, &mut w);
+    }
+
+    let length = libm::sqrtf(v.iter().map(|x| x * x).sum());
+    if length < f32::EPSILON {
+        return ([0.0; 4], 0.0);
+    }
+    for x in v.iter_mut() {
+        *x /= length;
+    }
+
+    (v, eigenvalue)
+}
+
+/// Maps an axis found in space scaled by the square roots of the channel weights back to
+/// the pixel space, as a unit vector. Channels without weight keep no extent.
+pub fn unweight_axis(axis: [f32; 4], weights: &[f32]) -> [f32; 4] {
+    let mut v = [0f32; 4];
+    for (c, &weight) in weights.iter().enumerate() {
+        if weight > f32::EPSILON {
+            v[c] = axis[c] / libm::sqrtf(weight);
+        }
+    }
+    let length = libm::sqrtf(v.iter().map(|x| x * x).sum());
+    if length < f32::EPSILON {
+        return [0.0; 4];
+    }
+    v.map(|x| x / length)
+}
+
+/// Reads bit fields from a 128-bit block, starting at the least significant bit
+pub struct BitReader {
+    bits: u128,
+}
+
+impl BitReader {
+    pub fn new(bytes: &[u8]) -> Self {
+        let mut tmp = [0u8; 16];
+        tmp.copy_from_slice(bytes);
+        BitReader {
+            bits: u128::from_le_bytes(tmp),
+        }
+    }
+
+    pub fn read(&mut self, count: usize) -> u32 {
+        let value = (self.bits & ((1u128 << count) - 1)) as u32;
+        self.bits >>= count;
+        value
+    }
+}
+
+/// Writes bit fields into a 128-bit block, starting at the least significant bit
+pub struct BitWriter {
+    bits: u128,
+    position: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        BitWriter {
+            bits: 0,
+            position: 0,
+        }
+    }
+
+    pub fn write(&mut self, value: u32, count: usize) {
+        debug_assert!(count == 32 || value >> count == 0);
+        self.bits |= u128::from(value) << self.position;
+        self.position += count;
+    }
+
+    pub fn finish(self, block: &mut [u8]) {
+        debug_assert!(self.position == 128);
+        block.copy_from_slice(&self.bits.to_le_bytes());
+    }
+}
@@ -22,9 +22,12 @@
 
 use core::cmp::Ordering;
 use core::f32;
+use core::ops::{Add, Mul, Sub};
 
 use crate::colourblock;
 use crate::colourset::ColourSet;
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+use crate::math::{Avx2, Vec4x2};
 use crate::math::{Sym3x3, Vec3, Vec4};
 use crate::{ColourWeights, Format};
 
@@ -32,6 +35,196 @@ use super::ColourFitImpl;
 
 const MAX_ITERATIONS: usize = 8;
 
+/// One or more candidate clusterings evaluated side by side, each lane giving exactly the
+/// same result as evaluating it on its own
+///
+/// Creating candidates takes a token of the CPU features they need.
+trait Candidates: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> {
+    const COUNT: usize;
+    type Token: Copy;
+
+    fn splat(token: Self::Token, v: Vec4) -> Self;
+    fn gather(token: Self::Token, v: &[Vec4]) -> Self;
+    fn get(self, index: usize) -> Vec4;
+    fn splat_x(self) -> Self;
+    fn splat_y(self) -> Self;
+    fn splat_z(self) -> Self;
+    fn splat_w(self) -> Self;
+    fn max(self, other: Self) -> Self;
+    fn min(self, other: Self) -> Self;
+    fn reciprocal(self) -> Self;
+    fn truncate(self) -> Self;
+}
+
+impl Candidates for Vec4 {
+    const COUNT: usize = 1;
+    type Token = ();
+
+    #[inline(always)]
+    fn splat(_token: (), v: Vec4) -> Self {
+        v
+    }
+
+    #[inline(always)]
+    fn gather(_token: (), v: &[Vec4]) -> Self {
+        v[0]
+    }
+
+    #[inline(always)]
+    fn get(self, _index: usize) -> Vec4 {
+        self
+    }
+
+    #[inline(always)]
+    fn splat_x(self) -> Self {
+        Vec4::splat_x(&self)
+    }
+
+    #[inline(always)]
+    fn splat_y(self) -> Self {
+        Vec4::splat_y(&self)
+    }
+
+    #[inline(always)]
+    fn splat_z(self) -> Self {
+        Vec4::splat_z(&self)
+    }
+
+    #[inline(always)]
+    fn splat_w(self) -> Self {
+        Vec4::splat_w(&self)
+    }
+
+    #[inline(always)]
+    fn max(self, other: Self) -> Self {
+        Vec4::max(&self, other)
+    }
+
+    #[inline(always)]
+    fn min(self, other: Self) -> Self {
+        Vec4::min(&self, other)
+    }
+
+    #[inline(always)]
+    fn reciprocal(self) -> Self {
+        Vec4::reciprocal(&self)
+    }
+
+    #[inline(always)]
+    fn truncate(self) -> Self {
+        Vec4::truncate(&self)
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+impl Candidates for Vec4x2 {
+    const COUNT: usize = 2;
+    type Token = Avx2;
+
+    #[inline(always)]
+    fn splat(avx2: Avx2, v: Vec4) -> Self {
+        Vec4x2::splat(avx2, v)
+    }
+
+    #[inline(always)]
+    fn gather(avx2: Avx2, v: &[Vec4]) -> Self {
+        Vec4x2::new(avx2, v[0], v[1])
+    }
+
+    #[inline(always)]
+    fn get(self, index: usize) -> Vec4 {
+        if index == 0 {
+            self.lo()
+        } else {
+            self.hi()
+        }
+    }
+
+    #[inline(always)]
+    fn splat_x(self) -> Self {
+        Vec4x2::splat_x(self)
+    }
+
+    #[inline(always)]
+    fn splat_y(self) -> Self {
+        Vec4x2::splat_y(self)
+    }
+
+    #[inline(always)]
+    fn splat_z(self) -> Self {
+        Vec4x2::splat_z(self)
+    }
+
+    #[inline(always)]
+    fn splat_w(self) -> Self {
+        Vec4x2::splat_w(self)
+    }
+
+    #[inline(always)]
+    fn max(self, other: Self) -> Self {
+        Vec4x2::max(self, other)
+    }
+
+    #[inline(always)]
+    fn min(self, other: Self) -> Self {
+        Vec4x2::min(self, other)
+    }
+
+    #[inline(always)]
+    fn reciprocal(self) -> Self {
+        Vec4x2::reciprocal(self)
+    }
+
+    #[inline(always)]
+    fn truncate(self) -> Self {
+        Vec4x2::truncate(self)
+    }
+}
+
+/// Computes the least squares endpoints of the candidates and their error, without the
+/// constant term
+#[inline(always)]
+fn solve<V: Candidates>(
+    token: V::Token,
+    alphax_sum: V,
+    betax_sum: V,
+    alphabeta_sum: V,
+    weights: V,
+) -> (V, V, V) {
+    let two = V::splat(token, Vec4::new(2.0, 2.0, 2.0, 2.0));
+    let one = V::splat(token, Vec4::new(1.0, 1.0, 1.0, 1.0));
+    let zero = V::splat(token, Vec4::new(0.0, 0.0, 0.0, 0.0));
+    let half = V::splat(token, Vec4::new(0.5, 0.5, 0.5, 0.5));
+    let grid = V::splat(token, Vec4::new(31.0, 63.0, 31.0, 0.0));
+    let gridrcp = V::splat(token, Vec4::new(1.0 / 31.0, 1.0 / 63.0, 1.0 / 31.0, 0.0));
+
+    let alpha2_sum = alphax_sum.splat_w();
+    let beta2_sum = betax_sum.splat_w();
+
+    // compute the least-squares optimal points
+    let factor = ((alpha2_sum * beta2_sum) - alphabeta_sum * alphabeta_sum).reciprocal();
+    let a = ((alphax_sum * beta2_sum) - betax_sum * alphabeta_sum) * factor;
+    let b = ((betax_sum * alpha2_sum) - alphax_sum * alphabeta_sum) * factor;
+
+    // clamp to the grid
+    let a = one.min(zero.max(a));
+    let b = one.min(zero.max(b));
+    let a = (grid * a + half).truncate() * gridrcp;
+    let b = (grid * b + half).truncate() * gridrcp;
+
+    // compute the error (we skip the constant xxsum)
+    let e1 = (a * a) * alpha2_sum + (b * b * beta2_sum);
+    let e2 = (a * b * alphabeta_sum) - a * alphax_sum;
+    let e3 = e2 - b * betax_sum;
+    let e4 = two * e3 + e1;
+
+    // apply the channel weights to the error term
+    let e5 = e4 * weights;
+    let error = e5.splat_x() + e5.splat_y() + e5.splat_z();
+
+    (a, b, error)
+}
+
 pub struct ClusterFit<'a> {
     colourset: &'a ColourSet,
     format: Format,
@@ -43,6 +236,8 @@ pub struct ClusterFit<'a> {
     xsum_wsum: Vec4,
     best_error: Vec4,
     best_compressed: [u8; 8],
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+    avx2: Option<Avx2>,
 }
 
 impl<'a> ClusterFit<'a> {
@@ -63,6 +258,8 @@ impl<'a> ClusterFit<'a> {
             xsum_wsum: Vec4::new(0.0, 0.0, 0.0, 0.0),
             best_error: Vec4::new(f32::MAX, f32::MAX, f32::MAX, f32::MAX),
             best_compressed: [0u8; 8],
+            #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+            avx2: Avx2::detect(),
         };
 
         // get the covariance matrix
@@ -134,30 +331,30 @@ impl<'a> ClusterFit<'a> {
 
         true
     }
-}
 
-impl<'a> ColourFitImpl<'a> for ClusterFit<'a> {
-    fn is_bc1(&self) -> bool {
-        self.format == Format::Bc1
+    /// Evaluates one candidate at a time even if the CPU supports AVX2
+    #[cfg(all(test, target_arch = "x86_64", target_feature = "sse2"))]
+    pub fn disable_avx2(&mut self) {
+        self.avx2 = None;
     }
 
-    fn is_transparent(&self) -> bool {
-        self.colourset.is_transparent()
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+    #[target_feature(enable = "avx2")]
+    unsafe fn compress3_avx2(&mut self, avx2: Avx2) {
+        self.compress3_with::<Vec4x2>(avx2);
     }
 
-    fn best_compressed(&'a self) -> &'a [u8] {
-        &self.best_compressed
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+    #[target_feature(enable = "avx2")]
+    unsafe fn compress4_avx2(&mut self, avx2: Avx2) {
+        self.compress4_with::<Vec4x2>(avx2);
     }
 
-    fn compress3(&mut self) {
+    #[inline(always)]
+    fn compress3_with<V: Candidates>(&mut self, token: V::Token) {
         let count = self.colourset.count();
-        let two = Vec4::new(2.0, 2.0, 2.0, 2.0);
-        let one = Vec4::new(1.0, 1.0, 1.0, 1.0);
         let half_half2 = Vec4::new(0.5, 0.5, 0.5, 0.25);
         let zero = Vec4::new(0.0, 0.0, 0.0, 0.0);
-        let half = Vec4::new(0.5, 0.5, 0.5, 0.5);
-        let grid = Vec4::new(31.0, 63.0, 31.0, 0.0);
-        let gridrcp = Vec4::new(1.0 / 31.0, 1.0 / 63.0, 1.0 / 31.0, 0.0);
 
         // check all possible clusters and iterate on the total order
         let mut best_start = zero;
@@ -184,55 +381,50 @@ impl<'a> ColourFitImpl<'a> for ClusterFit<'a> {
                 let mut part1 = if i == 0 { self.points_weights[0] } else { zero };
                 let jmin = if i == 0 { 1 } else { i };
 
-                for j in jmin..=count {
+                let mut j = jmin;
+                while j <= count {
+                    // the second cluster for the next few values of j
+                    let mut group = [zero; 2];
+                    for (n, part) in group.iter_mut().enumerate().take(V::COUNT) {
+                        *part = part1;
+                        if j + n < count {
+                            part1 += self.points_weights[j + n];
+                        }
+                    }
+
+                    let part0 = V::splat(token, part0);
+                    let part1 = V::gather(token, &group);
+
                     // last cluster [j,count) is at the end
-                    let part2 = self.xsum_wsum - part1 - part0;
+                    let part2 = V::splat(token, self.xsum_wsum) - part1 - part0;
 
                     // compute least squares term directly
-                    let alphax_sum = part1 * half_half2 + part0;
-                    let alpha2_sum = alphax_sum.splat_w();
-
-                    let betax_sum = part1 * half_half2 + part2;
-                    let beta2_sum = betax_sum.splat_w();
-
-                    let alphabeta_sum = (part1 * half_half2).splat_w();
-
-                    // compute the least-squares optimal points
-                    let factor =
-                        ((alpha2_sum * beta2_sum) - alphabeta_sum * alphabeta_sum).reciprocal();
-                    let a = ((alphax_sum * beta2_sum) - betax_sum * alphabeta_sum) * factor;
-                    let b = ((betax_sum * alpha2_sum) - alphax_sum * alphabeta_sum) * factor;
-
-                    // clamp to the grid
-                    let a = one.min(zero.max(a));
-                    let b = one.min(zero.max(b));
-                    let a = (grid * a + half).truncate() * gridrcp;
-                    let b = (grid * b + half).truncate() * gridrcp;
-
-                    // compute the error (we skip the constant xxsum)
-                    let e1 = (a * a) * alpha2_sum + (b * b * beta2_sum);
-                    let e2 = (a * b * alphabeta_sum) - a * alphax_sum;
-                    let e3 = e2 - b * betax_sum;
-                    let e4 = two * e3 + e1;
-
-                    // apply the channel weights to the error term
-                    let e5 = e4 * self.weights;
-                    let error = e5.splat_x() + e5.splat_y() + e5.splat_z();
-
-                    // keep the solution if it wins
-                    if error.any_less_than(&best_error) {
-                        best_start = a;
-                        best_end = b;
-                        best_i = i;
-                        best_j = j;
-                        best_error = error;
-                        best_iteration = iteration_index;
+                    let alphax_sum = part1 * V::splat(token, half_half2) + part0;
+                    let betax_sum = part1 * V::splat(token, half_half2) + part2;
+                    let alphabeta_sum = (part1 * V::splat(token, half_half2)).splat_w();
+
+                    let (a, b, error) = solve(
+                        token,
+                        alphax_sum,
+                        betax_sum,
+                        alphabeta_sum,
+                        V::splat(token, self.weights),
+                    );
+
+                    // keep the solution if it wins, in the same order as one at a time
+                    for n in 0..V::COUNT.min(count + 1 - j) {
+                        let error = error.get(n);
+                        if error.any_less_than(&best_error) {
+                            best_start = a.get(n);
+                            best_end = b.get(n);
+                            best_i = i;
+                            best_j = j + n;
+                            best_error = error;
+                            best_iteration = iteration_index;
+                        }
                     }
 
-                    // advance
-                    if j < count {
-                        part1 += self.points_weights[j];
-                    }
+                    j += V::COUNT;
                 }
 
                 // advance
@@ -247,7 +439,6 @@ impl<'a> ColourFitImpl<'a> for ClusterFit<'a> {
             // compute new axis for next iteration
             axis = (best_end - best_start).to_vec3();
         }
-
         // save the block if necessary
         if best_error.any_less_than(&self.best_error) {
             // remap indices
@@ -273,17 +464,13 @@ impl<'a> ColourFitImpl<'a> for ClusterFit<'a> {
         }
     }
 
-    fn compress4(&mut self) {
+    #[inline(always)]
+    fn compress4_with<V: Candidates>(&mut self, token: V::Token) {
         let count = self.colourset.count();
-        let two = Vec4::new(2.0, 2.0, 2.0, 2.0);
-        let one = Vec4::new(1.0, 1.0, 1.0, 1.0);
         let onethird_onethird2 = Vec4::new(1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0, 1.0 / 9.0);
         let twothirds_twothirds2 = Vec4::new(2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 4.0 / 9.0);
         let twoninths = Vec4::new(2.0 / 9.0, 2.0 / 9.0, 2.0 / 9.0, 2.0 / 9.0);
         let zero = Vec4::new(0.0, 0.0, 0.0, 0.0);
-        let half = Vec4::new(0.5, 0.5, 0.5, 0.5);
-        let grid = Vec4::new(31.0, 63.0, 31.0, 0.0);
-        let gridrcp = Vec4::new(1.0 / 31.0, 1.0 / 63.0, 1.0 / 31.0, 0.0);
 
         // check all possible clusters and iterate on the total order
         let mut best_start = zero;
@@ -315,58 +502,54 @@ impl<'a> ColourFitImpl<'a> for ClusterFit<'a> {
                     let mut part2 = if j == 0 { self.points_weights[0] } else { zero };
                     let kmin = if j == 0 { 1 } else { j };
 
-                    for k in kmin..=count {
+                    let mut k = kmin;
+                    while k <= count {
+                        // the third cluster for the next few values of k
+                        let mut group = [zero; 2];
+                        for (n, part) in group.iter_mut().enumerate().take(V::COUNT) {
+                            *part = part2;
+                            if k + n < count {
+                                part2 += self.points_weights[k + n];
+                            }
+                        }
+
+                        let part0 = V::splat(token, part0);
+                        let part1 = V::splat(token, part1);
+                        let part2 = V::gather(token, &group);
+
                         // last cluster [k, count) is at the end
-                        let part3 = self.xsum_wsum - part2 - part1 - part0;
+                        let part3 = V::splat(token, self.xsum_wsum) - part2 - part1 - part0;
 
                         // compute least squares terms directly
-                        let alphax_sum =
-                            part2 * onethird_onethird2 + (part1 * twothirds_twothirds2 + part0);
-                        let alpha2_sum = alphax_sum.splat_w();
-
-                        let betax_sum =
-                            part1 * onethird_onethird2 + (part2 * twothirds_twothirds2 + part3);
-                        let beta2_sum = betax_sum.splat_w();
-
-                        let alphabeta_sum = twoninths * (part1 + part2).splat_w();
-
-                        // compute the least-squares optimal points
-                        let factor =
-                            ((alpha2_sum * beta2_sum) - alphabeta_sum * alphabeta_sum).reciprocal();
-                        let a = ((alphax_sum * beta2_sum) - betax_sum * alphabeta_sum) * factor;
-                        let b = ((betax_sum * alpha2_sum) - alphax_sum * alphabeta_sum) * factor;
-
-                        // clamp to the grid
-                        let a = one.min(zero.max(a));
-                        let b = one.min(zero.max(b));
-                        let a = (grid * a + half).truncate() * gridrcp;
-                        let b = (grid * b + half).truncate() * gridrcp;
-
-                        // compute the error (we skip the constant xxsum)
-                        let e1 = (a * a) * alpha2_sum + (b * b * beta2_sum);
-                        let e2 = (a * b * alphabeta_sum) - a * alphax_sum;
-                        let e3 = e2 - b * betax_sum;
-                        let e4 = two * e3 + e1;
-
-                        // apply the channel weights to the error term
-                        let e5 = e4 * self.weights;
-                        let error = e5.splat_x() + e5.splat_y() + e5.splat_z();
-
-                        // keep the solution if it wins
-                        if error.any_less_than(&best_error) {
-                            best_start = a;
-                            best_end = b;
-                            best_i = i;
-                            best_j = j;
-                            best_k = k;
-                            best_error = error;
-                            best_iteration = iteration_index;
+                        let alphax_sum = part2 * V::splat(token, onethird_onethird2)
+                            + (part1 * V::splat(token, twothirds_twothirds2) + part0);
+                        let betax_sum = part1 * V::splat(token, onethird_onethird2)
+                            + (part2 * V::splat(token, twothirds_twothirds2) + part3);
+                        let alphabeta_sum = V::splat(token, twoninths) * (part1 + part2).splat_w();
+
+                        let (a, b, error) = solve(
+                            token,
+                            alphax_sum,
+                            betax_sum,
+                            alphabeta_sum,
+                            V::splat(token, self.weights),
+                        );
+
+                        // keep the solution if it wins, in the same order as one at a time
+                        for n in 0..V::COUNT.min(count + 1 - k) {
+                            let error = error.get(n);
+                            if error.any_less_than(&best_error) {
+                                best_start = a.get(n);
+                                best_end = b.get(n);
+                                best_i = i;
+                                best_j = j;
+                                best_k = k + n;
+                                best_error = error;
+                                best_iteration = iteration_index;
+                            }
                         }
 
-                        // advance
-                        if k < count {
-                            part2 += self.points_weights[k];
-                        }
+                        k += V::COUNT;
                     }
 
                     // advance
@@ -387,7 +570,6 @@ impl<'a> ColourFitImpl<'a> for ClusterFit<'a> {
             // compute new axis for next iteration
             axis = (best_end - best_start).to_vec3();
         }
-
         // save the block if necessary
         if best_error.any_less_than(&self.best_error) {
             // remap indices
@@ -416,3 +598,37 @@ impl<'a> ColourFitImpl<'a> for ClusterFit<'a> {
         }
     }
 }
+
+impl<'a> ColourFitImpl<'a> for ClusterFit<'a> {
+    fn is_bc1(&self) -> bool {
+        self.format == Format::Bc1
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.colourset.is_transparent()
+    }
+
+    fn best_compressed(&'a self) -> &'a [u8] {
+        &self.best_compressed
+    }
+
+    fn compress3(&mut self) {
+        #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+        if let Some(avx2) = self.avx2 {
+            // SAFETY: the token proves that the CPU supports AVX2
+            return unsafe { self.compress3_avx2(avx2) };
+        }
+
+        self.compress3_with::<Vec4>(());
+    }
+
+    fn compress4(&mut self) {
+        #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+        if let Some(avx2) = self.avx2 {
+            // SAFETY: the token proves that the CPU supports AVX2
+            return unsafe { self.compress4_avx2(avx2) };
+        }
+
+        self.compress4_with::<Vec4>(());
+    }
+}
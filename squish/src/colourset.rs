@@ -31,8 +31,24 @@ pub struct ColourSet {
     transparent: bool,
 }
 
+/// Returns the factor that converts squared errors of sRGB encoded colour to approximate
+/// squared errors in linear light
+fn linear_weight(pixel: &[u8; 4]) -> f32 {
+    let slopes = [0, 1, 2].map(|c| srgb_to_linear_slope(f32::from(pixel[c]) / 255.0));
+    (slopes[0] * slopes[0] + slopes[1] * slopes[1] + slopes[2] * slopes[2]) / 3.0
+}
+
 impl ColourSet {
-    pub fn new(rgba: &[[u8; 4]; 16], mask: u32, format: Format, alpha_weighted: bool) -> ColourSet {
+    /// Creates the set of distinct colours of a block
+    ///
+    /// With `srgb`, each colour is weighted by how strongly its errors show in linear light.
+    pub fn new(
+        rgba: &[[u8; 4]; 16],
+        mask: u32,
+        format: Format,
+        alpha_weighted: bool,
+        srgb: bool,
+    ) -> ColourSet {
         let mut set = ColourSet {
             count: 0,
             points: [Vec3::new(0f32, 0f32, 0f32); 16],
@@ -72,6 +88,9 @@ impl ColourSet {
                     // store point
                     set.points[set.count] = Vec3::new(x, y, z);
                     set.weights[set.count] = if alpha_weighted { w } else { 1f32 };
+                    if srgb {
+                        set.weights[set.count] *= linear_weight(&rgba[i]);
+                    }
                     set.remap[i] = set.count as i8;
 
                     // move to next pixel
@@ -94,7 +113,9 @@ impl ColourSet {
                     let w = (i32::from(rgba[i][3]) + 1) as f32 / 256f32;
 
                     // map this point to its duplicate and increase the duplicate's weight
-                    set.weights[index as usize] += if alpha_weighted { w } else { 1f32 };
+                    let w = if alpha_weighted { w } else { 1f32 };
+                    set.weights[index as usize] +=
+                        if srgb { w * linear_weight(&rgba[i]) } else { w };
                     set.remap[i] = index;
 
                     // move to next pixel
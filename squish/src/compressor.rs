@@ -0,0 +1,256 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Reusable compressor with progress reporting and cancellation
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{num_blocks, Error, Format, Params, PixelLayout};
+
+/// Compresses images with a fixed format and parameters, reporting progress after each row
+/// of blocks and stopping early when asked to
+///
+/// The progress callback receives the number of finished rows and the total number of
+/// rows, which counts every row twice when `Params::rdo_lambda` enables the second pass.
+/// With the `rayon` feature, both callbacks are called from several threads at once and
+/// rows may finish out of order.
+///
+/// With the `alloc` feature, `compress_to_buffer` keeps its output buffer between calls, so
+/// compressing images of the same size over and over does not allocate.
+#[derive(Clone)]
+pub struct Compressor {
+    format: Format,
+    params: Params,
+    #[cfg(feature = "alloc")]
+    buffer: Vec<u8>,
+}
+
+impl Compressor {
+    /// Creates a compressor for the given format and parameters
+    pub fn new(format: Format, params: Params) -> Self {
+        Self {
+            format,
+            params,
+            #[cfg(feature = "alloc")]
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Returns the format images are compressed to
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Returns the compressor parameters
+    pub fn params(&self) -> Params {
+        self.params
+    }
+
+    /// Replaces the compressor parameters for the following images
+    pub fn set_params(&mut self, params: Params) {
+        self.params = params;
+    }
+
+    /// Compresses an image in memory, checking the sizes of all buffers first
+    ///
+    /// Returns `Error::Cancelled` if `cancel` returned true before all rows were finished,
+    /// in which case the contents of `output` are unspecified.
+    ///
+    /// * `rgba`     - The uncompressed pixel data
+    /// * `width`    - The width of the source image
+    /// * `height`   - The height of the source image
+    /// * `output`   - Output buffer for the compressed image
+    /// * `progress` - Called with the number of finished rows and the total after each row
+    /// * `cancel`   - Called before each row, stops the compression by returning true
+    pub fn compress<P, C>(
+        &self,
+        rgba: &[u8],
+        width: usize,
+        height: usize,
+        output: &mut [u8],
+        progress: P,
+        cancel: C,
+    ) -> Result<(), Error>
+    where
+        P: Fn(usize, usize) + Sync,
+        C: Fn() -> bool + Sync,
+    {
+        let rgba_pitch = width.saturating_mul(4);
+        let output_pitch = num_blocks(width).saturating_mul(self.format.block_size());
+        self.compress_pitched(
+            rgba,
+            PixelLayout::Rgba,
+            rgba_pitch,
+            width,
+            height,
+            output,
+            output_pitch,
+            progress,
+            cancel,
+        )
+    }
+
+    /// Compresses an image in memory from pixels of the given layout with the given
+    /// pitches, checking the pitches and the sizes of all buffers first
+    ///
+    /// See `Format::compress_pitched` for the pitches and `compress` for the callbacks.
+    ///
+    /// * `pixels`       - The uncompressed pixel data
+    /// * `layout`       - The channel order of `pixels`
+    /// * `pixel_pitch`  - The distance between rows of pixels in `pixels`
+    /// * `width`        - The width of the source image
+    /// * `height`       - The height of the source image
+    /// * `output`       - Output buffer for the compressed image
+    /// * `output_pitch` - The distance between rows of blocks in `output`
+    /// * `progress`     - Called with the number of finished rows and the total after each row
+    /// * `cancel`       - Called before each row, stops the compression by returning true
+    #[allow(clippy::too_many_arguments)]
+    pub fn compress_pitched<P, C>(
+        &self,
+        pixels: &[u8],
+        layout: PixelLayout,
+        pixel_pitch: usize,
+        width: usize,
+        height: usize,
+        output: &mut [u8],
+        output_pitch: usize,
+        progress: P,
+        cancel: C,
+    ) -> Result<(), Error>
+    where
+        P: Fn(usize, usize) + Sync,
+        C: Fn() -> bool + Sync,
+    {
+        self.format.check_compress_buffers(
+            pixels.len(),
+            layout,
+            width,
+            height,
+            pixel_pitch,
+            output.len(),
+            output_pitch,
+        )?;
+
+        let passes = if self.params.rdo_lambda > 0.0 { 2 } else { 1 };
+        let progress = Progress::new(&progress, &cancel, passes * num_blocks(height));
+        self.format.compress_pitched_with_progress(
+            pixels,
+            layout,
+            pixel_pitch,
+            width,
+            height,
+            self.params,
+            output,
+            output_pitch,
+            Some(&progress),
+        );
+
+        if progress.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Compresses an image into a buffer owned by the compressor and returns it
+    ///
+    /// The buffer is reused by the next call, growing only when an image needs more space.
+    /// See `compress` for the callbacks.
+    ///
+    /// * `rgba`     - The uncompressed pixel data
+    /// * `width`    - The width of the source image
+    /// * `height`   - The height of the source image
+    /// * `progress` - Called with the number of finished rows and the total after each row
+    /// * `cancel`   - Called before each row, stops the compression by returning true
+    #[cfg(feature = "alloc")]
+    pub fn compress_to_buffer<P, C>(
+        &mut self,
+        rgba: &[u8],
+        width: usize,
+        height: usize,
+        progress: P,
+        cancel: C,
+    ) -> Result<&[u8], Error>
+    where
+        P: Fn(usize, usize) + Sync,
+        C: Fn() -> bool + Sync,
+    {
+        let size = num_blocks(width)
+            .checked_mul(num_blocks(height))
+            .and_then(|blocks| blocks.checked_mul(self.format.block_size()))
+            .ok_or(Error::DimensionOverflow)?;
+
+        let mut buffer = core::mem::take(&mut self.buffer);
+        buffer.resize(size, 0);
+        let result = self.compress(rgba, width, height, &mut buffer, progress, cancel);
+        self.buffer = buffer;
+        result.map(|()| &self.buffer[..])
+    }
+}
+
+/// Reports finished rows of blocks and checks for cancellation while compressing
+pub struct Progress<'a> {
+    report: &'a (dyn Fn(usize, usize) + Sync),
+    cancel: &'a (dyn Fn() -> bool + Sync),
+    total: usize,
+    finished: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(
+        report: &'a (dyn Fn(usize, usize) + Sync),
+        cancel: &'a (dyn Fn() -> bool + Sync),
+        total: usize,
+    ) -> Self {
+        Self {
+            report,
+            cancel,
+            total,
+            finished: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns whether to compress the next row, remembering a cancellation for all rows
+    /// that follow
+    pub fn proceed(&self) -> bool {
+        if self.is_cancelled() {
+            return false;
+        }
+        if (self.cancel)() {
+            self.cancelled.store(true, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    pub fn row_finished(&self) {
+        let finished = self.finished.fetch_add(1, Ordering::Relaxed) + 1;
+        (self.report)(finished, self.total);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,504 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Reading and writing DDS container files
+//!
+//! Both the legacy header with a FourCC code and the DX10 extension with a DXGI format
+//! are read, which covers mipmaps, texture arrays, cubemaps and volume textures. Files are
+//! always written with the DX10 extension, since it can express all formats and layouts.
+//!
+//! Surfaces are stored layer by layer, each face of a cubemap holds its own mipmap chain,
+//! and the slices of each level of a volume texture follow each other.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+pub use crate::layout::TextureKind;
+use crate::layout::{check_length, Layout, TooShort};
+use crate::Format;
+
+const MAGIC: [u8; 4] = *b"DDS ";
+const HEADER_SIZE: usize = 124;
+const PIXEL_FORMAT_SIZE: usize = 32;
+const DX10_HEADER_SIZE: usize = 20;
+
+// header flags
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDSD_LINEARSIZE: u32 = 0x80000;
+const DDSD_DEPTH: u32 = 0x80_0000;
+
+// pixel format flags
+const DDPF_FOURCC: u32 = 0x4;
+
+// capabilities
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x40_0000;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_CUBEMAP_ALLFACES: u32 = 0xFC00;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+
+// DX10 extension
+const DIMENSION_TEXTURE2D: u32 = 3;
+const DIMENSION_TEXTURE3D: u32 = 4;
+const MISC_TEXTURECUBE: u32 = 0x4;
+
+/// Describes why a DDS file could not be read or written
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DdsError {
+    /// The file ends before the header or the surfaces it describes
+    InputTooShort {
+        /// Number of bytes the file needs
+        required: usize,
+        /// Number of bytes the file holds
+        actual: usize,
+    },
+
+    /// The file does not start with the DDS magic number
+    NotDds,
+
+    /// The legacy pixel format is not one of the block compressed formats
+    UnsupportedFourCc([u8; 4]),
+
+    /// The legacy pixel format is uncompressed
+    UnsupportedPixelFormat,
+
+    /// The DXGI format is not one of the block compressed formats
+    UnsupportedDxgiFormat(u32),
+
+    /// The resource dimension is neither a 2D nor a 3D texture
+    UnsupportedDimension(u32),
+
+    /// The cubemap does not contain all six faces
+    IncompleteCubemap,
+
+    /// The header describes an impossible texture
+    InvalidHeader(&'static str),
+}
+
+impl fmt::Display for DdsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            DdsError::InputTooShort { required, actual } => write!(
+                f,
+                "DDS file too short: {} bytes required, {} available",
+                required, actual
+            ),
+            DdsError::NotDds => write!(f, "not a DDS file"),
+            DdsError::UnsupportedFourCc(code) => {
+                write!(f, "unsupported FourCC code ")?;
+                for &byte in &code {
+                    write!(f, "{}", char::from(byte).escape_default())?;
+                }
+                Ok(())
+            }
+            DdsError::UnsupportedPixelFormat => write!(f, "unsupported uncompressed pixel format"),
+            DdsError::UnsupportedDxgiFormat(code) => write!(f, "unsupported DXGI format {}", code),
+            DdsError::UnsupportedDimension(code) => {
+                write!(f, "unsupported resource dimension {}", code)
+            }
+            DdsError::IncompleteCubemap => write!(f, "cubemap does not contain all faces"),
+            DdsError::InvalidHeader(reason) => write!(f, "invalid DDS header: {}", reason),
+        }
+    }
+}
+
+impl core::error::Error for DdsError {}
+
+/// Defines how the alpha channel of a texture is to be interpreted
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AlphaMode {
+    #[default]
+    Unknown,
+    Straight,
+    Premultiplied,
+    Opaque,
+    Custom,
+}
+
+/// A block compressed texture along with its layout
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dds {
+    /// The compression format
+    pub format: Format,
+
+    /// Whether the colour is sRGB encoded
+    pub srgb: bool,
+
+    /// How the alpha channel is to be interpreted
+    pub alpha_mode: AlphaMode,
+
+    /// The kind of texture
+    pub kind: TextureKind,
+
+    /// The width of the first mipmap level
+    pub width: usize,
+
+    /// The height of the first mipmap level
+    pub height: usize,
+
+    /// The depth of the first mipmap level of a volume texture, 1 otherwise
+    pub depth: usize,
+
+    /// The number of mipmap levels
+    pub mip_levels: usize,
+
+    /// The number of array layers, counting whole cubemaps for cubemap arrays
+    pub array_layers: usize,
+
+    /// The compressed surfaces
+    pub data: Vec<u8>,
+}
+
+/// Maps a DXGI format code to a format and whether it is sRGB encoded
+///
+/// Typeless formats map to their UNORM variants.
+pub fn format_from_dxgi(code: u32) -> Result<(Format, bool), DdsError> {
+    Ok(match code {
+        70 | 71 => (Format::Bc1, false),
+        72 => (Format::Bc1, true),
+        73 | 74 => (Format::Bc2, false),
+        75 => (Format::Bc2, true),
+        76 | 77 => (Format::Bc3, false),
+        78 => (Format::Bc3, true),
+        79 | 80 => (Format::Bc4, false),
+        81 => (Format::Bc4Snorm, false),
+        82 | 83 => (Format::Bc5, false),
+        84 => (Format::Bc5Snorm, false),
+        94 | 95 => (Format::Bc6hUf16, false),
+        96 => (Format::Bc6hSf16, false),
+        97 | 98 => (Format::Bc7, false),
+        99 => (Format::Bc7, true),
+        _ => return Err(DdsError::UnsupportedDxgiFormat(code)),
+    })
+}
+
+/// Returns the DXGI format code of a format
+///
+/// Only BC1, BC2, BC3 and BC7 have sRGB variants, other formats ignore `srgb`.
+pub fn format_to_dxgi(format: Format, srgb: bool) -> u32 {
+    match format {
+        Format::Bc1 => 71 + u32::from(srgb),
+        Format::Bc2 => 74 + u32::from(srgb),
+        Format::Bc3 => 77 + u32::from(srgb),
+        Format::Bc4 => 80,
+        Format::Bc4Snorm => 81,
+        Format::Bc5 => 83,
+        Format::Bc5Snorm => 84,
+        Format::Bc6hUf16 => 95,
+        Format::Bc6hSf16 => 96,
+        Format::Bc7 => 98 + u32::from(srgb),
+    }
+}
+
+/// Maps a legacy FourCC code to a format
+pub fn format_from_fourcc(code: [u8; 4]) -> Result<Format, DdsError> {
+    match &code {
+        b"DXT1" => Ok(Format::Bc1),
+        b"DXT2" | b"DXT3" => Ok(Format::Bc2),
+        b"DXT4" | b"DXT5" => Ok(Format::Bc3),
+        b"ATI1" | b"BC4U" => Ok(Format::Bc4),
+        b"BC4S" => Ok(Format::Bc4Snorm),
+        b"ATI2" | b"BC5U" => Ok(Format::Bc5),
+        b"BC5S" => Ok(Format::Bc5Snorm),
+        _ => Err(DdsError::UnsupportedFourCc(code)),
+    }
+}
+
+/// Returns the legacy FourCC code of a format, if it has one
+pub fn format_to_fourcc(format: Format) -> Option<[u8; 4]> {
+    match format {
+        Format::Bc1 => Some(*b"DXT1"),
+        Format::Bc2 => Some(*b"DXT3"),
+        Format::Bc3 => Some(*b"DXT5"),
+        Format::Bc4 => Some(*b"BC4U"),
+        Format::Bc4Snorm => Some(*b"BC4S"),
+        Format::Bc5 => Some(*b"BC5U"),
+        Format::Bc5Snorm => Some(*b"BC5S"),
+        Format::Bc6hUf16 | Format::Bc6hSf16 | Format::Bc7 => None,
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+impl From<TooShort> for DdsError {
+    fn from(TooShort { required, actual }: TooShort) -> DdsError {
+        DdsError::InputTooShort { required, actual }
+    }
+}
+
+impl Dds {
+    /// Creates a zeroed 2D texture with the given number of mipmap levels
+    ///
+    /// * `format`     - The compression format
+    /// * `width`      - The width of the first mipmap level
+    /// * `height`     - The height of the first mipmap level
+    /// * `mip_levels` - The number of mipmap levels
+    pub fn new(format: Format, width: usize, height: usize, mip_levels: usize) -> Dds {
+        let mut dds = Dds {
+            format,
+            srgb: false,
+            alpha_mode: AlphaMode::Unknown,
+            kind: TextureKind::Texture2D,
+            width,
+            height,
+            depth: 1,
+            mip_levels,
+            array_layers: 1,
+            data: Vec::new(),
+        };
+        dds.data = vec![0u8; dds.data_size().unwrap_or(0)];
+        dds
+    }
+
+    /// Returns the number of faces in each array layer, six for cubemaps and one otherwise
+    pub fn faces(&self) -> usize {
+        self.layout().faces()
+    }
+
+    /// Returns the width, height and depth of a mipmap level
+    pub fn level_size(&self, level: usize) -> (usize, usize, usize) {
+        self.layout().level_size(level)
+    }
+
+    /// Returns the number of bytes of one mipmap level of one face, including all slices
+    /// of volume textures
+    pub fn surface_size(&self, level: usize) -> usize {
+        self.layout().surface_size(level)
+    }
+
+    /// Returns the compressed data of one mipmap level of one face of an array layer, or
+    /// `None` if it is out of range
+    ///
+    /// * `layer` - The array layer
+    /// * `face`  - The cubemap face, zero for other textures
+    /// * `level` - The mipmap level
+    pub fn surface(&self, layer: usize, face: usize, level: usize) -> Option<&[u8]> {
+        let range = self.layout().surface_range(layer, face, level)?;
+        self.data.get(range)
+    }
+
+    /// Returns the compressed data of one mipmap level of one face of an array layer for
+    /// writing, or `None` if it is out of range
+    ///
+    /// * `layer` - The array layer
+    /// * `face`  - The cubemap face, zero for other textures
+    /// * `level` - The mipmap level
+    pub fn surface_mut(&mut self, layer: usize, face: usize, level: usize) -> Option<&mut [u8]> {
+        let range = self.layout().surface_range(layer, face, level)?;
+        self.data.get_mut(range)
+    }
+
+    fn layout(&self) -> Layout {
+        Layout {
+            format: self.format,
+            kind: self.kind,
+            width: self.width,
+            height: self.height,
+            depth: self.depth,
+            mip_levels: self.mip_levels,
+            array_layers: self.array_layers,
+        }
+    }
+
+    /// Returns the number of bytes of all surfaces, checking that the layout is valid
+    fn data_size(&self) -> Result<usize, DdsError> {
+        self.layout().data_size().map_err(DdsError::InvalidHeader)
+    }
+
+    /// Reads a DDS file
+    ///
+    /// Data beyond the surfaces described by the header is ignored.
+    ///
+    /// * `bytes` - The contents of the file
+    pub fn read(bytes: &[u8]) -> Result<Dds, DdsError> {
+        check_length(bytes, 4 + HEADER_SIZE)?;
+        if bytes[..4] != MAGIC {
+            return Err(DdsError::NotDds);
+        }
+        let header = &bytes[4..4 + HEADER_SIZE];
+        let field = |offset: usize| read_u32(header, offset);
+        if field(0) as usize != HEADER_SIZE {
+            return Err(DdsError::InvalidHeader("wrong header size"));
+        }
+
+        let flags = field(4);
+        let height = field(8) as usize;
+        let width = field(12) as usize;
+        let depth = field(20) as usize;
+        let mip_levels = field(24).max(1) as usize;
+        let pixel_flags = field(76);
+        let fourcc = [header[80], header[81], header[82], header[83]];
+        let caps2 = field(108);
+
+        if pixel_flags & DDPF_FOURCC == 0 {
+            return Err(DdsError::UnsupportedPixelFormat);
+        }
+
+        let mut dds = Dds {
+            format: Format::Bc1,
+            srgb: false,
+            alpha_mode: AlphaMode::Unknown,
+            kind: TextureKind::Texture2D,
+            width,
+            height,
+            depth: 1,
+            mip_levels,
+            array_layers: 1,
+            data: Vec::new(),
+        };
+
+        let mut offset = 4 + HEADER_SIZE;
+        if &fourcc == b"DX10" {
+            check_length(bytes, offset + DX10_HEADER_SIZE)?;
+            let extension = &bytes[offset..offset + DX10_HEADER_SIZE];
+            let field = |offset: usize| read_u32(extension, offset);
+            (dds.format, dds.srgb) = format_from_dxgi(field(0))?;
+            match field(4) {
+                DIMENSION_TEXTURE2D if field(8) & MISC_TEXTURECUBE != 0 => {
+                    dds.kind = TextureKind::Cubemap;
+                }
+                DIMENSION_TEXTURE2D => {}
+                DIMENSION_TEXTURE3D => {
+                    dds.kind = TextureKind::Texture3D;
+                    dds.depth = depth;
+                }
+                dimension => return Err(DdsError::UnsupportedDimension(dimension)),
+            }
+            dds.array_layers = field(12) as usize;
+            dds.alpha_mode = match field(16) & 0x7 {
+                1 => AlphaMode::Straight,
+                2 => AlphaMode::Premultiplied,
+                3 => AlphaMode::Opaque,
+                4 => AlphaMode::Custom,
+                _ => AlphaMode::Unknown,
+            };
+            offset += DX10_HEADER_SIZE;
+        } else {
+            dds.format = format_from_fourcc(fourcc)?;
+            if &fourcc == b"DXT2" || &fourcc == b"DXT4" {
+                dds.alpha_mode = AlphaMode::Premultiplied;
+            }
+            if caps2 & DDSCAPS2_CUBEMAP != 0 {
+                if caps2 & DDSCAPS2_CUBEMAP_ALLFACES != DDSCAPS2_CUBEMAP_ALLFACES {
+                    return Err(DdsError::IncompleteCubemap);
+                }
+                dds.kind = TextureKind::Cubemap;
+            } else if caps2 & DDSCAPS2_VOLUME != 0 || flags & DDSD_DEPTH != 0 {
+                dds.kind = TextureKind::Texture3D;
+                dds.depth = depth.max(1);
+            }
+        }
+
+        let size = dds.data_size()?;
+        let end = offset
+            .checked_add(size)
+            .ok_or(DdsError::InvalidHeader("texture size overflows"))?;
+        check_length(bytes, end)?;
+        dds.data = bytes[offset..end].to_vec();
+        Ok(dds)
+    }
+
+    /// Writes the texture as a DDS file with the DX10 header extension
+    pub fn write(&self) -> Result<Vec<u8>, DdsError> {
+        let size = self.data_size()?;
+        check_length(&self.data, size)?;
+        let too_large = DdsError::InvalidHeader("dimension does not fit in 32 bits");
+        let dimension = |value: usize| u32::try_from(value).map_err(|_| too_large);
+
+        let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_LINEARSIZE;
+        let mut caps = DDSCAPS_TEXTURE;
+        let mut caps2 = 0;
+        if self.mip_levels > 1 {
+            flags |= DDSD_MIPMAPCOUNT;
+            caps |= DDSCAPS_COMPLEX | DDSCAPS_MIPMAP;
+        }
+        match self.kind {
+            TextureKind::Texture2D => {}
+            TextureKind::Cubemap => {
+                caps |= DDSCAPS_COMPLEX;
+                caps2 |= DDSCAPS2_CUBEMAP | DDSCAPS2_CUBEMAP_ALLFACES;
+            }
+            TextureKind::Texture3D => {
+                flags |= DDSD_DEPTH;
+                caps |= DDSCAPS_COMPLEX;
+                caps2 |= DDSCAPS2_VOLUME;
+            }
+        }
+        if self.array_layers > 1 {
+            caps |= DDSCAPS_COMPLEX;
+        }
+
+        let mut header = [0u32; HEADER_SIZE / 4];
+        header[0] = HEADER_SIZE as u32;
+        header[1] = flags;
+        header[2] = dimension(self.height)?;
+        header[3] = dimension(self.width)?;
+        header[4] = dimension(self.format.compressed_size(self.width, self.height))?;
+        header[5] = if self.kind == TextureKind::Texture3D {
+            dimension(self.depth)?
+        } else {
+            0
+        };
+        header[6] = dimension(self.mip_levels)?;
+        header[18] = PIXEL_FORMAT_SIZE as u32;
+        header[19] = DDPF_FOURCC;
+        header[20] = u32::from_le_bytes(*b"DX10");
+        header[26] = caps;
+        header[27] = caps2;
+
+        let extension = [
+            format_to_dxgi(self.format, self.srgb),
+            if self.kind == TextureKind::Texture3D {
+                DIMENSION_TEXTURE3D
+            } else {
+                DIMENSION_TEXTURE2D
+            },
+            if self.kind == TextureKind::Cubemap {
+                MISC_TEXTURECUBE
+            } else {
+                0
+            },
+            dimension(self.array_layers)?,
+            self.alpha_mode as u32,
+        ];
+
+        let mut bytes = Vec::with_capacity(4 + HEADER_SIZE + DX10_HEADER_SIZE + size);
+        bytes.extend_from_slice(&MAGIC);
+        for value in header.iter().chain(&extension) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.data[..size]);
+        Ok(bytes)
+    }
+}
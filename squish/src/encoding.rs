@@ -0,0 +1,117 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Alternative encodings of the channels stored in BC3 blocks
+
+/// Defines how pixels are mapped to the channels of a BC3 block
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Bc3Encoding {
+    /// Stores RGBA as is
+    #[default]
+    Rgba,
+
+    /// Stores the X component of a normal map in alpha and Y in green (DXT5nm), while Z is
+    /// reconstructed when decoding
+    Dxt5nm,
+
+    /// Stores luma in alpha and scaled chroma in red and green (YCoCg-DXT5), trading alpha
+    /// for higher colour fidelity
+    ///
+    /// Blue holds the chroma scale of each block, which is 1, 2 or 4 depending on the
+    /// largest chroma value.
+    YCoCg,
+}
+
+impl Bc3Encoding {
+    /// Maps a block of RGBA pixels to the channels stored in the BC3 block
+    ///
+    /// * `rgba` - The uncompressed block of pixels
+    /// * `mask` - The valid pixel mask
+    pub(crate) fn encode_block(self, rgba: &[[u8; 4]; 16], mask: u32) -> [[u8; 4]; 16] {
+        match self {
+            Bc3Encoding::Rgba => *rgba,
+            Bc3Encoding::Dxt5nm => rgba.map(|[x, y, _, _]| [0, y, 0, x]),
+            Bc3Encoding::YCoCg => {
+                let ycocg = rgba.map(|pixel| {
+                    let [r, g, b, _] = pixel.map(i32::from);
+                    let y = (r + 2 * g + b + 2) >> 2;
+                    let co = (2 * r - 2 * b + 2) >> 2;
+                    let cg = (-r + 2 * g - b + 2) >> 2;
+                    [co, cg, y]
+                });
+
+                // scale up the chroma of blocks with little saturation to reduce its
+                // quantisation error
+                let largest = (0..16)
+                    .filter(|&i| mask & (1 << i) != 0)
+                    .map(|i| ycocg[i][0].abs().max(ycocg[i][1].abs()))
+                    .max()
+                    .unwrap_or(0);
+                let scale = match largest {
+                    0..=31 => 4,
+                    32..=63 => 2,
+                    _ => 1,
+                };
+
+                ycocg.map(|[co, cg, y]| {
+                    let chroma = |value: i32| (value * scale + 128).clamp(0, 255) as u8;
+                    [chroma(co), chroma(cg), ((scale - 1) << 3) as u8, y as u8]
+                })
+            }
+        }
+    }
+
+    /// Reconstructs the original RGBA of a decompressed image in place
+    ///
+    /// * `rgba` - The decompressed RGBA pixels
+    pub fn decode(self, rgba: &mut [u8]) {
+        for pixel in rgba.chunks_exact_mut(4) {
+            let decoded = self.decode_pixel([pixel[0], pixel[1], pixel[2], pixel[3]]);
+            pixel.copy_from_slice(&decoded);
+        }
+    }
+
+    /// Reconstructs the original RGBA of a decompressed pixel
+    ///
+    /// DXT5nm pixels come out as an opaque normal with X, Y and Z mapped to RGB. YCoCg
+    /// pixels come out opaque as well, since their alpha holds luma.
+    pub fn decode_pixel(self, pixel: [u8; 4]) -> [u8; 4] {
+        match self {
+            Bc3Encoding::Rgba => pixel,
+            Bc3Encoding::Dxt5nm => {
+                let x = f32::from(pixel[3]) / 127.5 - 1.0;
+                let y = f32::from(pixel[1]) / 127.5 - 1.0;
+                let z = libm::sqrtf((1.0 - x * x - y * y).max(0.0));
+                let z = libm::roundf(127.5 * z + 127.5) as u8;
+                [pixel[3], pixel[1], z, 0xFF]
+            }
+            Bc3Encoding::YCoCg => {
+                let [co, cg, scale, y] = pixel.map(i32::from);
+                // round the scale, since blue passes through the 5-bit colour endpoints
+                let scale = ((scale + 4) >> 3) + 1;
+                let chroma = |value: i32| libm::roundf((value - 128) as f32 / scale as f32) as i32;
+                let (co, cg) = (chroma(co), chroma(cg));
+                let [r, g, b] = [y + co - cg, y + cg, y - co - cg].map(|value| value.clamp(0, 255));
+                [r as u8, g as u8, b as u8, 0xFF]
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Errors reported by the fallible compression and decompression functions
+
+use core::fmt;
+
+/// Describes why an image or block could not be compressed or decompressed
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The output buffer cannot hold the result
+    BufferTooSmall {
+        /// Number of elements the output needs
+        required: usize,
+        /// Number of elements the output holds
+        actual: usize,
+    },
+
+    /// The input does not contain all pixels or blocks of the image
+    InputTooShort {
+        /// Number of elements the input needs
+        required: usize,
+        /// Number of elements the input holds
+        actual: usize,
+    },
+
+    /// The distance between rows is shorter than a row
+    PitchTooSmall {
+        /// Minimum distance between rows
+        required: usize,
+        /// Given distance between rows
+        actual: usize,
+    },
+
+    /// The image has a width or height of zero
+    ZeroDimension,
+
+    /// The size of the image does not fit in a `usize`
+    DimensionOverflow,
+
+    /// The compression was cancelled before it finished
+    Cancelled,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::BufferTooSmall { required, actual } => write!(
+                f,
+                "output buffer too small: {} elements required, {} available",
+                required, actual
+            ),
+            Error::InputTooShort { required, actual } => write!(
+                f,
+                "input too short: {} elements required, {} available",
+                required, actual
+            ),
+            Error::PitchTooSmall { required, actual } => write!(
+                f,
+                "row pitch too small: at least {} required, {} given",
+                required, actual
+            ),
+            Error::ZeroDimension => write!(f, "image width or height is zero"),
+            Error::DimensionOverflow => write!(f, "image dimensions are too large"),
+            Error::Cancelled => write!(f, "compression was cancelled"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
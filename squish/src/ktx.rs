@@ -0,0 +1,440 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Reading and writing KTX 1.1 container files
+//!
+//! All formats are supported along with mipmaps, texture arrays, cubemaps, volume textures
+//! and key/value metadata. Files of either byte order are read, and files are always
+//! written in little endian order.
+//!
+//! Surfaces are stored layer by layer, each face of a cubemap holds its own mipmap chain,
+//! and the slices of each level of a volume texture follow each other. They are reordered
+//! level by level, largest first, when written to a file.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+pub use crate::layout::TextureKind;
+use crate::layout::{self, check_length, Layout, TooShort};
+use crate::Format;
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'1', b'1', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+const HEADER_SIZE: usize = 64;
+const ENDIANNESS: u32 = 0x0403_0201;
+
+// OpenGL internal formats
+const GL_COMPRESSED_RGB_S3TC_DXT1_EXT: u32 = 0x83F0;
+const GL_COMPRESSED_RGBA_S3TC_DXT1_EXT: u32 = 0x83F1;
+const GL_COMPRESSED_RGBA_S3TC_DXT3_EXT: u32 = 0x83F2;
+const GL_COMPRESSED_RGBA_S3TC_DXT5_EXT: u32 = 0x83F3;
+const GL_COMPRESSED_SRGB_S3TC_DXT1_EXT: u32 = 0x8C4C;
+const GL_COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT: u32 = 0x8C4D;
+const GL_COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT: u32 = 0x8C4E;
+const GL_COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT: u32 = 0x8C4F;
+const GL_COMPRESSED_RED_RGTC1: u32 = 0x8DBB;
+const GL_COMPRESSED_SIGNED_RED_RGTC1: u32 = 0x8DBC;
+const GL_COMPRESSED_RG_RGTC2: u32 = 0x8DBD;
+const GL_COMPRESSED_SIGNED_RG_RGTC2: u32 = 0x8DBE;
+const GL_COMPRESSED_RGBA_BPTC_UNORM: u32 = 0x8E8C;
+const GL_COMPRESSED_SRGB_ALPHA_BPTC_UNORM: u32 = 0x8E8D;
+const GL_COMPRESSED_RGB_BPTC_SIGNED_FLOAT: u32 = 0x8E8E;
+const GL_COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT: u32 = 0x8E8F;
+
+// OpenGL base internal formats
+const GL_RED: u32 = 0x1903;
+const GL_RGB: u32 = 0x1907;
+const GL_RGBA: u32 = 0x1908;
+const GL_RG: u32 = 0x8227;
+
+/// Describes why a KTX file could not be read or written
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KtxError {
+    /// The file ends before the header or the data it describes
+    InputTooShort {
+        /// Number of bytes the file needs
+        required: usize,
+        /// Number of bytes the file holds
+        actual: usize,
+    },
+
+    /// The file does not start with the KTX 1.1 identifier
+    NotKtx,
+
+    /// The OpenGL internal format is not one of the block compressed formats
+    UnsupportedGlFormat(u32),
+
+    /// The header describes an impossible texture
+    InvalidHeader(&'static str),
+
+    /// The key/value data is malformed
+    InvalidKeyValue(&'static str),
+}
+
+impl fmt::Display for KtxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            KtxError::InputTooShort { required, actual } => write!(
+                f,
+                "KTX file too short: {} bytes required, {} available",
+                required, actual
+            ),
+            KtxError::NotKtx => write!(f, "not a KTX 1.1 file"),
+            KtxError::UnsupportedGlFormat(code) => {
+                write!(f, "unsupported OpenGL internal format {:#06x}", code)
+            }
+            KtxError::InvalidHeader(reason) => write!(f, "invalid KTX header: {}", reason),
+            KtxError::InvalidKeyValue(reason) => {
+                write!(f, "invalid KTX key/value data: {}", reason)
+            }
+        }
+    }
+}
+
+impl core::error::Error for KtxError {}
+
+/// A block compressed texture along with its layout and metadata
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ktx {
+    /// The compression format
+    pub format: Format,
+
+    /// Whether the colour is sRGB encoded
+    pub srgb: bool,
+
+    /// The kind of texture
+    pub kind: TextureKind,
+
+    /// The width of the first mipmap level
+    pub width: usize,
+
+    /// The height of the first mipmap level
+    pub height: usize,
+
+    /// The depth of the first mipmap level of a volume texture, 1 otherwise
+    pub depth: usize,
+
+    /// The number of mipmap levels
+    pub mip_levels: usize,
+
+    /// The number of array layers, counting whole cubemaps for cubemap arrays
+    pub array_layers: usize,
+
+    /// The key/value metadata, in the order it is stored in the file
+    pub key_values: Vec<(String, Vec<u8>)>,
+
+    /// The compressed surfaces
+    pub data: Vec<u8>,
+}
+
+/// Maps an OpenGL internal format to a format and whether it is sRGB encoded
+///
+/// The BC1 formats without alpha map to BC1 as well.
+pub fn format_from_gl(code: u32) -> Result<(Format, bool), KtxError> {
+    Ok(match code {
+        GL_COMPRESSED_RGB_S3TC_DXT1_EXT | GL_COMPRESSED_RGBA_S3TC_DXT1_EXT => (Format::Bc1, false),
+        GL_COMPRESSED_SRGB_S3TC_DXT1_EXT | GL_COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT => {
+            (Format::Bc1, true)
+        }
+        GL_COMPRESSED_RGBA_S3TC_DXT3_EXT => (Format::Bc2, false),
+        GL_COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT => (Format::Bc2, true),
+        GL_COMPRESSED_RGBA_S3TC_DXT5_EXT => (Format::Bc3, false),
+        GL_COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT => (Format::Bc3, true),
+        GL_COMPRESSED_RED_RGTC1 => (Format::Bc4, false),
+        GL_COMPRESSED_SIGNED_RED_RGTC1 => (Format::Bc4Snorm, false),
+        GL_COMPRESSED_RG_RGTC2 => (Format::Bc5, false),
+        GL_COMPRESSED_SIGNED_RG_RGTC2 => (Format::Bc5Snorm, false),
+        GL_COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT => (Format::Bc6hUf16, false),
+        GL_COMPRESSED_RGB_BPTC_SIGNED_FLOAT => (Format::Bc6hSf16, false),
+        GL_COMPRESSED_RGBA_BPTC_UNORM => (Format::Bc7, false),
+        GL_COMPRESSED_SRGB_ALPHA_BPTC_UNORM => (Format::Bc7, true),
+        _ => return Err(KtxError::UnsupportedGlFormat(code)),
+    })
+}
+
+/// Returns the OpenGL internal format and base internal format of a format
+///
+/// `srgb` picks one of the `GL_COMPRESSED_SRGB*` formats, which exist for BC1, BC2, BC3 and
+/// BC7 only. BC1 is always stored with alpha, since it may contain punch-through alpha.
+pub fn format_to_gl(format: Format, srgb: bool) -> (u32, u32) {
+    match (format, srgb) {
+        (Format::Bc1, false) => (GL_COMPRESSED_RGBA_S3TC_DXT1_EXT, GL_RGBA),
+        (Format::Bc1, true) => (GL_COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT, GL_RGBA),
+        (Format::Bc2, false) => (GL_COMPRESSED_RGBA_S3TC_DXT3_EXT, GL_RGBA),
+        (Format::Bc2, true) => (GL_COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT, GL_RGBA),
+        (Format::Bc3, false) => (GL_COMPRESSED_RGBA_S3TC_DXT5_EXT, GL_RGBA),
+        (Format::Bc3, true) => (GL_COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT, GL_RGBA),
+        (Format::Bc4, _) => (GL_COMPRESSED_RED_RGTC1, GL_RED),
+        (Format::Bc4Snorm, _) => (GL_COMPRESSED_SIGNED_RED_RGTC1, GL_RED),
+        (Format::Bc5, _) => (GL_COMPRESSED_RG_RGTC2, GL_RG),
+        (Format::Bc5Snorm, _) => (GL_COMPRESSED_SIGNED_RG_RGTC2, GL_RG),
+        (Format::Bc6hUf16, _) => (GL_COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT, GL_RGB),
+        (Format::Bc6hSf16, _) => (GL_COMPRESSED_RGB_BPTC_SIGNED_FLOAT, GL_RGB),
+        (Format::Bc7, false) => (GL_COMPRESSED_RGBA_BPTC_UNORM, GL_RGBA),
+        (Format::Bc7, true) => (GL_COMPRESSED_SRGB_ALPHA_BPTC_UNORM, GL_RGBA),
+    }
+}
+
+impl From<TooShort> for KtxError {
+    fn from(TooShort { required, actual }: TooShort) -> KtxError {
+        KtxError::InputTooShort { required, actual }
+    }
+}
+
+impl Ktx {
+    /// Creates a zeroed 2D texture with the given number of mipmap levels
+    ///
+    /// * `format`     - The compression format
+    /// * `width`      - The width of the first mipmap level
+    /// * `height`     - The height of the first mipmap level
+    /// * `mip_levels` - The number of mipmap levels
+    pub fn new(format: Format, width: usize, height: usize, mip_levels: usize) -> Ktx {
+        let mut ktx = Ktx {
+            format,
+            srgb: false,
+            kind: TextureKind::Texture2D,
+            width,
+            height,
+            depth: 1,
+            mip_levels,
+            array_layers: 1,
+            key_values: Vec::new(),
+            data: Vec::new(),
+        };
+        ktx.data = vec![0u8; ktx.layout().data_size().unwrap_or(0)];
+        ktx
+    }
+
+    /// Returns the number of faces in each array layer, six for cubemaps and one otherwise
+    pub fn faces(&self) -> usize {
+        self.layout().faces()
+    }
+
+    /// Returns the width, height and depth of a mipmap level
+    pub fn level_size(&self, level: usize) -> (usize, usize, usize) {
+        self.layout().level_size(level)
+    }
+
+    /// Returns the number of bytes of one mipmap level of one face, including all slices
+    /// of volume textures
+    pub fn surface_size(&self, level: usize) -> usize {
+        self.layout().surface_size(level)
+    }
+
+    /// Returns the compressed data of one mipmap level of one face of an array layer, or
+    /// `None` if it is out of range
+    ///
+    /// * `layer` - The array layer
+    /// * `face`  - The cubemap face, zero for other textures
+    /// * `level` - The mipmap level
+    pub fn surface(&self, layer: usize, face: usize, level: usize) -> Option<&[u8]> {
+        let range = self.layout().surface_range(layer, face, level)?;
+        self.data.get(range)
+    }
+
+    /// Returns the compressed data of one mipmap level of one face of an array layer for
+    /// writing, or `None` if it is out of range
+    ///
+    /// * `layer` - The array layer
+    /// * `face`  - The cubemap face, zero for other textures
+    /// * `level` - The mipmap level
+    pub fn surface_mut(&mut self, layer: usize, face: usize, level: usize) -> Option<&mut [u8]> {
+        let range = self.layout().surface_range(layer, face, level)?;
+        self.data.get_mut(range)
+    }
+
+    /// Returns the value of a key, or `None` if it is not present
+    pub fn key_value(&self, key: &str) -> Option<&[u8]> {
+        layout::key_value(&self.key_values, key)
+    }
+
+    fn layout(&self) -> Layout {
+        Layout {
+            format: self.format,
+            kind: self.kind,
+            width: self.width,
+            height: self.height,
+            depth: self.depth,
+            mip_levels: self.mip_levels,
+            array_layers: self.array_layers,
+        }
+    }
+
+    /// Returns the image size stored before a mipmap level, which only covers one face of
+    /// cubemaps that are not arrays
+    fn image_size(&self, level: usize, array: bool) -> usize {
+        if self.kind == TextureKind::Cubemap && !array {
+            self.surface_size(level)
+        } else {
+            self.surface_size(level) * self.faces() * self.array_layers
+        }
+    }
+
+    /// Reads a KTX 1.1 file
+    ///
+    /// Data beyond the mipmap levels described by the header is ignored.
+    ///
+    /// * `bytes` - The contents of the file
+    pub fn read(bytes: &[u8]) -> Result<Ktx, KtxError> {
+        check_length(bytes, HEADER_SIZE)?;
+        if bytes[..IDENTIFIER.len()] != IDENTIFIER {
+            return Err(KtxError::NotKtx);
+        }
+        let swap = match u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]) {
+            ENDIANNESS => false,
+            value if value.swap_bytes() == ENDIANNESS => true,
+            _ => return Err(KtxError::InvalidHeader("unknown byte order")),
+        };
+        let read_u32 = |offset: usize| {
+            let value = u32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ]);
+            if swap {
+                value.swap_bytes()
+            } else {
+                value
+            }
+        };
+
+        let internal_format = read_u32(28);
+        // uncompressed textures have a type and format
+        if read_u32(16) != 0 || read_u32(24) != 0 {
+            return Err(KtxError::UnsupportedGlFormat(internal_format));
+        }
+        let (format, srgb) = format_from_gl(internal_format)?;
+        let height = read_u32(40) as usize;
+        if height == 0 {
+            return Err(KtxError::InvalidHeader("1D textures are not supported"));
+        }
+        let depth = read_u32(44) as usize;
+        let array = read_u32(48) != 0;
+        let kind = match (read_u32(52), depth) {
+            (1, 0) => TextureKind::Texture2D,
+            (1, _) => TextureKind::Texture3D,
+            (6, 0) => TextureKind::Cubemap,
+            (6, _) => return Err(KtxError::InvalidHeader("cubemap with depth")),
+            _ => return Err(KtxError::InvalidHeader("face count is neither 1 nor 6")),
+        };
+
+        let mut ktx = Ktx {
+            format,
+            srgb,
+            kind,
+            width: read_u32(36) as usize,
+            height,
+            depth: depth.max(1),
+            mip_levels: (read_u32(56) as usize).max(1),
+            array_layers: (read_u32(48) as usize).max(1),
+            key_values: Vec::new(),
+            data: Vec::new(),
+        };
+        let size = ktx.layout().data_size().map_err(KtxError::InvalidHeader)?;
+
+        let kvd_end = HEADER_SIZE
+            .checked_add(read_u32(60) as usize)
+            .ok_or(KtxError::InvalidHeader("key/value data overflows"))?;
+        check_length(bytes, kvd_end)?;
+        ktx.key_values = layout::read_key_values(&bytes[HEADER_SIZE..kvd_end], swap)
+            .map_err(KtxError::InvalidKeyValue)?;
+
+        // block sizes are multiples of four, so surfaces never need padding
+        ktx.data = vec![0u8; size];
+        let mut offset = kvd_end;
+        for level in 0..ktx.mip_levels {
+            check_length(bytes, offset + 4)?;
+            if read_u32(offset) as usize != ktx.image_size(level, array) {
+                return Err(KtxError::InvalidHeader("mipmap level has the wrong size"));
+            }
+            offset += 4;
+            let surface_size = ktx.surface_size(level);
+            for layer in 0..ktx.array_layers {
+                for face in 0..ktx.faces() {
+                    check_length(bytes, offset + surface_size)?;
+                    ktx.surface_mut(layer, face, level)
+                        .unwrap()
+                        .copy_from_slice(&bytes[offset..offset + surface_size]);
+                    offset += surface_size;
+                }
+            }
+        }
+        Ok(ktx)
+    }
+
+    /// Writes the texture as a little endian KTX 1.1 file
+    pub fn write(&self) -> Result<Vec<u8>, KtxError> {
+        let size = self.layout().data_size().map_err(KtxError::InvalidHeader)?;
+        check_length(&self.data, size)?;
+        let too_large = KtxError::InvalidHeader("dimension does not fit in 32 bits");
+        let dimension = |value: usize| u32::try_from(value).map_err(|_| too_large);
+
+        let kvd = layout::write_key_values(&self.key_values).map_err(KtxError::InvalidKeyValue)?;
+
+        let array = self.array_layers > 1;
+        let (internal_format, base_internal_format) = format_to_gl(self.format, self.srgb);
+        let header = [
+            ENDIANNESS,
+            // glType, glTypeSize and glFormat of compressed textures
+            0,
+            1,
+            0,
+            internal_format,
+            base_internal_format,
+            dimension(self.width)?,
+            dimension(self.height)?,
+            if self.kind == TextureKind::Texture3D {
+                dimension(self.depth)?
+            } else {
+                0
+            },
+            if array {
+                dimension(self.array_layers)?
+            } else {
+                0
+            },
+            dimension(self.faces())?,
+            dimension(self.mip_levels)?,
+            dimension(kvd.len())?,
+        ];
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + kvd.len() + 4 * self.mip_levels + size);
+        bytes.extend_from_slice(&IDENTIFIER);
+        for value in &header {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&kvd);
+        for level in 0..self.mip_levels {
+            let image_size = dimension(self.image_size(level, array))?;
+            bytes.extend_from_slice(&image_size.to_le_bytes());
+            for layer in 0..self.array_layers {
+                for face in 0..self.faces() {
+                    bytes.extend_from_slice(self.surface(layer, face, level).unwrap());
+                }
+            }
+        }
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,624 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Reading and writing KTX2 container files
+//!
+//! All formats are supported along with mipmaps, texture arrays, cubemaps, volume textures
+//! and key/value metadata. A basic data format descriptor is generated when writing. With
+//! the `zstd` feature, each mipmap level can be supercompressed with Zstandard.
+//!
+//! Surfaces are stored layer by layer, each face of a cubemap holds its own mipmap chain,
+//! and the slices of each level of a volume texture follow each other. They are reordered
+//! level by level, smallest first, when written to a file.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+pub use crate::layout::TextureKind;
+use crate::layout::{self, check_length, Layout, TooShort};
+use crate::Format;
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n',
+];
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_SIZE: usize = 24;
+const DFD_BLOCK_HEADER_SIZE: usize = 24;
+const DFD_SAMPLE_SIZE: usize = 16;
+
+// supercompression schemes
+const SCHEME_NONE: u32 = 0;
+const SCHEME_ZSTD: u32 = 2;
+
+// data format descriptor
+const KHR_DF_VERSION: u32 = 2;
+const KHR_DF_MODEL_BC1A: u32 = 128;
+const KHR_DF_MODEL_BC2: u32 = 129;
+const KHR_DF_MODEL_BC3: u32 = 130;
+const KHR_DF_MODEL_BC4: u32 = 131;
+const KHR_DF_MODEL_BC5: u32 = 132;
+const KHR_DF_MODEL_BC6H: u32 = 133;
+const KHR_DF_MODEL_BC7: u32 = 134;
+const KHR_DF_PRIMARIES_BT709: u32 = 1;
+const KHR_DF_TRANSFER_LINEAR: u32 = 1;
+const KHR_DF_TRANSFER_SRGB: u32 = 2;
+const KHR_DF_FLAG_ALPHA_PREMULTIPLIED: u32 = 1;
+const KHR_DF_CHANNEL_COLOUR: u32 = 0;
+const KHR_DF_CHANNEL_BC1A_ALPHAPRESENT: u32 = 1;
+const KHR_DF_CHANNEL_GREEN: u32 = 1;
+const KHR_DF_CHANNEL_ALPHA: u32 = 15;
+const KHR_DF_SAMPLE_DATATYPE_SIGNED: u32 = 0x40;
+const KHR_DF_SAMPLE_DATATYPE_FLOAT: u32 = 0x80;
+
+/// Describes why a KTX2 file could not be read or written
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Ktx2Error {
+    /// The file ends before the header or the data it describes
+    InputTooShort {
+        /// Number of bytes the file needs
+        required: usize,
+        /// Number of bytes the file holds
+        actual: usize,
+    },
+
+    /// The file does not start with the KTX2 identifier
+    NotKtx2,
+
+    /// The Vulkan format is not one of the block compressed formats
+    UnsupportedVkFormat(u32),
+
+    /// The supercompression scheme is not supported, or the `zstd` feature is disabled
+    UnsupportedSupercompression(u32),
+
+    /// The supercompressed data of a mipmap level could not be decompressed
+    CorruptLevel(usize),
+
+    /// The header describes an impossible texture
+    InvalidHeader(&'static str),
+
+    /// The key/value data is malformed
+    InvalidKeyValue(&'static str),
+}
+
+impl fmt::Display for Ktx2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Ktx2Error::InputTooShort { required, actual } => write!(
+                f,
+                "KTX2 file too short: {} bytes required, {} available",
+                required, actual
+            ),
+            Ktx2Error::NotKtx2 => write!(f, "not a KTX2 file"),
+            Ktx2Error::UnsupportedVkFormat(code) => write!(f, "unsupported Vulkan format {}", code),
+            Ktx2Error::UnsupportedSupercompression(scheme) => {
+                write!(f, "unsupported supercompression scheme {}", scheme)
+            }
+            Ktx2Error::CorruptLevel(level) => {
+                write!(
+                    f,
+                    "supercompressed data of mipmap level {} is corrupt",
+                    level
+                )
+            }
+            Ktx2Error::InvalidHeader(reason) => write!(f, "invalid KTX2 header: {}", reason),
+            Ktx2Error::InvalidKeyValue(reason) => {
+                write!(f, "invalid KTX2 key/value data: {}", reason)
+            }
+        }
+    }
+}
+
+impl core::error::Error for Ktx2Error {}
+
+/// Defines how the mipmap levels are supercompressed
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Supercompression {
+    /// The levels are stored as they are
+    #[default]
+    None,
+
+    /// Each level is compressed with Zstandard, which requires the `zstd` feature
+    Zstd,
+}
+
+/// A block compressed texture along with its layout and metadata
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ktx2 {
+    /// The compression format
+    pub format: Format,
+
+    /// Whether the colour is sRGB encoded
+    pub srgb: bool,
+
+    /// Whether the colour is premultiplied by alpha
+    pub premultiplied: bool,
+
+    /// The kind of texture
+    pub kind: TextureKind,
+
+    /// The width of the first mipmap level
+    pub width: usize,
+
+    /// The height of the first mipmap level
+    pub height: usize,
+
+    /// The depth of the first mipmap level of a volume texture, 1 otherwise
+    pub depth: usize,
+
+    /// The number of mipmap levels
+    pub mip_levels: usize,
+
+    /// The number of array layers, counting whole cubemaps for cubemap arrays
+    pub array_layers: usize,
+
+    /// How the mipmap levels are supercompressed in the file
+    pub supercompression: Supercompression,
+
+    /// The key/value metadata, which is sorted by key when written
+    pub key_values: Vec<(String, Vec<u8>)>,
+
+    /// The compressed surfaces
+    pub data: Vec<u8>,
+}
+
+/// Maps a Vulkan format code to a format and whether it is sRGB encoded
+///
+/// The BC1 formats without alpha map to BC1 as well.
+pub fn format_from_vk(code: u32) -> Result<(Format, bool), Ktx2Error> {
+    Ok(match code {
+        131 | 133 => (Format::Bc1, false),
+        132 | 134 => (Format::Bc1, true),
+        135 => (Format::Bc2, false),
+        136 => (Format::Bc2, true),
+        137 => (Format::Bc3, false),
+        138 => (Format::Bc3, true),
+        139 => (Format::Bc4, false),
+        140 => (Format::Bc4Snorm, false),
+        141 => (Format::Bc5, false),
+        142 => (Format::Bc5Snorm, false),
+        143 => (Format::Bc6hUf16, false),
+        144 => (Format::Bc6hSf16, false),
+        145 => (Format::Bc7, false),
+        146 => (Format::Bc7, true),
+        _ => return Err(Ktx2Error::UnsupportedVkFormat(code)),
+    })
+}
+
+/// Returns the Vulkan format code of a format
+///
+/// `srgb` picks the `_SRGB_BLOCK` variant of BC1, BC2, BC3 and BC7 and is ignored for the
+/// other formats. BC1 always uses the RGBA variant to keep its punch-through alpha.
+pub fn format_to_vk(format: Format, srgb: bool) -> u32 {
+    match format {
+        Format::Bc1 => 133 + u32::from(srgb),
+        Format::Bc2 => 135 + u32::from(srgb),
+        Format::Bc3 => 137 + u32::from(srgb),
+        Format::Bc4 => 139,
+        Format::Bc4Snorm => 140,
+        Format::Bc5 => 141,
+        Format::Bc5Snorm => 142,
+        Format::Bc6hUf16 => 143,
+        Format::Bc6hSf16 => 144,
+        Format::Bc7 => 145 + u32::from(srgb),
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from(read_u32(bytes, offset)) | (u64::from(read_u32(bytes, offset + 4)) << 32)
+}
+
+impl From<TooShort> for Ktx2Error {
+    fn from(TooShort { required, actual }: TooShort) -> Ktx2Error {
+        Ktx2Error::InputTooShort { required, actual }
+    }
+}
+
+/// Returns the end of a region of the file, checking that the file holds it
+fn region(bytes: &[u8], offset: u64, length: u64) -> Result<(usize, usize), Ktx2Error> {
+    let overflow = Ktx2Error::InvalidHeader("region of the file overflows");
+    let offset = usize::try_from(offset).map_err(|_| overflow)?;
+    let length = usize::try_from(length).map_err(|_| overflow)?;
+    let end = offset.checked_add(length).ok_or(overflow)?;
+    check_length(bytes, end)?;
+    Ok((offset, end))
+}
+
+/// Builds a basic data format descriptor with one block of samples
+fn descriptor(format: Format, srgb: bool, premultiplied: bool) -> Vec<u8> {
+    let (model, channels): (u32, &[u32]) = match format {
+        Format::Bc1 => (KHR_DF_MODEL_BC1A, &[KHR_DF_CHANNEL_BC1A_ALPHAPRESENT]),
+        Format::Bc2 => (
+            KHR_DF_MODEL_BC2,
+            &[KHR_DF_CHANNEL_ALPHA, KHR_DF_CHANNEL_COLOUR],
+        ),
+        Format::Bc3 => (
+            KHR_DF_MODEL_BC3,
+            &[KHR_DF_CHANNEL_ALPHA, KHR_DF_CHANNEL_COLOUR],
+        ),
+        Format::Bc4 | Format::Bc4Snorm => (KHR_DF_MODEL_BC4, &[KHR_DF_CHANNEL_COLOUR]),
+        Format::Bc5 | Format::Bc5Snorm => (
+            KHR_DF_MODEL_BC5,
+            &[KHR_DF_CHANNEL_COLOUR, KHR_DF_CHANNEL_GREEN],
+        ),
+        Format::Bc6hUf16 | Format::Bc6hSf16 => (KHR_DF_MODEL_BC6H, &[KHR_DF_CHANNEL_COLOUR]),
+        Format::Bc7 => (KHR_DF_MODEL_BC7, &[KHR_DF_CHANNEL_COLOUR]),
+    };
+    let (datatype, lower, upper) = match format {
+        Format::Bc4Snorm | Format::Bc5Snorm => {
+            (KHR_DF_SAMPLE_DATATYPE_SIGNED, 0x8000_0000, 0x7FFF_FFFF)
+        }
+        Format::Bc6hUf16 => (KHR_DF_SAMPLE_DATATYPE_FLOAT, 0, 1f32.to_bits()),
+        Format::Bc6hSf16 => (
+            KHR_DF_SAMPLE_DATATYPE_FLOAT | KHR_DF_SAMPLE_DATATYPE_SIGNED,
+            (-1f32).to_bits(),
+            1f32.to_bits(),
+        ),
+        _ => (0, 0, 0xFFFF_FFFF),
+    };
+    let srgb = srgb && format_to_vk(format, true) != format_to_vk(format, false);
+    let transfer = if srgb {
+        KHR_DF_TRANSFER_SRGB
+    } else {
+        KHR_DF_TRANSFER_LINEAR
+    };
+    let flags = if premultiplied {
+        KHR_DF_FLAG_ALPHA_PREMULTIPLIED
+    } else {
+        0
+    };
+
+    let block_size = format.block_size() as u32;
+    let bits = 8 * block_size / channels.len() as u32;
+    let descriptor_size = (DFD_BLOCK_HEADER_SIZE + DFD_SAMPLE_SIZE * channels.len()) as u32;
+    let mut words = vec![
+        4 + descriptor_size,
+        0,
+        KHR_DF_VERSION | (descriptor_size << 16),
+        model | (KHR_DF_PRIMARIES_BT709 << 8) | (transfer << 16) | (flags << 24),
+        // 4x4 texel blocks, stored as one less than the size
+        3 | (3 << 8),
+        block_size,
+        0,
+    ];
+    for (i, &channel) in channels.iter().enumerate() {
+        let offset = i as u32 * bits;
+        words.extend_from_slice(&[
+            offset | ((bits - 1) << 16) | ((channel | datatype) << 24),
+            0,
+            lower,
+            upper,
+        ]);
+    }
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_compress(data: &[u8]) -> Result<Vec<u8>, Ktx2Error> {
+    use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+    Ok(compress_to_vec(data, CompressionLevel::Fastest))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_compress(_data: &[u8]) -> Result<Vec<u8>, Ktx2Error> {
+    Err(Ktx2Error::UnsupportedSupercompression(SCHEME_ZSTD))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_decompress(data: &[u8], size: usize, level: usize) -> Result<Vec<u8>, Ktx2Error> {
+    let mut output = vec![0u8; size];
+    let mut decoder = ruzstd::decoding::FrameDecoder::new();
+    match decoder.decode_all(data, &mut output) {
+        Ok(written) if written == size => Ok(output),
+        _ => Err(Ktx2Error::CorruptLevel(level)),
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decompress(_data: &[u8], _size: usize, _level: usize) -> Result<Vec<u8>, Ktx2Error> {
+    Err(Ktx2Error::UnsupportedSupercompression(SCHEME_ZSTD))
+}
+
+impl Ktx2 {
+    /// Creates a zeroed 2D texture with the given number of mipmap levels
+    ///
+    /// * `format`     - The compression format
+    /// * `width`      - The width of the first mipmap level
+    /// * `height`     - The height of the first mipmap level
+    /// * `mip_levels` - The number of mipmap levels
+    pub fn new(format: Format, width: usize, height: usize, mip_levels: usize) -> Ktx2 {
+        let mut ktx2 = Ktx2 {
+            format,
+            srgb: false,
+            premultiplied: false,
+            kind: TextureKind::Texture2D,
+            width,
+            height,
+            depth: 1,
+            mip_levels,
+            array_layers: 1,
+            supercompression: Supercompression::None,
+            key_values: Vec::new(),
+            data: Vec::new(),
+        };
+        ktx2.data = vec![0u8; ktx2.layout().data_size().unwrap_or(0)];
+        ktx2
+    }
+
+    /// Returns the number of faces in each array layer, six for cubemaps and one otherwise
+    pub fn faces(&self) -> usize {
+        self.layout().faces()
+    }
+
+    /// Returns the width, height and depth of a mipmap level
+    pub fn level_size(&self, level: usize) -> (usize, usize, usize) {
+        self.layout().level_size(level)
+    }
+
+    /// Returns the number of bytes of one mipmap level of one face, including all slices
+    /// of volume textures
+    pub fn surface_size(&self, level: usize) -> usize {
+        self.layout().surface_size(level)
+    }
+
+    /// Returns the compressed data of one mipmap level of one face of an array layer, or
+    /// `None` if it is out of range
+    ///
+    /// * `layer` - The array layer
+    /// * `face`  - The cubemap face, zero for other textures
+    /// * `level` - The mipmap level
+    pub fn surface(&self, layer: usize, face: usize, level: usize) -> Option<&[u8]> {
+        let range = self.layout().surface_range(layer, face, level)?;
+        self.data.get(range)
+    }
+
+    /// Returns the compressed data of one mipmap level of one face of an array layer for
+    /// writing, or `None` if it is out of range
+    ///
+    /// * `layer` - The array layer
+    /// * `face`  - The cubemap face, zero for other textures
+    /// * `level` - The mipmap level
+    pub fn surface_mut(&mut self, layer: usize, face: usize, level: usize) -> Option<&mut [u8]> {
+        let range = self.layout().surface_range(layer, face, level)?;
+        self.data.get_mut(range)
+    }
+
+    /// Returns the value of a key, or `None` if it is not present
+    pub fn key_value(&self, key: &str) -> Option<&[u8]> {
+        layout::key_value(&self.key_values, key)
+    }
+
+    fn layout(&self) -> Layout {
+        Layout {
+            format: self.format,
+            kind: self.kind,
+            width: self.width,
+            height: self.height,
+            depth: self.depth,
+            mip_levels: self.mip_levels,
+            array_layers: self.array_layers,
+        }
+    }
+
+    /// Returns the number of bytes of all surfaces of one mipmap level
+    fn level_bytes(&self, level: usize) -> usize {
+        self.surface_size(level) * self.faces() * self.array_layers
+    }
+
+    /// Reads a KTX2 file
+    ///
+    /// Data beyond the regions described by the header is ignored.
+    ///
+    /// * `bytes` - The contents of the file
+    pub fn read(bytes: &[u8]) -> Result<Ktx2, Ktx2Error> {
+        check_length(bytes, HEADER_SIZE)?;
+        if bytes[..IDENTIFIER.len()] != IDENTIFIER {
+            return Err(Ktx2Error::NotKtx2);
+        }
+        let field = |offset: usize| read_u32(bytes, offset);
+        let (format, srgb) = format_from_vk(field(12))?;
+        let depth = field(28) as usize;
+        let faces = field(36);
+        let kind = match (faces, depth) {
+            (1, 0) => TextureKind::Texture2D,
+            (1, _) => TextureKind::Texture3D,
+            (6, 0) => TextureKind::Cubemap,
+            (6, _) => return Err(Ktx2Error::InvalidHeader("cubemap with depth")),
+            _ => return Err(Ktx2Error::InvalidHeader("face count is neither 1 nor 6")),
+        };
+        let height = field(24) as usize;
+        if height == 0 {
+            return Err(Ktx2Error::InvalidHeader("1D textures are not supported"));
+        }
+        let supercompression = match field(44) {
+            SCHEME_NONE => Supercompression::None,
+            SCHEME_ZSTD => Supercompression::Zstd,
+            scheme => return Err(Ktx2Error::UnsupportedSupercompression(scheme)),
+        };
+
+        let mut ktx2 = Ktx2 {
+            format,
+            srgb,
+            premultiplied: false,
+            kind,
+            width: field(20) as usize,
+            height,
+            depth: depth.max(1),
+            mip_levels: (field(40) as usize).max(1),
+            array_layers: (field(32) as usize).max(1),
+            supercompression,
+            key_values: Vec::new(),
+            data: Vec::new(),
+        };
+        let layout = ktx2.layout();
+        let size = layout.data_size().map_err(Ktx2Error::InvalidHeader)?;
+        check_length(bytes, HEADER_SIZE + LEVEL_INDEX_SIZE * ktx2.mip_levels)?;
+
+        let (dfd_start, dfd_end) = region(bytes, field(48).into(), field(52).into())?;
+        if dfd_end - dfd_start >= 4 + DFD_BLOCK_HEADER_SIZE {
+            let flags = read_u32(bytes, dfd_start + 12) >> 24;
+            ktx2.premultiplied = flags & KHR_DF_FLAG_ALPHA_PREMULTIPLIED != 0;
+        }
+
+        let (kvd_start, kvd_end) = region(bytes, field(56).into(), field(60).into())?;
+        ktx2.key_values = layout::read_key_values(&bytes[kvd_start..kvd_end], false)
+            .map_err(Ktx2Error::InvalidKeyValue)?;
+
+        ktx2.data = vec![0u8; size];
+        for level in 0..ktx2.mip_levels {
+            let entry = HEADER_SIZE + LEVEL_INDEX_SIZE * level;
+            let (start, end) = region(bytes, read_u64(bytes, entry), read_u64(bytes, entry + 8))?;
+            let level_size = ktx2.level_bytes(level);
+            let decompressed;
+            let level_data = match supercompression {
+                Supercompression::None => &bytes[start..end],
+                Supercompression::Zstd => {
+                    if read_u64(bytes, entry + 16) != level_size as u64 {
+                        return Err(Ktx2Error::InvalidHeader("mipmap level has the wrong size"));
+                    }
+                    decompressed = zstd_decompress(&bytes[start..end], level_size, level)?;
+                    &decompressed
+                }
+            };
+            if level_data.len() != level_size {
+                return Err(Ktx2Error::InvalidHeader("mipmap level has the wrong size"));
+            }
+            let surfaces = level_data.chunks_exact(ktx2.surface_size(level));
+            for (i, surface) in surfaces.enumerate() {
+                let (layer, face) = (i / ktx2.faces(), i % ktx2.faces());
+                ktx2.surface_mut(layer, face, level)
+                    .unwrap()
+                    .copy_from_slice(surface);
+            }
+        }
+        Ok(ktx2)
+    }
+
+    /// Writes the texture as a KTX2 file
+    pub fn write(&self) -> Result<Vec<u8>, Ktx2Error> {
+        let size = self
+            .layout()
+            .data_size()
+            .map_err(Ktx2Error::InvalidHeader)?;
+        check_length(&self.data, size)?;
+        let too_large = Ktx2Error::InvalidHeader("dimension does not fit in 32 bits");
+        let dimension = |value: usize| u32::try_from(value).map_err(|_| too_large);
+
+        let mut key_values: Vec<&(String, Vec<u8>)> = self.key_values.iter().collect();
+        key_values.sort_by(|a, b| a.0.cmp(&b.0));
+        if key_values.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Err(Ktx2Error::InvalidKeyValue("duplicate key"));
+        }
+        let kvd = layout::write_key_values(key_values).map_err(Ktx2Error::InvalidKeyValue)?;
+
+        let mut levels = Vec::with_capacity(self.mip_levels);
+        for level in 0..self.mip_levels {
+            let mut level_data = Vec::with_capacity(self.level_bytes(level));
+            for layer in 0..self.array_layers {
+                for face in 0..self.faces() {
+                    level_data.extend_from_slice(self.surface(layer, face, level).unwrap());
+                }
+            }
+            levels.push(match self.supercompression {
+                Supercompression::None => level_data,
+                Supercompression::Zstd => zstd_compress(&level_data)?,
+            });
+        }
+
+        let dfd = descriptor(self.format, self.srgb, self.premultiplied);
+        let dfd_offset = HEADER_SIZE + LEVEL_INDEX_SIZE * self.mip_levels;
+        let kvd_offset = dfd_offset + dfd.len();
+
+        // levels are stored smallest first, uncompressed ones aligned to whole blocks
+        let alignment = match self.supercompression {
+            Supercompression::None => self.format.block_size(),
+            Supercompression::Zstd => 1,
+        };
+        let mut offsets = vec![0usize; self.mip_levels];
+        let mut end = kvd_offset + kvd.len();
+        for level in (0..self.mip_levels).rev() {
+            offsets[level] = end.next_multiple_of(alignment);
+            end = offsets[level] + levels[level].len();
+        }
+
+        let header = [
+            format_to_vk(self.format, self.srgb),
+            1,
+            dimension(self.width)?,
+            dimension(self.height)?,
+            if self.kind == TextureKind::Texture3D {
+                dimension(self.depth)?
+            } else {
+                0
+            },
+            if self.array_layers > 1 {
+                dimension(self.array_layers)?
+            } else {
+                0
+            },
+            dimension(self.faces())?,
+            dimension(self.mip_levels)?,
+            match self.supercompression {
+                Supercompression::None => SCHEME_NONE,
+                Supercompression::Zstd => SCHEME_ZSTD,
+            },
+            dimension(dfd_offset)?,
+            dimension(dfd.len())?,
+            if kvd.is_empty() {
+                0
+            } else {
+                dimension(kvd_offset)?
+            },
+            dimension(kvd.len())?,
+        ];
+
+        let mut bytes = Vec::with_capacity(end);
+        bytes.extend_from_slice(&IDENTIFIER);
+        for value in &header {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        // no supercompression global data
+        bytes.extend_from_slice(&[0u8; 16]);
+        for (level, level_data) in levels.iter().enumerate() {
+            let uncompressed = self.level_bytes(level);
+            for value in [offsets[level], level_data.len(), uncompressed] {
+                bytes.extend_from_slice(&(value as u64).to_le_bytes());
+            }
+        }
+        bytes.extend_from_slice(&dfd);
+        bytes.extend_from_slice(&kvd);
+        for level in (0..self.mip_levels).rev() {
+            bytes.resize(offsets[level], 0);
+            bytes.extend_from_slice(&levels[level]);
+        }
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,212 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! The surface layout and file parts shared by the texture containers
+//!
+//! Surfaces are stored layer by layer, each face of a cubemap holds its own mipmap chain,
+//! and the slices of each level of a volume texture follow each other.
+
+#[cfg(any(feature = "ktx", feature = "ktx2"))]
+use alloc::string::String;
+#[cfg(any(feature = "ktx", feature = "ktx2"))]
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::Format;
+
+/// Defines the kind of texture stored in a container
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TextureKind {
+    /// A 2D texture or texture array
+    #[default]
+    Texture2D,
+
+    /// A cubemap or cubemap array with six faces per layer
+    Cubemap,
+
+    /// A volume texture
+    Texture3D,
+}
+
+/// The dimensions of a texture, which determine where each surface lives in its data
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Layout {
+    pub format: Format,
+    pub kind: TextureKind,
+    pub width: usize,
+    pub height: usize,
+    pub depth: usize,
+    pub mip_levels: usize,
+    pub array_layers: usize,
+}
+
+fn shrink(size: usize, level: usize) -> usize {
+    size.checked_shr(level as u32).unwrap_or(0).max(1)
+}
+
+impl Layout {
+    pub fn faces(&self) -> usize {
+        if self.kind == TextureKind::Cubemap {
+            6
+        } else {
+            1
+        }
+    }
+
+    pub fn level_size(&self, level: usize) -> (usize, usize, usize) {
+        (
+            shrink(self.width, level),
+            shrink(self.height, level),
+            shrink(self.depth, level),
+        )
+    }
+
+    pub fn surface_size(&self, level: usize) -> usize {
+        let (width, height, depth) = self.level_size(level);
+        self.format.compressed_size(width, height) * depth
+    }
+
+    pub fn surface_range(&self, layer: usize, face: usize, level: usize) -> Option<Range<usize>> {
+        if layer >= self.array_layers || face >= self.faces() || level >= self.mip_levels {
+            return None;
+        }
+        let chain: usize = (0..self.mip_levels).map(|l| self.surface_size(l)).sum();
+        let offset = (layer * self.faces() + face) * chain
+            + (0..level).map(|l| self.surface_size(l)).sum::<usize>();
+        Some(offset..offset + self.surface_size(level))
+    }
+
+    /// Returns the number of bytes of all surfaces, or the reason why the layout is invalid
+    pub fn data_size(&self) -> Result<usize, &'static str> {
+        if self.width == 0 || self.height == 0 || self.depth == 0 {
+            return Err("zero width, height or depth");
+        }
+        if self.kind != TextureKind::Texture3D && self.depth != 1 {
+            return Err("depth of a texture that is not 3D");
+        }
+        if self.kind == TextureKind::Texture3D && self.array_layers != 1 {
+            return Err("array of volume textures");
+        }
+        if self.kind == TextureKind::Cubemap && self.width != self.height {
+            return Err("cubemap faces are not square");
+        }
+        if self.array_layers == 0 {
+            return Err("zero array layers");
+        }
+        let largest = self.width.max(self.height).max(self.depth);
+        let max_levels = (usize::BITS - largest.leading_zeros()) as usize;
+        if self.mip_levels == 0 || self.mip_levels > max_levels {
+            return Err("invalid number of mipmap levels");
+        }
+
+        let overflow = "texture size overflows";
+        let mut chain = 0usize;
+        for level in 0..self.mip_levels {
+            let (width, height, depth) = self.level_size(level);
+            let blocks = width.div_ceil(4).checked_mul(height.div_ceil(4));
+            let size = blocks
+                .and_then(|blocks| blocks.checked_mul(self.format.block_size()))
+                .and_then(|size| size.checked_mul(depth))
+                .ok_or(overflow)?;
+            chain = chain.checked_add(size).ok_or(overflow)?;
+        }
+        chain
+            .checked_mul(self.faces())
+            .and_then(|size| size.checked_mul(self.array_layers))
+            .ok_or(overflow)
+    }
+}
+
+/// A file or buffer that is shorter than its header requires, which each container turns
+/// into its own error
+pub(crate) struct TooShort {
+    pub required: usize,
+    pub actual: usize,
+}
+
+pub(crate) fn check_length(bytes: &[u8], required: usize) -> Result<(), TooShort> {
+    if bytes.len() < required {
+        return Err(TooShort {
+            required,
+            actual: bytes.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Parses the key/value data of a KTX or KTX2 file, in which each entry holds its length,
+/// a NUL-terminated key and a value, padded to four bytes
+///
+/// * `data` - The key/value data
+/// * `swap` - Whether the lengths are stored in the opposite byte order
+#[cfg(any(feature = "ktx", feature = "ktx2"))]
+pub(crate) fn read_key_values(
+    data: &[u8],
+    swap: bool,
+) -> Result<Vec<(String, Vec<u8>)>, &'static str> {
+    let mut key_values = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let invalid = "entry exceeds the key/value data";
+        let length = data.get(offset..offset + 4).ok_or(invalid)?;
+        let length = u32::from_le_bytes([length[0], length[1], length[2], length[3]]);
+        let length = if swap { length.swap_bytes() } else { length } as usize;
+        let entry = data[offset + 4..].get(..length).ok_or(invalid)?;
+        let nul = entry
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or("key is not terminated")?;
+        let key = core::str::from_utf8(&entry[..nul]).map_err(|_| "key is not UTF-8")?;
+        key_values.push((String::from(key), entry[nul + 1..].to_vec()));
+        offset += 4 + length.next_multiple_of(4);
+    }
+    Ok(key_values)
+}
+
+/// Serialises key/value data in little endian order, in the order of the entries
+#[cfg(any(feature = "ktx", feature = "ktx2"))]
+pub(crate) fn write_key_values<'a>(
+    key_values: impl IntoIterator<Item = &'a (String, Vec<u8>)>,
+) -> Result<Vec<u8>, &'static str> {
+    let mut data = Vec::new();
+    for (key, value) in key_values {
+        if key.is_empty() || key.contains('\0') {
+            return Err("key is empty or contains NUL");
+        }
+        let length = u32::try_from(key.len() + 1 + value.len())
+            .map_err(|_| "entry does not fit in 32 bits")?;
+        data.extend_from_slice(&length.to_le_bytes());
+        data.extend_from_slice(key.as_bytes());
+        data.push(0);
+        data.extend_from_slice(value);
+        data.resize(data.len().next_multiple_of(4), 0);
+    }
+    Ok(data)
+}
+
+/// Returns the value of a key, or `None` if it is not present
+#[cfg(any(feature = "ktx", feature = "ktx2"))]
+pub(crate) fn key_value<'a>(key_values: &'a [(String, Vec<u8>)], key: &str) -> Option<&'a [u8]> {
+    key_values
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, value)| value.as_slice())
+}
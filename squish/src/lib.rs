@@ -20,19 +20,44 @@
 // TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
 // SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-//! A pure Rust BC1/2/3 compressor and decompressor based on Simon Brown's
+//! A pure Rust BC1/2/3/4/5/6H/7 compressor and decompressor based on Simon Brown's
 //! **libsquish**
 
 #![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 mod alpha;
+mod bc6h;
+mod bc7;
+mod bptc;
 mod colourblock;
 mod colourfit;
 mod colourset;
+mod compressor;
+#[cfg(feature = "dds")]
+pub mod dds;
+mod encoding;
+mod error;
+#[cfg(feature = "ktx")]
+pub mod ktx;
+#[cfg(feature = "ktx2")]
+pub mod ktx2;
+#[cfg(any(feature = "dds", feature = "ktx", feature = "ktx2"))]
+mod layout;
 mod math;
+pub mod metrics;
+#[cfg(feature = "alloc")]
+pub mod mipmap;
+mod normal;
+mod rdo;
 
 use crate::colourfit::{ClusterFit, ColourFit, RangeFit, SingleColourFit};
 use crate::colourset::ColourSet;
+pub use crate::compressor::Compressor;
+pub use crate::encoding::Bc3Encoding;
+pub use crate::error::Error;
 #[cfg(feature = "rayon")]
 use rayon::prelude::*;
 
@@ -43,28 +68,34 @@ pub enum Format {
     Bc2,
     Bc3,
     Bc4,
+    Bc4Snorm,
     Bc5,
+    Bc5Snorm,
+    Bc6hUf16,
+    Bc6hSf16,
+    Bc7,
 }
 
 /// Defines a compression algorithm
-#[derive(Clone, Copy, PartialEq, Eq)]
+///
+/// For BC7 the algorithm selects an effort level instead: `RangeFit` encodes only the
+/// most promising partition of each mode, `ClusterFit` additionally refines endpoints
+/// and tries all channel rotations, and `IterativeClusterFit` searches more partitions
+/// and the neighbourhood of each quantised endpoint. BC6H uses the same partition and
+/// refinement levels, with `IterativeClusterFit` trying every partition.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum Algorithm {
     /// Fast, low quality
     RangeFit,
 
     /// Slow, high quality
+    #[default]
     ClusterFit,
 
     /// Very slow, very high quality
     IterativeClusterFit,
 }
 
-impl Default for Algorithm {
-    fn default() -> Self {
-        Algorithm::ClusterFit
-    }
-}
-
 /// RGB colour channel weights for use in block fitting
 pub type ColourWeights = [f32; 3];
 
@@ -74,7 +105,7 @@ pub const COLOUR_WEIGHTS_UNIFORM: ColourWeights = [1.0, 1.0, 1.0];
 /// Weights based on the perceived brightness of each colour channel
 pub const COLOUR_WEIGHTS_PERCEPTUAL: ColourWeights = [0.2126, 0.7152, 0.0722];
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct Params {
     /// The compression algorithm to be used
     pub algorithm: Algorithm,
@@ -88,6 +119,38 @@ pub struct Params {
     /// This can significantly increase perceived quality for images that are rendered
     /// using alpha blending.
     pub weigh_colour_by_alpha: bool,
+
+    /// Treat colours as sRGB encoded and process them in linear light (defaults to false)
+    ///
+    /// Fitting BC1, BC2 and BC3 colour weighs each pixel by how strongly its error shows in
+    /// linear light, while the endpoints stay sRGB encoded. `RangeFit` only uses these
+    /// weights to pick the colour axis. Other formats ignore this option.
+    pub srgb: bool,
+
+    /// Treat pixels as unit normals with X, Y and Z mapped to RGB (defaults to false)
+    ///
+    /// Colour is fitted with uniform weights, ignoring `weights`, `weigh_colour_by_alpha`
+    /// and `srgb`. BC5 stores X and Y, and BC3 stores X in alpha and Y in green (DXT5nm).
+    /// Both choose their endpoints to minimise the angular error of the normals with Z
+    /// reconstructed from X and Y.
+    pub normal_map: bool,
+
+    /// How BC3 stores pixels (defaults to RGBA)
+    ///
+    /// Encodings other than RGBA fit colour with uniform weights, ignoring `weights`,
+    /// `weigh_colour_by_alpha` and `srgb`. `normal_map` implies DXT5nm. Use
+    /// `Bc3Encoding::decode` to reconstruct the original pixels after decompression.
+    /// Other formats ignore this option.
+    pub bc3_encoding: Bc3Encoding,
+
+    /// Rate-distortion optimisation strength for BC1 to BC5 images (defaults to 0, off)
+    ///
+    /// After compressing an image, blocks repeat the endpoints, indices or whole colour and
+    /// alpha blocks of nearby earlier blocks wherever each bit saved costs at most this much
+    /// summed squared error, so that e.g. zstd or deflate compress the output much better.
+    /// The colour error is weighted by `weights`. Values around 10 to 100 are typical.
+    /// Only image compression applies this, single blocks and other formats ignore it.
+    pub rdo_lambda: f32,
 }
 
 impl Default for Params {
@@ -96,25 +159,84 @@ impl Default for Params {
             algorithm: Algorithm::default(),
             weights: COLOUR_WEIGHTS_PERCEPTUAL,
             weigh_colour_by_alpha: false,
+            srgb: false,
+            normal_map: false,
+            bc3_encoding: Bc3Encoding::Rgba,
+            rdo_lambda: 0.0,
+        }
+    }
+}
+
+/// Defines the order and number of channels of uncompressed pixels
+///
+/// Missing colour channels read as zero and missing alpha as opaque. Decompression only
+/// writes the channels present in the layout.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PixelLayout {
+    #[default]
+    Rgba,
+    Bgra,
+    Argb,
+    Rgb,
+    Rg,
+    R,
+}
+
+impl PixelLayout {
+    /// Returns how many channels each pixel has
+    pub fn channels(self) -> usize {
+        self.order().len()
+    }
+
+    /// Returns the RGBA channel each channel of a pixel stores
+    fn order(self) -> &'static [usize] {
+        match self {
+            PixelLayout::Rgba => &[0, 1, 2, 3],
+            PixelLayout::Bgra => &[2, 1, 0, 3],
+            PixelLayout::Argb => &[3, 0, 1, 2],
+            PixelLayout::Rgb => &[0, 1, 2],
+            PixelLayout::Rg => &[0, 1],
+            PixelLayout::R => &[0],
         }
     }
 }
 
 /// Returns number of blocks needed for an image of given dimension
 pub fn num_blocks(size: usize) -> usize {
-    (size + 3) / 4
+    size.div_ceil(4)
 }
 
-/// BCn formats are laid out in 8-byte blocks of the following types:
+/// Returns the size of `rows` rows that start `pitch` apart, the last of which has `row_size`
+fn pitched_size(pitch: usize, rows: usize, row_size: usize) -> usize {
+    match rows {
+        0 => 0,
+        _ => pitch * (rows - 1) + row_size,
+    }
+}
+
+/// BCn formats are laid out in 8- or 16-byte blocks of the following types:
 /// * BC1: colour with optional 1-bit alpha
 /// * BC2: paletted alpha, colour
 /// * BC3: gradient alpha, colour
 /// * BC4: gradient alpha
 /// * BC5: gradient alpha, gradient alpha
+/// * BC6H: one of fourteen modes with up to two partitioned RGB half float gradients
+/// * BC7: one of eight modes with up to three partitioned colour or colour+alpha gradients
 ///
 /// BC4 and BC5 reuse the alpha compression scheme for arbitrary one- and two-channel images.
 /// Graphics APIs commonly refer to them as "grayscale", "luminance" or simply "red" for BC4 and
 /// "rg" or "luminance + alpha" for BC5 respectively.
+///
+/// BC4 and BC5 also come in signed variants (`Bc4Snorm` and `Bc5Snorm`) that store values in
+/// [-1, 1], e.g. for tangent-space normal maps. The 8-bit functions map their channels with
+/// a bias of 128, so that 0 becomes -1.0 and 128 becomes 0.0, while the `_i8` variants take
+/// and return the signed values directly. Both -128 and -127 represent -1.0.
+///
+/// BC6H comes in an unsigned (`Bc6hUf16`) and a signed (`Bc6hSf16`) variant and stores
+/// high dynamic range colour without alpha. The `_f16` and `_f32` variants of the
+/// compression functions take and return RGB pixels at full precision; the 8-bit functions
+/// still work for BC6H but are limited to [0, 1]. Conversely, the float functions work for
+/// all other formats by clamping to [0, 1] and dropping alpha.
 impl Format {
     /// Decompresses an image in memory
     ///
@@ -123,37 +245,194 @@ impl Format {
     /// * `height` - The height of the source image
     /// * `output` - Space to store the decompressed image
     pub fn decompress(self, data: &[u8], width: usize, height: usize, output: &mut [u8]) {
-        let blocks_wide = num_blocks(width);
-        let block_size = self.block_size();
+        let data_pitch = self.compressed_row_size(width);
+        self.decompress_pitched(
+            data,
+            data_pitch,
+            width,
+            height,
+            output,
+            PixelLayout::Rgba,
+            4 * width,
+        );
+    }
 
-        #[cfg(feature = "rayon")]
-        let output_rows = output.par_chunks_mut(width * 4 * 4);
-        #[cfg(not(feature = "rayon"))]
-        let output_rows = output.chunks_mut(width * 4 * 4);
+    /// Decompresses an image in memory, checking the sizes of all buffers first
+    ///
+    /// * `data`   - The compressed image data
+    /// * `width`  - The width of the source image
+    /// * `height` - The height of the source image
+    /// * `output` - Space to store the decompressed image
+    pub fn try_decompress(
+        self,
+        data: &[u8],
+        width: usize,
+        height: usize,
+        output: &mut [u8],
+    ) -> Result<(), Error> {
+        // overflowing pitches are reported by the pitched variant
+        let data_pitch = num_blocks(width).saturating_mul(self.block_size());
+        let output_pitch = width.saturating_mul(4);
+        self.try_decompress_pitched(
+            data,
+            data_pitch,
+            width,
+            height,
+            output,
+            PixelLayout::Rgba,
+            output_pitch,
+        )
+    }
 
-        // loop over blocks
-        output_rows.enumerate().for_each(|(y, output_row)| {
-            for x in 0..blocks_wide {
-                // decompress the block
-                let bidx = (x + y * blocks_wide) * block_size;
-                let rgba = self.decompress_block(&data[bidx..bidx + block_size]);
+    /// Decompresses an image in memory to pixels of the given layout, reading each row of
+    /// blocks `data_pitch` bytes after the previous one and writing each row of pixels
+    /// `output_pitch` bytes after the previous one
+    ///
+    /// Pitches larger than the rows allow e.g. decompressing into a mapped staging buffer with
+    /// padded rows. The bytes between rows are left untouched. For tightly packed images, pass
+    /// `compressed_row_size(width)` and `layout.channels() * width`.
+    ///
+    /// * `data`         - The compressed image data
+    /// * `data_pitch`   - The distance between rows of blocks in `data`
+    /// * `width`        - The width of the source image
+    /// * `height`       - The height of the source image
+    /// * `output`       - Space to store the decompressed image
+    /// * `layout`       - The channel order of the pixels in `output`
+    /// * `output_pitch` - The distance between rows of pixels in `output`
+    #[allow(clippy::too_many_arguments)]
+    pub fn decompress_pitched(
+        self,
+        data: &[u8],
+        data_pitch: usize,
+        width: usize,
+        height: usize,
+        output: &mut [u8],
+        layout: PixelLayout,
+        output_pitch: usize,
+    ) {
+        self.decompress_image(
+            data,
+            width,
+            height,
+            data_pitch,
+            output,
+            layout,
+            output_pitch,
+            |block| self.decompress_block(block),
+        );
+    }
 
-                // write the decompressed pixels to the correct image location
-                for py in 0..4 {
-                    for px in 0..4 {
-                        // get target location
-                        let sx = 4 * x + px;
-                        let sy = py;
+    /// Decompresses an image in memory to pixels of the given layout with the given pitches,
+    /// checking the pitches and the sizes of all buffers first
+    ///
+    /// * `data`         - The compressed image data
+    /// * `data_pitch`   - The distance between rows of blocks in `data`
+    /// * `width`        - The width of the source image
+    /// * `height`       - The height of the source image
+    /// * `output`       - Space to store the decompressed image
+    /// * `layout`       - The channel order of the pixels in `output`
+    /// * `output_pitch` - The distance between rows of pixels in `output`
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_decompress_pitched(
+        self,
+        data: &[u8],
+        data_pitch: usize,
+        width: usize,
+        height: usize,
+        output: &mut [u8],
+        layout: PixelLayout,
+        output_pitch: usize,
+    ) -> Result<(), Error> {
+        let channels = layout.channels();
+        let (values, compressed) =
+            self.checked_sizes(width, height, channels, output_pitch, data_pitch)?;
+        if data.len() < compressed {
+            return Err(Error::InputTooShort {
+                required: compressed,
+                actual: data.len(),
+            });
+        }
+        if output.len() < values {
+            return Err(Error::BufferTooSmall {
+                required: values,
+                actual: output.len(),
+            });
+        }
 
-                        if sx < width && sy < height {
-                            for i in 0..4 {
-                                output_row[4 * (sx + sy * width) + i] = rgba[px + py * 4][i];
-                            }
-                        }
-                    }
-                }
-            }
-        });
+        self.decompress_pitched(
+            data,
+            data_pitch,
+            width,
+            height,
+            output,
+            layout,
+            output_pitch,
+        );
+        Ok(())
+    }
+
+    /// Decompresses an image in memory to signed pixels
+    ///
+    /// * `data`   - The compressed image data
+    /// * `width`  - The width of the source image
+    /// * `height` - The height of the source image
+    /// * `output` - Space to store the decompressed image
+    pub fn decompress_i8(self, data: &[u8], width: usize, height: usize, output: &mut [i8]) {
+        let data_pitch = self.compressed_row_size(width);
+        self.decompress_image(
+            data,
+            width,
+            height,
+            data_pitch,
+            output,
+            PixelLayout::Rgba,
+            4 * width,
+            |block| self.decompress_block_i8(block),
+        );
+    }
+
+    /// Decompresses an image in memory to RGB half floats
+    ///
+    /// * `data`   - The compressed image data
+    /// * `width`  - The width of the source image
+    /// * `height` - The height of the source image
+    /// * `output` - Space to store the decompressed image, as the bit patterns of three
+    ///   half floats per pixel
+    pub fn decompress_f16(self, data: &[u8], width: usize, height: usize, output: &mut [u16]) {
+        let data_pitch = self.compressed_row_size(width);
+        self.decompress_image(
+            data,
+            width,
+            height,
+            data_pitch,
+            output,
+            PixelLayout::Rgb,
+            3 * width,
+            |block| self.decompress_block_f16(block),
+        );
+    }
+
+    /// Decompresses an image in memory to RGB floats
+    ///
+    /// * `data`   - The compressed image data
+    /// * `width`  - The width of the source image
+    /// * `height` - The height of the source image
+    /// * `output` - Space to store the decompressed image, three floats per pixel
+    pub fn decompress_f32(self, data: &[u8], width: usize, height: usize, output: &mut [f32]) {
+        let data_pitch = self.compressed_row_size(width);
+        self.decompress_image(
+            data,
+            width,
+            height,
+            data_pitch,
+            output,
+            PixelLayout::Rgb,
+            3 * width,
+            |block| {
+                self.decompress_block_f16(block)
+                    .map(|pixel| pixel.map(math::f16_to_f32))
+            },
+        );
     }
 
     /// Returns how many bytes a 4x4 block of pixels will compress into
@@ -164,10 +443,20 @@ impl Format {
             Format::Bc2 => 16,
             Format::Bc3 => 16,
             Format::Bc4 => 8,
+            Format::Bc4Snorm => 8,
             Format::Bc5 => 16,
+            Format::Bc5Snorm => 16,
+            Format::Bc6hUf16 => 16,
+            Format::Bc6hSf16 => 16,
+            Format::Bc7 => 16,
         }
     }
 
+    /// Returns true if the format stores high dynamic range colour
+    pub fn is_hdr(self) -> bool {
+        matches!(self, Format::Bc6hUf16 | Format::Bc6hSf16)
+    }
+
     /// Computes the amount of space in bytes needed for an image of given size,
     /// accounting for padding to a multiple of 4x4 pixels
     ///
@@ -179,6 +468,60 @@ impl Format {
         blocks * self.block_size()
     }
 
+    /// Returns how many bytes a row of blocks takes up for an image of given width
+    ///
+    /// * `width`  - Width of the uncompressed image
+    pub fn compressed_row_size(self, width: usize) -> usize {
+        num_blocks(width) * self.block_size()
+    }
+
+    /// Checks the dimensions and pitches of an image with `channels` values per pixel and
+    /// computes how many values its pixels and how many bytes its blocks span
+    fn checked_sizes(
+        self,
+        width: usize,
+        height: usize,
+        channels: usize,
+        pitch: usize,
+        block_pitch: usize,
+    ) -> Result<(usize, usize), Error> {
+        if width == 0 || height == 0 {
+            return Err(Error::ZeroDimension);
+        }
+
+        let row_size = width
+            .checked_mul(channels)
+            .ok_or(Error::DimensionOverflow)?;
+        let block_row_size = num_blocks(width)
+            .checked_mul(self.block_size())
+            .ok_or(Error::DimensionOverflow)?;
+        if pitch < row_size {
+            return Err(Error::PitchTooSmall {
+                required: row_size,
+                actual: pitch,
+            });
+        }
+        if block_pitch < block_row_size {
+            return Err(Error::PitchTooSmall {
+                required: block_row_size,
+                actual: block_pitch,
+            });
+        }
+
+        // a row of blocks must be addressable as well, even if the image is shorter
+        pitch.checked_mul(4).ok_or(Error::DimensionOverflow)?;
+        let values = pitch
+            .checked_mul(height - 1)
+            .and_then(|values| values.checked_add(row_size))
+            .ok_or(Error::DimensionOverflow)?;
+        let compressed = block_pitch
+            .checked_mul(num_blocks(height) - 1)
+            .and_then(|bytes| bytes.checked_add(block_row_size))
+            .ok_or(Error::DimensionOverflow)?;
+
+        Ok((values, compressed))
+    }
+
     /// Compresses a 4x4 block of pixels, masking out some pixels e.g. for padding the
     /// image to a multiple of the block size.
     ///
@@ -193,15 +536,38 @@ impl Format {
         params: Params,
         output: &mut [u8],
     ) {
+        if params.normal_map {
+            return normal::compress(self, rgba, mask, params, output);
+        }
+        if self == Format::Bc3 && params.bc3_encoding != Bc3Encoding::Rgba {
+            let encoded = params.bc3_encoding.encode_block(&rgba, mask);
+            let params = Params {
+                weights: COLOUR_WEIGHTS_UNIFORM,
+                weigh_colour_by_alpha: false,
+                srgb: false,
+                bc3_encoding: Bc3Encoding::Rgba,
+                ..params
+            };
+            return self.compress_block_masked(encoded, mask, params, output);
+        }
+
         // compress alpha block(s)
         match self {
-            Format::Bc1 => {}
+            Format::Bc1 | Format::Bc6hUf16 | Format::Bc6hSf16 | Format::Bc7 => {}
             Format::Bc2 => alpha::compress_bc2(&rgba, mask, &mut output[..8]),
-            Format::Bc3 => alpha::compress_bc3(&rgba, 3, mask, &mut output[..8]),
-            Format::Bc4 => alpha::compress_bc3(&rgba, 0, mask, &mut output[..8]),
+            Format::Bc3 => alpha::compress_bc3(&rgba, 3, false, mask, &mut output[..8]),
+            Format::Bc4 => alpha::compress_bc3(&rgba, 0, false, mask, &mut output[..8]),
             Format::Bc5 => {
-                alpha::compress_bc3(&rgba, 0, mask, &mut output[0..8]);
-                alpha::compress_bc3(&rgba, 1, mask, &mut output[8..16]);
+                alpha::compress_bc3(&rgba, 0, false, mask, &mut output[0..8]);
+                alpha::compress_bc3(&rgba, 1, false, mask, &mut output[8..16]);
+            }
+            Format::Bc4Snorm | Format::Bc5Snorm => {
+                // the signed formats work on two's complement bytes
+                let signed = rgba.map(|pixel| pixel.map(|value| value ^ 0x80));
+                alpha::compress_bc3(&signed, 0, true, mask, &mut output[0..8]);
+                if self == Format::Bc5Snorm {
+                    alpha::compress_bc3(&signed, 1, true, mask, &mut output[8..16]);
+                }
             }
         }
 
@@ -209,7 +575,8 @@ impl Format {
         match self {
             Format::Bc1 | Format::Bc2 | Format::Bc3 => {
                 // create the minimal point set
-                let colours = ColourSet::new(&rgba, mask, self, params.weigh_colour_by_alpha);
+                let colours =
+                    ColourSet::new(&rgba, mask, self, params.weigh_colour_by_alpha, params.srgb);
 
                 let colour_offset = if self == Format::Bc1 { 0 } else { 8 };
                 let colour_block = &mut output[colour_offset..colour_offset + 8];
@@ -230,7 +597,43 @@ impl Format {
                     fit.compress(colour_block);
                 }
             }
-            Format::Bc4 | Format::Bc5 => {}
+            Format::Bc6hUf16 | Format::Bc6hSf16 => {
+                let rgb = rgba.map(|pixel| [0, 1, 2].map(|c| math::unorm8_to_f16(pixel[c])));
+                self.compress_block_masked_f16(rgb, mask, params, output);
+            }
+            Format::Bc7 => bc7::compress(&rgba, mask, &params, &mut output[..16]),
+            Format::Bc4 | Format::Bc4Snorm | Format::Bc5 | Format::Bc5Snorm => {}
+        }
+    }
+
+    /// Compresses a 4x4 block of RGB half float pixels, masking out some pixels e.g. for
+    /// padding the image to a multiple of the block size.
+    ///
+    /// Formats other than BC6H clamp the pixels to [0, 1] and treat them as opaque.
+    ///
+    /// * `rgb`    - The uncompressed block of pixels as half float bit patterns
+    /// * `mask`   - The valid pixel mask
+    /// * `params` - Additional compressor parameters
+    /// * `output` - Storage for the compressed block
+    pub fn compress_block_masked_f16(
+        self,
+        rgb: [[u16; 3]; 16],
+        mask: u32,
+        params: Params,
+        output: &mut [u8],
+    ) {
+        match self {
+            Format::Bc6hUf16 | Format::Bc6hSf16 => {
+                let signed = self == Format::Bc6hSf16;
+                bc6h::compress(&rgb, mask, signed, &params, &mut output[..16]);
+            }
+            _ => {
+                let rgba = rgb.map(|pixel| {
+                    let [r, g, b] = pixel.map(math::f16_to_unorm8);
+                    [r, g, b, 0xFF]
+                });
+                self.compress_block_masked(rgba, mask, params, output);
+            }
         }
     }
 
@@ -250,6 +653,20 @@ impl Format {
                 // decompress colour block
                 rgba = colourblock::decompress(colour_block, self == Format::Bc1);
             }
+            Format::Bc6hUf16 | Format::Bc6hSf16 => {
+                let rgb = self.decompress_block_f16(block);
+                rgba = rgb.map(|pixel| {
+                    let [r, g, b] = pixel.map(math::f16_to_unorm8);
+                    [r, g, b, 0xFF]
+                });
+            }
+            Format::Bc7 => {
+                rgba = bc7::decompress(&block[..16]);
+            }
+            Format::Bc4Snorm | Format::Bc5Snorm => {
+                // two's complement bytes until the alpha blocks are decompressed
+                rgba = [[0, 0, 0, 0x7F]; 16];
+            }
             _ => {
                 rgba = [[0, 0, 0, 0xFF]; 16];
             }
@@ -257,26 +674,97 @@ impl Format {
 
         // decompress alpha block(s)
         match self {
-            Format::Bc1 => (),
+            Format::Bc1 | Format::Bc6hUf16 | Format::Bc6hSf16 | Format::Bc7 => (),
             Format::Bc2 => alpha::decompress_bc2(&mut rgba, &block[..8]),
-            Format::Bc3 => alpha::decompress_bc3(&mut rgba, 3, &block[..8]),
-            Format::Bc4 => {
-                alpha::decompress_bc3(&mut rgba, 0, &block[..8]);
+            Format::Bc3 => alpha::decompress_bc3(&mut rgba, 3, false, &block[..8]),
+            Format::Bc4 | Format::Bc4Snorm => {
+                let signed = self == Format::Bc4Snorm;
+                alpha::decompress_bc3(&mut rgba, 0, signed, &block[..8]);
                 // splat decompressed value into g and b channels
                 for pixel in &mut rgba {
                     pixel[1] = pixel[0];
                     pixel[2] = pixel[0];
                 }
             }
-            Format::Bc5 => {
-                alpha::decompress_bc3(&mut rgba, 0, &block[..8]);
-                alpha::decompress_bc3(&mut rgba, 1, &block[8..16]);
+            Format::Bc5 | Format::Bc5Snorm => {
+                let signed = self == Format::Bc5Snorm;
+                alpha::decompress_bc3(&mut rgba, 0, signed, &block[..8]);
+                alpha::decompress_bc3(&mut rgba, 1, signed, &block[8..16]);
+            }
+        }
+
+        // map two's complement bytes to the biased representation
+        if matches!(self, Format::Bc4Snorm | Format::Bc5Snorm) {
+            for pixel in &mut rgba {
+                for value in pixel.iter_mut() {
+                    *value ^= 0x80;
+                }
             }
         }
 
         rgba
     }
 
+    /// Decompresses a 4x4 block of pixels, checking the size of the block first
+    ///
+    /// * `block`  - The compressed block of pixels
+    pub fn try_decompress_block(self, block: &[u8]) -> Result<[[u8; 4]; 16], Error> {
+        if block.len() < self.block_size() {
+            return Err(Error::InputTooShort {
+                required: self.block_size(),
+                actual: block.len(),
+            });
+        }
+
+        Ok(self.decompress_block(block))
+    }
+
+    /// Decompresses a 4x4 block of pixels to RGB half floats
+    ///
+    /// Formats other than BC6H drop the alpha channel.
+    ///
+    /// * `block`  - The compressed block of pixels
+    pub fn decompress_block_f16(self, block: &[u8]) -> [[u16; 3]; 16] {
+        match self {
+            Format::Bc6hUf16 | Format::Bc6hSf16 => {
+                bc6h::decompress(&block[..16], self == Format::Bc6hSf16)
+            }
+            _ => self
+                .decompress_block(block)
+                .map(|pixel| [0, 1, 2].map(|c| math::unorm8_to_f16(pixel[c]))),
+        }
+    }
+
+    /// Compresses a 4x4 block of signed pixels, masking out some pixels e.g. for padding the
+    /// image to a multiple of the block size.
+    ///
+    /// Formats other than BC4 and BC5 SNORM map the values to [0, 255] by adding 128.
+    ///
+    /// * `rgba`   - The uncompressed block of pixels
+    /// * `mask`   - The valid pixel mask
+    /// * `params` - Additional compressor parameters
+    /// * `output` - Storage for the compressed block
+    pub fn compress_block_masked_i8(
+        self,
+        rgba: [[i8; 4]; 16],
+        mask: u32,
+        params: Params,
+        output: &mut [u8],
+    ) {
+        let biased = rgba.map(|pixel| pixel.map(|value| value as u8 ^ 0x80));
+        self.compress_block_masked(biased, mask, params, output);
+    }
+
+    /// Decompresses a 4x4 block of pixels to signed values
+    ///
+    /// Formats other than BC4 and BC5 SNORM map the values from [0, 255] by subtracting 128.
+    ///
+    /// * `block`  - The compressed block of pixels
+    pub fn decompress_block_i8(self, block: &[u8]) -> [[i8; 4]; 16] {
+        self.decompress_block(block)
+            .map(|pixel| pixel.map(|value| (value ^ 0x80) as i8))
+    }
+
     /// Compresses an image in memory
     ///
     /// * `rgba`   - The uncompressed pixel data
@@ -284,7 +772,7 @@ impl Format {
     /// * `height` - The height of the source image
     /// * `params` - Additional compressor parameters
     /// * `output` - Output buffer for the compressed image. Ensure that this has
-    /// at least as much space available as `compute_compressed_size` suggests.
+    ///   at least as much space available as `compressed_size` suggests.
     pub fn compress(
         self,
         rgba: &[u8],
@@ -293,51 +781,522 @@ impl Format {
         params: Params,
         output: &mut [u8],
     ) {
-        assert!(output.len() >= self.compressed_size(width, height));
-
-        let block_size = self.block_size();
-        let blocks_wide = num_blocks(width);
-
-        #[cfg(feature = "rayon")]
-        let output_rows = output.par_chunks_mut(blocks_wide * block_size);
-        #[cfg(not(feature = "rayon"))]
-        let output_rows = output.chunks_mut(blocks_wide * block_size);
-
-        output_rows.enumerate().for_each(|(y, output_row)| {
-            let mut source_rgba = [[0u8; 4]; 16];
-            let output_blocks = output_row.chunks_mut(block_size);
-
-            output_blocks.enumerate().for_each(|(x, output_block)| {
-                // build the 4x4 block of pixels
-                let mut mask = 0u32;
-                for py in 0..4 {
-                    for px in 0..4 {
-                        let index = 4 * py + px;
+        let output_pitch = self.compressed_row_size(width);
+        self.compress_pitched(
+            rgba,
+            PixelLayout::Rgba,
+            4 * width,
+            width,
+            height,
+            params,
+            output,
+            output_pitch,
+        );
+    }
 
-                        // get position in source image
-                        let sx = 4 * x + px;
-                        let sy = 4 * y + py;
+    /// Compresses an image in memory and measures the error of each compressed block
+    ///
+    /// The errors are measured by decoding the finished blocks again, like
+    /// `metrics::block_errors`, rather than taken from the colour fits. Their best errors
+    /// only cover the colour block of BC1, BC2 and BC3, are scaled by the fit's own weights
+    /// and, with `srgb`, measured in linear light, and miss the alpha blocks as well as any
+    /// changes made by rate-distortion optimisation afterwards.
+    ///
+    /// BC3 blocks in one of the other encodings, or holding DXT5nm normals in normal map
+    /// mode, are decoded to RGB with `Bc3Encoding::decode_pixel` before they are compared.
+    /// Normal maps are measured with uniform weights.
+    ///
+    /// * `rgba`   - The uncompressed pixel data
+    /// * `width`  - The width of the source image
+    /// * `height` - The height of the source image
+    /// * `params` - Additional compressor parameters
+    /// * `output` - Output buffer for the compressed image. Ensure that this has
+    ///   at least as much space available as `compressed_size` suggests.
+    /// * `errors` - Output buffer for the error of each block in row-major order. Ensure that
+    ///   this has space for `num_blocks(width) * num_blocks(height)` blocks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the buffers is too small. Use `try_compress_with_errors` to have
+    /// this reported as an `Error` instead.
+    pub fn compress_with_errors(
+        self,
+        rgba: &[u8],
+        width: usize,
+        height: usize,
+        params: Params,
+        output: &mut [u8],
+        errors: &mut [metrics::BlockError],
+    ) {
+        if let Err(e) = self.try_compress_with_errors(rgba, width, height, params, output, errors) {
+            panic!("{}", e);
+        }
+    }
 
-                        // enable pixel if within bounds
-                        if sx < width && sy < height {
-                            // copy pixel value
-                            let src_index = 4 * (width * sy + sx);
-                            source_rgba[index].copy_from_slice(&rgba[src_index..src_index + 4]);
+    /// Compresses an image in memory and measures the error of each compressed block like
+    /// `compress_with_errors`, checking the sizes of all buffers first
+    ///
+    /// * `rgba`   - The uncompressed pixel data
+    /// * `width`  - The width of the source image
+    /// * `height` - The height of the source image
+    /// * `params` - Additional compressor parameters
+    /// * `output` - Output buffer for the compressed image
+    /// * `errors` - Output buffer for the error of each block in row-major order
+    pub fn try_compress_with_errors(
+        self,
+        rgba: &[u8],
+        width: usize,
+        height: usize,
+        params: Params,
+        output: &mut [u8],
+        errors: &mut [metrics::BlockError],
+    ) -> Result<(), Error> {
+        let blocks = num_blocks(width).saturating_mul(num_blocks(height));
+        if errors.len() < blocks {
+            return Err(Error::BufferTooSmall {
+                required: blocks,
+                actual: errors.len(),
+            });
+        }
 
-                            // enable pixel
-                            mask |= 1 << index;
-                        }
-                    }
-                }
+        self.try_compress(rgba, width, height, params, output)?;
+        let (encoding, weights) = if params.normal_map {
+            (Bc3Encoding::Dxt5nm, COLOUR_WEIGHTS_UNIFORM)
+        } else {
+            (params.bc3_encoding, params.weights)
+        };
+        metrics::encoded_block_errors(self, encoding, rgba, width, height, output, weights, errors)
+    }
 
-                self.compress_block_masked(source_rgba, mask, params, output_block);
-            });
-        });
+    /// Compresses an image in memory, checking the sizes of all buffers first
+    ///
+    /// * `rgba`   - The uncompressed pixel data
+    /// * `width`  - The width of the source image
+    /// * `height` - The height of the source image
+    /// * `params` - Additional compressor parameters
+    /// * `output` - Output buffer for the compressed image
+    pub fn try_compress(
+        self,
+        rgba: &[u8],
+        width: usize,
+        height: usize,
+        params: Params,
+        output: &mut [u8],
+    ) -> Result<(), Error> {
+        // overflowing pitches are reported by the pitched variant
+        let rgba_pitch = width.saturating_mul(4);
+        let output_pitch = num_blocks(width).saturating_mul(self.block_size());
+        self.try_compress_pitched(
+            rgba,
+            PixelLayout::Rgba,
+            rgba_pitch,
+            width,
+            height,
+            params,
+            output,
+            output_pitch,
+        )
     }
-}
 
-//--------------------------------------------------------------------------------
-// Unit tests
+    /// Compresses an image in memory from pixels of the given layout, reading each row of
+    /// pixels `pixel_pitch` bytes after the previous one and writing each row of blocks
+    /// `output_pitch` bytes after the previous one
+    ///
+    /// Pitches larger than the rows allow e.g. compressing a region of a texture atlas or a
+    /// readback buffer with padded rows. The bytes between rows of blocks are left untouched.
+    /// For tightly packed images, pass `layout.channels() * width` and
+    /// `compressed_row_size(width)`.
+    ///
+    /// * `pixels`       - The uncompressed pixel data
+    /// * `layout`       - The channel order of `pixels`
+    /// * `pixel_pitch`  - The distance between rows of pixels in `pixels`
+    /// * `width`        - The width of the source image
+    /// * `height`       - The height of the source image
+    /// * `params`       - Additional compressor parameters
+    /// * `output`       - Output buffer for the compressed image
+    /// * `output_pitch` - The distance between rows of blocks in `output`
+    #[allow(clippy::too_many_arguments)]
+    pub fn compress_pitched(
+        self,
+        pixels: &[u8],
+        layout: PixelLayout,
+        pixel_pitch: usize,
+        width: usize,
+        height: usize,
+        params: Params,
+        output: &mut [u8],
+        output_pitch: usize,
+    ) {
+        self.compress_pitched_with_progress(
+            pixels,
+            layout,
+            pixel_pitch,
+            width,
+            height,
+            params,
+            output,
+            output_pitch,
+            None,
+        );
+    }
+
+    /// Compresses an image like `compress_pitched`, reporting each finished row of blocks
+    /// to `progress` and skipping the remaining rows once it is cancelled
+    #[allow(clippy::too_many_arguments)]
+    fn compress_pitched_with_progress(
+        self,
+        pixels: &[u8],
+        layout: PixelLayout,
+        pixel_pitch: usize,
+        width: usize,
+        height: usize,
+        params: Params,
+        output: &mut [u8],
+        output_pitch: usize,
+        progress: Option<&compressor::Progress>,
+    ) {
+        self.compress_image(
+            pixels,
+            layout,
+            [0, 0, 0, u8::MAX],
+            width,
+            height,
+            pixel_pitch,
+            output,
+            output_pitch,
+            progress,
+            |pixels, mask, block| self.compress_block_masked(pixels, mask, params, block),
+        );
+        if params.rdo_lambda > 0.0 {
+            rdo::optimise(
+                self,
+                pixels,
+                layout,
+                width,
+                height,
+                pixel_pitch,
+                &params,
+                output,
+                output_pitch,
+                progress,
+            );
+        }
+    }
+
+    /// Compresses an image in memory from pixels of the given layout with the given pitches,
+    /// checking the pitches and the sizes of all buffers first
+    ///
+    /// * `pixels`       - The uncompressed pixel data
+    /// * `layout`       - The channel order of `pixels`
+    /// * `pixel_pitch`  - The distance between rows of pixels in `pixels`
+    /// * `width`        - The width of the source image
+    /// * `height`       - The height of the source image
+    /// * `params`       - Additional compressor parameters
+    /// * `output`       - Output buffer for the compressed image
+    /// * `output_pitch` - The distance between rows of blocks in `output`
+    #[allow(clippy::too_many_arguments)]
+    pub fn try_compress_pitched(
+        self,
+        pixels: &[u8],
+        layout: PixelLayout,
+        pixel_pitch: usize,
+        width: usize,
+        height: usize,
+        params: Params,
+        output: &mut [u8],
+        output_pitch: usize,
+    ) -> Result<(), Error> {
+        self.check_compress_buffers(
+            pixels.len(),
+            layout,
+            width,
+            height,
+            pixel_pitch,
+            output.len(),
+            output_pitch,
+        )?;
+
+        self.compress_pitched(
+            pixels,
+            layout,
+            pixel_pitch,
+            width,
+            height,
+            params,
+            output,
+            output_pitch,
+        );
+        Ok(())
+    }
+
+    /// Checks the pitches and the buffer sizes for compressing an image
+    #[allow(clippy::too_many_arguments)]
+    fn check_compress_buffers(
+        self,
+        pixels: usize,
+        layout: PixelLayout,
+        width: usize,
+        height: usize,
+        pixel_pitch: usize,
+        output: usize,
+        output_pitch: usize,
+    ) -> Result<(), Error> {
+        let channels = layout.channels();
+        let (values, compressed) =
+            self.checked_sizes(width, height, channels, pixel_pitch, output_pitch)?;
+        if pixels < values {
+            return Err(Error::InputTooShort {
+                required: values,
+                actual: pixels,
+            });
+        }
+        if output < compressed {
+            return Err(Error::BufferTooSmall {
+                required: compressed,
+                actual: output,
+            });
+        }
+        Ok(())
+    }
+
+    /// Compresses an image of signed pixels in memory
+    ///
+    /// * `rgba`   - The uncompressed pixel data
+    /// * `width`  - The width of the source image
+    /// * `height` - The height of the source image
+    /// * `params` - Additional compressor parameters
+    /// * `output` - Output buffer for the compressed image. Ensure that this has
+    ///   at least as much space available as `compressed_size` suggests.
+    pub fn compress_i8(
+        self,
+        rgba: &[i8],
+        width: usize,
+        height: usize,
+        params: Params,
+        output: &mut [u8],
+    ) {
+        let output_pitch = self.compressed_row_size(width);
+        self.compress_image(
+            rgba,
+            PixelLayout::Rgba,
+            [0; 4],
+            width,
+            height,
+            4 * width,
+            output,
+            output_pitch,
+            None,
+            |pixels, mask, block| self.compress_block_masked_i8(pixels, mask, params, block),
+        );
+    }
+
+    /// Compresses an image of RGB half floats in memory
+    ///
+    /// * `rgb`    - The uncompressed pixel data as the bit patterns of three half floats
+    ///   per pixel
+    /// * `width`  - The width of the source image
+    /// * `height` - The height of the source image
+    /// * `params` - Additional compressor parameters
+    /// * `output` - Output buffer for the compressed image. Ensure that this has
+    ///   at least as much space available as `compressed_size` suggests.
+    pub fn compress_f16(
+        self,
+        rgb: &[u16],
+        width: usize,
+        height: usize,
+        params: Params,
+        output: &mut [u8],
+    ) {
+        let output_pitch = self.compressed_row_size(width);
+        self.compress_image(
+            rgb,
+            PixelLayout::Rgb,
+            [0; 3],
+            width,
+            height,
+            3 * width,
+            output,
+            output_pitch,
+            None,
+            |pixels, mask, block| self.compress_block_masked_f16(pixels, mask, params, block),
+        );
+    }
+
+    /// Compresses an image of RGB floats in memory
+    ///
+    /// * `rgb`    - The uncompressed pixel data, three floats per pixel
+    /// * `width`  - The width of the source image
+    /// * `height` - The height of the source image
+    /// * `params` - Additional compressor parameters
+    /// * `output` - Output buffer for the compressed image. Ensure that this has
+    ///   at least as much space available as `compressed_size` suggests.
+    pub fn compress_f32(
+        self,
+        rgb: &[f32],
+        width: usize,
+        height: usize,
+        params: Params,
+        output: &mut [u8],
+    ) {
+        let output_pitch = self.compressed_row_size(width);
+        self.compress_image(
+            rgb,
+            PixelLayout::Rgb,
+            [0.0; 3],
+            width,
+            height,
+            3 * width,
+            output,
+            output_pitch,
+            None,
+            |pixels, mask, block| {
+                let pixels = pixels.map(|pixel| pixel.map(math::f32_to_f16));
+                self.compress_block_masked_f16(pixels, mask, params, block)
+            },
+        );
+    }
+
+    /// Gathers the pixels of each block of an image with `N` channels of type `T`
+    /// and compresses them with the given function
+    ///
+    /// Channels missing from the layout are taken from `defaults`. Rows are skipped once
+    /// `progress` is cancelled.
+    #[allow(clippy::too_many_arguments)]
+    fn compress_image<T, const N: usize, F>(
+        self,
+        pixels: &[T],
+        layout: PixelLayout,
+        defaults: [T; N],
+        width: usize,
+        height: usize,
+        pitch: usize,
+        output: &mut [u8],
+        output_pitch: usize,
+        progress: Option<&compressor::Progress>,
+        compress_block: F,
+    ) where
+        T: Copy + Sync,
+        F: Fn([[T; N]; 16], u32, &mut [u8]) + Sync,
+    {
+        let block_size = self.block_size();
+        let block_row_size = self.compressed_row_size(width);
+        let order = layout.order();
+        let channels = order.len();
+        assert!(pitch >= channels * width && output_pitch >= block_row_size);
+
+        let compressed = pitched_size(output_pitch, num_blocks(height), block_row_size);
+        assert!(output.len() >= compressed);
+        let output = &mut output[..compressed];
+
+        #[cfg(feature = "rayon")]
+        let output_rows = output.par_chunks_mut(output_pitch);
+        #[cfg(not(feature = "rayon"))]
+        let output_rows = output.chunks_mut(output_pitch);
+
+        output_rows.enumerate().for_each(|(y, output_row)| {
+            if progress.is_some_and(|progress| !progress.proceed()) {
+                return;
+            }
+
+            let mut source = [defaults; 16];
+            let output_blocks = output_row[..block_row_size].chunks_mut(block_size);
+
+            output_blocks.enumerate().for_each(|(x, output_block)| {
+                // build the 4x4 block of pixels
+                let mut mask = 0u32;
+                for py in 0..4 {
+                    for px in 0..4 {
+                        let index = 4 * py + px;
+
+                        // get position in source image
+                        let sx = 4 * x + px;
+                        let sy = 4 * y + py;
+
+                        // enable pixel if within bounds
+                        if sx < width && sy < height {
+                            // copy pixel value
+                            let src_index = pitch * sy + channels * sx;
+                            let pixel = &pixels[src_index..src_index + channels];
+                            for (&value, &channel) in pixel.iter().zip(order) {
+                                source[index][channel] = value;
+                            }
+
+                            // enable pixel
+                            mask |= 1 << index;
+                        }
+                    }
+                }
+
+                compress_block(source, mask, output_block);
+            });
+
+            if let Some(progress) = progress {
+                progress.row_finished();
+            }
+        });
+    }
+
+    /// Decompresses each block of an image with the given function and scatters the
+    /// resulting pixels with `N` channels of type `T` to their image locations
+    #[allow(clippy::too_many_arguments)]
+    fn decompress_image<T, const N: usize, F>(
+        self,
+        data: &[u8],
+        width: usize,
+        height: usize,
+        data_pitch: usize,
+        output: &mut [T],
+        layout: PixelLayout,
+        pitch: usize,
+        decompress_block: F,
+    ) where
+        T: Copy + Send,
+        F: Fn(&[u8]) -> [[T; N]; 16] + Sync,
+    {
+        let blocks_wide = num_blocks(width);
+        let block_size = self.block_size();
+        let order = layout.order();
+        let channels = order.len();
+        assert!(pitch >= channels * width && data_pitch >= self.compressed_row_size(width));
+
+        // ignore any excess output space that has no blocks to decompress into it
+        let output = &mut output[..pitched_size(pitch, height, channels * width)];
+
+        #[cfg(feature = "rayon")]
+        let output_rows = output.par_chunks_mut(pitch * 4);
+        #[cfg(not(feature = "rayon"))]
+        let output_rows = output.chunks_mut(pitch * 4);
+
+        // loop over blocks
+        output_rows.enumerate().for_each(|(y, output_row)| {
+            for x in 0..blocks_wide {
+                // decompress the block
+                let bidx = y * data_pitch + x * block_size;
+                let pixels = decompress_block(&data[bidx..bidx + block_size]);
+
+                // write the decompressed pixels to the correct image location
+                for py in 0..4 {
+                    for px in 0..4 {
+                        // get target location
+                        let sx = 4 * x + px;
+                        let sy = 4 * y + py;
+
+                        if sx < width && sy < height {
+                            let index = py * pitch + channels * sx;
+                            let pixel = &mut output_row[index..index + channels];
+                            for (value, &channel) in pixel.iter_mut().zip(order) {
+                                *value = pixels[px + py * 4][channel];
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+//--------------------------------------------------------------------------------
+// Unit tests
 //--------------------------------------------------------------------------------
 
 #[cfg(test)]
@@ -359,6 +1318,1387 @@ mod tests {
         assert_eq!(Format::Bc4.compressed_size(15, 32), 256);
         assert_eq!(Format::Bc5.compressed_size(16, 32), 512);
         assert_eq!(Format::Bc5.compressed_size(15, 32), 512);
+        assert_eq!(Format::Bc4Snorm.compressed_size(15, 32), 256);
+        assert_eq!(Format::Bc5Snorm.compressed_size(15, 32), 512);
+        assert_eq!(Format::Bc6hUf16.compressed_size(16, 32), 512);
+        assert_eq!(Format::Bc6hUf16.compressed_size(15, 32), 512);
+        assert_eq!(Format::Bc6hSf16.compressed_size(16, 32), 512);
+        assert_eq!(Format::Bc6hSf16.compressed_size(15, 32), 512);
+        assert_eq!(Format::Bc7.compressed_size(16, 32), 512);
+        assert_eq!(Format::Bc7.compressed_size(15, 32), 512);
+    }
+
+    #[test]
+    fn test_bptc_anchors_belong_to_their_subset() {
+        for partition in 0..64 {
+            for subsets in 2..=3 {
+                for subset in 0..subsets {
+                    let anchor = bptc::anchor(subsets, partition, subset);
+                    assert_eq!(bptc::subset(subsets, partition, anchor), subset);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_try_compress_errors() {
+        let rgba = [0u8; 4 * 8 * 8];
+        let mut output = [0u8; 64];
+        let params = Params::default();
+
+        assert_eq!(
+            Format::Bc1.try_compress(&rgba, 0, 8, params, &mut output),
+            Err(Error::ZeroDimension)
+        );
+        assert_eq!(
+            Format::Bc1.try_compress(&rgba, usize::MAX, 2, params, &mut output),
+            Err(Error::DimensionOverflow)
+        );
+        assert_eq!(
+            Format::Bc1.try_compress(&rgba[..100], 8, 8, params, &mut output),
+            Err(Error::InputTooShort {
+                required: 256,
+                actual: 100
+            })
+        );
+        assert_eq!(
+            Format::Bc3.try_compress(&rgba, 8, 8, params, &mut output[..32]),
+            Err(Error::BufferTooSmall {
+                required: 64,
+                actual: 32
+            })
+        );
+        assert_eq!(
+            Format::Bc3.try_compress(&rgba, 8, 8, params, &mut output),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_try_decompress_errors() {
+        let data = [0u8; 64];
+        let mut output = [0u8; 4 * 8 * 8];
+
+        assert_eq!(
+            Format::Bc1.try_decompress(&data, 8, 0, &mut output),
+            Err(Error::ZeroDimension)
+        );
+        assert_eq!(
+            Format::Bc1.try_decompress(&data, usize::MAX / 2, usize::MAX / 2, &mut output),
+            Err(Error::DimensionOverflow)
+        );
+        assert_eq!(
+            Format::Bc3.try_decompress(&data[..48], 8, 8, &mut output),
+            Err(Error::InputTooShort {
+                required: 64,
+                actual: 48
+            })
+        );
+        assert_eq!(
+            Format::Bc1.try_decompress(&data, 8, 8, &mut output[..255]),
+            Err(Error::BufferTooSmall {
+                required: 256,
+                actual: 255
+            })
+        );
+        assert_eq!(Format::Bc1.try_decompress(&data, 8, 8, &mut output), Ok(()));
+
+        // excess output space is left untouched
+        let mut output = [0xAAu8; 4 * 8 * 8 + 4];
+        assert_eq!(Format::Bc1.try_decompress(&data, 8, 8, &mut output), Ok(()));
+        assert_eq!(output[4 * 8 * 8..], [0xAA; 4]);
+    }
+
+    #[test]
+    fn test_pitched_compression() {
+        // compress the 6x5 region at (2, 1) of a 10x8 atlas
+        let mut atlas = [0u8; 4 * 10 * 8];
+        for (i, value) in atlas.iter_mut().enumerate() {
+            *value = (i * 37 % 251) as u8;
+        }
+        let mut region = [0u8; 4 * 6 * 5];
+        for (y, row) in region.chunks_mut(4 * 6).enumerate() {
+            let start = 4 * (10 * (y + 1) + 2);
+            row.copy_from_slice(&atlas[start..start + 4 * 6]);
+        }
+
+        let params = Params::default();
+        let mut expected = [0u8; 64];
+        Format::Bc3.compress(&region, 6, 5, params, &mut expected);
+
+        // rows of blocks are written 40 bytes apart
+        let mut actual = [0xAAu8; 40 + 32];
+        let source = &atlas[4 * (10 + 2)..];
+        assert_eq!(
+            Format::Bc3.try_compress_pitched(
+                source,
+                PixelLayout::Rgba,
+                40,
+                6,
+                5,
+                params,
+                &mut actual,
+                40
+            ),
+            Ok(())
+        );
+        assert_eq!(actual[..32], expected[..32]);
+        assert_eq!(actual[32..40], [0xAA; 8]);
+        assert_eq!(actual[40..], expected[32..]);
+
+        assert_eq!(
+            Format::Bc3.try_compress_pitched(
+                source,
+                PixelLayout::Rgba,
+                20,
+                6,
+                5,
+                params,
+                &mut actual,
+                40
+            ),
+            Err(Error::PitchTooSmall {
+                required: 24,
+                actual: 20
+            })
+        );
+        assert_eq!(
+            Format::Bc3.try_compress_pitched(
+                source,
+                PixelLayout::Rgba,
+                40,
+                6,
+                5,
+                params,
+                &mut actual,
+                16
+            ),
+            Err(Error::PitchTooSmall {
+                required: 32,
+                actual: 16
+            })
+        );
+    }
+
+    #[test]
+    fn test_pitched_decompression() {
+        let data = [test_data::BC1_COLOUR.encoded, test_data::BC1_GRAY.encoded].concat();
+        let mut expected = [0u8; 4 * 8 * 3];
+        Format::Bc1.decompress(&data, 8, 3, &mut expected);
+
+        // rows of pixels are written 40 bytes apart, blocks are read 16 bytes apart
+        let mut actual = [0xAAu8; 40 * 2 + 32];
+        assert_eq!(
+            Format::Bc1.try_decompress_pitched(&data, 16, 8, 3, &mut actual, PixelLayout::Rgba, 40),
+            Ok(())
+        );
+        for (y, row) in actual.chunks(40).enumerate() {
+            assert_eq!(row[..32], expected[32 * y..32 * (y + 1)]);
+            assert!(row[32..].iter().all(|&value| value == 0xAA));
+        }
+
+        assert_eq!(
+            Format::Bc1.try_decompress_pitched(
+                &data,
+                16,
+                8,
+                3,
+                &mut actual[..100],
+                PixelLayout::Rgba,
+                40
+            ),
+            Err(Error::BufferTooSmall {
+                required: 112,
+                actual: 100
+            })
+        );
+    }
+
+    #[test]
+    fn test_pixel_layout_compression() {
+        let rgba = test_data::BC3_COLOUR.decoded;
+        let params = Params::default();
+        let mut expected = [0u8; 16];
+        Format::Bc3.compress(rgba, 4, 4, params, &mut expected);
+
+        let mut bgra = [0u8; 4 * 4 * 4];
+        let mut argb = [0u8; 4 * 4 * 4];
+        for ((bgra, argb), rgba) in bgra
+            .chunks_mut(4)
+            .zip(argb.chunks_mut(4))
+            .zip(rgba.chunks(4))
+        {
+            bgra.copy_from_slice(&[rgba[2], rgba[1], rgba[0], rgba[3]]);
+            argb.copy_from_slice(&[rgba[3], rgba[0], rgba[1], rgba[2]]);
+        }
+        for (layout, pixels) in [(PixelLayout::Bgra, bgra), (PixelLayout::Argb, argb)] {
+            let mut actual = [0u8; 16];
+            Format::Bc3.compress_pitched(&pixels, layout, 16, 4, 4, params, &mut actual, 16);
+            assert_eq!(actual, expected);
+        }
+
+        // single channel pixels compress like opaque red
+        let red: [u8; 16] = core::array::from_fn(|i| rgba[4 * i]);
+        let opaque_red: [u8; 4 * 4 * 4] = core::array::from_fn(|i| match i % 4 {
+            0 => red[i / 4],
+            3 => u8::MAX,
+            _ => 0,
+        });
+        let mut expected = [0u8; 16];
+        Format::Bc3.compress(&opaque_red, 4, 4, params, &mut expected);
+        let mut actual = [0u8; 16];
+        Format::Bc3.compress_pitched(&red, PixelLayout::R, 4, 4, 4, params, &mut actual, 16);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_pixel_layout_decompression() {
+        let data = test_data::BC5_GRAY;
+        let mut rg = [0u8; 2 * 4 * 4];
+        assert_eq!(
+            Format::Bc5.try_decompress_pitched(data.encoded, 16, 4, 4, &mut rg, PixelLayout::Rg, 8),
+            Ok(())
+        );
+        for (rg, rgba) in rg.chunks(2).zip(data.decoded.chunks(4)) {
+            assert_eq!(rg, &rgba[..2]);
+        }
+
+        let mut bgr = [0u8; 4 * 4 * 4];
+        Format::Bc5.decompress_pitched(data.encoded, 16, 4, 4, &mut bgr, PixelLayout::Bgra, 16);
+        for (bgr, rgba) in bgr.chunks(4).zip(data.decoded.chunks(4)) {
+            assert_eq!(bgr, &[rgba[2], rgba[1], rgba[0], rgba[3]]);
+        }
+
+        assert_eq!(
+            Format::Bc5.try_decompress_pitched(
+                data.encoded,
+                16,
+                4,
+                4,
+                &mut rg,
+                PixelLayout::Rgb,
+                12
+            ),
+            Err(Error::BufferTooSmall {
+                required: 48,
+                actual: 32
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_mipmap_sizes() {
+        assert_eq!(mipmap::level_count(1, 1), 1);
+        assert_eq!(mipmap::level_count(256, 128), 9);
+        assert_eq!(mipmap::level_count(5, 3), 3);
+        assert_eq!(mipmap::level_size(5, 3, 1), (2, 1));
+        assert_eq!(mipmap::level_size(5, 3, 2), (1, 1));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_mipmap_filters() {
+        use mipmap::{EdgeMode, Filter, MipmapParams};
+
+        // every filter preserves flat colours
+        let flat = [0x20, 0x40, 0x80, 0xFF].repeat(12 * 7);
+        for filter in [
+            Filter::Box,
+            Filter::Triangle,
+            Filter::Kaiser,
+            Filter::Lanczos,
+        ] {
+            for edge_mode in [EdgeMode::Clamp, EdgeMode::Wrap] {
+                let params = MipmapParams {
+                    filter,
+                    edge_mode,
+                    srgb: false,
+                    alpha_coverage: None,
+                    normal_map: false,
+                };
+                let chain = mipmap::generate(&flat, 12, 7, usize::MAX, &params);
+                assert_eq!(chain.len(), 4);
+                for level in &chain {
+                    assert_eq!(level.data, flat[..4 * level.width * level.height]);
+                }
+            }
+        }
+
+        // the box filter averages 2x2 pixels
+        let rgba = [0, 0, 0, 0, 2, 4, 6, 8, 10, 20, 30, 40, 0, 0, 0, 0];
+        let chain = mipmap::generate(&rgba, 2, 2, 2, &MipmapParams::default());
+        assert_eq!(chain[1].data, [3, 6, 9, 12]);
+
+        // wrapping pulls in the opposite edge
+        let rgba = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255];
+        let params = |edge_mode| MipmapParams {
+            filter: Filter::Triangle,
+            edge_mode,
+            srgb: false,
+            alpha_coverage: None,
+            normal_map: false,
+        };
+        let clamped = mipmap::generate(&rgba, 4, 1, 2, &params(EdgeMode::Clamp));
+        let wrapped = mipmap::generate(&rgba, 4, 1, 2, &params(EdgeMode::Wrap));
+        assert_eq!(clamped[1].data[..4], [0; 4]);
+        assert_eq!(wrapped[1].data[..4], [32; 4]);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_mipmap_srgb() {
+        use mipmap::MipmapParams;
+
+        // every sRGB value survives the conversion to linear light and back
+        let rgba: [u8; 4 * 8 * 8] = core::array::from_fn(|i| (i / 4) as u8 * 4 + (i % 4) as u8);
+        let params = MipmapParams {
+            srgb: true,
+            ..Default::default()
+        };
+        let chain = mipmap::generate(&rgba, 8, 8, 1, &params);
+        assert_eq!(chain[0].data, rgba);
+
+        // averaging black and white in linear light yields a brighter grey than in sRGB,
+        // while alpha is still averaged linearly
+        let rgba = [0, 0, 0, 0, 255, 255, 255, 255].repeat(2);
+        let chain = mipmap::generate(&rgba, 2, 2, 2, &MipmapParams::default());
+        assert_eq!(chain[1].data, [128, 128, 128, 128]);
+        let chain = mipmap::generate(&rgba, 2, 2, 2, &params);
+        assert_eq!(chain[1].data, [188, 188, 188, 128]);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_mipmap_alpha_coverage() {
+        use mipmap::MipmapParams;
+
+        // mostly transparent noise with few opaque pixels, like distant foliage
+        let rgba: [u8; 4 * 16 * 16] = core::array::from_fn(|i| match i % 4 {
+            3 => {
+                let noise = ((i / 4) * 97 % 251) as u32;
+                (noise * noise / 251) as u8
+            }
+            _ => 0x80,
+        });
+        let coverage = |image: &mipmap::Image| {
+            let covered = image.data.chunks(4).filter(|pixel| pixel[3] >= 128);
+            covered.count() as f32 / (image.width * image.height) as f32
+        };
+
+        let plain = mipmap::generate(&rgba, 16, 16, 4, &MipmapParams::default());
+        let params = MipmapParams {
+            alpha_coverage: Some(128),
+            ..Default::default()
+        };
+        let preserved = mipmap::generate(&rgba, 16, 16, 4, &params);
+        assert_eq!(preserved[0], plain[0]);
+
+        let source = coverage(&plain[0]);
+        for (plain, preserved) in plain.iter().zip(&preserved).skip(1) {
+            assert!(coverage(plain) < source / 2.0);
+            assert!((coverage(preserved) - source).abs() < 0.1);
+            // colours are unaffected
+            for (a, b) in plain.data.chunks(4).zip(preserved.data.chunks(4)) {
+                assert_eq!(a[..3], b[..3]);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_srgb_colour_fit() {
+        // a dark gradient with a single bright pixel
+        let rgba: [u8; 4 * 4 * 4] = core::array::from_fn(|i| match (i / 4, i % 4) {
+            (_, 3) => u8::MAX,
+            (15, _) => 200,
+            (pixel, _) => 8 * pixel as u8,
+        });
+        for algorithm in [Algorithm::ClusterFit, Algorithm::IterativeClusterFit] {
+            let measure = |srgb| {
+                let params = Params {
+                    algorithm,
+                    weights: COLOUR_WEIGHTS_UNIFORM,
+                    srgb,
+                    ..Default::default()
+                };
+                let mut block = [0u8; 8];
+                Format::Bc1.compress(&rgba, 4, 4, params, &mut block);
+                let decoded = Format::Bc1.decompress_block(&block);
+                let mut error = 0.0;
+                for (source, decoded) in rgba.chunks(4).zip(decoded) {
+                    for c in 0..3 {
+                        let linear = |value: u8| math::srgb_to_linear(f32::from(value) / 255.0);
+                        error += (linear(source[c]) - linear(decoded[c])).powi(2);
+                    }
+                }
+                error
+            };
+
+            // fitting in linear light lowers the error in linear light
+            assert!(measure(true) < measure(false));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_mipmap_chain_compression() {
+        let rgba = [0xFF, 0x00, 0x00, 0xFF].repeat(8 * 8);
+        let chain = mipmap::generate(&rgba, 8, 8, usize::MAX, &Default::default());
+        let compressed = mipmap::compress_chain(Format::Bc1, &chain, Params::default());
+        assert_eq!(compressed.offsets, [0, 32, 40, 48]);
+        assert_eq!(compressed.data.len(), 56);
+        for &offset in &compressed.offsets {
+            let block = Format::Bc1.decompress_block(&compressed.data[offset..]);
+            assert_eq!(block[0], [0xFF, 0x00, 0x00, 0xFF]);
+        }
+    }
+
+    #[test]
+    fn test_metrics_identical() {
+        let data = test_data::BC1_COLOUR;
+        let metrics = metrics::compare(
+            Format::Bc1,
+            data.decoded,
+            4,
+            4,
+            data.encoded,
+            COLOUR_WEIGHTS_UNIFORM,
+        )
+        .unwrap();
+        assert_eq!(metrics.rmse, [0.0; 4]);
+        assert_eq!(metrics.psnr, [f32::INFINITY; 4]);
+        assert_eq!(metrics.weighted_psnr, f32::INFINITY);
+        assert_eq!(metrics.ssim, [1.0; 4]);
+        assert_eq!(metrics.weighted_ssim, 1.0);
+    }
+
+    #[test]
+    fn test_metrics_constant_error() {
+        // offset the red channel of an image spanning several windows by two
+        let source: [u8; 4 * 10 * 9] = core::array::from_fn(|i| (i * 7 % 200) as u8);
+        let mut other = source;
+        for pixel in other.chunks_mut(4) {
+            pixel[0] += 2;
+        }
+
+        let metrics =
+            metrics::compare_images(&source, &other, 10, 9, COLOUR_WEIGHTS_UNIFORM).unwrap();
+        assert_eq!(metrics.rmse, [2.0, 0.0, 0.0, 0.0]);
+        assert!((metrics.psnr[0] - 42.11).abs() < 0.01);
+        assert!((metrics.weighted_rmse - 2.0 / 3f32.sqrt()).abs() < 1e-5);
+        assert!(metrics.ssim[0] < 1.0 && metrics.ssim[0] > 0.99);
+        assert_eq!(metrics.ssim[1..], [1.0; 3]);
+
+        // weights that ignore red also ignore its error
+        let metrics = metrics::compare_images(&source, &other, 10, 9, [0.0, 1.0, 1.0]).unwrap();
+        assert_eq!(metrics.weighted_rmse, 0.0);
+        assert_eq!(metrics.weighted_ssim, 1.0);
+    }
+
+    #[test]
+    fn test_rdo() {
+        // smooth gradients with some noise, not a multiple of the block size
+        let (width, height) = (30, 30);
+        let rgba: [u8; 4 * 30 * 30] = core::array::from_fn(|i| {
+            let (x, y) = ((i / 4) % 30, (i / 4) / 30);
+            let noise = ((i * 2_654_435_761) >> 13) % 9;
+            match i % 4 {
+                0 => (5 * x + 3 * y + noise) as u8,
+                1 => (8 * y + noise) as u8,
+                2 => (4 * (x + y) + noise) as u8,
+                _ => (255 - 4 * x - noise) as u8,
+            }
+        });
+        // groups of four bytes that repeat an earlier group at the same position in a block
+        let repeats = |output: &[u8], block_size: usize| {
+            let group = |i: usize| &output[4 * i..4 * i + 4];
+            let stride = block_size / 4;
+            (0..output.len() / 4)
+                .filter(|&i| {
+                    (i % stride..i)
+                        .step_by(stride)
+                        .any(|j| group(j) == group(i))
+                })
+                .count()
+        };
+
+        for format in [Format::Bc1, Format::Bc3, Format::Bc4, Format::Bc5Snorm] {
+            let size = format.compressed_size(width, height);
+            let mut plain = [0u8; 1024];
+            let mut optimised = [0u8; 1024];
+            format.compress(&rgba, width, height, Params::default(), &mut plain);
+            let params = Params {
+                rdo_lambda: 30.0,
+                ..Params::default()
+            };
+            format.compress(&rgba, width, height, params, &mut optimised);
+
+            let block_size = format.block_size();
+            let blocks = size / block_size;
+            let before = repeats(&plain[..size], block_size);
+            let after = repeats(&optimised[..size], block_size);
+            assert!(after > before + blocks / 4, "{:?}", format);
+
+            let weights = COLOUR_WEIGHTS_PERCEPTUAL;
+            let compare = |data: &[u8]| {
+                metrics::compare(format, &rgba, width, height, data, weights)
+                    .unwrap()
+                    .weighted_psnr
+            };
+            let (plain_psnr, optimised_psnr) = (compare(&plain), compare(&optimised));
+            assert!(optimised_psnr > plain_psnr - 4.0, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_vec4_lanes() {
+        // every operation has to match plain floats bit for bit, including the edge cases
+        let values = [
+            0.0,
+            -0.0,
+            0.49,
+            -0.5,
+            1.5,
+            -1.5,
+            63.5,
+            8_388_607.5,
+            8_388_608.0,
+            -16_777_217.0,
+            3.0e9,
+            -1.0e-40,
+            f32::MIN_POSITIVE,
+            f32::MAX,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::NAN,
+        ];
+        let same = |a: f32, b: f32| a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan());
+        let check = |vector: math::Vec4, scalar: [f32; 4]| {
+            let lanes = vector.to_array();
+            for (&a, &b) in lanes.iter().zip(&scalar) {
+                assert!(same(a, b), "{:?} != {:?}", lanes, scalar);
+            }
+        };
+
+        for (n, &a) in values.iter().enumerate() {
+            for &b in &values[n..] {
+                let x = [a, b, -a, -b];
+                let y = [b, a, b, -a];
+                let (vx, vy) = (
+                    math::Vec4::new(x[0], x[1], x[2], x[3]),
+                    math::Vec4::new(y[0], y[1], y[2], y[3]),
+                );
+                let zip = |f: fn(f32, f32) -> f32| core::array::from_fn(|i| f(x[i], y[i]));
+
+                check(vx + vy, zip(|a, b| a + b));
+                check(vx - vy, zip(|a, b| a - b));
+                check(vx * vy, zip(|a, b| a * b));
+                check(vx.reciprocal(), core::array::from_fn(|i| 1.0 / x[i]));
+                check(vx.truncate(), core::array::from_fn(|i| libm::truncf(x[i])));
+                check(vx.splat_z(), [x[2]; 4]);
+                assert_eq!(vx.any_less_than(&vy), (0..4).any(|i| x[i] < y[i]));
+
+                // the sign of a zero picked by min and max is unspecified for floats too
+                if a != 0.0 || b != 0.0 {
+                    check(vx.min(vy), zip(f32::min));
+                    check(vx.max(vy), zip(f32::max));
+                }
+            }
+        }
+    }
+
+    #[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+    #[test]
+    fn test_cluster_fit_avx2() {
+        use colourfit::{ClusterFit, ColourFit};
+        use colourset::ColourSet;
+
+        if math::Avx2::detect().is_none() {
+            return;
+        }
+
+        // blocks of random colours from small palettes, some with transparent pixels
+        let mut state = 1u32;
+        let mut random = move || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            state >> 8
+        };
+        for _ in 0..500 {
+            let palette: [[u8; 4]; 5] = core::array::from_fn(|_| {
+                let value = random();
+                let alpha = if value % 7 == 0 { 0 } else { 255 };
+                [value as u8, (value >> 8) as u8, (value >> 16) as u8, alpha]
+            });
+            let colours = random() % 4 + 2;
+            let rgba: [[u8; 4]; 16] =
+                core::array::from_fn(|_| palette[(random() % colours) as usize]);
+
+            for format in [Format::Bc1, Format::Bc3] {
+                for iterate in [false, true] {
+                    let set = ColourSet::new(&rgba, 0xFFFF, format, false, false);
+                    let mut wide = [0u8; 8];
+                    let mut fit = ClusterFit::new(&set, format, COLOUR_WEIGHTS_PERCEPTUAL, iterate);
+                    fit.compress(&mut wide);
+
+                    let mut single = [0u8; 8];
+                    let mut fit = ClusterFit::new(&set, format, COLOUR_WEIGHTS_PERCEPTUAL, iterate);
+                    fit.disable_avx2();
+                    fit.compress(&mut single);
+
+                    assert_eq!(wide, single);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compressor() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        // four rows of blocks, the last one partial
+        let (width, height) = (16, 14);
+        let rgba: [u8; 4 * 16 * 14] = core::array::from_fn(|i| (i * 37 % 251) as u8);
+        let mut expected = [0u8; 128];
+        Format::Bc1.compress(&rgba, width, height, Params::default(), &mut expected);
+
+        let compressor = Compressor::new(Format::Bc1, Params::default());
+        let mut output = [0u8; 128];
+        let finished = AtomicUsize::new(0);
+        let progress = |rows, total| {
+            assert_eq!(total, 4);
+            finished.fetch_max(rows, Ordering::Relaxed);
+        };
+        compressor
+            .compress(&rgba, width, height, &mut output, progress, || false)
+            .unwrap();
+        assert_eq!(finished.load(Ordering::Relaxed), 4);
+        assert_eq!(output, expected);
+
+        // the second pass of RDO counts the rows again, cancelling stops before the end
+        let params = Params {
+            rdo_lambda: 10.0,
+            ..Params::default()
+        };
+        let compressor = Compressor::new(Format::Bc1, params);
+        let finished = AtomicUsize::new(0);
+        let progress = |_, total| {
+            assert_eq!(total, 8);
+            finished.fetch_add(1, Ordering::Relaxed);
+        };
+        let checks = AtomicUsize::new(0);
+        let cancel = || checks.fetch_add(1, Ordering::Relaxed) >= 6;
+        let result = compressor.compress(&rgba, width, height, &mut output, progress, cancel);
+        assert_eq!(result, Err(Error::Cancelled));
+        assert_eq!(finished.load(Ordering::Relaxed), 6);
+
+        // the buffers are checked before anything is compressed
+        let result = compressor.compress(
+            &rgba,
+            width,
+            height,
+            &mut output[..64],
+            |_, _| panic!("compressed into a short buffer"),
+            || false,
+        );
+        assert_eq!(
+            result,
+            Err(Error::BufferTooSmall {
+                required: 128,
+                actual: 64
+            })
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_compressor_buffer() {
+        let rgba: [u8; 4 * 16 * 14] = core::array::from_fn(|i| (i * 37 % 251) as u8);
+        let mut expected = [0u8; 256];
+        Format::Bc3.compress(&rgba, 16, 14, Params::default(), &mut expected);
+
+        let mut compressor = Compressor::new(Format::Bc3, Params::default());
+        let output = compressor.compress_to_buffer(&rgba, 16, 14, |_, _| {}, || false);
+        assert_eq!(output.unwrap(), &expected[..]);
+
+        // a smaller image reuses the start of the buffer
+        let output = compressor.compress_to_buffer(&rgba, 8, 8, |_, _| {}, || false);
+        assert_eq!(output.unwrap().len(), 64);
+        assert_eq!(
+            compressor.compress_to_buffer(&rgba, 0, 8, |_, _| {}, || false),
+            Err(Error::ZeroDimension)
+        );
+    }
+
+    #[test]
+    fn test_block_errors() {
+        // an 8x4 image with a flat block on the left and a noisy one on the right
+        let rgba: [u8; 4 * 8 * 4] = core::array::from_fn(|i| match (i % 32 < 16, i % 4) {
+            (_, 3) => u8::MAX,
+            (true, _) => 0,
+            (false, _) => (i * 97 % 256) as u8,
+        });
+        let mut output = [0u8; 16];
+        let mut errors = [metrics::BlockError::default(); 2];
+        Format::Bc1.compress_with_errors(&rgba, 8, 4, Params::default(), &mut output, &mut errors);
+        assert_eq!(errors[0].colour, 0.0);
+        assert!(errors[1].colour > 100.0);
+        assert_eq!(errors[0].alpha, 0.0);
+        assert_eq!(errors[1].alpha, 0.0);
+
+        // BC4 only counts the stored channel
+        let mut output = [0u8; 8];
+        let red: [u8; 4 * 4 * 4] = core::array::from_fn(|i| if i % 4 == 0 { 0x80 } else { 0x10 });
+        Format::Bc4.compress_with_errors(&red, 4, 4, Params::default(), &mut output, &mut errors);
+        assert_eq!(errors[0], metrics::BlockError::default());
+        assert_eq!(
+            Format::Bc4.try_compress_with_errors(
+                &red,
+                4,
+                4,
+                Params::default(),
+                &mut output,
+                &mut []
+            ),
+            Err(Error::BufferTooSmall {
+                required: 1,
+                actual: 0
+            })
+        );
+
+        assert_eq!(
+            metrics::block_errors(Format::Bc4, &red, 8, 4, &output, [1.0; 3], &mut errors),
+            Err(Error::InputTooShort {
+                required: 128,
+                actual: 64
+            })
+        );
+        assert_eq!(
+            metrics::block_errors(Format::Bc4, &red, 4, 4, &output, [1.0; 3], &mut []),
+            Err(Error::BufferTooSmall {
+                required: 1,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_block_errors_bc3_encodings() {
+        // flat colours and normals survive the encodings, so their decoded errors are tiny
+        let colour: [u8; 4 * 4 * 4] = core::array::from_fn(|i| [200, 120, 40, 0x80][i % 4]);
+        let normal: [u8; 4 * 4 * 4] = core::array::from_fn(|i| [128, 128, 255, 0x80][i % 4]);
+        let encoded = |bc3_encoding| Params {
+            bc3_encoding,
+            ..Params::default()
+        };
+        let normal_map = Params {
+            normal_map: true,
+            ..Params::default()
+        };
+
+        let mut output = [0u8; 16];
+        let mut errors = [metrics::BlockError::default()];
+        for (rgba, params) in [
+            (colour, encoded(Bc3Encoding::YCoCg)),
+            (normal, encoded(Bc3Encoding::Dxt5nm)),
+            (normal, normal_map),
+        ] {
+            Format::Bc3.compress_with_errors(&rgba, 4, 4, params, &mut output, &mut errors);
+            assert!(errors[0].colour < 1.0, "{:?}", params.bc3_encoding);
+            assert_eq!(errors[0].alpha, 0.0);
+        }
+    }
+
+    /// Builds an 8x8 normal map of bumps with X, Y and Z mapped to RGB
+    fn bumpy_normals() -> [u8; 4 * 8 * 8] {
+        let mut rgba = [0xFF; 4 * 8 * 8];
+        for (i, pixel) in rgba.chunks_mut(4).enumerate() {
+            let x = 0.6 * libm::sinf((i % 8) as f32 * 0.5 + (i / 8) as f32 * 0.2);
+            let y = 0.5 * libm::cosf((i / 8) as f32 * 0.6);
+            let z = libm::sqrtf(1.0 - x * x - y * y);
+            for (value, component) in pixel.iter_mut().zip([x, y, z]) {
+                *value = libm::roundf(127.5 * component + 127.5) as u8;
+            }
+        }
+        rgba
+    }
+
+    #[test]
+    fn test_normal_map_angular_error() {
+        let rgba = bumpy_normals();
+        let normal_params = Params {
+            normal_map: true,
+            ..Default::default()
+        };
+        for format in [Format::Bc5, Format::Bc5Snorm] {
+            let mut plain = [0u8; 64];
+            let mut refined = [0u8; 64];
+            format.compress(&rgba, 8, 8, Params::default(), &mut plain);
+            format.compress(&rgba, 8, 8, normal_params, &mut refined);
+
+            let plain = metrics::compare_normals(format, &rgba, 8, 8, &plain).unwrap();
+            let refined = metrics::compare_normals(format, &rgba, 8, 8, &refined).unwrap();
+            assert!(refined.mean < plain.mean);
+        }
+    }
+
+    #[test]
+    fn test_normal_map_dxt5nm() {
+        let rgba = bumpy_normals();
+        let params = Params {
+            normal_map: true,
+            ..Default::default()
+        };
+        let mut output = [0u8; 64];
+        Format::Bc3.compress(&rgba, 8, 8, params, &mut output);
+
+        // X ends up in alpha and Y in green
+        let mut decoded = [0u8; 4 * 8 * 8];
+        Format::Bc3.decompress(&output, 8, 8, &mut decoded);
+        for (source, decoded) in rgba.chunks(4).zip(decoded.chunks(4)) {
+            assert!(source[0].abs_diff(decoded[3]) <= 10);
+            assert!(source[1].abs_diff(decoded[1]) <= 8);
+        }
+
+        // splitting the components beats storing the whole normal in BC1 colour
+        let error = metrics::compare_normals(Format::Bc3, &rgba, 8, 8, &output).unwrap();
+        let mut bc1 = [0u8; 32];
+        Format::Bc1.compress(&rgba, 8, 8, params, &mut bc1);
+        let bc1_error = metrics::compare_normals(Format::Bc1, &rgba, 8, 8, &bc1).unwrap();
+        assert!(error.mean < bc1_error.mean);
+    }
+
+    #[test]
+    fn test_bc3_encoding_round_trip() {
+        // the transforms alone are close to lossless, for saturated and muted blocks alike
+        for spread in [4, 40, 120] {
+            let rgba: [[u8; 4]; 16] = core::array::from_fn(|i| {
+                let value = |c: usize| 128 + (i * (c + 3) % 7) as i32 * spread / 7 - spread / 2;
+                [value(0) as u8, value(1) as u8, value(2) as u8, 0xFF]
+            });
+            let encoded = Bc3Encoding::YCoCg.encode_block(&rgba, u32::MAX);
+            for (source, encoded) in rgba.iter().zip(encoded) {
+                let decoded = Bc3Encoding::YCoCg.decode_pixel(encoded);
+                for c in 0..4 {
+                    assert!(source[c].abs_diff(decoded[c]) <= 1);
+                }
+            }
+        }
+
+        let rgba = bumpy_normals();
+        let mut block = [[0u8; 4]; 16];
+        for (pixel, source) in block.iter_mut().zip(rgba.chunks(4)) {
+            pixel.copy_from_slice(source);
+        }
+        let encoded = Bc3Encoding::Dxt5nm.encode_block(&block, u32::MAX);
+        for (source, encoded) in block.iter().zip(encoded) {
+            let decoded = Bc3Encoding::Dxt5nm.decode_pixel(encoded);
+            assert_eq!(decoded[..2], source[..2]);
+            assert!(source[2].abs_diff(decoded[2]) <= 1);
+        }
+    }
+
+    #[test]
+    fn test_bc3_ycocg() {
+        // smooth gradients across several hues
+        let rgba: [u8; 4 * 8 * 8] = core::array::from_fn(|i| {
+            let (x, y) = ((i / 4) % 8, (i / 4) / 8);
+            match i % 4 {
+                0 => (96 + 9 * x + 3 * y) as u8,
+                1 => (64 + 5 * y + 2 * x) as u8,
+                2 => (160 - 7 * x + 4 * y) as u8,
+                _ => 0xFF,
+            }
+        });
+        let quality = |bc3_encoding: Bc3Encoding| {
+            let params = Params {
+                bc3_encoding,
+                ..Default::default()
+            };
+            let mut output = [0u8; 64];
+            let mut decoded = [0u8; 4 * 8 * 8];
+            Format::Bc3.compress(&rgba, 8, 8, params, &mut output);
+            Format::Bc3.decompress(&output, 8, 8, &mut decoded);
+            bc3_encoding.decode(&mut decoded);
+            metrics::compare_images(&rgba, &decoded, 8, 8, COLOUR_WEIGHTS_UNIFORM)
+                .unwrap()
+                .weighted_psnr
+        };
+
+        // luma in the alpha block leaves more precision for colour
+        assert!(quality(Bc3Encoding::YCoCg) > quality(Bc3Encoding::Rgba));
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn test_mipmap_normal_map() {
+        use mipmap::MipmapParams;
+
+        let params = MipmapParams {
+            normal_map: true,
+            ..Default::default()
+        };
+        let chain = mipmap::generate(&bumpy_normals(), 8, 8, usize::MAX, &params);
+        for level in &chain[1..] {
+            for pixel in level.data.chunks(4) {
+                let length = pixel[..3]
+                    .iter()
+                    .map(|&value| (f32::from(value) / 127.5 - 1.0).powi(2))
+                    .sum::<f32>();
+                assert!((libm::sqrtf(length) - 1.0).abs() < 0.01);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "dds")]
+    fn test_dds_round_trip() {
+        use dds::{AlphaMode, Dds, TextureKind};
+
+        let mut textures = [
+            Dds::new(Format::Bc3, 37, 29, 6),
+            Dds::new(Format::Bc7, 8, 8, 4),
+            Dds::new(Format::Bc4, 8, 4, 3),
+        ];
+        textures[0].srgb = true;
+        textures[0].alpha_mode = AlphaMode::Premultiplied;
+        textures[1].kind = TextureKind::Cubemap;
+        textures[1].array_layers = 2;
+        textures[2].kind = TextureKind::Texture3D;
+        textures[2].depth = 4;
+
+        for mut texture in textures {
+            let chain: usize = (0..texture.mip_levels)
+                .map(|level| texture.surface_size(level))
+                .sum();
+            let size = chain * texture.array_layers * texture.faces();
+            texture.data = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+
+            let bytes = texture.write().unwrap();
+            assert_eq!(Dds::read(&bytes).unwrap(), texture);
+
+            // surfaces tile the data without gaps
+            let layers = texture.array_layers;
+            let last = texture.surface(layers - 1, texture.faces() - 1, texture.mip_levels - 1);
+            assert!(texture.data.ends_with(last.unwrap()));
+            assert_eq!(texture.surface(layers, 0, 0), None);
+        }
+
+        // the slices of volume texture levels add up
+        let mut volume = Dds::new(Format::Bc1, 8, 8, 4);
+        volume.kind = TextureKind::Texture3D;
+        volume.depth = 4;
+        assert_eq!(volume.surface_size(0), 4 * 32);
+        assert_eq!(volume.surface_size(3), 8);
+    }
+
+    #[test]
+    #[cfg(feature = "dds")]
+    fn test_dds_formats() {
+        use dds::DdsError;
+
+        let formats = [
+            Format::Bc1,
+            Format::Bc2,
+            Format::Bc3,
+            Format::Bc4,
+            Format::Bc4Snorm,
+            Format::Bc5,
+            Format::Bc5Snorm,
+            Format::Bc6hUf16,
+            Format::Bc6hSf16,
+            Format::Bc7,
+        ];
+        for format in formats {
+            for srgb in [false, true] {
+                let code = dds::format_to_dxgi(format, srgb);
+                let srgb = srgb
+                    && matches!(
+                        format,
+                        Format::Bc1 | Format::Bc2 | Format::Bc3 | Format::Bc7
+                    );
+                assert_eq!(dds::format_from_dxgi(code), Ok((format, srgb)));
+            }
+            if let Some(code) = dds::format_to_fourcc(format) {
+                assert_eq!(dds::format_from_fourcc(code), Ok(format));
+            }
+        }
+        assert_eq!(
+            dds::format_from_dxgi(28),
+            Err(DdsError::UnsupportedDxgiFormat(28))
+        );
+        assert_eq!(
+            dds::format_from_fourcc(*b"ETC1"),
+            Err(DdsError::UnsupportedFourCc(*b"ETC1"))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "dds")]
+    fn test_dds_legacy_header() {
+        use dds::{Dds, DdsError, TextureKind};
+
+        // turn a DX10 file into a legacy one by dropping the extension
+        let texture = Dds::new(Format::Bc3, 8, 8, 4);
+        let bytes = texture.write().unwrap();
+        let mut legacy = [&bytes[..128], &bytes[148..]].concat();
+        legacy[84..88].copy_from_slice(b"DXT5");
+        assert_eq!(Dds::read(&legacy), Ok(texture));
+
+        // cubemaps are flagged in the capabilities
+        legacy[112..116].copy_from_slice(&0xFE00u32.to_le_bytes());
+        assert_eq!(
+            Dds::read(&legacy),
+            Err(DdsError::InputTooShort {
+                required: 128 + 6 * 112,
+                actual: 128 + 112
+            })
+        );
+        let cubemap = [&legacy[..], &[0; 5 * 112]].concat();
+        assert_eq!(Dds::read(&cubemap).unwrap().kind, TextureKind::Cubemap);
+        legacy[112..116].copy_from_slice(&0x0600u32.to_le_bytes());
+        assert_eq!(Dds::read(&legacy), Err(DdsError::IncompleteCubemap));
+
+        legacy[84..88].copy_from_slice(b"ETC1");
+        assert_eq!(
+            Dds::read(&legacy),
+            Err(DdsError::UnsupportedFourCc(*b"ETC1"))
+        );
+        legacy[0] = b'X';
+        assert_eq!(Dds::read(&legacy), Err(DdsError::NotDds));
+        assert_eq!(
+            Dds::read(&legacy[..100]),
+            Err(DdsError::InputTooShort {
+                required: 128,
+                actual: 100
+            })
+        );
+
+        // layouts that cannot be described are rejected when writing
+        let mut texture = Dds::new(Format::Bc1, 4, 4, 4);
+        assert_eq!(
+            texture.write(),
+            Err(DdsError::InvalidHeader("invalid number of mipmap levels"))
+        );
+        texture.mip_levels = 1;
+        texture.kind = TextureKind::Cubemap;
+        texture.height = 8;
+        assert_eq!(
+            texture.write(),
+            Err(DdsError::InvalidHeader("cubemap faces are not square"))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ktx")]
+    fn test_ktx_round_trip() {
+        use alloc::string::String;
+        use ktx::{Ktx, TextureKind};
+
+        let mut textures = [
+            Ktx::new(Format::Bc3, 37, 29, 6),
+            Ktx::new(Format::Bc7, 8, 8, 4),
+            Ktx::new(Format::Bc4, 8, 4, 3),
+            Ktx::new(Format::Bc1, 16, 16, 5),
+        ];
+        textures[0].srgb = true;
+        textures[0].key_values = alloc::vec![
+            (String::from("KTXorientation"), b"S=r,T=d\0".to_vec()),
+            (String::from("Author"), b"squish".to_vec()),
+        ];
+        textures[1].kind = TextureKind::Cubemap;
+        textures[2].kind = TextureKind::Texture3D;
+        textures[2].depth = 4;
+        textures[3].kind = TextureKind::Cubemap;
+        textures[3].array_layers = 2;
+
+        for mut texture in textures {
+            let chain: usize = (0..texture.mip_levels)
+                .map(|level| texture.surface_size(level))
+                .sum();
+            let size = chain * texture.array_layers * texture.faces();
+            texture.data = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+
+            let bytes = texture.write().unwrap();
+            assert_eq!(Ktx::read(&bytes).unwrap(), texture);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ktx")]
+    fn test_ktx_file_layout() {
+        use ktx::{Ktx, KtxError, TextureKind};
+
+        let read_u32 = |bytes: &[u8], offset: usize| {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+        };
+
+        let formats = [
+            (Format::Bc1, false, 0x83F1),
+            (Format::Bc1, true, 0x8C4D),
+            (Format::Bc2, true, 0x8C4E),
+            (Format::Bc3, false, 0x83F3),
+            (Format::Bc3, true, 0x8C4F),
+            (Format::Bc4, false, 0x8DBB),
+            (Format::Bc5Snorm, false, 0x8DBE),
+            (Format::Bc6hUf16, false, 0x8E8F),
+            (Format::Bc7, true, 0x8E8D),
+        ];
+        for (format, srgb, code) in formats {
+            assert_eq!(ktx::format_to_gl(format, srgb).0, code);
+            assert_eq!(ktx::format_from_gl(code), Ok((format, srgb)));
+        }
+
+        // the image size of a cubemap covers one face
+        let mut texture = Ktx::new(Format::Bc3, 8, 8, 2);
+        texture.kind = TextureKind::Cubemap;
+        texture.data = (0..6 * (64 + 16)).map(|i| i as u8).collect();
+        let bytes = texture.write().unwrap();
+        assert_eq!(&bytes[12..16], &[1, 2, 3, 4]);
+        assert_eq!(read_u32(&bytes, 16), 0);
+        assert_eq!(read_u32(&bytes, 20), 1);
+        assert_eq!(read_u32(&bytes, 28), 0x83F3);
+        assert_eq!(read_u32(&bytes, 32), 0x1908);
+        assert_eq!(read_u32(&bytes, 52), 6);
+        assert_eq!(read_u32(&bytes, 56), 2);
+        assert_eq!(read_u32(&bytes, 64), 64);
+        assert_eq!(&bytes[68..132], texture.surface(0, 0, 0).unwrap());
+        assert_eq!(read_u32(&bytes, 68 + 6 * 64), 16);
+        assert_eq!(bytes.len(), 64 + 2 * 4 + 6 * (64 + 16));
+
+        // big endian files swap the header and image sizes
+        let mut swapped = bytes.clone();
+        for offset in (12..64).step_by(4).chain([64, 68 + 6 * 64]) {
+            swapped[offset..offset + 4].reverse();
+        }
+        assert_eq!(Ktx::read(&swapped), Ok(texture));
+
+        let mut corrupt = bytes.clone();
+        corrupt[28..32].copy_from_slice(&0x8058u32.to_le_bytes());
+        assert_eq!(
+            Ktx::read(&corrupt),
+            Err(KtxError::UnsupportedGlFormat(0x8058))
+        );
+        corrupt[64] = 32;
+        assert_eq!(
+            Ktx::read(&corrupt[..60]),
+            Err(KtxError::InputTooShort {
+                required: 64,
+                actual: 60
+            })
+        );
+        corrupt[12] = 2;
+        assert_eq!(
+            Ktx::read(&corrupt),
+            Err(KtxError::InvalidHeader("unknown byte order"))
+        );
+        corrupt[5] = b'2';
+        assert_eq!(Ktx::read(&corrupt), Err(KtxError::NotKtx));
+        let mut corrupt = bytes.clone();
+        corrupt[64] = 32;
+        assert_eq!(
+            Ktx::read(&corrupt),
+            Err(KtxError::InvalidHeader("mipmap level has the wrong size"))
+        );
+
+        // key/value entries hold their length in the byte order of the file
+        let mut texture = Ktx::new(Format::Bc4, 4, 4, 1);
+        texture.key_values = alloc::vec![(alloc::string::String::from("a"), b"xyz".to_vec())];
+        let mut bytes = texture.write().unwrap();
+        assert_eq!(read_u32(&bytes, 60), 12);
+        assert_eq!(&bytes[64..76], b"\x05\0\0\0a\0xyz\0\0\0");
+        for offset in (12..64).step_by(4).chain([64, 76]) {
+            bytes[offset..offset + 4].reverse();
+        }
+        assert_eq!(Ktx::read(&bytes), Ok(texture));
+        bytes[64] = 9;
+        assert_eq!(
+            Ktx::read(&bytes),
+            Err(KtxError::InvalidKeyValue(
+                "entry exceeds the key/value data"
+            ))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ktx2")]
+    fn test_ktx2_round_trip() {
+        use alloc::string::String;
+        use ktx2::{Ktx2, Supercompression, TextureKind};
+
+        let mut textures = [
+            Ktx2::new(Format::Bc3, 37, 29, 6),
+            Ktx2::new(Format::Bc7, 8, 8, 4),
+            Ktx2::new(Format::Bc4, 8, 4, 3),
+            Ktx2::new(Format::Bc6hSf16, 16, 16, 5),
+        ];
+        textures[0].srgb = true;
+        textures[0].premultiplied = true;
+        textures[0].key_values = alloc::vec![
+            (String::from("KTXwriter"), b"squish\0".to_vec()),
+            (String::from("KTXorientation"), b"rd\0".to_vec()),
+        ];
+        textures[1].kind = TextureKind::Cubemap;
+        textures[1].array_layers = 2;
+        textures[2].kind = TextureKind::Texture3D;
+        textures[2].depth = 4;
+        textures[3].array_layers = 3;
+
+        for mut texture in textures {
+            let chain: usize = (0..texture.mip_levels)
+                .map(|level| texture.surface_size(level))
+                .sum();
+            let size = chain * texture.array_layers * texture.faces();
+            texture.data = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+
+            let bytes = texture.write().unwrap();
+            let mut read = Ktx2::read(&bytes).unwrap();
+            // keys come back sorted
+            texture.key_values.sort();
+            assert_eq!(read, texture);
+
+            texture.supercompression = Supercompression::Zstd;
+            read.supercompression = Supercompression::Zstd;
+            #[cfg(feature = "zstd")]
+            {
+                let compressed = texture.write().unwrap();
+                assert_eq!(Ktx2::read(&compressed).unwrap(), read);
+            }
+            #[cfg(not(feature = "zstd"))]
+            assert_eq!(
+                texture.write(),
+                Err(ktx2::Ktx2Error::UnsupportedSupercompression(2))
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ktx2")]
+    fn test_ktx2_file_layout() {
+        use alloc::string::String;
+        use ktx2::{Ktx2, Ktx2Error};
+
+        let read_u32 = |bytes: &[u8], offset: usize| {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+        };
+        let read_u64 = |bytes: &[u8], offset: usize| {
+            u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+        };
+
+        let mut texture = Ktx2::new(Format::Bc5Snorm, 16, 8, 3);
+        texture.key_values = alloc::vec![(String::from("KTXwriter"), b"squish\0".to_vec())];
+        for (i, byte) in texture.data.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let bytes = texture.write().unwrap();
+
+        // header
+        assert_eq!(read_u32(&bytes, 12), 142);
+        assert_eq!(read_u32(&bytes, 16), 1);
+        assert_eq!(read_u32(&bytes, 20), 16);
+        assert_eq!(read_u32(&bytes, 24), 8);
+        assert_eq!(read_u32(&bytes, 28), 0);
+        assert_eq!(read_u32(&bytes, 32), 0);
+        assert_eq!(read_u32(&bytes, 36), 1);
+        assert_eq!(read_u32(&bytes, 40), 3);
+        assert_eq!(read_u32(&bytes, 44), 0);
+
+        // the descriptor follows the level index, with two signed 64-bit samples
+        assert_eq!(read_u32(&bytes, 48), 80 + 3 * 24);
+        assert_eq!(read_u32(&bytes, 52), 4 + 24 + 2 * 16);
+        let dfd = 80 + 3 * 24;
+        assert_eq!(read_u32(&bytes, dfd), 60);
+        assert_eq!(read_u32(&bytes, dfd + 12) & 0xFF, 132);
+        assert_eq!(read_u32(&bytes, dfd + 20), 16);
+        assert_eq!(read_u32(&bytes, dfd + 28), (63 << 16) | (0x40 << 24));
+        assert_eq!(read_u32(&bytes, dfd + 44), 64 | (63 << 16) | (0x41 << 24));
+
+        // one key/value entry padded to four bytes
+        assert_eq!(read_u32(&bytes, 56), dfd as u32 + 60);
+        assert_eq!(read_u32(&bytes, 60), 24);
+        assert_eq!(read_u32(&bytes, dfd + 60), 17);
+
+        // levels are stored smallest first and aligned to whole blocks
+        let level = |level: usize| {
+            let entry = 80 + 24 * level;
+            (
+                read_u64(&bytes, entry) as usize,
+                read_u64(&bytes, entry + 8) as usize,
+                read_u64(&bytes, entry + 16) as usize,
+            )
+        };
+        assert_eq!(level(2), (240, 16, 16));
+        assert_eq!(level(1), (256, 32, 32));
+        assert_eq!(level(0), (288, 128, 128));
+        assert_eq!(bytes.len(), 416);
+        assert_eq!(&bytes[288..416], texture.surface(0, 0, 0).unwrap());
+        assert_eq!(&bytes[240..256], texture.surface(0, 0, 2).unwrap());
+
+        let mut corrupt = bytes.clone();
+        corrupt[12] = 0;
+        assert_eq!(Ktx2::read(&corrupt), Err(Ktx2Error::UnsupportedVkFormat(0)));
+        corrupt[0] = 0;
+        assert_eq!(Ktx2::read(&corrupt), Err(Ktx2Error::NotKtx2));
+        assert_eq!(
+            Ktx2::read(&bytes[..415]),
+            Err(Ktx2Error::InputTooShort {
+                required: 416,
+                actual: 415
+            })
+        );
+        let mut corrupt = bytes.clone();
+        corrupt[44] = 1;
+        assert_eq!(
+            Ktx2::read(&corrupt),
+            Err(Ktx2Error::UnsupportedSupercompression(1))
+        );
+
+        texture
+            .key_values
+            .push((String::from("KTXwriter"), alloc::vec::Vec::new()));
+        assert_eq!(
+            texture.write(),
+            Err(Ktx2Error::InvalidKeyValue("duplicate key"))
+        );
+    }
+
+    #[test]
+    fn test_metrics_errors() {
+        let data = test_data::BC3_GRAY;
+        assert_eq!(
+            metrics::compare(
+                Format::Bc3,
+                data.decoded,
+                4,
+                4,
+                &data.encoded[..8],
+                [1.0; 3]
+            ),
+            Err(Error::InputTooShort {
+                required: 16,
+                actual: 8
+            })
+        );
+        assert_eq!(
+            metrics::compare_images(data.decoded, data.decoded, 0, 4, [1.0; 3]),
+            Err(Error::ZeroDimension)
+        );
+    }
+
+    #[test]
+    fn test_try_decompress_block_errors() {
+        let data = test_data::BC7_GRAY.encoded;
+        assert_eq!(
+            Format::Bc7.try_decompress_block(&data[..8]),
+            Err(Error::InputTooShort {
+                required: 16,
+                actual: 8
+            })
+        );
+        assert_eq!(
+            Format::Bc7.try_decompress_block(data),
+            Ok(Format::Bc7.decompress_block(data))
+        );
     }
 
     fn execute_decompression_test(format: Format, data: &test_data::TestDataSet) {
@@ -372,7 +2712,7 @@ mod tests {
             // As no std is available, use a buffer of huge static size
             // and slice it into the dynamic size of the format.
             let mut output_buffer = [0u8; 128];
-            let mut output_actual: &mut [u8] = &mut output_buffer[0..format.block_size()];
+            let output_actual: &mut [u8] = &mut output_buffer[0..format.block_size()];
             format.compress(
                 data.decoded,
                 4,
@@ -381,8 +2721,12 @@ mod tests {
                     algorithm,
                     weights: COLOUR_WEIGHTS_UNIFORM,
                     weigh_colour_by_alpha: false,
+                    srgb: false,
+                    normal_map: false,
+                    bc3_encoding: Bc3Encoding::Rgba,
+                    rdo_lambda: 0.0,
                 },
-                &mut output_actual,
+                output_actual,
             );
             assert_eq!(output_actual, data.encoded);
         };
@@ -393,6 +2737,149 @@ mod tests {
         test(Algorithm::IterativeClusterFit);
     }
 
+    fn execute_lossy_compression_test(
+        format: Format,
+        data: &test_data::TestDataSet,
+        tolerance: u8,
+    ) {
+        let test = |algorithm: Algorithm| {
+            let mut output_buffer = [0u8; 128];
+            let output_actual: &mut [u8] = &mut output_buffer[0..format.block_size()];
+            format.compress(
+                data.decoded,
+                4,
+                4,
+                Params {
+                    algorithm,
+                    weights: COLOUR_WEIGHTS_UNIFORM,
+                    weigh_colour_by_alpha: false,
+                    srgb: false,
+                    normal_map: false,
+                    bc3_encoding: Bc3Encoding::Rgba,
+                    rdo_lambda: 0.0,
+                },
+                output_actual,
+            );
+
+            let mut decoded_actual = [0u8; 4 * 4 * 4];
+            format.decompress(output_actual, 4, 4, &mut decoded_actual);
+            for (actual, expected) in decoded_actual.iter().zip(data.decoded) {
+                assert!(actual.abs_diff(*expected) <= tolerance);
+            }
+        };
+
+        // all algorithms should stay within the given error bound
+        test(Algorithm::ClusterFit);
+        test(Algorithm::RangeFit);
+        test(Algorithm::IterativeClusterFit);
+    }
+
+    fn execute_hdr_decompression_test(format: Format, data: &test_data::HdrTestDataSet) {
+        let mut output_actual = [0u16; 4 * 4 * 3];
+        format.decompress_f16(data.encoded, 4, 4, &mut output_actual);
+        assert_eq!(output_actual, data.decoded);
+    }
+
+    fn execute_hdr_compression_test(
+        format: Format,
+        data: &test_data::HdrTestDataSet,
+        tolerance: f32,
+    ) {
+        let test = |algorithm: Algorithm| {
+            let source = data.decoded.iter().map(|&h| math::f16_to_f32(h));
+            let mut source_buffer = [0f32; 4 * 4 * 3];
+            for (s, value) in source_buffer.iter_mut().zip(source) {
+                *s = value;
+            }
+
+            let mut output_buffer = [0u8; 128];
+            let output_actual: &mut [u8] = &mut output_buffer[0..format.block_size()];
+            format.compress_f32(
+                &source_buffer,
+                4,
+                4,
+                Params {
+                    algorithm,
+                    weights: COLOUR_WEIGHTS_UNIFORM,
+                    weigh_colour_by_alpha: false,
+                    srgb: false,
+                    normal_map: false,
+                    bc3_encoding: Bc3Encoding::Rgba,
+                    rdo_lambda: 0.0,
+                },
+                output_actual,
+            );
+
+            // the error should be small relative to the magnitude of each value
+            let mut decoded_actual = [0f32; 4 * 4 * 3];
+            format.decompress_f32(output_actual, 4, 4, &mut decoded_actual);
+            for (actual, expected) in decoded_actual.iter().zip(&source_buffer) {
+                assert!((actual - expected).abs() <= tolerance * expected.abs());
+            }
+        };
+
+        // all algorithms should stay within the given error bound
+        test(Algorithm::ClusterFit);
+        test(Algorithm::RangeFit);
+        test(Algorithm::IterativeClusterFit);
+    }
+
+    #[test]
+    fn test_half_float_conversion() {
+        assert_eq!(math::f32_to_f16(0.0), 0x0000);
+        assert_eq!(math::f32_to_f16(-0.0), 0x8000);
+        assert_eq!(math::f32_to_f16(1.0), 0x3C00);
+        assert_eq!(math::f32_to_f16(-2.5), 0xC100);
+        assert_eq!(math::f32_to_f16(65504.0), 0x7BFF);
+        assert_eq!(math::f32_to_f16(65520.0), 0x7C00);
+        assert_eq!(math::f32_to_f16(f32::NEG_INFINITY), 0xFC00);
+        assert_eq!(math::f32_to_f16(1.0 + 1.5 / 1024.0), 0x3C02);
+        assert_eq!(math::f32_to_f16(1.0 + 0.5 / 1024.0), 0x3C00);
+        assert_eq!(math::f32_to_f16(5.9604645e-8), 0x0001);
+        assert!(math::f16_to_f32(math::f32_to_f16(f32::NAN)).is_nan());
+
+        // every finite half float survives a round trip through f32
+        for half in (0..=0xFFFFu16).filter(|h| (h & 0x7C00) != 0x7C00) {
+            assert_eq!(math::f32_to_f16(math::f16_to_f32(half)), half);
+        }
+    }
+
+    #[test]
+    fn test_decompression_partial_block_rows() {
+        // a 4x6 image whose second row of blocks is only partially covered
+        let mut data = [0u8; 16];
+        data[..8].copy_from_slice(test_data::BC1_COLOUR.encoded);
+        data[8..].copy_from_slice(test_data::BC1_GRAY.encoded);
+
+        let mut output_actual = [0u8; 4 * 6 * 4];
+        Format::Bc1.decompress(&data, 4, 6, &mut output_actual);
+        assert_eq!(output_actual[..64], test_data::BC1_COLOUR.decoded[..]);
+        assert_eq!(output_actual[64..], test_data::BC1_GRAY.decoded[..32]);
+    }
+
+    #[test]
+    fn test_float_compression_ldr_format() {
+        let data = &test_data::BC1_COLOUR;
+        let mut source = [0f32; 4 * 4 * 3];
+        for (i, s) in source.iter_mut().enumerate() {
+            *s = f32::from(data.decoded[4 * (i / 3) + i % 3]) / 255.0;
+        }
+
+        let mut output_actual = [0u8; 8];
+        let params = Params {
+            weights: COLOUR_WEIGHTS_UNIFORM,
+            ..Params::default()
+        };
+        Format::Bc1.compress_f32(&source, 4, 4, params, &mut output_actual);
+        assert_eq!(output_actual, data.encoded);
+
+        let mut decoded_actual = [0f32; 4 * 4 * 3];
+        Format::Bc1.decompress_f32(data.encoded, 4, 4, &mut decoded_actual);
+        for (actual, expected) in decoded_actual.iter().zip(&source) {
+            assert!((actual - expected).abs() < 1e-3);
+        }
+    }
+
     #[test]
     fn test_bc1_decompression_gray() {
         execute_decompression_test(Format::Bc1, &test_data::BC1_GRAY);
@@ -472,4 +2959,169 @@ mod tests {
     fn test_bc5_compression_gray() {
         execute_compression_test(Format::Bc5, &test_data::BC5_GRAY);
     }
+
+    #[test]
+    fn test_bc4_snorm_decompression_gray() {
+        execute_decompression_test(Format::Bc4Snorm, &test_data::BC4_SNORM_GRAY);
+    }
+
+    #[test]
+    fn test_bc4_snorm_compression_gray() {
+        execute_compression_test(Format::Bc4Snorm, &test_data::BC4_SNORM_GRAY);
+    }
+
+    #[test]
+    fn test_bc5_snorm_decompression_gray() {
+        execute_decompression_test(Format::Bc5Snorm, &test_data::BC5_SNORM_GRAY);
+    }
+
+    #[test]
+    fn test_bc5_snorm_compression_gray() {
+        execute_compression_test(Format::Bc5Snorm, &test_data::BC5_SNORM_GRAY);
+    }
+
+    #[test]
+    fn test_bc5_snorm_signed_values() {
+        // ramps crossing zero survive with little error
+        let mut input = [0i8; 4 * 4 * 4];
+        for (i, pixel) in input.chunks_mut(4).enumerate() {
+            pixel[0] = (i as i32 * 5 - 40) as i8;
+            pixel[1] = (20 - i as i32 * 3) as i8;
+        }
+        let mut block = [0u8; 16];
+        Format::Bc5Snorm.compress_i8(&input, 4, 4, Params::default(), &mut block);
+
+        let mut output = [0i8; 4 * 4 * 4];
+        Format::Bc5Snorm.decompress_i8(&block, 4, 4, &mut output);
+        for (expected, actual) in input.chunks(4).zip(output.chunks(4)) {
+            for channel in 0..2 {
+                let error = i32::from(expected[channel]) - i32::from(actual[channel]);
+                assert!(error.abs() <= 6);
+            }
+            assert_eq!(&actual[2..], &[0, 127]);
+        }
+    }
+
+    #[test]
+    fn test_bc6h_uf16_decompression_ramp() {
+        execute_hdr_decompression_test(Format::Bc6hUf16, &test_data::BC6H_UF16_RAMP);
+    }
+
+    #[test]
+    fn test_bc6h_uf16_compression_ramp() {
+        execute_hdr_compression_test(Format::Bc6hUf16, &test_data::BC6H_UF16_RAMP, 0.05);
+    }
+
+    #[test]
+    fn test_bc6h_sf16_decompression_ramp() {
+        execute_hdr_decompression_test(Format::Bc6hSf16, &test_data::BC6H_SF16_RAMP);
+    }
+
+    #[test]
+    fn test_bc6h_sf16_compression_ramp() {
+        execute_hdr_compression_test(Format::Bc6hSf16, &test_data::BC6H_SF16_RAMP, 0.05);
+    }
+
+    #[test]
+    fn test_bc6h_decompression_reserved_mode() {
+        let mut block = [0xFFu8; 16];
+        block[0] = 0b10011;
+        assert_eq!(
+            Format::Bc6hUf16.decompress_block_f16(&block),
+            [[0u16; 3]; 16]
+        );
+        assert_eq!(
+            Format::Bc6hSf16.decompress_block_f16(&block),
+            [[0u16; 3]; 16]
+        );
+    }
+
+    #[test]
+    fn test_bc6h_compression_perceptual_weights() {
+        // a gradient whose channels change at different rates
+        let mut rgb = [0f32; 3 * 16];
+        for (i, pixel) in rgb.chunks_mut(3).enumerate() {
+            let t = i as f32 / 15.0;
+            pixel.copy_from_slice(&[0.1 + 3.9 * t, 2.0 - 1.5 * t, 0.3 + 0.4 * t]);
+        }
+
+        for format in [Format::Bc6hUf16, Format::Bc6hSf16] {
+            for algorithm in [
+                Algorithm::RangeFit,
+                Algorithm::ClusterFit,
+                Algorithm::IterativeClusterFit,
+            ] {
+                let params = Params {
+                    algorithm,
+                    ..Params::default()
+                };
+                let mut block = [0u8; 16];
+                format.compress_f32(&rgb, 4, 4, params, &mut block);
+                let mut decoded = [0f32; 3 * 16];
+                format.decompress_f32(&block, 4, 4, &mut decoded);
+                for (&actual, &expected) in decoded.iter().zip(&rgb) {
+                    assert!(
+                        (actual - expected).abs() <= 0.2 * expected,
+                        "{:?} {:?}",
+                        format,
+                        algorithm
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_bc7_decompression_gray() {
+        execute_decompression_test(Format::Bc7, &test_data::BC7_GRAY);
+    }
+
+    #[test]
+    fn test_bc7_compression_gray() {
+        execute_lossy_compression_test(Format::Bc7, &test_data::BC7_GRAY, 1);
+    }
+
+    #[test]
+    fn test_bc7_decompression_colour() {
+        execute_decompression_test(Format::Bc7, &test_data::BC7_COLOUR);
+    }
+
+    #[test]
+    fn test_bc7_compression_colour() {
+        execute_lossy_compression_test(Format::Bc7, &test_data::BC7_COLOUR, 8);
+    }
+
+    #[test]
+    fn test_bc7_compression_perceptual_weights() {
+        // an opaque gradient whose channels change at different rates
+        let mut rgba = [0u8; 4 * 16];
+        for (i, pixel) in rgba.chunks_mut(4).enumerate() {
+            let t = i as f32 / 15.0;
+            let ramp = |from: f32, to: f32| (from + (to - from) * t).round() as u8;
+            pixel.copy_from_slice(&[ramp(20.0, 230.0), ramp(200.0, 60.0), ramp(40.0, 90.0), 255]);
+        }
+
+        for algorithm in [
+            Algorithm::RangeFit,
+            Algorithm::ClusterFit,
+            Algorithm::IterativeClusterFit,
+        ] {
+            let params = Params {
+                algorithm,
+                ..Params::default()
+            };
+            let mut block = [0u8; 16];
+            Format::Bc7.compress(&rgba, 4, 4, params, &mut block);
+            let mut decoded = [0u8; 4 * 16];
+            Format::Bc7.decompress(&block, 4, 4, &mut decoded);
+            for (&actual, &expected) in decoded.iter().zip(&rgba) {
+                assert!(actual.abs_diff(expected) <= 4, "{:?}", algorithm);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bc7_decompression_reserved_mode() {
+        assert_eq!(Format::Bc7.decompress_block(&[0u8; 16]), [[0u8; 4]; 16]);
+    }
 }
@@ -100,3 +100,104 @@ impl Sym3x3 {
 pub fn f32_to_i32_clamped(a: f32, limit: i32) -> i32 {
     libm::roundf(a).max(0.0).min(limit as f32) as i32
 }
+
+/// Converts a float to the bit pattern of the nearest half float
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32;
+    let mantissa = bits & 0x7F_FFFF;
+
+    // infinity and NaN, keeping NaNs quiet
+    if exponent == 0xFF {
+        return sign | 0x7C00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1F {
+        return sign | 0x7C00;
+    }
+
+    // round to nearest even, a carry out of the mantissa correctly bumps the exponent
+    let round = |value: u32, shift: u32| {
+        let truncated = value >> shift;
+        let remainder = value & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        if remainder > half || (remainder == half && (truncated & 1) != 0) {
+            truncated + 1
+        } else {
+            truncated
+        }
+    };
+
+    if exponent <= 0 {
+        // denormal or zero
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        sign | round(mantissa, (14 - exponent) as u32) as u16
+    } else {
+        sign | round(((exponent as u32) << 23) | mantissa, 13) as u16
+    }
+}
+
+/// Converts the bit pattern of a half float to a float
+pub fn f16_to_f32(half: u16) -> f32 {
+    let sign = u32::from(half & 0x8000) << 16;
+    let exponent = u32::from((half >> 10) & 0x1F);
+    let mantissa = u32::from(half & 0x3FF);
+
+    match exponent {
+        0 => {
+            let value = mantissa as f32 / (1 << 24) as f32;
+            if sign != 0 {
+                -value
+            } else {
+                value
+            }
+        }
+        0x1F => f32::from_bits(sign | 0x7F80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13)),
+    }
+}
+
+/// Converts an 8-bit unsigned normalised value to a half float
+pub fn unorm8_to_f16(value: u8) -> u16 {
+    f32_to_f16(f32::from(value) / 255.0)
+}
+
+/// Converts a half float to an 8-bit unsigned normalised value, clamping it to [0, 1]
+pub fn f16_to_unorm8(half: u16) -> u8 {
+    f32_to_i32_clamped(f16_to_f32(half) * 255.0, 255) as u8
+}
+
+/// Converts an sRGB encoded value in [0, 1] to linear light
+#[cfg(feature = "alloc")]
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        libm::powf((value + 0.055) / 1.055, 2.4)
+    }
+}
+
+/// Converts a linear light value in [0, 1] to sRGB encoding
+#[cfg(feature = "alloc")]
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * libm::powf(value, 1.0 / 2.4) - 0.055
+    }
+}
+
+/// Returns the derivative of `srgb_to_linear`, i.e. how much a small change of an sRGB
+/// encoded value changes the linear light value
+pub fn srgb_to_linear_slope(value: f32) -> f32 {
+    if value <= 0.04045 {
+        1.0 / 12.92
+    } else {
+        2.4 / 1.055 * libm::powf((value + 0.055) / 1.055, 1.4)
+    }
+}
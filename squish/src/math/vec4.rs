@@ -24,88 +24,128 @@ use core::ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign};
 
 use super::Vec3;
 
-#[derive(Copy, Clone, PartialEq)]
+// the lanes are implemented with SSE2 on x86, NEON on AArch64 and plain floats elsewhere,
+// all of which give bit-identical results
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "sse2"
+))]
+mod sse2;
+#[cfg(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "sse2"
+))]
+use self::sse2 as lanes;
+
+#[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+mod neon;
+#[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+use self::neon as lanes;
+
+#[cfg(not(any(
+    all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse2"
+    ),
+    all(target_arch = "aarch64", target_feature = "neon")
+)))]
+mod scalar;
+#[cfg(not(any(
+    all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse2"
+    ),
+    all(target_arch = "aarch64", target_feature = "neon")
+)))]
+use self::scalar as lanes;
+
+// two vectors at once with AVX2, which is detected at runtime
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+mod avx2;
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2"))]
+pub use self::avx2::{Avx2, Vec4x2};
+
+#[derive(Copy, Clone)]
 pub struct Vec4 {
-    x: f32,
-    y: f32,
-    z: f32,
-    w: f32,
+    v: lanes::Lanes,
 }
 
 impl Vec4 {
     pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
-        Self { x, y, z, w }
+        Self {
+            v: lanes::new(x, y, z, w),
+        }
+    }
+
+    fn from_lanes(v: lanes::Lanes) -> Self {
+        Self { v }
+    }
+
+    pub fn to_array(self) -> [f32; 4] {
+        lanes::to_array(self.v)
     }
 
     pub fn x(&self) -> f32 {
-        self.x
+        self.to_array()[0]
     }
 
     pub fn y(&self) -> f32 {
-        self.y
+        self.to_array()[1]
     }
 
     pub fn z(&self) -> f32 {
-        self.z
+        self.to_array()[2]
     }
 
     pub fn w(&self) -> f32 {
-        self.w
+        self.to_array()[3]
     }
 
-    pub fn to_vec3(&self) -> Vec3 {
-        Vec3::new(self.x, self.y, self.z)
+    pub fn to_vec3(self) -> Vec3 {
+        let [x, y, z, _] = self.to_array();
+        Vec3::new(x, y, z)
     }
 
     pub fn splat_x(&self) -> Vec4 {
-        Vec4::new(self.x, self.x, self.x, self.x)
+        Vec4::from_lanes(lanes::splat_x(self.v))
     }
 
     pub fn splat_y(&self) -> Vec4 {
-        Vec4::new(self.y, self.y, self.y, self.y)
+        Vec4::from_lanes(lanes::splat_y(self.v))
     }
 
     pub fn splat_z(&self) -> Vec4 {
-        Vec4::new(self.z, self.z, self.z, self.z)
+        Vec4::from_lanes(lanes::splat_z(self.v))
     }
 
     pub fn splat_w(&self) -> Vec4 {
-        Vec4::new(self.w, self.w, self.w, self.w)
+        Vec4::from_lanes(lanes::splat_w(self.v))
     }
 
     pub fn max(&self, other: Vec4) -> Vec4 {
-        Vec4::new(
-            self.x.max(other.x),
-            self.y.max(other.y),
-            self.z.max(other.z),
-            self.w.max(other.w),
-        )
+        Vec4::from_lanes(lanes::max(self.v, other.v))
     }
 
     pub fn min(&self, other: Vec4) -> Vec4 {
-        Vec4::new(
-            self.x.min(other.x),
-            self.y.min(other.y),
-            self.z.min(other.z),
-            self.w.min(other.w),
-        )
+        Vec4::from_lanes(lanes::min(self.v, other.v))
     }
 
     pub fn reciprocal(&self) -> Vec4 {
-        Vec4::new(1.0 / self.x, 1.0 / self.y, 1.0 / self.z, 1.0 / self.w)
+        Vec4::from_lanes(lanes::div(lanes::new(1.0, 1.0, 1.0, 1.0), self.v))
     }
 
     pub fn any_less_than(&self, other: &Vec4) -> bool {
-        self.x < other.x || self.y < other.y || self.z < other.z || self.w < other.w
+        lanes::any_less_than(self.v, other.v)
     }
 
     pub fn truncate(&self) -> Vec4 {
-        Vec4::new(
-            libm::truncf(self.x),
-            libm::truncf(self.y),
-            libm::truncf(self.z),
-            libm::truncf(self.w),
-        )
+        Vec4::from_lanes(lanes::truncate(self.v))
+    }
+}
+
+impl PartialEq for Vec4 {
+    fn eq(&self, other: &Vec4) -> bool {
+        self.to_array() == other.to_array()
     }
 }
 
@@ -113,12 +153,7 @@ impl Add for Vec4 {
     type Output = Vec4;
 
     fn add(self, other: Vec4) -> Vec4 {
-        Vec4::new(
-            self.x + other.x,
-            self.y + other.y,
-            self.z + other.z,
-            self.w + other.w,
-        )
+        Vec4::from_lanes(lanes::add(self.v, other.v))
     }
 }
 
@@ -126,25 +161,15 @@ impl<'a> Add for &'a Vec4 {
     type Output = Vec4;
 
     fn add(self, other: &'a Vec4) -> Vec4 {
-        Vec4::new(
-            self.x + other.x,
-            self.y + other.y,
-            self.z + other.z,
-            self.w + other.w,
-        )
+        *self + *other
     }
 }
 
-impl<'a> Add<Vec4> for &'a Vec4 {
+impl Add<Vec4> for &Vec4 {
     type Output = Vec4;
 
     fn add(self, other: Vec4) -> Vec4 {
-        Vec4::new(
-            self.x + other.x,
-            self.y + other.y,
-            self.z + other.z,
-            self.w + other.w,
-        )
+        *self + other
     }
 }
 
@@ -152,12 +177,7 @@ impl<'a> Add<&'a Vec4> for Vec4 {
     type Output = Vec4;
 
     fn add(self, other: &'a Vec4) -> Vec4 {
-        Vec4::new(
-            self.x + other.x,
-            self.y + other.y,
-            self.z + other.z,
-            self.w + other.w,
-        )
+        self + *other
     }
 }
 
@@ -165,52 +185,33 @@ impl Add<f32> for Vec4 {
     type Output = Vec4;
 
     fn add(self, other: f32) -> Vec4 {
-        Vec4::new(
-            self.x + other,
-            self.y + other,
-            self.z + other,
-            self.w + other,
-        )
+        self + Vec4::new(other, other, other, other)
     }
 }
 
-impl<'a> Add<f32> for &'a Vec4 {
+impl Add<f32> for &Vec4 {
     type Output = Vec4;
 
     fn add(self, other: f32) -> Vec4 {
-        Vec4::new(
-            self.x + other,
-            self.y + other,
-            self.z + other,
-            self.w + other,
-        )
+        *self + other
     }
 }
 
 impl AddAssign<Vec4> for Vec4 {
     fn add_assign(&mut self, other: Vec4) {
-        self.x += other.x;
-        self.y += other.y;
-        self.z += other.z;
-        self.w += other.w;
+        *self = *self + other;
     }
 }
 
 impl<'a> AddAssign<&'a Vec4> for Vec4 {
     fn add_assign(&mut self, other: &'a Vec4) {
-        self.x += other.x;
-        self.y += other.y;
-        self.z += other.z;
-        self.w += other.w;
+        *self = *self + *other;
     }
 }
 
 impl AddAssign<f32> for Vec4 {
     fn add_assign(&mut self, other: f32) {
-        self.x += other;
-        self.y += other;
-        self.z += other;
-        self.w += other;
+        *self = *self + other;
     }
 }
 
@@ -218,12 +219,7 @@ impl Sub for Vec4 {
     type Output = Vec4;
 
     fn sub(self, other: Vec4) -> Vec4 {
-        Vec4::new(
-            self.x - other.x,
-            self.y - other.y,
-            self.z - other.z,
-            self.w - other.w,
-        )
+        Vec4::from_lanes(lanes::sub(self.v, other.v))
     }
 }
 
@@ -231,25 +227,15 @@ impl<'a> Sub for &'a Vec4 {
     type Output = Vec4;
 
     fn sub(self, other: &'a Vec4) -> Vec4 {
-        Vec4::new(
-            self.x - other.x,
-            self.y - other.y,
-            self.z - other.z,
-            self.w - other.w,
-        )
+        *self - *other
     }
 }
 
-impl<'a> Sub<Vec4> for &'a Vec4 {
+impl Sub<Vec4> for &Vec4 {
     type Output = Vec4;
 
     fn sub(self, other: Vec4) -> Vec4 {
-        Vec4::new(
-            self.x - other.x,
-            self.y - other.y,
-            self.z - other.z,
-            self.w - other.w,
-        )
+        *self - other
     }
 }
 
@@ -257,12 +243,7 @@ impl<'a> Sub<&'a Vec4> for Vec4 {
     type Output = Vec4;
 
     fn sub(self, other: &'a Vec4) -> Vec4 {
-        Vec4::new(
-            self.x - other.x,
-            self.y - other.y,
-            self.z - other.z,
-            self.w - other.w,
-        )
+        self - *other
     }
 }
 
@@ -270,52 +251,33 @@ impl Sub<f32> for Vec4 {
     type Output = Vec4;
 
     fn sub(self, other: f32) -> Vec4 {
-        Vec4::new(
-            self.x - other,
-            self.y - other,
-            self.z - other,
-            self.w - other,
-        )
+        self - Vec4::new(other, other, other, other)
     }
 }
 
-impl<'a> Sub<f32> for &'a Vec4 {
+impl Sub<f32> for &Vec4 {
     type Output = Vec4;
 
     fn sub(self, other: f32) -> Vec4 {
-        Vec4::new(
-            self.x - other,
-            self.y - other,
-            self.z - other,
-            self.w - other,
-        )
+        *self - other
     }
 }
 
 impl SubAssign<Vec4> for Vec4 {
     fn sub_assign(&mut self, other: Vec4) {
-        self.x -= other.x;
-        self.y -= other.y;
-        self.z -= other.z;
-        self.w -= other.w;
+        *self = *self - other;
     }
 }
 
 impl<'a> SubAssign<&'a Vec4> for Vec4 {
     fn sub_assign(&mut self, other: &'a Vec4) {
-        self.x -= other.x;
-        self.y -= other.y;
-        self.z -= other.z;
-        self.w -= other.w;
+        *self = *self - *other;
     }
 }
 
 impl SubAssign<f32> for Vec4 {
     fn sub_assign(&mut self, other: f32) {
-        self.x -= other;
-        self.y -= other;
-        self.z -= other;
-        self.w -= other;
+        *self = *self - other;
     }
 }
 
@@ -323,12 +285,7 @@ impl Mul for Vec4 {
     type Output = Vec4;
 
     fn mul(self, other: Vec4) -> Vec4 {
-        Vec4::new(
-            self.x * other.x,
-            self.y * other.y,
-            self.z * other.z,
-            self.w * other.w,
-        )
+        Vec4::from_lanes(lanes::mul(self.v, other.v))
     }
 }
 
@@ -336,25 +293,15 @@ impl<'a> Mul for &'a Vec4 {
     type Output = Vec4;
 
     fn mul(self, other: &'a Vec4) -> Vec4 {
-        Vec4::new(
-            self.x * other.x,
-            self.y * other.y,
-            self.z * other.z,
-            self.w * other.w,
-        )
+        *self * *other
     }
 }
 
-impl<'a> Mul<Vec4> for &'a Vec4 {
+impl Mul<Vec4> for &Vec4 {
     type Output = Vec4;
 
     fn mul(self, other: Vec4) -> Vec4 {
-        Vec4::new(
-            self.x * other.x,
-            self.y * other.y,
-            self.z * other.z,
-            self.w * other.w,
-        )
+        *self * other
     }
 }
 
@@ -362,12 +309,7 @@ impl<'a> Mul<&'a Vec4> for Vec4 {
     type Output = Vec4;
 
     fn mul(self, other: &'a Vec4) -> Vec4 {
-        Vec4::new(
-            self.x * other.x,
-            self.y * other.y,
-            self.z * other.z,
-            self.w * other.w,
-        )
+        self * *other
     }
 }
 
@@ -375,51 +317,32 @@ impl Mul<f32> for Vec4 {
     type Output = Vec4;
 
     fn mul(self, other: f32) -> Vec4 {
-        Vec4::new(
-            self.x * other,
-            self.y * other,
-            self.z * other,
-            self.w * other,
-        )
+        self * Vec4::new(other, other, other, other)
     }
 }
 
-impl<'a> Mul<f32> for &'a Vec4 {
+impl Mul<f32> for &Vec4 {
     type Output = Vec4;
 
     fn mul(self, other: f32) -> Vec4 {
-        Vec4::new(
-            self.x * other,
-            self.y * other,
-            self.z * other,
-            self.w * other,
-        )
+        *self * other
     }
 }
 
 impl MulAssign<Vec4> for Vec4 {
     fn mul_assign(&mut self, other: Vec4) {
-        self.x *= other.x;
-        self.y *= other.y;
-        self.z *= other.z;
-        self.w *= other.w;
+        *self = *self * other;
     }
 }
 
 impl<'a> MulAssign<&'a Vec4> for Vec4 {
     fn mul_assign(&mut self, other: &'a Vec4) {
-        self.x *= other.x;
-        self.y *= other.y;
-        self.z *= other.z;
-        self.w *= other.w;
+        *self = *self * *other;
     }
 }
 
 impl MulAssign<f32> for Vec4 {
     fn mul_assign(&mut self, other: f32) {
-        self.x *= other;
-        self.y *= other;
-        self.z *= other;
-        self.w *= other;
+        *self = *self * other;
     }
 }
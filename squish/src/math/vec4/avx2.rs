@@ -0,0 +1,196 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! AVX2 pair of `Vec4`s for evaluating two candidates at once
+//!
+//! The intrinsics are only unsafe to call without the target feature. A `Vec4x2` can only
+//! be created with an `Avx2` token, which `Avx2::detect` returns if the CPU supports AVX2,
+//! so every `Vec4x2` that exists may use them.
+
+use core::arch::x86_64::*;
+use core::ops::{Add, Mul, Sub};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use super::Vec4;
+
+/// Proof that the CPU and the operating system support AVX2
+#[derive(Copy, Clone)]
+pub struct Avx2 {
+    _private: (),
+}
+
+impl Avx2 {
+    /// Returns a token if the CPU and the operating system support AVX2
+    pub fn detect() -> Option<Avx2> {
+        let supported = cfg!(target_feature = "avx2") || {
+            // 0 until the first check, then 1 if unsupported and 2 if supported
+            static SUPPORTED: AtomicU8 = AtomicU8::new(0);
+            match SUPPORTED.load(Ordering::Relaxed) {
+                0 => {
+                    let supported = detect();
+                    SUPPORTED.store(1 + supported as u8, Ordering::Relaxed);
+                    supported
+                }
+                value => value == 2,
+            }
+        };
+        supported.then_some(Avx2 { _private: () })
+    }
+}
+
+/// Two `Vec4`s in the low and high half of an AVX register, with every operation giving
+/// the same result as on each `Vec4` on its own
+#[derive(Copy, Clone)]
+pub struct Vec4x2 {
+    v: __m256,
+}
+
+impl Vec4x2 {
+    #[inline(always)]
+    pub fn new(_avx2: Avx2, lo: Vec4, hi: Vec4) -> Self {
+        Self {
+            v: unsafe { _mm256_set_m128(hi.v, lo.v) },
+        }
+    }
+
+    #[inline(always)]
+    pub fn splat(avx2: Avx2, v: Vec4) -> Self {
+        Self::new(avx2, v, v)
+    }
+
+    #[inline(always)]
+    pub fn lo(self) -> Vec4 {
+        Vec4::from_lanes(unsafe { _mm256_castps256_ps128(self.v) })
+    }
+
+    #[inline(always)]
+    pub fn hi(self) -> Vec4 {
+        Vec4::from_lanes(unsafe { _mm256_extractf128_ps::<1>(self.v) })
+    }
+
+    #[inline(always)]
+    pub fn splat_x(self) -> Self {
+        Self {
+            v: unsafe { _mm256_permute_ps::<0x00>(self.v) },
+        }
+    }
+
+    #[inline(always)]
+    pub fn splat_y(self) -> Self {
+        Self {
+            v: unsafe { _mm256_permute_ps::<0x55>(self.v) },
+        }
+    }
+
+    #[inline(always)]
+    pub fn splat_z(self) -> Self {
+        Self {
+            v: unsafe { _mm256_permute_ps::<0xAA>(self.v) },
+        }
+    }
+
+    #[inline(always)]
+    pub fn splat_w(self) -> Self {
+        Self {
+            v: unsafe { _mm256_permute_ps::<0xFF>(self.v) },
+        }
+    }
+
+    #[inline(always)]
+    pub fn max(self, other: Self) -> Self {
+        // like the SSE2 version, return the lane that is a number
+        unsafe {
+            let nan = _mm256_cmp_ps::<_CMP_UNORD_Q>(other.v, other.v);
+            Self {
+                v: _mm256_blendv_ps(_mm256_max_ps(self.v, other.v), self.v, nan),
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn min(self, other: Self) -> Self {
+        unsafe {
+            let nan = _mm256_cmp_ps::<_CMP_UNORD_Q>(other.v, other.v);
+            Self {
+                v: _mm256_blendv_ps(_mm256_min_ps(self.v, other.v), self.v, nan),
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn reciprocal(self) -> Self {
+        Self {
+            v: unsafe { _mm256_div_ps(_mm256_set1_ps(1.0), self.v) },
+        }
+    }
+
+    #[inline(always)]
+    pub fn truncate(self) -> Self {
+        Self {
+            v: unsafe { _mm256_round_ps::<{ _MM_FROUND_TO_ZERO | _MM_FROUND_NO_EXC }>(self.v) },
+        }
+    }
+}
+
+impl Add for Vec4x2 {
+    type Output = Vec4x2;
+
+    #[inline(always)]
+    fn add(self, other: Vec4x2) -> Vec4x2 {
+        Vec4x2 {
+            v: unsafe { _mm256_add_ps(self.v, other.v) },
+        }
+    }
+}
+
+impl Sub for Vec4x2 {
+    type Output = Vec4x2;
+
+    #[inline(always)]
+    fn sub(self, other: Vec4x2) -> Vec4x2 {
+        Vec4x2 {
+            v: unsafe { _mm256_sub_ps(self.v, other.v) },
+        }
+    }
+}
+
+impl Mul for Vec4x2 {
+    type Output = Vec4x2;
+
+    #[inline(always)]
+    fn mul(self, other: Vec4x2) -> Vec4x2 {
+        Vec4x2 {
+            v: unsafe { _mm256_mul_ps(self.v, other.v) },
+        }
+    }
+}
+
+fn detect() -> bool {
+    // the CPU needs AVX and XSAVE, and the OS needs to save the upper halves of the registers
+    let features = __cpuid(1);
+    if features.ecx & (1 << 27) == 0 || features.ecx & (1 << 28) == 0 {
+        return false;
+    }
+    if unsafe { _xgetbv(0) } & 0b110 != 0b110 {
+        return false;
+    }
+    __get_cpuid_max(0).0 >= 7 && __cpuid_count(7, 0).ebx & (1 << 5) != 0
+}
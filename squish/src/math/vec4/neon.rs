@@ -0,0 +1,112 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! NEON implementation of the `Vec4` lanes
+//!
+//! The intrinsics are only unsafe to call without the target feature, and this module is
+//! only compiled when NEON is enabled.
+
+use core::arch::aarch64::*;
+
+pub type Lanes = float32x4_t;
+
+#[inline]
+pub fn new(x: f32, y: f32, z: f32, w: f32) -> Lanes {
+    unsafe {
+        let a = vdupq_n_f32(x);
+        let a = vsetq_lane_f32::<1>(y, a);
+        let a = vsetq_lane_f32::<2>(z, a);
+        vsetq_lane_f32::<3>(w, a)
+    }
+}
+
+#[inline]
+pub fn to_array(a: Lanes) -> [f32; 4] {
+    unsafe {
+        [
+            vgetq_lane_f32::<0>(a),
+            vgetq_lane_f32::<1>(a),
+            vgetq_lane_f32::<2>(a),
+            vgetq_lane_f32::<3>(a),
+        ]
+    }
+}
+
+#[inline]
+pub fn splat_x(a: Lanes) -> Lanes {
+    unsafe { vdupq_laneq_f32::<0>(a) }
+}
+
+#[inline]
+pub fn splat_y(a: Lanes) -> Lanes {
+    unsafe { vdupq_laneq_f32::<1>(a) }
+}
+
+#[inline]
+pub fn splat_z(a: Lanes) -> Lanes {
+    unsafe { vdupq_laneq_f32::<2>(a) }
+}
+
+#[inline]
+pub fn splat_w(a: Lanes) -> Lanes {
+    unsafe { vdupq_laneq_f32::<3>(a) }
+}
+
+#[inline]
+pub fn add(a: Lanes, b: Lanes) -> Lanes {
+    unsafe { vaddq_f32(a, b) }
+}
+
+#[inline]
+pub fn sub(a: Lanes, b: Lanes) -> Lanes {
+    unsafe { vsubq_f32(a, b) }
+}
+
+#[inline]
+pub fn mul(a: Lanes, b: Lanes) -> Lanes {
+    unsafe { vmulq_f32(a, b) }
+}
+
+#[inline]
+pub fn div(a: Lanes, b: Lanes) -> Lanes {
+    unsafe { vdivq_f32(a, b) }
+}
+
+// fminnm and fmaxnm return the lane that is a number like f32::min and f32::max
+#[inline]
+pub fn min(a: Lanes, b: Lanes) -> Lanes {
+    unsafe { vminnmq_f32(a, b) }
+}
+
+#[inline]
+pub fn max(a: Lanes, b: Lanes) -> Lanes {
+    unsafe { vmaxnmq_f32(a, b) }
+}
+
+#[inline]
+pub fn any_less_than(a: Lanes, b: Lanes) -> bool {
+    unsafe { vmaxvq_u32(vcltq_f32(a, b)) != 0 }
+}
+
+#[inline]
+pub fn truncate(a: Lanes) -> Lanes {
+    unsafe { vrndq_f32(a) }
+}
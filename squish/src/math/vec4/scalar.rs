@@ -0,0 +1,102 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Portable implementation of the `Vec4` lanes
+
+pub type Lanes = [f32; 4];
+
+fn map(a: Lanes, f: impl Fn(f32) -> f32) -> Lanes {
+    [f(a[0]), f(a[1]), f(a[2]), f(a[3])]
+}
+
+fn zip(a: Lanes, b: Lanes, f: impl Fn(f32, f32) -> f32) -> Lanes {
+    [f(a[0], b[0]), f(a[1], b[1]), f(a[2], b[2]), f(a[3], b[3])]
+}
+
+#[inline]
+pub fn new(x: f32, y: f32, z: f32, w: f32) -> Lanes {
+    [x, y, z, w]
+}
+
+#[inline]
+pub fn to_array(a: Lanes) -> [f32; 4] {
+    a
+}
+
+#[inline]
+pub fn splat_x(a: Lanes) -> Lanes {
+    [a[0]; 4]
+}
+
+#[inline]
+pub fn splat_y(a: Lanes) -> Lanes {
+    [a[1]; 4]
+}
+
+#[inline]
+pub fn splat_z(a: Lanes) -> Lanes {
+    [a[2]; 4]
+}
+
+#[inline]
+pub fn splat_w(a: Lanes) -> Lanes {
+    [a[3]; 4]
+}
+
+#[inline]
+pub fn add(a: Lanes, b: Lanes) -> Lanes {
+    zip(a, b, |a, b| a + b)
+}
+
+#[inline]
+pub fn sub(a: Lanes, b: Lanes) -> Lanes {
+    zip(a, b, |a, b| a - b)
+}
+
+#[inline]
+pub fn mul(a: Lanes, b: Lanes) -> Lanes {
+    zip(a, b, |a, b| a * b)
+}
+
+#[inline]
+pub fn div(a: Lanes, b: Lanes) -> Lanes {
+    zip(a, b, |a, b| a / b)
+}
+
+#[inline]
+pub fn min(a: Lanes, b: Lanes) -> Lanes {
+    zip(a, b, f32::min)
+}
+
+#[inline]
+pub fn max(a: Lanes, b: Lanes) -> Lanes {
+    zip(a, b, f32::max)
+}
+
+#[inline]
+pub fn any_less_than(a: Lanes, b: Lanes) -> bool {
+    a.iter().zip(&b).any(|(a, b)| a < b)
+}
+
+#[inline]
+pub fn truncate(a: Lanes) -> Lanes {
+    map(a, libm::truncf)
+}
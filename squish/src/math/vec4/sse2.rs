@@ -0,0 +1,132 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! SSE2 implementation of the `Vec4` lanes
+//!
+//! The intrinsics are only unsafe to call without the target feature, and this module is
+//! only compiled when SSE2 is enabled.
+
+#[cfg(target_arch = "x86")]
+use core::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+
+pub type Lanes = __m128;
+
+#[inline]
+pub fn new(x: f32, y: f32, z: f32, w: f32) -> Lanes {
+    unsafe { _mm_setr_ps(x, y, z, w) }
+}
+
+#[inline]
+pub fn to_array(a: Lanes) -> [f32; 4] {
+    unsafe {
+        [
+            _mm_cvtss_f32(a),
+            _mm_cvtss_f32(splat_y(a)),
+            _mm_cvtss_f32(splat_z(a)),
+            _mm_cvtss_f32(splat_w(a)),
+        ]
+    }
+}
+
+#[inline]
+pub fn splat_x(a: Lanes) -> Lanes {
+    unsafe { _mm_shuffle_ps::<0x00>(a, a) }
+}
+
+#[inline]
+pub fn splat_y(a: Lanes) -> Lanes {
+    unsafe { _mm_shuffle_ps::<0x55>(a, a) }
+}
+
+#[inline]
+pub fn splat_z(a: Lanes) -> Lanes {
+    unsafe { _mm_shuffle_ps::<0xAA>(a, a) }
+}
+
+#[inline]
+pub fn splat_w(a: Lanes) -> Lanes {
+    unsafe { _mm_shuffle_ps::<0xFF>(a, a) }
+}
+
+#[inline]
+pub fn add(a: Lanes, b: Lanes) -> Lanes {
+    unsafe { _mm_add_ps(a, b) }
+}
+
+#[inline]
+pub fn sub(a: Lanes, b: Lanes) -> Lanes {
+    unsafe { _mm_sub_ps(a, b) }
+}
+
+#[inline]
+pub fn mul(a: Lanes, b: Lanes) -> Lanes {
+    unsafe { _mm_mul_ps(a, b) }
+}
+
+#[inline]
+pub fn div(a: Lanes, b: Lanes) -> Lanes {
+    unsafe { _mm_div_ps(a, b) }
+}
+
+/// Picks lanes from `a` where `mask` is set and from `b` elsewhere
+#[inline]
+fn select(mask: Lanes, a: Lanes, b: Lanes) -> Lanes {
+    unsafe { _mm_or_ps(_mm_and_ps(mask, a), _mm_andnot_ps(mask, b)) }
+}
+
+#[inline]
+pub fn min(a: Lanes, b: Lanes) -> Lanes {
+    // minps returns b if either lane is NaN, f32::min returns the lane that is a number
+    unsafe { select(_mm_cmpunord_ps(b, b), a, _mm_min_ps(a, b)) }
+}
+
+#[inline]
+pub fn max(a: Lanes, b: Lanes) -> Lanes {
+    unsafe { select(_mm_cmpunord_ps(b, b), a, _mm_max_ps(a, b)) }
+}
+
+#[inline]
+pub fn any_less_than(a: Lanes, b: Lanes) -> bool {
+    unsafe { _mm_movemask_ps(_mm_cmplt_ps(a, b)) != 0 }
+}
+
+#[cfg(target_feature = "sse4.1")]
+#[inline]
+pub fn truncate(a: Lanes) -> Lanes {
+    unsafe { _mm_round_ps::<{ _MM_FROUND_TO_ZERO | _MM_FROUND_NO_EXC }>(a) }
+}
+
+#[cfg(not(target_feature = "sse4.1"))]
+#[inline]
+pub fn truncate(a: Lanes) -> Lanes {
+    // floats of 2^23 and above are already whole numbers, as are infinities, and NaN
+    // fails the comparison so it is passed through as well
+    unsafe {
+        let sign = _mm_set1_ps(-0.0);
+        let small = _mm_cmplt_ps(_mm_andnot_ps(sign, a), _mm_set1_ps(8_388_608.0));
+        let truncated = _mm_cvtepi32_ps(_mm_cvttps_epi32(a));
+        // keep the sign of values that truncate to zero
+        let truncated = _mm_or_ps(truncated, _mm_and_ps(sign, a));
+        select(small, truncated, a)
+    }
+}
@@ -0,0 +1,518 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Image quality metrics for comparing compressed images against their source
+//!
+//! All metrics are computed on 8-bit RGBA pixels, so HDR formats are compared in the range
+//! [0, 1] and signed formats in their biased representation. The structural similarity
+//! index (SSIM) uses uniformly weighted 8x8 windows that step by 4 pixels.
+//!
+//! Normal maps are better judged by the angle between source and decoded normals, which
+//! `compare_normals` measures.
+
+use crate::{normal, num_blocks, Bc3Encoding, ColourWeights, Error, Format};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// Quality of an image compared against its source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Metrics {
+    /// Root mean square error of each RGBA channel
+    pub rmse: [f32; 4],
+
+    /// Root mean square error of the colour channels, weighted by the colour weights
+    pub weighted_rmse: f32,
+
+    /// Peak signal-to-noise ratio of each RGBA channel in decibels, infinite if the
+    /// channel is identical
+    pub psnr: [f32; 4],
+
+    /// Peak signal-to-noise ratio of the weighted colour error in decibels
+    pub weighted_psnr: f32,
+
+    /// Mean structural similarity of each RGBA channel, 1.0 if the channel is identical
+    pub ssim: [f32; 4],
+
+    /// Mean structural similarity of the luminance given by the colour weights
+    pub weighted_ssim: f32,
+}
+
+/// Error of a single compressed block
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BlockError {
+    /// Mean squared error of the colour channels, weighted by the colour weights
+    ///
+    /// For BC4 and BC5 this is the unweighted error of the stored channels instead.
+    pub colour: f32,
+
+    /// Mean squared error of the alpha channel, zero for formats without alpha
+    pub alpha: f32,
+}
+
+/// Angular error of a compressed normal map
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AngularError {
+    /// Mean angle between source and decoded normals in degrees
+    pub mean: f32,
+
+    /// Largest angle between source and decoded normals in degrees
+    pub max: f32,
+}
+
+/// Compares an RGBA image against its compressed version
+///
+/// * `format`     - The format of the compressed image
+/// * `rgba`       - The source pixel data
+/// * `width`      - The width of the source image
+/// * `height`     - The height of the source image
+/// * `compressed` - The compressed image data
+/// * `weights`    - The colour weights for the weighted metrics
+pub fn compare(
+    format: Format,
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    compressed: &[u8],
+    weights: ColourWeights,
+) -> Result<Metrics, Error> {
+    // overflowing pitches are reported by the size check
+    let pitch = width.saturating_mul(4);
+    let block_pitch = num_blocks(width).saturating_mul(format.block_size());
+    let (values, size) = format.checked_sizes(width, height, 4, pitch, block_pitch)?;
+    check_input(rgba, values)?;
+    check_input(compressed, size)?;
+
+    let blocks_wide = num_blocks(width);
+    let block_size = format.block_size();
+    Ok(measure(
+        width,
+        height,
+        weights,
+        |x, y| read_block(rgba, width, height, x, y),
+        |x, y| {
+            let offset = (y * blocks_wide + x) * block_size;
+            format.decompress_block(&compressed[offset..offset + block_size])
+        },
+    ))
+}
+
+/// Compares two RGBA images of the same size, e.g. a source and a decompressed image
+///
+/// * `source`  - The source pixel data
+/// * `other`   - The pixel data to compare against the source
+/// * `width`   - The width of the images
+/// * `height`  - The height of the images
+/// * `weights` - The colour weights for the weighted metrics
+pub fn compare_images(
+    source: &[u8],
+    other: &[u8],
+    width: usize,
+    height: usize,
+    weights: ColourWeights,
+) -> Result<Metrics, Error> {
+    if width == 0 || height == 0 {
+        return Err(Error::ZeroDimension);
+    }
+    let values = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(4))
+        .ok_or(Error::DimensionOverflow)?;
+    check_input(source, values)?;
+    check_input(other, values)?;
+
+    Ok(measure(
+        width,
+        height,
+        weights,
+        |x, y| read_block(source, width, height, x, y),
+        |x, y| read_block(other, width, height, x, y),
+    ))
+}
+
+/// Computes the error of each block of a compressed image against its source
+///
+/// * `format`     - The format of the compressed image
+/// * `rgba`       - The source pixel data
+/// * `width`      - The width of the source image
+/// * `height`     - The height of the source image
+/// * `compressed` - The compressed image data
+/// * `weights`    - The colour weights for the colour error
+/// * `errors`     - Output buffer for the error of each block in row-major order
+pub fn block_errors(
+    format: Format,
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    compressed: &[u8],
+    weights: ColourWeights,
+    errors: &mut [BlockError],
+) -> Result<(), Error> {
+    encoded_block_errors(
+        format,
+        Bc3Encoding::Rgba,
+        rgba,
+        width,
+        height,
+        compressed,
+        weights,
+        errors,
+    )
+}
+
+/// Computes the error of each block of a compressed image against its source like
+/// `block_errors`, reconstructing the original RGB of BC3 blocks in the given encoding
+/// first. Encoded BC3 blocks have no alpha error, since their alpha holds other data.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn encoded_block_errors(
+    format: Format,
+    encoding: Bc3Encoding,
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    compressed: &[u8],
+    weights: ColourWeights,
+    errors: &mut [BlockError],
+) -> Result<(), Error> {
+    // overflowing pitches are reported by the size check
+    let pitch = width.saturating_mul(4);
+    let block_pitch = num_blocks(width).saturating_mul(format.block_size());
+    let (values, size) = format.checked_sizes(width, height, 4, pitch, block_pitch)?;
+    check_input(rgba, values)?;
+    check_input(compressed, size)?;
+
+    let blocks_wide = num_blocks(width);
+    let blocks = blocks_wide * num_blocks(height);
+    if errors.len() < blocks {
+        return Err(Error::BufferTooSmall {
+            required: blocks,
+            actual: errors.len(),
+        });
+    }
+
+    // the colour error of BC4 and BC5 covers the channels they store
+    let weights = match format {
+        Format::Bc4 | Format::Bc4Snorm => [1.0, 0.0, 0.0],
+        Format::Bc5 | Format::Bc5Snorm => [0.5, 0.5, 0.0],
+        _ => normalise(weights),
+    };
+    let encoding = if format == Format::Bc3 {
+        encoding
+    } else {
+        Bc3Encoding::Rgba
+    };
+    let has_alpha = matches!(
+        format,
+        Format::Bc1 | Format::Bc2 | Format::Bc3 | Format::Bc7
+    ) && encoding == Bc3Encoding::Rgba;
+    let block_size = format.block_size();
+
+    #[cfg(feature = "rayon")]
+    let error_rows = errors[..blocks].par_chunks_mut(blocks_wide);
+    #[cfg(not(feature = "rayon"))]
+    let error_rows = errors[..blocks].chunks_mut(blocks_wide);
+
+    error_rows.enumerate().for_each(|(y, error_row)| {
+        for (x, error) in error_row.iter_mut().enumerate() {
+            let offset = (y * blocks_wide + x) * block_size;
+            let source = read_block(rgba, width, height, x, y);
+            let decoded = format
+                .decompress_block(&compressed[offset..offset + block_size])
+                .map(|pixel| encoding.decode_pixel(pixel));
+
+            let mut colour = 0.0;
+            let mut alpha = 0.0;
+            let mut pixels = 0.0;
+            for i in (0..16).filter(|&i| 4 * x + i % 4 < width && 4 * y + i / 4 < height) {
+                let squared = |c: usize| {
+                    let error = f64::from(source[i][c]) - f64::from(decoded[i][c]);
+                    error * error
+                };
+                colour +=
+                    weights[0] * squared(0) + weights[1] * squared(1) + weights[2] * squared(2);
+                if has_alpha {
+                    alpha += squared(3);
+                }
+                pixels += 1.0;
+            }
+
+            *error = BlockError {
+                colour: (colour / pixels) as f32,
+                alpha: (alpha / pixels) as f32,
+            };
+        }
+    });
+
+    Ok(())
+}
+
+/// Compares a normal map against its compressed version
+///
+/// The source holds X, Y and Z mapped to RGB. Z is reconstructed for BC5 and BC3, which
+/// is expected to hold X in alpha and Y in green (DXT5nm) as written in normal map mode.
+///
+/// * `format`     - The format of the compressed image
+/// * `rgba`       - The source pixel data
+/// * `width`      - The width of the source image
+/// * `height`     - The height of the source image
+/// * `compressed` - The compressed image data
+pub fn compare_normals(
+    format: Format,
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    compressed: &[u8],
+) -> Result<AngularError, Error> {
+    // overflowing pitches are reported by the size check
+    let pitch = width.saturating_mul(4);
+    let block_pitch = num_blocks(width).saturating_mul(format.block_size());
+    let (values, size) = format.checked_sizes(width, height, 4, pitch, block_pitch)?;
+    check_input(rgba, values)?;
+    check_input(compressed, size)?;
+
+    let blocks_wide = num_blocks(width);
+    let block_size = format.block_size();
+    let mut total = 0.0f64;
+    let mut max = 0.0f32;
+    for y in 0..num_blocks(height) {
+        for x in 0..blocks_wide {
+            let offset = (y * blocks_wide + x) * block_size;
+            let source = read_block(rgba, width, height, x, y);
+            let decoded = format.decompress_block(&compressed[offset..offset + block_size]);
+            for i in (0..16).filter(|&i| 4 * x + i % 4 < width && 4 * y + i / 4 < height) {
+                let angle = normal::angle(
+                    normal::source(&source[i], format),
+                    normal::decoded(&decoded[i], format),
+                )
+                .to_degrees();
+                total += f64::from(angle);
+                max = max.max(angle);
+            }
+        }
+    }
+
+    Ok(AngularError {
+        mean: (total / (width * height) as f64) as f32,
+        max,
+    })
+}
+
+fn check_input(input: &[u8], required: usize) -> Result<(), Error> {
+    if input.len() < required {
+        return Err(Error::InputTooShort {
+            required,
+            actual: input.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Scales colour weights to sum up to one, falling back to uniform weights if they are all
+/// zero
+fn normalise(weights: ColourWeights) -> [f64; 3] {
+    let total = f64::from(weights[0] + weights[1] + weights[2]);
+    if total > 0.0 {
+        weights.map(|weight| f64::from(weight) / total)
+    } else {
+        [1.0 / 3.0; 3]
+    }
+}
+
+/// Reads the pixels of the block at the given block coordinates, leaving pixels outside
+/// the image zeroed
+fn read_block(rgba: &[u8], width: usize, height: usize, x: usize, y: usize) -> [[u8; 4]; 16] {
+    let mut block = [[0u8; 4]; 16];
+    for (i, pixel) in block.iter_mut().enumerate() {
+        let sx = 4 * x + i % 4;
+        let sy = 4 * y + i / 4;
+        if sx < width && sy < height {
+            let index = 4 * (width * sy + sx);
+            pixel.copy_from_slice(&rgba[index..index + 4]);
+        }
+    }
+    block
+}
+
+/// Running sums of a window of value pairs for computing their structural similarity
+#[derive(Clone, Copy, Default)]
+struct Moments {
+    count: f64,
+    a: f64,
+    b: f64,
+    aa: f64,
+    bb: f64,
+    ab: f64,
+}
+
+impl Moments {
+    fn add(&mut self, a: f64, b: f64) {
+        self.count += 1.0;
+        self.a += a;
+        self.b += b;
+        self.aa += a * a;
+        self.bb += b * b;
+        self.ab += a * b;
+    }
+
+    fn merge(&mut self, other: &Moments) {
+        self.count += other.count;
+        self.a += other.a;
+        self.b += other.b;
+        self.aa += other.aa;
+        self.bb += other.bb;
+        self.ab += other.ab;
+    }
+
+    fn ssim(&self) -> f64 {
+        // stabilising constants for 8-bit values
+        const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+        const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+        let mean_a = self.a / self.count;
+        let mean_b = self.b / self.count;
+        let var_a = self.aa / self.count - mean_a * mean_a;
+        let var_b = self.bb / self.count - mean_b * mean_b;
+        let covariance = self.ab / self.count - mean_a * mean_b;
+
+        ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+            / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
+    }
+}
+
+/// Converts a mean square error of 8-bit values to a peak signal-to-noise ratio
+fn psnr(mse: f64) -> f32 {
+    if mse == 0.0 {
+        f32::INFINITY
+    } else {
+        (10.0 * libm::log10(255.0 * 255.0 / mse)) as f32
+    }
+}
+
+/// Number of block columns whose statistics `measure` keeps at a time. The blocks on the
+/// border between two strips of this width are decoded twice.
+const STRIP: usize = 16;
+
+/// Computes the metrics of two images given as functions that return the block at the
+/// given block coordinates
+fn measure<A, B>(
+    width: usize,
+    height: usize,
+    weights: ColourWeights,
+    source: A,
+    other: B,
+) -> Metrics
+where
+    A: Fn(usize, usize) -> [[u8; 4]; 16],
+    B: Fn(usize, usize) -> [[u8; 4]; 16],
+{
+    let blocks_wide = num_blocks(width);
+    let blocks_high = num_blocks(height);
+    let inside = |x: usize, y: usize, i: usize| 4 * x + i % 4 < width && 4 * y + i / 4 < height;
+
+    let weights = normalise(weights);
+    let luminance = |pixel: &[u8; 4]| {
+        weights[0] * f64::from(pixel[0])
+            + weights[1] * f64::from(pixel[1])
+            + weights[2] * f64::from(pixel[2])
+    };
+
+    // the structural similarity is averaged over windows of 2x2 blocks, which are summed
+    // up from the moments of each block in the current and the previous row of blocks,
+    // one strip of block columns at a time
+    let mut squared_errors = [0u64; 4];
+    let mut ssim = [0f64; 5];
+    let mut windows = 0;
+    let mut first = 0;
+    loop {
+        let end = (first + STRIP).min(blocks_wide);
+        let window_end = if end == blocks_wide {
+            blocks_wide.saturating_sub(1).max(1)
+        } else {
+            end - 1
+        };
+
+        let mut previous = [[Moments::default(); 5]; STRIP];
+        for y in 0..blocks_high {
+            let mut current = [[Moments::default(); 5]; STRIP];
+            for (x, moments) in (first..end).zip(current.iter_mut()) {
+                let a = source(x, y);
+                let b = other(x, y);
+                // the first column was measured as the last one of the previous strip
+                let counted = x == first && first > 0;
+                for i in (0..16).filter(|&i| inside(x, y, i)) {
+                    if !counted {
+                        for (sum, (&a, &b)) in squared_errors.iter_mut().zip(a[i].iter().zip(&b[i]))
+                        {
+                            let error = u64::from(a.abs_diff(b));
+                            *sum += error * error;
+                        }
+                    }
+                    for c in 0..4 {
+                        moments[c].add(f64::from(a[i][c]), f64::from(b[i][c]));
+                    }
+                    moments[4].add(luminance(&a[i]), luminance(&b[i]));
+                }
+            }
+
+            if y > 0 || blocks_high == 1 {
+                let rows: &[_] = if y > 0 {
+                    &[&previous, &current]
+                } else {
+                    &[&current]
+                };
+                for x in first..window_end {
+                    let mut window = [Moments::default(); 5];
+                    for row in rows {
+                        for column in (x..(x + 2).min(end)).map(|x| &row[x - first]) {
+                            for (window, moments) in window.iter_mut().zip(column) {
+                                window.merge(moments);
+                            }
+                        }
+                    }
+                    for (sum, moments) in ssim.iter_mut().zip(&window) {
+                        *sum += moments.ssim();
+                    }
+                    windows += 1;
+                }
+            }
+            previous = current;
+        }
+
+        if end == blocks_wide {
+            break;
+        }
+        first = end - 1;
+    }
+
+    let pixels = (width * height) as f64;
+    let mse = squared_errors.map(|sum| sum as f64 / pixels);
+    let weighted_mse = weights[0] * mse[0] + weights[1] * mse[1] + weights[2] * mse[2];
+
+    let ssim = ssim.map(|sum| (sum / f64::from(windows)) as f32);
+
+    Metrics {
+        rmse: mse.map(|mse| libm::sqrt(mse) as f32),
+        weighted_rmse: libm::sqrt(weighted_mse) as f32,
+        psnr: mse.map(psnr),
+        weighted_psnr: psnr(weighted_mse),
+        ssim: [ssim[0], ssim[1], ssim[2], ssim[3]],
+        weighted_ssim: ssim[4],
+    }
+}
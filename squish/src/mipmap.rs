@@ -0,0 +1,405 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Mipmap chain generation and compression
+//!
+//! Each level is resampled from the previous one with a separable filter, keeping the
+//! intermediate levels at full precision. Levels halve in size, rounding down, until they
+//! reach 1x1 pixels. sRGB encoded colour can be filtered in linear light, which keeps
+//! the brightness of high contrast details from dropping in smaller levels.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::math::{linear_to_srgb, srgb_to_linear};
+use crate::{normal, Format, Params};
+
+/// Defines the filter used for downsampling
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Filter {
+    /// Averages the pixels covered by each destination pixel
+    #[default]
+    Box,
+
+    /// Linear interpolation, slightly smoother than box filtering
+    Triangle,
+
+    /// Kaiser windowed sinc, sharp with little ringing
+    Kaiser,
+
+    /// Lanczos windowed sinc, sharpest but prone to ringing
+    Lanczos,
+}
+
+/// Defines how the filter samples pixels beyond the edges of an image
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum EdgeMode {
+    /// Repeats the pixels at the edges
+    #[default]
+    Clamp,
+
+    /// Samples the opposite edge, for tiling textures
+    Wrap,
+}
+
+/// Parameters for generating mipmap chains
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MipmapParams {
+    /// The filter used for downsampling
+    pub filter: Filter,
+
+    /// How pixels beyond the edges of the image are sampled
+    pub edge_mode: EdgeMode,
+
+    /// Treat colours as sRGB encoded and filter them in linear light
+    pub srgb: bool,
+
+    /// Rescale the alpha of each level so that the fraction of pixels with alpha at or
+    /// above this reference value matches the source image, which keeps alpha-tested
+    /// textures from thinning out in smaller levels
+    pub alpha_coverage: Option<u8>,
+
+    /// Treat pixels as unit normals with X, Y and Z mapped to RGB and renormalise them in
+    /// every level, overriding `srgb`
+    pub normal_map: bool,
+}
+
+/// An uncompressed RGBA image, e.g. a level of a mipmap chain
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+/// A compressed mipmap chain stored in one contiguous buffer
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompressedChain {
+    /// The compressed levels, largest first
+    pub data: Vec<u8>,
+
+    /// The offset of each level in `data`
+    pub offsets: Vec<usize>,
+}
+
+/// Returns the number of levels in a full mipmap chain for an image of given size
+pub fn level_count(width: usize, height: usize) -> usize {
+    let size = width.max(height).max(1);
+    (usize::BITS - size.leading_zeros()) as usize
+}
+
+/// Returns the size of a mipmap level for an image of given size
+pub fn level_size(width: usize, height: usize, level: usize) -> (usize, usize) {
+    let shrink = |size: usize| size.checked_shr(level as u32).unwrap_or(0).max(1);
+    (shrink(width), shrink(height))
+}
+
+/// Generates a mipmap chain for an RGBA image, starting with a copy of the image itself
+///
+/// * `rgba`   - The source pixel data
+/// * `width`  - The width of the source image
+/// * `height` - The height of the source image
+/// * `levels` - The number of levels to generate, limited to a full chain
+/// * `params` - The mipmap generation parameters
+pub fn generate(
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    levels: usize,
+    params: &MipmapParams,
+) -> Vec<Image> {
+    assert!(width > 0 && height > 0 && rgba.len() >= 4 * width * height);
+    let levels = levels.min(level_count(width, height));
+
+    let mut chain = Vec::with_capacity(levels);
+    // decode colour to linear light if requested, alpha is always linear
+    let srgb = params.srgb && !params.normal_map;
+    let decode = |i: usize, value: u8| {
+        let value = f32::from(value) / 255.0;
+        if srgb && i % 4 != 3 {
+            srgb_to_linear(value)
+        } else {
+            value
+        }
+    };
+    let encode = |i: usize, value: f32, alpha_scale: f32| {
+        let value = if i % 4 == 3 {
+            value * alpha_scale
+        } else {
+            value
+        };
+        let value = value.clamp(0.0, 1.0);
+        let value = if srgb && i % 4 != 3 {
+            linear_to_srgb(value)
+        } else {
+            value
+        };
+        libm::roundf(value * 255.0) as u8
+    };
+
+    // the coverage that alpha scaling aims for in every level
+    let coverage = params
+        .alpha_coverage
+        .filter(|&reference| reference > 0)
+        .map(|reference| {
+            let pixels = rgba[..4 * width * height].chunks_exact(4);
+            let covered = pixels.filter(|pixel| pixel[3] >= reference).count();
+            (reference, covered as f32 / (width * height) as f32)
+        });
+
+    let mut pixels: Vec<f32> = rgba[..4 * width * height]
+        .iter()
+        .enumerate()
+        .map(|(i, &value)| decode(i, value))
+        .collect();
+    for level in 0..levels {
+        let (level_width, level_height) = level_size(width, height, level);
+        if level > 0 {
+            let (previous_width, previous_height) = level_size(width, height, level - 1);
+            pixels = resample(
+                &pixels,
+                previous_width,
+                previous_height,
+                level_width,
+                params,
+            );
+            pixels = transpose(&pixels, level_width, previous_height);
+            pixels = resample(&pixels, previous_height, level_width, level_height, params);
+            pixels = transpose(&pixels, level_height, level_width);
+        }
+
+        // filtered intermediate levels stay unscaled so that scaling doesn't accumulate
+        let alpha_scale = match coverage {
+            Some((reference, coverage)) if level > 0 => {
+                coverage_scale(&pixels, f32::from(reference) / 255.0, coverage)
+            }
+            _ => 1.0,
+        };
+
+        chain.push(Image {
+            width: level_width,
+            height: level_height,
+            data: pixels
+                .chunks_exact(4)
+                .flat_map(|pixel| {
+                    let mut pixel = [pixel[0], pixel[1], pixel[2], pixel[3]];
+                    if params.normal_map {
+                        let normal = normal::normalise([0, 1, 2].map(|c| 2.0 * pixel[c] - 1.0));
+                        for c in 0..3 {
+                            pixel[c] = 0.5 * normal[c] + 0.5;
+                        }
+                    }
+                    (0..4).map(move |c| encode(c, pixel[c], alpha_scale))
+                })
+                .collect(),
+        });
+    }
+
+    chain
+}
+
+/// Returns the fraction of pixels with alpha at or above the threshold
+fn alpha_coverage(pixels: &[f32], threshold: f32) -> f32 {
+    let count = pixels.len() / 4;
+    let covered = pixels
+        .chunks_exact(4)
+        .filter(|pixel| pixel[3] >= threshold)
+        .count();
+    covered as f32 / count as f32
+}
+
+/// Returns the factor for scaling alpha such that the given fraction of pixels reaches
+/// the reference value
+///
+/// * `pixels`    - The RGBA pixels of the level
+/// * `reference` - The alpha reference value
+/// * `coverage`  - The desired fraction of pixels at or above the reference
+fn coverage_scale(pixels: &[f32], reference: f32, coverage: f32) -> f32 {
+    // coverage decreases with the threshold, so bisect for the threshold that yields the
+    // desired coverage before scaling, then map that threshold onto the reference
+    let mut low = 0.0f32;
+    let mut high = 1.0f32;
+    let mut best = (reference, f32::MAX);
+    for _ in 0..16 {
+        let threshold = 0.5 * (low + high);
+        let current = alpha_coverage(pixels, threshold);
+        let error = (current - coverage).abs();
+        if error < best.1 {
+            best = (threshold, error);
+        }
+        if current > coverage {
+            low = threshold;
+        } else {
+            high = threshold;
+        }
+    }
+
+    reference / best.0
+}
+
+/// Compresses a mipmap chain into one contiguous buffer
+///
+/// * `format` - The compression format
+/// * `levels` - The uncompressed levels, largest first
+/// * `params` - Additional compressor parameters
+pub fn compress_chain(format: Format, levels: &[Image], params: Params) -> CompressedChain {
+    let mut offsets = Vec::with_capacity(levels.len());
+    let mut size = 0;
+    for level in levels {
+        offsets.push(size);
+        size += format.compressed_size(level.width, level.height);
+    }
+
+    let mut data = vec![0u8; size];
+    for (level, &offset) in levels.iter().zip(&offsets) {
+        let output = &mut data[offset..offset + format.compressed_size(level.width, level.height)];
+        format.compress(&level.data, level.width, level.height, params, output);
+    }
+
+    CompressedChain { data, offsets }
+}
+
+/// Returns the distance from the centre at which the filter becomes zero
+fn support(filter: Filter) -> f32 {
+    match filter {
+        Filter::Box => 0.5,
+        Filter::Triangle => 1.0,
+        Filter::Kaiser | Filter::Lanczos => 3.0,
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-4 {
+        1.0
+    } else {
+        let x = core::f32::consts::PI * x;
+        libm::sinf(x) / x
+    }
+}
+
+/// The zeroth order modified Bessel function of the first kind
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-8 {
+        term *= (x * x) / (4.0 * k * k);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+/// Evaluates the filter at a distance given in destination pixels
+fn evaluate(filter: Filter, x: f32) -> f32 {
+    let x = x.abs();
+    match filter {
+        Filter::Box => {
+            if x <= 0.5 {
+                1.0
+            } else {
+                0.0
+            }
+        }
+        Filter::Triangle => (1.0 - x).max(0.0),
+        Filter::Kaiser => {
+            const ALPHA: f32 = 4.0;
+            let t = x / support(filter);
+            if t >= 1.0 {
+                0.0
+            } else {
+                sinc(x) * bessel_i0(ALPHA * libm::sqrtf(1.0 - t * t)) / bessel_i0(ALPHA)
+            }
+        }
+        Filter::Lanczos => {
+            if x >= support(filter) {
+                0.0
+            } else {
+                sinc(x) * sinc(x / support(filter))
+            }
+        }
+    }
+}
+
+/// Resamples each row of an image of RGBA floats to a new width
+fn resample(
+    pixels: &[f32],
+    width: usize,
+    height: usize,
+    new_width: usize,
+    params: &MipmapParams,
+) -> Vec<f32> {
+    let scale = width as f32 / new_width as f32;
+    let radius = support(params.filter) * scale.max(1.0);
+
+    // compute the normalised filter taps of each destination pixel once
+    let mut taps = Vec::with_capacity(new_width);
+    for x in 0..new_width {
+        let centre = (x as f32 + 0.5) * scale - 0.5;
+        let first = libm::ceilf(centre - radius) as isize;
+        let last = libm::floorf(centre + radius) as isize;
+
+        let mut weights: Vec<(usize, f32)> = (first..=last)
+            .map(|source| {
+                let weight = evaluate(params.filter, (source as f32 - centre) / scale.max(1.0));
+                let source = match params.edge_mode {
+                    EdgeMode::Clamp => source.clamp(0, width as isize - 1) as usize,
+                    EdgeMode::Wrap => source.rem_euclid(width as isize) as usize,
+                };
+                (source, weight)
+            })
+            .filter(|&(_, weight)| weight != 0.0)
+            .collect();
+        let total: f32 = weights.iter().map(|&(_, weight)| weight).sum();
+        for (_, weight) in &mut weights {
+            *weight /= total;
+        }
+        taps.push(weights);
+    }
+
+    let mut output = vec![0f32; 4 * new_width * height];
+    for (row, output_row) in pixels
+        .chunks(4 * width)
+        .zip(output.chunks_mut(4 * new_width))
+    {
+        for (pixel, weights) in output_row.chunks_mut(4).zip(&taps) {
+            for &(source, weight) in weights {
+                for (value, &source) in pixel.iter_mut().zip(&row[4 * source..4 * source + 4]) {
+                    *value += weight * source;
+                }
+            }
+        }
+    }
+    output
+}
+
+/// Swaps the rows and columns of an image of RGBA floats
+fn transpose(pixels: &[f32], width: usize, height: usize) -> Vec<f32> {
+    let mut output = vec![0f32; pixels.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let source = 4 * (y * width + x);
+            let target = 4 * (x * height + y);
+            output[target..target + 4].copy_from_slice(&pixels[source..source + 4]);
+        }
+    }
+    output
+}
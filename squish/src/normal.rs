@@ -0,0 +1,189 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Compression of tangent space normal maps
+//!
+//! Normals are stored with their components mapped from [-1, 1] to the colour channels.
+//! BC5 stores X and Y in its two channels and BC3 stores X in alpha and Y in green
+//! (DXT5nm), with Z reconstructed as the positive root of `1 - x² - y²`.
+
+use crate::alpha;
+use crate::{Bc3Encoding, Format, Params, COLOUR_WEIGHTS_UNIFORM};
+
+/// Maps a stored component to [-1, 1]
+fn component(value: u8, signed: bool) -> f32 {
+    if signed {
+        // two's complement value in the biased representation of the 8-bit API
+        f32::from((value ^ 0x80) as i8).max(-127.0) / 127.0
+    } else {
+        f32::from(value) / 127.5 - 1.0
+    }
+}
+
+/// Scales a vector to unit length, falling back to the unperturbed normal
+pub fn normalise(v: [f32; 3]) -> [f32; 3] {
+    let length = libm::sqrtf(v[0] * v[0] + v[1] * v[1] + v[2] * v[2]);
+    if length > 0.0 {
+        v.map(|x| x / length)
+    } else {
+        [0.0, 0.0, 1.0]
+    }
+}
+
+/// Builds a unit normal from its X and Y components
+fn reconstruct(x: f32, y: f32) -> [f32; 3] {
+    let z = libm::sqrtf((1.0 - x * x - y * y).max(0.0));
+    normalise([x, y, z])
+}
+
+/// Reads the normal of an uncompressed pixel
+pub fn source(pixel: &[u8; 4], format: Format) -> [f32; 3] {
+    let signed = format == Format::Bc5Snorm;
+    normalise([0, 1, 2].map(|c| component(pixel[c], signed)))
+}
+
+/// Reads the normal of a decompressed pixel, reconstructing Z for formats that only store
+/// X and Y
+pub fn decoded(pixel: &[u8; 4], format: Format) -> [f32; 3] {
+    match format {
+        Format::Bc3 => reconstruct(component(pixel[3], false), component(pixel[1], false)),
+        Format::Bc5 | Format::Bc5Snorm => {
+            let signed = format == Format::Bc5Snorm;
+            reconstruct(component(pixel[0], signed), component(pixel[1], signed))
+        }
+        _ => source(pixel, format),
+    }
+}
+
+/// Returns the angle between two unit vectors in radians
+pub fn angle(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let dot = a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+    libm::acosf(dot.clamp(-1.0, 1.0))
+}
+
+/// Compresses a 4x4 block of normals
+///
+/// Colour is fitted with uniform weights, and the BC3 and BC5 channels that store X and Y
+/// are refined to minimise the angular error of the reconstructed normals.
+///
+/// * `format` - The compression format
+/// * `rgba`   - The uncompressed block of normals
+/// * `mask`   - The valid pixel mask
+/// * `params` - Additional compressor parameters
+/// * `output` - Storage for the compressed block
+pub fn compress(format: Format, rgba: [[u8; 4]; 16], mask: u32, params: Params, output: &mut [u8]) {
+    let params = Params {
+        weights: COLOUR_WEIGHTS_UNIFORM,
+        weigh_colour_by_alpha: false,
+        srgb: false,
+        normal_map: false,
+        bc3_encoding: Bc3Encoding::Rgba,
+        ..params
+    };
+    let normals = rgba.map(|pixel| source(&pixel, format));
+
+    match format {
+        Format::Bc3 => {
+            // DXT5nm leaves red and blue empty so that colour fitting only sees Y
+            let swizzled = Bc3Encoding::Dxt5nm.encode_block(&rgba, mask);
+            format.compress_block_masked(swizzled, mask, params, output);
+            refine(format, &swizzled, &normals, mask, 3, 0, output);
+        }
+        Format::Bc5 | Format::Bc5Snorm => {
+            format.compress_block_masked(rgba, mask, params, output);
+
+            // the alpha blocks take the two's complement bytes of signed formats
+            let rgba = if format == Format::Bc5Snorm {
+                rgba.map(|pixel| pixel.map(|value| value ^ 0x80))
+            } else {
+                rgba
+            };
+            for _ in 0..2 {
+                refine(format, &rgba, &normals, mask, 0, 0, output);
+                refine(format, &rgba, &normals, mask, 1, 8, output);
+            }
+        }
+        _ => format.compress_block_masked(rgba, mask, params, output),
+    }
+}
+
+/// Searches around the endpoints of the alpha block holding one normal component for the
+/// ones that minimise the angular error of the whole block
+///
+/// * `format`  - The compression format
+/// * `rgba`    - The block as passed to the alpha block compressor
+/// * `normals` - The source normals
+/// * `mask`    - The valid pixel mask
+/// * `channel` - The channel of `rgba` stored in the alpha block
+/// * `offset`  - The offset of the alpha block in the compressed block
+/// * `output`  - The compressed block to refine
+fn refine(
+    format: Format,
+    rgba: &[[u8; 4]; 16],
+    normals: &[[f32; 3]; 16],
+    mask: u32,
+    channel: usize,
+    offset: usize,
+    output: &mut [u8],
+) {
+    let signed = format == Format::Bc5Snorm;
+    let (lo, hi) = if signed { (-127, 127) } else { (0, 255) };
+    let size = format.block_size();
+
+    let error = |block: &[u8]| -> f32 {
+        let decompressed = format.decompress_block(block);
+        (0..16)
+            .filter(|&i| mask & (1 << i) != 0)
+            .map(|i| angle(normals[i], decoded(&decompressed[i], format)))
+            .sum()
+    };
+
+    let mut best = [0u8; 16];
+    best[..size].copy_from_slice(&output[..size]);
+    let mut best_error = error(&best[..size]);
+
+    // hill climb until no neighbouring pair of endpoints improves the error
+    for _ in 0..8 {
+        let (min, max, _) = alpha::endpoints_bc3(&best[offset..offset + 8], signed);
+        let mut improved = false;
+        for (low, high) in (-2..=2).flat_map(|a| (-2..=2).map(move |b| (min + a, max + b))) {
+            if low < lo || high > hi || low >= high {
+                continue;
+            }
+            for seven in [false, true] {
+                let mut candidate = best;
+                let block = &mut candidate[offset..offset + 8];
+                alpha::compress_bc3_endpoints(rgba, channel, signed, mask, low, high, seven, block);
+                let candidate_error = error(&candidate[..size]);
+                if candidate_error < best_error {
+                    best = candidate;
+                    best_error = candidate_error;
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+
+    output[..size].copy_from_slice(&best[..size]);
+}
@@ -0,0 +1,369 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Rate-distortion optimisation of compressed images
+//!
+//! Compressed blocks look like random data to LZ based compressors such as zstd or
+//! deflate. After an image has been compressed, each 8 byte unit of a block, i.e. a colour
+//! block or an alpha block, is replaced by a variant that repeats bytes of a nearby earlier
+//! block when the extra error is worth the bits saved. The variants copy a whole unit,
+//! copy its endpoints and refit the indices, or copy its indices and keep the endpoints.
+
+use crate::compressor::Progress;
+use crate::{num_blocks, Bc3Encoding, Format, Params, PixelLayout};
+
+/// Number of preceding blocks in raster order that are searched for repeats
+const WINDOW: usize = 32;
+
+/// Estimated cost of a byte that does not repeat earlier data
+const LITERAL_BITS: f32 = 8.0;
+
+/// Estimated cost of a repeat, i.e. an LZ match of its offset and length
+const MATCH_BITS: f32 = 20.0;
+
+/// Shortest repeat that LZ based compressors encode as a match
+const MIN_MATCH: usize = 3;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    /// Two RGB565 endpoints and 2-bit indices
+    Colour,
+    /// Two 8-bit endpoints and 3-bit indices
+    Alpha,
+    /// 4-bit values without endpoints
+    ExplicitAlpha,
+}
+
+/// An 8 byte unit of a block and the channels it decodes to
+#[derive(Clone, Copy)]
+struct Unit {
+    offset: usize,
+    kind: Kind,
+    channels: &'static [usize],
+}
+
+impl Unit {
+    /// Returns the number of bytes of endpoints at the start of the unit
+    fn endpoint_bytes(self) -> usize {
+        match self.kind {
+            Kind::Colour => 4,
+            Kind::Alpha => 2,
+            Kind::ExplicitAlpha => 8,
+        }
+    }
+}
+
+const fn unit(offset: usize, kind: Kind, channels: &'static [usize]) -> Unit {
+    Unit {
+        offset,
+        kind,
+        channels,
+    }
+}
+
+// punch-through alpha comes from the BC1 colour block
+const BC1: [Unit; 1] = [unit(0, Kind::Colour, &[0, 1, 2, 3])];
+const BC2: [Unit; 2] = [
+    unit(0, Kind::ExplicitAlpha, &[3]),
+    unit(8, Kind::Colour, &[0, 1, 2]),
+];
+const BC3: [Unit; 2] = [
+    unit(0, Kind::Alpha, &[3]),
+    unit(8, Kind::Colour, &[0, 1, 2]),
+];
+const BC4: [Unit; 1] = [unit(0, Kind::Alpha, &[0])];
+const BC5: [Unit; 2] = [unit(0, Kind::Alpha, &[0]), unit(8, Kind::Alpha, &[1])];
+
+fn units(format: Format) -> &'static [Unit] {
+    match format {
+        Format::Bc1 => &BC1,
+        Format::Bc2 => &BC2,
+        Format::Bc3 => &BC3,
+        Format::Bc4 | Format::Bc4Snorm => &BC4,
+        Format::Bc5 | Format::Bc5Snorm => &BC5,
+        Format::Bc6hUf16 | Format::Bc6hSf16 | Format::Bc7 => &[],
+    }
+}
+
+/// Estimates the bits an LZ based compressor spends on a unit after the units of the
+/// same kind in `window`
+fn rate(unit: Unit, bytes: &[u8], window: &[&[u8]]) -> f32 {
+    if window.contains(&bytes) {
+        return MATCH_BITS;
+    }
+    let split = unit.endpoint_bytes();
+    let repeats = |range: core::ops::Range<usize>| {
+        range.len() >= MIN_MATCH
+            && window
+                .iter()
+                .any(|other| other[range.clone()] == bytes[range.clone()])
+    };
+    let cost = |range: core::ops::Range<usize>| {
+        if repeats(range.clone()) {
+            MATCH_BITS
+        } else {
+            LITERAL_BITS * range.len() as f32
+        }
+    };
+    cost(0..split) + cost(split..8)
+}
+
+/// Returns the weighted squared error of a compressed block against its target pixels
+fn distortion(
+    format: Format,
+    block: &[u8],
+    target: &[[u8; 4]; 16],
+    weights: &[f32; 4],
+    mask: u32,
+) -> f32 {
+    let decoded = format.decompress_block(block);
+    let mut error = 0.0;
+    for i in (0..16).filter(|&i| mask & (1 << i) != 0) {
+        for c in 0..4 {
+            let difference = f32::from(target[i][c]) - f32::from(decoded[i][c]);
+            error += weights[c] * difference * difference;
+        }
+    }
+    error
+}
+
+/// Chooses the best indices of a unit for the endpoints it holds
+fn refit(
+    format: Format,
+    unit: Unit,
+    block: &mut [u8],
+    target: &[[u8; 4]; 16],
+    weights: &[f32; 4],
+    mask: u32,
+) {
+    let bytes = &mut block[unit.offset..unit.offset + 8];
+    // index patterns that decode palette entry i to pixel i
+    let (pattern, entries, bits): (&[u8], usize, usize) = match unit.kind {
+        Kind::Colour => (&[0xE4; 4], 4, 2),
+        Kind::Alpha => (&[0x88, 0xC6, 0xFA, 0x88, 0xC6, 0xFA], 8, 3),
+        Kind::ExplicitAlpha => return,
+    };
+    let split = unit.endpoint_bytes();
+    bytes[split..].copy_from_slice(pattern);
+    let palette = format.decompress_block(block);
+
+    let mut packed = 0u64;
+    for (i, pixel) in target.iter().enumerate() {
+        if mask & (1 << i) == 0 {
+            continue;
+        }
+        let error = |entry: usize| -> f32 {
+            unit.channels
+                .iter()
+                .map(|&c| {
+                    let difference = f32::from(pixel[c]) - f32::from(palette[entry][c]);
+                    weights[c] * difference * difference
+                })
+                .sum()
+        };
+        let best = (0..entries)
+            .min_by(|&a, &b| error(a).total_cmp(&error(b)))
+            .unwrap();
+        packed |= (best as u64) << (bits * i);
+    }
+    let bytes = &mut block[unit.offset + split..unit.offset + 8];
+    bytes.copy_from_slice(&packed.to_le_bytes()[..8 - split]);
+}
+
+/// Reads a block of pixels from an image, returning the pixels and the valid pixel mask
+fn read_block(
+    pixels: &[u8],
+    layout: PixelLayout,
+    width: usize,
+    height: usize,
+    pitch: usize,
+    x: usize,
+    y: usize,
+) -> ([[u8; 4]; 16], u32) {
+    let order = layout.order();
+    let channels = order.len();
+    let mut block = [[0, 0, 0, u8::MAX]; 16];
+    let mut mask = 0u32;
+    for (i, pixel) in block.iter_mut().enumerate() {
+        let (sx, sy) = (4 * x + i % 4, 4 * y + i / 4);
+        if sx < width && sy < height {
+            let index = pitch * sy + channels * sx;
+            for (&value, &channel) in pixels[index..index + channels].iter().zip(order) {
+                pixel[channel] = value;
+            }
+            mask |= 1 << i;
+        }
+    }
+    (block, mask)
+}
+
+/// Returns the pixels that the blocks approximate and the weight of each channel
+fn target(
+    format: Format,
+    rgba: [[u8; 4]; 16],
+    mask: u32,
+    params: &Params,
+) -> ([[u8; 4]; 16], [f32; 4]) {
+    let encoding = if params.normal_map {
+        Bc3Encoding::Dxt5nm
+    } else {
+        params.bc3_encoding
+    };
+    match format {
+        Format::Bc3 if encoding != Bc3Encoding::Rgba => (
+            encoding.encode_block(&rgba, mask),
+            [1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0, 1.0],
+        ),
+        Format::Bc4 | Format::Bc4Snorm => (rgba, [1.0, 0.0, 0.0, 0.0]),
+        Format::Bc5 | Format::Bc5Snorm => (rgba, [1.0, 1.0, 0.0, 0.0]),
+        _ => {
+            let [r, g, b] = if params.normal_map {
+                [1.0; 3]
+            } else {
+                params.weights
+            };
+            let sum = r + g + b;
+            (rgba, [r / sum, g / sum, b / sum, 1.0])
+        }
+    }
+}
+
+/// Rewrites the blocks of a compressed image to repeat bytes of nearby blocks, as long as
+/// each bit saved costs at most `params.rdo_lambda` of weighted squared error
+///
+/// * `format`       - The compression format
+/// * `pixels`       - The uncompressed pixel data
+/// * `layout`       - The channel order of `pixels`
+/// * `width`        - The width of the source image
+/// * `height`       - The height of the source image
+/// * `pixel_pitch`  - The distance between rows of pixels in `pixels`
+/// * `params`       - Additional compressor parameters
+/// * `output`       - The compressed image
+/// * `output_pitch` - The distance between rows of blocks in `output`
+/// * `progress`     - Reports each finished row of blocks and stops when cancelled
+#[allow(clippy::too_many_arguments)]
+pub fn optimise(
+    format: Format,
+    pixels: &[u8],
+    layout: PixelLayout,
+    width: usize,
+    height: usize,
+    pixel_pitch: usize,
+    params: &Params,
+    output: &mut [u8],
+    output_pitch: usize,
+    progress: Option<&Progress>,
+) {
+    let lambda = params.rdo_lambda;
+    let block_size = format.block_size();
+    let blocks_wide = num_blocks(width);
+    let offset = |x: usize, y: usize| y * output_pitch + x * block_size;
+
+    for y in 0..num_blocks(height) {
+        if progress.is_some_and(|progress| !progress.proceed()) {
+            return;
+        }
+
+        for x in 0..blocks_wide {
+            let (rgba, mask) = read_block(pixels, layout, width, height, pixel_pitch, x, y);
+            let (target, weights) = target(format, rgba, mask, params);
+
+            // earlier blocks in raster order and the blocks above
+            let index = y * blocks_wide + x;
+            let mut neighbours: [usize; WINDOW + 3] = [0; WINDOW + 3];
+            let mut count = 0;
+            for previous in index.saturating_sub(WINDOW)..index {
+                neighbours[count] = offset(previous % blocks_wide, previous / blocks_wide);
+                count += 1;
+            }
+            if y > 0 {
+                for above in x.saturating_sub(1)..(x + 2).min(blocks_wide) {
+                    if (y - 1) * blocks_wide + above + WINDOW < index {
+                        neighbours[count] = offset(above, y - 1);
+                        count += 1;
+                    }
+                }
+            }
+
+            let start = offset(x, y);
+            let mut block = [0u8; 16];
+            block[..block_size].copy_from_slice(&output[start..start + block_size]);
+            let block = &mut block[..block_size];
+
+            for &unit in units(format) {
+                let range = unit.offset..unit.offset + 8;
+                let split = unit.endpoint_bytes();
+                let mut window = [&[][..]; WINDOW + 3];
+                for (slot, &neighbour) in window.iter_mut().zip(&neighbours[..count]) {
+                    *slot = &output[neighbour + unit.offset..neighbour + unit.offset + 8];
+                }
+                let window = &window[..count];
+
+                let cost = |block: &[u8]| {
+                    distortion(format, block, &target, &weights, mask)
+                        + lambda * rate(unit, &block[range.clone()], window)
+                };
+                let mut best = [0u8; 16];
+                best[..block_size].copy_from_slice(block);
+                let mut best_cost = cost(block);
+
+                for other in window {
+                    let mut candidates = [[0u8; 16]; 3];
+                    for candidate in &mut candidates {
+                        candidate[..block_size].copy_from_slice(block);
+                    }
+                    // the whole unit
+                    candidates[0][range.clone()].copy_from_slice(other);
+                    // its endpoints with refitted indices, if they are long enough to repeat
+                    if split >= MIN_MATCH {
+                        candidates[1][unit.offset..unit.offset + split]
+                            .copy_from_slice(&other[..split]);
+                        refit(
+                            format,
+                            unit,
+                            &mut candidates[1][..block_size],
+                            &target,
+                            &weights,
+                            mask,
+                        );
+                    }
+                    // its indices with our endpoints
+                    candidates[2][unit.offset + split..range.end].copy_from_slice(&other[split..]);
+
+                    for candidate in &candidates {
+                        let candidate_cost = cost(&candidate[..block_size]);
+                        if candidate_cost < best_cost {
+                            best = *candidate;
+                            best_cost = candidate_cost;
+                        }
+                    }
+                }
+                block.copy_from_slice(&best[..block_size]);
+            }
+
+            output[start..start + block_size].copy_from_slice(block);
+        }
+
+        if let Some(progress) = progress {
+            progress.row_finished();
+        }
+    }
+}
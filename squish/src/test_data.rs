@@ -9,6 +9,14 @@ pub struct TestDataSet {
     pub decoded: &'static [u8],
 }
 
+/// A data set for testing HDR formats holds the encoded values and the decoded RGB half
+/// float bit patterns for a single 4x4 block of pixels.
+#[derive(Debug)]
+pub struct HdrTestDataSet {
+    pub encoded: &'static [u8],
+    pub decoded: &'static [u16],
+}
+
 /// The test-pattern is a gray-scale checkerboard of size 4x4 starting with 0xFF in the top-left.
 /// On top of that, the four middle pixels are set to 0x7F.
 /// BC1 data created with AMD Compressonator v4.1.5083.
@@ -134,8 +142,111 @@ pub const BC5_GRAY: TestDataSet = TestDataSet {
     ),
 };
 
+/// The BC1_GRAY test pattern as signed values, with -1.0 in place of 0x00 and 0.0 in place of
+/// 0x7F, stored with a bias of 128. Verified against the bcdec decoder.
+pub const BC4_SNORM_GRAY: TestDataSet = TestDataSet {
+    encoded: &[0x00, 0x05, 0xF7, 0x6D, 0xE0, 0x07, 0xEC, 0xFB],
+    decoded: &add_alpha_to_rgb(
+        &expand_single_to_rgb(&[
+            0xFF, 0x01, 0xFF, 0x01, // row 0
+            0x01, 0x80, 0x80, 0xFF, // row 1
+            0xFF, 0x80, 0x80, 0x01, // row 2
+            0x01, 0xFF, 0x01, 0xFF, // row 3
+        ]),
+        &[0xFF; 16],
+    ),
+};
+
+/// The BC4_SNORM_GRAY test pattern for the red channel and the inverse pattern for the green
+/// channel. Blue decodes to 0.0.
+pub const BC5_SNORM_GRAY: TestDataSet = TestDataSet {
+    encoded: &[
+        0x00, 0x05, 0xF7, 0x6D, 0xE0, 0x07, 0xEC, 0xFB, // Alpha 0
+        0x00, 0x05, 0xBE, 0x7F, 0xC0, 0x06, 0x7E, 0xDF, // Alpha 1
+    ],
+    decoded: &add_alpha_to_rgb(
+        &[
+            0xFF, 0x01, 0x80, 0x01, 0xFF, 0x80, 0xFF, 0x01, 0x80, 0x01, 0xFF, 0x80, // row 0
+            0x01, 0xFF, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0xFF, 0x01, 0x80, // row 1
+            0xFF, 0x01, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01, 0xFF, 0x80, // row 2
+            0x01, 0xFF, 0x80, 0xFF, 0x01, 0x80, 0x01, 0xFF, 0x80, 0xFF, 0x01, 0x80, // row 3
+        ],
+        &[0xFF; 16],
+    ),
+};
+
+/// The BC2_GRAY test pattern without alpha, which BC7 mode 3 reproduces exactly.
+/// BC7 data created with squish and verified against the bcdec decoder.
+pub const BC7_GRAY: TestDataSet = TestDataSet {
+    encoded: &[
+        0xF8, 0xFE, 0x01, 0x2A, 0xD5, 0x1F, 0xA0, 0x52, // Mode, partition, endpoints
+        0xFD, 0x01, 0x2A, 0x55, 0x99, 0x2F, 0xD4, 0x33, // Endpoints, p-bits, indices
+    ],
+    decoded: &add_alpha_to_rgb(&expand_single_to_rgb(&GRAY_BLOCK_LUMA), &[0xFF; 16]),
+};
+
+/// The BC2_COLOUR test pattern encoded in BC7 mode 4 with 3-bit alpha indices.
+/// BC7 data created with squish and verified against the bcdec decoder.
+pub const BC7_COLOUR: TestDataSet = TestDataSet {
+    encoded: &[
+        0x10, 0xFF, 0xCB, 0x96, 0x8A, 0xD0, 0x03, 0x54, // Mode, rotation, endpoints
+        0xFF, 0xFF, 0x41, 0x22, 0x6D, 0x64, 0x6B, 0xFF, // Colour and alpha indices
+    ],
+    decoded: &[
+        0xFF, 0x94, 0x4A, 0x08, 0xFF, 0x94, 0x4A, 0x08, // row 0, left half
+        0xFF, 0x94, 0x4A, 0x2A, 0xFF, 0x94, 0x4A, 0x2A, // row 0, right half
+        0xFF, 0x78, 0x34, 0x4B, 0xFF, 0x78, 0x34, 0x4B, // row 1, left half
+        0xFF, 0x78, 0x34, 0x6D, 0xFF, 0x78, 0x34, 0x6D, // row 1, right half
+        0xFF, 0x6B, 0x29, 0x92, 0xFF, 0x6B, 0x29, 0x92, // row 2, left half
+        0xFF, 0x6B, 0x29, 0xB4, 0xFF, 0x6B, 0x29, 0xB4, // row 2, right half
+        0xFF, 0x6B, 0x29, 0xD5, 0xFF, 0x6B, 0x29, 0xD5, // row 3, left half
+        0xFF, 0x6B, 0x29, 0xF7, 0xFF, 0x6B, 0x29, 0xF7, // row 3, right half
+    ],
+};
+
+/// An exponential ramp from 0.25 to 3.36 in the red channel and from 1.0 to 0.27 in the
+/// blue channel over all 16 pixels, with a constant green channel of 1.0.
+/// BC6H data created with squish and verified against the bcdec decoder.
+pub const BC6H_UF16_RAMP: HdrTestDataSet = HdrTestDataSet {
+    encoded: &[
+        0xE7, 0x6A, 0xEF, 0xB7, 0xBF, 0x07, 0x80, 0xC1, // Mode, endpoints
+        0x10, 0x32, 0x54, 0x76, 0x98, 0xBA, 0xDC, 0xFE, // Indices
+    ],
+    decoded: &[
+        0x33CC, 0x3BF8, 0x3BCA, 0x34BB, 0x3BF8, 0x3B51, // row 0, left half
+        0x35E6, 0x3BF8, 0x3ABA, 0x36D6, 0x3BF8, 0x3A40, // row 0, right half
+        0x37C5, 0x3BF8, 0x39C7, 0x38B4, 0x3BF8, 0x394E, // row 1, left half
+        0x39DF, 0x3BF8, 0x38B7, 0x3ACE, 0x3BF8, 0x383E, // row 1, right half
+        0x3BBE, 0x3BF8, 0x37C4, 0x3CAD, 0x3BF8, 0x374B, // row 2, left half
+        0x3DD8, 0x3BF8, 0x36B4, 0x3EC8, 0x3BF8, 0x363B, // row 2, right half
+        0x3FB7, 0x3BF8, 0x35C2, 0x40A6, 0x3BF8, 0x3549, // row 3, left half
+        0x41D1, 0x3BF8, 0x34B1, 0x42C0, 0x3BF8, 0x3438, // row 3, right half
+    ],
+};
+
+/// An exponential ramp from -0.5 to -6.73 in the red channel and from 0.25 to 0.92 in the
+/// blue channel over all 16 pixels, with a constant green channel of 0.5.
+/// BC6H data created with squish and verified against the bcdec decoder.
+pub const BC6H_SF16_RAMP: HdrTestDataSet = HdrTestDataSet {
+    encoded: &[
+        0x87, 0x46, 0xE7, 0x56, 0x23, 0x1C, 0x00, 0x1F, // Mode, endpoints
+        0x10, 0x32, 0x54, 0x76, 0x98, 0xBA, 0xDC, 0xFE, // Indices
+    ],
+    decoded: &[
+        0xB7C3, 0x3801, 0x33C4, 0xB8B3, 0x3801, 0x343C, // row 0, left half
+        0xB9E0, 0x3801, 0x34D2, 0xBAD0, 0x3801, 0x354A, // row 0, right half
+        0xBBC0, 0x3801, 0x35C3, 0xBCB0, 0x3801, 0x363B, // row 1, left half
+        0xBDDD, 0x3801, 0x36D1, 0xBECD, 0x3801, 0x3749, // row 1, right half
+        0xBFBD, 0x3801, 0x37C1, 0xC0AD, 0x3801, 0x3839, // row 2, left half
+        0xC1DA, 0x3801, 0x38CF, 0xC2CA, 0x3801, 0x3947, // row 2, right half
+        0xC3BA, 0x3801, 0x39C0, 0xC4AA, 0x3801, 0x3A38, // row 3, left half
+        0xC5D7, 0x3801, 0x3ACE, 0xC6C7, 0x3801, 0x3B46, // row 3, right half
+    ],
+};
+
 /// Expands an array with a single value per pixel to an array with this value expanded
 /// into the RGB channels.
+#[allow(clippy::identity_op)]
 const fn expand_single_to_rgb(input: &[u8; 4 * 4]) -> [u8; 4 * 4 * 3] {
     let mut output = [0u8; 4 * 4 * 3];
     let mut i = 0;
@@ -157,6 +268,7 @@ const LINEAR_RAMP: [u8; 4 * 4] = [
 
 /// Appends a list of 16 alpha values to the RGB values.
 /// I.e. each RGB is extended to RGBA with the alpha value from the list.
+#[allow(clippy::identity_op)]
 const fn add_alpha_to_rgb(input: &[u8; 4 * 4 * 3], alpha_values: &[u8; 4 * 4]) -> [u8; 4 * 4 * 4] {
     let mut output = [0u8; 4 * 4 * 4];
     let mut i = 0;
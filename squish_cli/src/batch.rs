@@ -0,0 +1,359 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Compressing many files at once from lists of files, directories and glob patterns
+
+use std::collections::hash_map::{Entry, HashMap};
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Extensions of the images picked up from directories and glob patterns
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "tga", "bmp", "exr", "hdr"];
+
+/// An image to compress and the file to write it to
+pub struct Job {
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+impl AsRef<Job> for Job {
+    fn as_ref(&self) -> &Job {
+        self
+    }
+}
+
+/// Returns whether the inputs need batch mode, i.e. there is more than one, one of them
+/// is a directory or a glob pattern, or an output directory was given
+pub fn is_batch(inputs: &[PathBuf], output_dir: Option<&Path>) -> bool {
+    output_dir.is_some()
+        || inputs.len() > 1
+        || inputs
+            .iter()
+            .any(|input| input.is_dir() || is_pattern(input))
+}
+
+fn is_pattern(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// Returns the leading directories of a glob pattern that contain no wildcards
+fn pattern_base(pattern: &Path) -> PathBuf {
+    pattern
+        .components()
+        .take_while(|component| !is_pattern(Path::new(component.as_os_str())))
+        .collect()
+}
+
+/// Adds all images below a directory, in a stable order
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let read_error = |e| format!("Failed to read directory {}: {}", dir.display(), e);
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .map_err(read_error)?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type().map_err(read_error)?.is_dir() {
+            walk(&path, files)?;
+        } else if is_image(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Expands files, directories and glob patterns into jobs
+///
+/// Images below a directory or the part of a pattern without wildcards keep their relative
+/// path in `output_dir`, while files given directly are written to it by name. Without an
+/// output directory, each output is written next to its input. Outputs get `extension`.
+/// Different inputs that would be written to the same output are an error.
+pub fn jobs(
+    inputs: &[PathBuf],
+    output_dir: Option<&Path>,
+    extension: &str,
+) -> Result<Vec<Job>, String> {
+    let mut jobs = Vec::new();
+    for input in inputs {
+        // the directory that is mirrored and the images found for this input
+        let mut files = Vec::new();
+        let base = if is_pattern(input) {
+            let pattern = input
+                .to_str()
+                .ok_or_else(|| format!("Pattern {} is not valid UTF-8", input.display()))?;
+            let paths =
+                glob::glob(pattern).map_err(|e| format!("Invalid pattern {}: {}", pattern, e))?;
+            for path in paths {
+                let path = path.map_err(|e| e.to_string())?;
+                if path.is_dir() {
+                    walk(&path, &mut files)?;
+                } else if is_image(&path) {
+                    files.push(path);
+                }
+            }
+            pattern_base(input)
+        } else if input.is_dir() {
+            walk(input, &mut files)?;
+            input.clone()
+        } else {
+            files.push(input.clone());
+            input.parent().map(Path::to_path_buf).unwrap_or_default()
+        };
+
+        for file in files {
+            let relative = file
+                .strip_prefix(&base)
+                .ok()
+                .filter(|relative| !relative.components().any(|c| c == Component::ParentDir))
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| file.file_name().unwrap_or_default().into());
+            let output = output_dir.unwrap_or(&base).join(relative);
+            jobs.push(Job {
+                input: file,
+                output: output.with_extension(extension),
+            });
+        }
+    }
+
+    // files matched by more than one input are only compressed once
+    jobs.sort_by(|a, b| a.input.cmp(&b.input));
+    jobs.dedup_by(|a, b| a.input == b.input);
+
+    // e.g. a.png and a.tga, or files of the same name given from different directories
+    let mut outputs = HashMap::new();
+    let conflicts: Vec<String> = jobs
+        .iter()
+        .filter_map(|job| match outputs.entry(&job.output) {
+            Entry::Vacant(entry) => {
+                entry.insert(&job.input);
+                None
+            }
+            Entry::Occupied(entry) => Some(format!(
+                "{} and {} would both be written to {}",
+                entry.get().display(),
+                job.input.display(),
+                job.output.display()
+            )),
+        })
+        .collect();
+    if !conflicts.is_empty() {
+        return Err(conflicts.join("\n"));
+    }
+    Ok(jobs)
+}
+
+/// Runs `compress` for each job on `threads` threads and prints a summary. `compress`
+/// returns whether the output came from the cache, and failures, including panics, are
+/// reported in the summary. Returns the number of failed jobs.
+pub fn run<J, F>(jobs: &[J], threads: usize, compress: F) -> usize
+where
+    J: AsRef<Job> + Sync,
+    F: Fn(&J) -> Result<bool, String> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let cached = AtomicUsize::new(0);
+    let failures = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, jobs.len().max(1)) {
+            scope.spawn(|| {
+                while let Some(item) = jobs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let job = item.as_ref();
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| compress(item)))
+                        .unwrap_or_else(|payload| Err(panic_message(payload)));
+                    match result {
+                        Ok(false) => {
+                            println!("{} -> {}", job.input.display(), job.output.display())
+                        }
+                        Ok(true) => {
+                            cached.fetch_add(1, Ordering::Relaxed);
+                            println!(
+                                "{} -> {} (cached)",
+                                job.input.display(),
+                                job.output.display()
+                            );
+                        }
+                        Err(message) => failures.lock().unwrap().push((&job.input, message)),
+                    }
+                }
+            });
+        }
+    });
+
+    let mut failures = failures.into_inner().unwrap();
+    failures.sort();
+    let cached = match cached.into_inner() {
+        0 => String::new(),
+        cached => format!(" ({} cached)", cached),
+    };
+    println!(
+        "Compressed {} of {} files{}, {} failed",
+        jobs.len() - failures.len(),
+        jobs.len(),
+        cached,
+        failures.len()
+    );
+    for (input, message) in &failures {
+        eprintln!("{}: {}", input.display(), message);
+    }
+    failures.len()
+}
+
+/// Returns the message of a panic, which is a string unless it was raised with a value
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => String::from("panicked"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates empty files below `dir`
+    fn touch(dir: &Path, files: &[&str]) {
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, []).unwrap();
+        }
+    }
+
+    fn outputs(jobs: &[Job], dir: &Path) -> Vec<PathBuf> {
+        jobs.iter()
+            .map(|job| job.output.strip_prefix(dir).unwrap().to_path_buf())
+            .collect()
+    }
+
+    #[test]
+    fn test_pattern_base() {
+        let base = |pattern: &str| pattern_base(Path::new(pattern));
+        assert_eq!(base("textures/**/*.png"), Path::new("textures"));
+        assert_eq!(base("textures/ui/icon?.png"), Path::new("textures/ui"));
+        assert_eq!(base("textures/[ab]/*.png"), Path::new("textures"));
+        assert_eq!(base("*.png"), Path::new(""));
+    }
+
+    #[test]
+    fn test_jobs_mirror_directories() {
+        let dir = crate::test_dir("batch-mirror");
+        touch(
+            &dir,
+            &["tex/a.png", "tex/ui/b.tga", "tex/notes.txt", "c.jpg"],
+        );
+        let out = dir.join("out");
+
+        // directories are mirrored, files given directly are written by name
+        let inputs = [dir.join("tex"), dir.join("c.jpg")];
+        let result = jobs(&inputs, Some(&out), "ktx2").unwrap();
+        assert_eq!(
+            outputs(&result, &out),
+            ["c.ktx2", "a.ktx2", "ui/b.ktx2"].map(PathBuf::from)
+        );
+
+        // patterns are mirrored from their directories without wildcards
+        let result = jobs(&[dir.join("tex/*/*.tga")], Some(&out), "dds").unwrap();
+        assert_eq!(outputs(&result, &out), [PathBuf::from("ui/b.dds")]);
+
+        // without an output directory, outputs are written next to the inputs
+        let result = jobs(&[dir.join("tex")], None, "dds").unwrap();
+        assert_eq!(
+            outputs(&result, &dir),
+            ["tex/a.dds", "tex/ui/b.dds"].map(PathBuf::from)
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_jobs_duplicates() {
+        let dir = crate::test_dir("batch-duplicates");
+        touch(
+            &dir,
+            &["tex/a.png", "tex/b.png", "other/a.png", "tex/b.bmp"],
+        );
+        let out = dir.join("out");
+
+        // inputs matched more than once are compressed once
+        let inputs = [
+            dir.join("tex/a.png"),
+            dir.join("tex/*.png"),
+            dir.join("tex"),
+        ];
+        let result = jobs(&inputs[..2], Some(&out), "dds").unwrap();
+        assert_eq!(
+            outputs(&result, &out),
+            ["a.dds", "b.dds"].map(PathBuf::from)
+        );
+
+        // different inputs must not be written to the same output
+        let error = jobs(&inputs[2..], Some(&out), "dds").err().unwrap();
+        assert!(
+            error.contains("b.bmp") && error.contains("b.png"),
+            "{}",
+            error
+        );
+        let inputs = [dir.join("tex/a.png"), dir.join("other/a.png")];
+        assert!(jobs(&inputs, Some(&out), "dds").is_err());
+        assert_eq!(jobs(&inputs, None, "dds").unwrap().len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_failures() {
+        let jobs: Vec<Job> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| Job {
+                input: PathBuf::from(name),
+                output: PathBuf::from(name).with_extension("dds"),
+            })
+            .collect();
+
+        // a panicking job is a failure like any other and the remaining jobs still run
+        let finished = AtomicUsize::new(0);
+        let failed = run(&jobs, 2, |job| {
+            finished.fetch_add(1, Ordering::Relaxed);
+            match job.input.to_str().unwrap() {
+                "b" => panic!("corrupt input"),
+                "c" => Err(String::from("unsupported format")),
+                _ => Ok(false),
+            }
+        });
+        assert_eq!(failed, 2);
+        assert_eq!(finished.into_inner(), 4);
+        assert_eq!(panic_message(Box::new(format!("{}", 1))), "1");
+        assert_eq!(panic_message(Box::new("message")), "message");
+        assert_eq!(panic_message(Box::new(1)), "panicked");
+    }
+}
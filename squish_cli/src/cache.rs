@@ -0,0 +1,196 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Cache of compressed outputs, keyed by the input pixels and the compression settings
+//!
+//! Each entry is a complete output file named after the BLAKE3 hash of the decoded image,
+//! the settings, the container and the version of squish, so any change to them misses
+//! the cache.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::image::Image;
+use crate::{Compression, Container};
+
+/// The directory of the cache, and whether it is only written to
+pub struct Cache {
+    pub dir: PathBuf,
+    pub force: bool,
+}
+
+/// An entry of the cache
+pub struct Entry {
+    path: PathBuf,
+}
+
+impl Cache {
+    /// Returns the entry for an image compressed with these settings
+    pub fn entry(&self, image: &Image, compression: &Compression, container: Container) -> Entry {
+        let settings = format!("squish {} {:?}", env!("CARGO_PKG_VERSION"), compression);
+        let (width, height) = image.size();
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(settings.as_bytes());
+        hasher.update(&(width as u64).to_le_bytes());
+        hasher.update(&(height as u64).to_le_bytes());
+        match image {
+            Image::Raw(image) => {
+                hasher.update(&image.data);
+                if let Some(data16) = &image.data16 {
+                    update(&mut hasher, data16, |x| x.to_le_bytes());
+                }
+            }
+            Image::Float(image) => update(&mut hasher, &image.data, |x| x.to_le_bytes()),
+        }
+        let name = format!("{}.{}", hasher.finalize().to_hex(), container.extension());
+        Entry {
+            path: self.dir.join(name),
+        }
+    }
+
+    /// Returns the cached output of an entry, unless the cache is forced to miss
+    pub fn load(&self, entry: &Entry) -> Option<Vec<u8>> {
+        if self.force {
+            return None;
+        }
+        fs::read(&entry.path).ok()
+    }
+
+    /// Stores the output of an entry
+    pub fn store(&self, entry: &Entry, bytes: &[u8]) -> io::Result<()> {
+        // jobs with identical inputs may store the same entry at the same time
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        fs::create_dir_all(&self.dir)?;
+        let temp = entry.path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&temp, bytes)?;
+        fs::rename(&temp, &entry.path).inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })
+    }
+}
+
+/// Hashes values wider than a byte in chunks
+fn update<T: Copy, const N: usize>(
+    hasher: &mut blake3::Hasher,
+    data: &[T],
+    bytes: fn(T) -> [u8; N],
+) {
+    let mut buffer = Vec::new();
+    for chunk in data.chunks(4096) {
+        buffer.clear();
+        buffer.extend(chunk.iter().flat_map(|&x| bytes(x)));
+        hasher.update(&buffer);
+    }
+}
+
+/// Writes an output file unless it already has these contents
+pub fn write_if_changed(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if fs::read(path).is_ok_and(|existing| existing == bytes) {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{FloatImage, RawImage, ToneMap};
+    use squish::{Format, Params};
+
+    fn raw(data: Vec<u8>, data16: Option<Vec<u16>>) -> Image {
+        Image::Raw(RawImage {
+            width: 2,
+            height: 1,
+            data,
+            data16,
+        })
+    }
+
+    #[test]
+    fn test_entry_key() {
+        let cache = Cache {
+            dir: PathBuf::from("cache"),
+            force: false,
+        };
+        let compression = Compression {
+            format: Format::Bc1,
+            params: Params::default(),
+            mipmaps: None,
+            zstd: false,
+            tone_map: ToneMap::Clamp,
+            exposure: 0.0,
+        };
+        let image = raw(vec![1, 2, 3, 4, 5, 6, 7, 8], None);
+        let key = |image: &Image, compression: &Compression, container| {
+            cache.entry(image, compression, container).path
+        };
+
+        let entry = key(&image, &compression, Container::Dds);
+        assert_eq!(entry.parent(), Some(Path::new("cache")));
+        assert_eq!(entry.extension().unwrap(), "dds");
+        assert_eq!(entry, key(&image, &compression, Container::Dds));
+
+        // any change to the settings, the container or the pixels misses the cache
+        let mut others = vec![key(&image, &compression, Container::Ktx2)];
+        for compression in [
+            Compression {
+                format: Format::Bc7,
+                ..compression
+            },
+            Compression {
+                params: Params {
+                    srgb: true,
+                    ..compression.params
+                },
+                ..compression
+            },
+            Compression {
+                exposure: 1.0,
+                ..compression
+            },
+        ] {
+            others.push(key(&image, &compression, Container::Dds));
+        }
+        for other in [
+            raw(vec![1, 2, 3, 4, 5, 6, 7, 9], None),
+            raw(vec![1, 2, 3, 4, 5, 6, 7, 8], Some(vec![0x101; 8])),
+            Image::Float(FloatImage {
+                width: 2,
+                height: 1,
+                data: vec![0.5; 8],
+            }),
+        ] {
+            others.push(key(&other, &compression, Container::Dds));
+        }
+        for (i, other) in others.iter().enumerate() {
+            assert_ne!(&entry, other, "{}", i);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Reader for uncompressed Windows and OS/2 bitmaps with 1 to 32 bits per pixel
+
+use std::fs;
+use std::path::Path;
+
+use super::{scale_to_u8, RawImage};
+
+const FILE_HEADER_SIZE: usize = 14;
+
+// compression methods
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+pub fn read(path: &Path) -> Result<RawImage, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    decode(&bytes).map_err(|e| format!("Failed to read BMP file: {}", e))
+}
+
+fn decode(bytes: &[u8]) -> Result<RawImage, &'static str> {
+    let u16_at = |offset: usize| {
+        bytes
+            .get(offset..offset + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .ok_or("header is truncated")
+    };
+    let u32_at = |offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or("header is truncated")
+    };
+
+    if !bytes.starts_with(b"BM") {
+        return Err("missing BM signature");
+    }
+    let data_offset = u32_at(10)? as usize;
+    let info_size = u32_at(FILE_HEADER_SIZE)? as usize;
+
+    let (width, height, depth, compression, colours_used, entry_size);
+    if info_size == 12 {
+        // OS/2 BITMAPCOREHEADER
+        width = i32::from(u16_at(18)?);
+        height = i32::from(u16_at(20)? as i16);
+        depth = u16_at(24)?;
+        compression = BI_RGB;
+        colours_used = 0;
+        entry_size = 3;
+    } else if info_size >= 40 {
+        width = u32_at(18)? as i32;
+        height = u32_at(22)? as i32;
+        depth = u16_at(28)?;
+        compression = u32_at(30)?;
+        colours_used = u32_at(46)? as usize;
+        entry_size = 4;
+    } else {
+        return Err("unsupported header");
+    }
+    if width <= 0 || height == 0 {
+        return Err("image is empty");
+    }
+    let width = width as usize;
+    let top_down = height < 0;
+    let height = height.unsigned_abs() as usize;
+
+    // the masks follow BITMAPINFOHEADER, and are part of the later headers
+    let mut masks_end = FILE_HEADER_SIZE + info_size;
+    let mask_count = match compression {
+        BI_RGB => 0,
+        BI_BITFIELDS => 3,
+        BI_ALPHABITFIELDS => 4,
+        _ => return Err("compressed bitmaps are not supported"),
+    };
+    let mut masks = match depth {
+        16 => [0x7c00, 0x03e0, 0x001f, 0],
+        24 | 32 => [0xff_0000, 0xff00, 0xff, 0],
+        _ => [0; 4],
+    };
+    if mask_count > 0 {
+        if info_size == 40 {
+            masks_end += mask_count * 4;
+        }
+        for (i, mask) in masks.iter_mut().enumerate().take(mask_count) {
+            *mask = u32_at(FILE_HEADER_SIZE + 40 + i * 4)?;
+        }
+        if info_size >= 56 {
+            masks[3] = u32_at(FILE_HEADER_SIZE + 52)?;
+        }
+    }
+
+    let palette = if depth <= 8 {
+        let count = match colours_used {
+            0 => 1 << depth,
+            count => count.min(1 << depth),
+        };
+        let table = bytes
+            .get(masks_end..masks_end + count * entry_size)
+            .ok_or("colour table is truncated")?;
+        table
+            .chunks(entry_size)
+            .map(|bgr| [bgr[2], bgr[1], bgr[0], 255])
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let stride = (width * usize::from(depth)).div_ceil(32) * 4;
+    let data = bytes
+        .get(data_offset..)
+        .and_then(|data| data.get(..stride * height))
+        .ok_or("image data is truncated")?;
+
+    // the fourth byte of BI_RGB pixels is unused, but some writers store alpha in it
+    if depth == 32 && compression == BI_RGB {
+        let has_alpha = data
+            .chunks(stride)
+            .any(|row| row[..width * 4].chunks(4).any(|bgra| bgra[3] != 0));
+        if has_alpha {
+            masks[3] = 0xff00_0000;
+        }
+    }
+
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        // rows are stored bottom to top unless the height is negative
+        let row = if top_down { y } else { height - 1 - y };
+        let row = &data[row * stride..(row + 1) * stride];
+        for x in 0..width {
+            let rgba = match depth {
+                1 | 2 | 4 | 8 => {
+                    let depth = usize::from(depth);
+                    let bit = x * depth;
+                    let byte = usize::from(row[bit / 8]);
+                    let index = (byte >> (8 - depth - bit % 8)) & ((1 << depth) - 1);
+                    *palette
+                        .get(index)
+                        .ok_or("colour index is outside the colour table")?
+                }
+                16 => unpack(
+                    u32::from(u16::from_le_bytes([row[x * 2], row[x * 2 + 1]])),
+                    &masks,
+                ),
+                24 => [row[x * 3 + 2], row[x * 3 + 1], row[x * 3], 255],
+                32 => {
+                    let bytes = [row[x * 4], row[x * 4 + 1], row[x * 4 + 2], row[x * 4 + 3]];
+                    unpack(u32::from_le_bytes(bytes), &masks)
+                }
+                _ => return Err("unsupported bit depth"),
+            };
+            pixels.extend_from_slice(&rgba);
+        }
+    }
+
+    Ok(RawImage {
+        width,
+        height,
+        data: pixels,
+        data16: None,
+    })
+}
+
+/// Extracts the channels of a pixel with masks, alpha is opaque without a mask
+fn unpack(pixel: u32, masks: &[u32; 4]) -> [u8; 4] {
+    let channel = |mask: u32, missing: u8| {
+        if mask == 0 {
+            missing
+        } else {
+            scale_to_u8((pixel & mask) >> mask.trailing_zeros(), mask.count_ones())
+        }
+    };
+    [
+        channel(masks[0], 0),
+        channel(masks[1], 0),
+        channel(masks[2], 0),
+        channel(masks[3], 255),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a file from an info header, the masks or colour table and the pixel data
+    fn bitmap(info: &[u8], table: &[u8], data: &[u8]) -> Vec<u8> {
+        let offset = (FILE_HEADER_SIZE + info.len() + table.len()) as u32;
+        let mut bytes = b"BM".to_vec();
+        bytes.extend_from_slice(&(offset + data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&offset.to_le_bytes());
+        bytes.extend_from_slice(info);
+        bytes.extend_from_slice(table);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    /// Builds a BITMAPINFOHEADER
+    fn info(width: i32, height: i32, depth: u16, compression: u32) -> Vec<u8> {
+        let mut info = 40u32.to_le_bytes().to_vec();
+        info.extend_from_slice(&width.to_le_bytes());
+        info.extend_from_slice(&height.to_le_bytes());
+        info.extend_from_slice(&1u16.to_le_bytes());
+        info.extend_from_slice(&depth.to_le_bytes());
+        info.extend_from_slice(&compression.to_le_bytes());
+        info.extend_from_slice(&[0; 20]);
+        info
+    }
+
+    fn pixels(image: &RawImage) -> Vec<[u8; 4]> {
+        image
+            .data
+            .chunks(4)
+            .map(|p| [p[0], p[1], p[2], p[3]])
+            .collect()
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    #[test]
+    fn test_row_order() {
+        // one pixel per row, padded to four bytes
+        let data = [0, 0, 255, 0, 0, 255, 0, 0];
+        let image = decode(&bitmap(&info(1, 2, 24, BI_RGB), &[], &data)).unwrap();
+        assert_eq!((image.width, image.height), (1, 2));
+        assert_eq!(pixels(&image), [GREEN, RED]);
+
+        let image = decode(&bitmap(&info(1, -2, 24, BI_RGB), &[], &data)).unwrap();
+        assert_eq!(pixels(&image), [RED, GREEN]);
+
+        assert_eq!(
+            decode(&bitmap(&info(1, 3, 24, BI_RGB), &[], &data)).err(),
+            Some("image data is truncated")
+        );
+    }
+
+    #[test]
+    fn test_bitfields() {
+        // 5:6:5 masks after BITMAPINFOHEADER
+        let masks = [0xf800u32, 0x07e0, 0x001f].map(u32::to_le_bytes).concat();
+        let data = [0xf800u16, 0x07e0].map(u16::to_le_bytes).concat();
+        let bytes = bitmap(&info(2, 1, 16, BI_BITFIELDS), &masks, &data);
+        assert_eq!(pixels(&decode(&bytes).unwrap()), [RED, GREEN]);
+
+        // the same pixels are 5:5:5 without masks
+        let bytes = bitmap(&info(2, 1, 16, BI_RGB), &[], &data);
+        assert_eq!(
+            pixels(&decode(&bytes).unwrap()),
+            [[247, 0, 0, 255], [8, 255, 0, 255]]
+        );
+
+        // BITMAPV3INFOHEADER holds the masks of RGBA pixels, including alpha
+        let mut v3 = info(2, 1, 32, BI_BITFIELDS);
+        v3[..4].copy_from_slice(&56u32.to_le_bytes());
+        for mask in [0xffu32, 0xff00, 0xff_0000, 0xff00_0000] {
+            v3.extend_from_slice(&mask.to_le_bytes());
+        }
+        let data = [255, 0, 0, 0x80, 0, 0, 255, 255];
+        assert_eq!(
+            pixels(&decode(&bitmap(&v3, &[], &data)).unwrap()),
+            [[255, 0, 0, 0x80], BLUE]
+        );
+    }
+
+    #[test]
+    fn test_os2_header() {
+        // BITMAPCOREHEADER with a colour table of three-byte entries
+        let mut core = 12u32.to_le_bytes().to_vec();
+        for value in [2u16, 1, 1, 1] {
+            core.extend_from_slice(&value.to_le_bytes());
+        }
+        let table = [255, 0, 0, 0, 0, 255];
+        let bytes = bitmap(&core, &table, &[0b0100_0000, 0, 0, 0]);
+        assert_eq!(pixels(&decode(&bytes).unwrap()), [BLUE, RED]);
+    }
+}
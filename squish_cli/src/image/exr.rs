@@ -0,0 +1,629 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Reader and writer for single-part scanline OpenEXR images
+//!
+//! Supports uncompressed, RLE, ZIPS and ZIP compression and half, float and unsigned
+//! integer channels. The R, G, B and A channels are read, or Y for greyscale images.
+//! Images are written uncompressed with half channels.
+
+use std::fs;
+use std::path::Path;
+
+use super::{f16_to_f32, FloatImage};
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+
+// version flags
+const TILED: u32 = 0x200;
+const NON_IMAGE: u32 = 0x800;
+const MULTIPART: u32 = 0x1000;
+
+// compression methods
+const NO_COMPRESSION: u8 = 0;
+const RLE_COMPRESSION: u8 = 1;
+const ZIPS_COMPRESSION: u8 = 2;
+const ZIP_COMPRESSION: u8 = 3;
+
+const TRUNCATED: &str = "file is truncated";
+const TOO_LARGE: &str = "image is too large";
+
+/// Largest factor by which a chunk can expand, which is 128 bytes from a two byte run for
+/// RLE and a little over 1000 for deflate
+const fn max_expansion(compression: u8) -> usize {
+    match compression {
+        NO_COMPRESSION => 1,
+        RLE_COMPRESSION => 64,
+        _ => 1032,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PixelType {
+    Uint,
+    Half,
+    Float,
+}
+
+struct Channel {
+    name: String,
+    pixel_type: PixelType,
+}
+
+impl PixelType {
+    fn size(self) -> usize {
+        match self {
+            PixelType::Half => 2,
+            PixelType::Uint | PixelType::Float => 4,
+        }
+    }
+}
+
+pub fn read(path: &Path) -> Result<FloatImage, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    decode(&bytes).map_err(|e| format!("Failed to read OpenEXR file: {}", e))
+}
+
+/// Writes an uncompressed image of half float channels
+///
+/// * `names` - The name of each channel
+/// * `data`  - The bit patterns of the half floats, one per channel for each pixel
+pub fn write(
+    path: &Path,
+    width: usize,
+    height: usize,
+    names: &[&str],
+    data: &[u16],
+) -> Result<(), String> {
+    let attribute = |bytes: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]| {
+        for text in [name, kind] {
+            bytes.extend_from_slice(text.as_bytes());
+            bytes.push(0);
+        }
+        bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(value);
+    };
+
+    // the channel list is sorted by name
+    let mut order: Vec<usize> = (0..names.len()).collect();
+    order.sort_by_key(|&i| names[i]);
+    let mut channels = Vec::new();
+    for &i in &order {
+        channels.extend_from_slice(names[i].as_bytes());
+        channels.push(0);
+        // half, not linear, no subsampling
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&[0; 4]);
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&2u32.to_le_bytes());
+    attribute(&mut bytes, "channels", "chlist", &channels);
+    attribute(&mut bytes, "compression", "compression", &[NO_COMPRESSION]);
+    attribute(&mut bytes, "dataWindow", "box2i", &window);
+    attribute(&mut bytes, "displayWindow", "box2i", &window);
+    attribute(&mut bytes, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut bytes, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute(&mut bytes, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut bytes,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    bytes.push(0);
+
+    // one line per chunk, each storing all samples of each channel in turn
+    let line_size = 8 + width * names.len() * 2;
+    let first_line = bytes.len() + height * 8;
+    for y in 0..height {
+        bytes.extend_from_slice(&((first_line + y * line_size) as u64).to_le_bytes());
+    }
+    for (y, row) in data.chunks(width * names.len()).enumerate() {
+        bytes.extend_from_slice(&(y as i32).to_le_bytes());
+        bytes.extend_from_slice(&((line_size - 8) as u32).to_le_bytes());
+        for &i in &order {
+            for pixel in row.chunks(names.len()) {
+                bytes.extend_from_slice(&pixel[i].to_le_bytes());
+            }
+        }
+    }
+    fs::write(path, bytes).map_err(|e| format!("Failed to write OpenEXR file: {}", e))
+}
+
+/// Reads little endian values from a byte slice
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], &'static str> {
+        if count > self.data.len() {
+            return Err(TRUNCATED);
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i32(&mut self) -> Result<i32, &'static str> {
+        let bytes = self.bytes(4)?;
+        Ok(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, &'static str> {
+        Ok(self.i32()? as u32)
+    }
+
+    fn u64(&mut self) -> Result<u64, &'static str> {
+        Ok(u64::from(self.u32()?) | (u64::from(self.u32()?) << 32))
+    }
+
+    /// Reads a null-terminated string
+    fn string(&mut self) -> Result<&'a str, &'static str> {
+        let length = self.data.iter().position(|&b| b == 0).ok_or(TRUNCATED)?;
+        let bytes = self.bytes(length + 1)?;
+        std::str::from_utf8(&bytes[..length]).map_err(|_| "invalid attribute name")
+    }
+}
+
+fn decode(bytes: &[u8]) -> Result<FloatImage, &'static str> {
+    let mut reader = Reader { data: bytes };
+    if reader.bytes(4)? != MAGIC {
+        return Err("missing OpenEXR signature");
+    }
+    let version = reader.u32()?;
+    if version & 0xff != 2 {
+        return Err("unsupported version");
+    }
+    if version & (TILED | NON_IMAGE | MULTIPART) != 0 {
+        return Err("only single-part scanline images are supported");
+    }
+
+    let mut channels = None;
+    let mut compression = None;
+    let mut data_window = None;
+    loop {
+        let name = reader.string()?;
+        if name.is_empty() {
+            break;
+        }
+        let kind = reader.string()?;
+        let size = reader.u32()? as usize;
+        let mut value = Reader {
+            data: reader.bytes(size)?,
+        };
+        match (name, kind) {
+            ("channels", "chlist") => channels = Some(read_channels(&mut value)?),
+            ("compression", "compression") => compression = Some(value.u8()?),
+            ("dataWindow", "box2i") => {
+                let window = [value.i32()?, value.i32()?, value.i32()?, value.i32()?];
+                data_window = Some(window);
+            }
+            _ => {}
+        }
+    }
+    let channels = channels.ok_or("channel list is missing")?;
+    let compression = compression.ok_or("compression is missing")?;
+    let [x_min, y_min, x_max, y_max] = data_window.ok_or("data window is missing")?;
+    if x_max < x_min || y_max < y_min {
+        return Err("image is empty");
+    }
+    let width = usize::try_from(i64::from(x_max) - i64::from(x_min) + 1).map_err(|_| TOO_LARGE)?;
+    let height = usize::try_from(i64::from(y_max) - i64::from(y_min) + 1).map_err(|_| TOO_LARGE)?;
+    let values = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(4))
+        .ok_or(TOO_LARGE)?;
+
+    let lines_per_chunk = match compression {
+        NO_COMPRESSION | RLE_COMPRESSION | ZIPS_COMPRESSION => 1,
+        ZIP_COMPRESSION => 16,
+        _ => return Err("unsupported compression, only none, RLE, ZIPS and ZIP are supported"),
+    };
+    let line_size = channels
+        .iter()
+        .try_fold(0usize, |sum, c| {
+            sum.checked_add(c.pixel_type.size() * width)
+        })
+        .ok_or(TOO_LARGE)?;
+
+    // the targets of each channel, R, G and B default to 0 and alpha to 1
+    let greyscale = !channels
+        .iter()
+        .any(|c| matches!(c.name.as_str(), "R" | "G" | "B"));
+    let targets = |name: &str| -> &'static [usize] {
+        match name {
+            "R" => &[0],
+            "G" => &[1],
+            "B" => &[2],
+            "A" => &[3],
+            "Y" if greyscale => &[0, 1, 2],
+            _ => &[],
+        }
+    };
+    // the offset table and the chunks it points to need to be in the file before the
+    // dimensions are trusted with the allocation
+    let count = height.div_ceil(lines_per_chunk);
+    if reader.data.len() / 8 < count {
+        return Err(TRUNCATED);
+    }
+    let mut chunks = Vec::with_capacity(count);
+    for _ in 0..count {
+        let offset = usize::try_from(reader.u64()?).map_err(|_| TRUNCATED)?;
+        let mut chunk = Reader {
+            data: bytes.get(offset..).ok_or(TRUNCATED)?,
+        };
+        let y = i64::from(chunk.i32()?) - i64::from(y_min);
+        let size = chunk.u32()? as usize;
+        let data = chunk.bytes(size)?;
+        if y < 0 || y as usize >= height {
+            return Err("chunk is outside the data window");
+        }
+        let y = y as usize;
+        let lines = lines_per_chunk.min(height - y);
+        let expected = lines.checked_mul(line_size).ok_or(TOO_LARGE)?;
+        if size.saturating_mul(max_expansion(compression)) < expected {
+            return Err("chunk is too small for its lines");
+        }
+        chunks.push((y, lines, expected, data));
+    }
+
+    let mut pixels = [0.0, 0.0, 0.0, 1.0].repeat(values / 4);
+    for (y, lines, expected, data) in chunks {
+        // chunks that do not get smaller are stored uncompressed
+        let data = if compression == NO_COMPRESSION || data.len() == expected {
+            data.to_vec()
+        } else {
+            let data = match compression {
+                RLE_COMPRESSION => decompress_rle(data, expected)?,
+                _ => miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(data, expected)
+                    .map_err(|_| "invalid ZIP data")?,
+            };
+            reconstruct(&data)
+        };
+        if data.len() != expected {
+            return Err("chunk has the wrong size");
+        }
+
+        // each line stores all samples of each channel in turn
+        let mut samples = data.as_slice();
+        for line in 0..lines {
+            let row = (y + line) * width;
+            for channel in &channels {
+                let size = channel.pixel_type.size();
+                let (values, rest) = samples.split_at(size * width);
+                samples = rest;
+                let targets = targets(&channel.name);
+                for (x, value) in values.chunks(size).enumerate() {
+                    let value = match channel.pixel_type {
+                        PixelType::Half => f16_to_f32(u16::from_le_bytes([value[0], value[1]])),
+                        PixelType::Float => {
+                            f32::from_le_bytes([value[0], value[1], value[2], value[3]])
+                        }
+                        PixelType::Uint => {
+                            u32::from_le_bytes([value[0], value[1], value[2], value[3]]) as f32
+                        }
+                    };
+                    for &target in targets {
+                        pixels[(row + x) * 4 + target] = value;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(FloatImage {
+        width,
+        height,
+        data: pixels,
+    })
+}
+
+fn read_channels(reader: &mut Reader) -> Result<Vec<Channel>, &'static str> {
+    let mut channels = Vec::new();
+    loop {
+        let name = reader.string()?;
+        if name.is_empty() {
+            return Ok(channels);
+        }
+        let pixel_type = match reader.i32()? {
+            0 => PixelType::Uint,
+            1 => PixelType::Half,
+            2 => PixelType::Float,
+            _ => return Err("unknown channel type"),
+        };
+        // linear flag and reserved bytes
+        reader.bytes(4)?;
+        if reader.i32()? != 1 || reader.i32()? != 1 {
+            return Err("subsampled channels are not supported");
+        }
+        channels.push(Channel {
+            name: name.to_string(),
+            pixel_type,
+        });
+    }
+}
+
+/// Expands runs, where a negative count is followed by that many literal bytes and other
+/// counts by a byte repeated count + 1 times
+fn decompress_rle(data: &[u8], size: usize) -> Result<Vec<u8>, &'static str> {
+    let mut output = Vec::with_capacity(size);
+    let mut reader = Reader { data };
+    while !reader.data.is_empty() {
+        let count = reader.u8()? as i8;
+        if count < 0 {
+            output.extend_from_slice(reader.bytes(usize::from(count.unsigned_abs()))?);
+        } else {
+            let value = reader.u8()?;
+            output.resize(output.len() + count as usize + 1, value);
+        }
+        if output.len() > size {
+            return Err("run is too long");
+        }
+    }
+    Ok(output)
+}
+
+/// Undoes the byte delta predictor and the split into even and odd bytes of RLE and ZIP
+fn reconstruct(data: &[u8]) -> Vec<u8> {
+    let mut deltas = data.to_vec();
+    for i in 1..deltas.len() {
+        deltas[i] = deltas[i - 1].wrapping_add(deltas[i]).wrapping_sub(128);
+    }
+    let (even, odd) = deltas.split_at(deltas.len().div_ceil(2));
+    let mut output = Vec::with_capacity(data.len());
+    for (i, &byte) in even.iter().enumerate() {
+        output.push(byte);
+        if let Some(&byte) = odd.get(i) {
+            output.push(byte);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inverse of `reconstruct`
+    fn predict(data: &[u8]) -> Vec<u8> {
+        let split: Vec<u8> = data
+            .iter()
+            .step_by(2)
+            .chain(data.iter().skip(1).step_by(2))
+            .copied()
+            .collect();
+        let mut deltas = split.clone();
+        for i in 1..split.len() {
+            deltas[i] = split[i].wrapping_sub(split[i - 1]).wrapping_add(128);
+        }
+        deltas
+    }
+
+    /// Encodes runs of three or more equal bytes as repeats and everything else literally
+    fn compress_rle(data: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            let run = data[i..]
+                .iter()
+                .take(128)
+                .take_while(|&&b| b == data[i])
+                .count();
+            if run >= 3 {
+                output.extend_from_slice(&[(run - 1) as u8, data[i]]);
+                i += run;
+                continue;
+            }
+            let start = i;
+            while i < data.len() && i - start < 127 && !data[i..].starts_with(&[data[i]; 3]) {
+                i += 1;
+            }
+            output.push((-((i - start) as i8)) as u8);
+            output.extend_from_slice(&data[start..i]);
+        }
+        output
+    }
+
+    /// Builds an image of half float channels, named in sorted order, whose lines hold the
+    /// samples of each channel in turn
+    fn image(compression: u8, width: usize, names: &[&str], lines: &[Vec<u16>]) -> Vec<u8> {
+        let attribute = |bytes: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]| {
+            for text in [name, kind] {
+                bytes.extend_from_slice(text.as_bytes());
+                bytes.push(0);
+            }
+            bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(value);
+        };
+        let mut channels = Vec::new();
+        for name in names {
+            channels.extend_from_slice(name.as_bytes());
+            channels.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+        }
+        channels.push(0);
+        let window: Vec<u8> = [0, 0, width as i32 - 1, lines.len() as i32 - 1]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        attribute(&mut bytes, "channels", "chlist", &channels);
+        attribute(&mut bytes, "compression", "compression", &[compression]);
+        attribute(&mut bytes, "dataWindow", "box2i", &window);
+        bytes.push(0);
+
+        let lines_per_chunk = if compression == ZIP_COMPRESSION {
+            16
+        } else {
+            1
+        };
+        let chunks: Vec<Vec<u8>> = lines
+            .chunks(lines_per_chunk)
+            .map(|lines| {
+                let raw: Vec<u8> = lines
+                    .concat()
+                    .iter()
+                    .flat_map(|x| x.to_le_bytes())
+                    .collect();
+                let chunk = match compression {
+                    NO_COMPRESSION => return raw,
+                    RLE_COMPRESSION => compress_rle(&predict(&raw)),
+                    _ => miniz_oxide::deflate::compress_to_vec_zlib(&predict(&raw), 6),
+                };
+                // chunks of the same size would be read as uncompressed
+                assert_ne!(chunk.len(), raw.len());
+                chunk
+            })
+            .collect();
+        let mut offset = bytes.len() + chunks.len() * 8;
+        for chunk in &chunks {
+            bytes.extend_from_slice(&(offset as u64).to_le_bytes());
+            offset += 8 + chunk.len();
+        }
+        for (i, chunk) in chunks.iter().enumerate() {
+            bytes.extend_from_slice(&((i * lines_per_chunk) as i32).to_le_bytes());
+            bytes.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(chunk);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_reconstruct() {
+        assert_eq!(reconstruct(&[1, 129, 2]), [1, 132, 2]);
+        let data: Vec<u8> = (0..=255).rev().chain(0..7).collect();
+        assert_eq!(reconstruct(&predict(&data)), data);
+    }
+
+    #[test]
+    fn test_compression() {
+        // halves of 0, 0.5, 1 and 2 with runs for RLE to find
+        let (zero, half, one, two) = (0, 0x3800, 0x3c00, 0x4000);
+        let lines = [
+            [
+                [one; 8],
+                [half; 8],
+                [two, one, half, zero, zero, zero, zero, one],
+            ]
+            .concat(),
+            [
+                [zero; 8],
+                [one, two, one, two, one, two, one, two],
+                [half; 8],
+            ]
+            .concat(),
+        ];
+        let expected = decode(&image(NO_COMPRESSION, 8, &["B", "G", "R"], &lines)).unwrap();
+        assert_eq!((expected.width, expected.height), (8, 2));
+        assert_eq!(expected.data[..8], [2.0, 0.5, 1.0, 1.0, 1.0, 0.5, 1.0, 1.0]);
+        assert_eq!(expected.data[32..36], [0.5, 1.0, 0.0, 1.0]);
+
+        for compression in [RLE_COMPRESSION, ZIPS_COMPRESSION, ZIP_COMPRESSION] {
+            let bytes = image(compression, 8, &["B", "G", "R"], &lines);
+            assert_eq!(
+                decode(&bytes).unwrap().data,
+                expected.data,
+                "{}",
+                compression
+            );
+            assert_eq!(decode(&bytes[..bytes.len() - 1]).err(), Some(TRUNCATED));
+        }
+        assert!(decode(&image(4, 8, &["B", "G", "R"], &lines)).is_err());
+    }
+
+    #[test]
+    fn test_greyscale() {
+        // Y fills RGB of greyscale images, and missing alpha is opaque
+        let image = decode(&image(NO_COMPRESSION, 2, &["Y"], &[vec![0x3800, 0x4000]])).unwrap();
+        assert_eq!(image.data, [0.5, 0.5, 0.5, 1.0, 2.0, 2.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn test_huge_data_window() {
+        // the data window is trusted only as far as the offset table and chunks back it up
+        let bytes = image(NO_COMPRESSION, 2, &["Y"], &[vec![0x3800, 0x4000]]);
+        let name = b"dataWindow\0box2i\0";
+        let window = bytes.windows(name.len()).position(|w| w == name).unwrap() + name.len() + 4;
+        let with_window = |x_min: i32, x_max: i32, y_max: i32| {
+            let mut bytes = bytes.clone();
+            for (i, value) in [x_min, 0, x_max, y_max].iter().enumerate() {
+                let offset = window + 4 * i;
+                bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            }
+            decode(&bytes).err()
+        };
+        assert_eq!(with_window(0, 1, 0), None);
+        assert_eq!(with_window(0, 1, i32::MAX), Some(TRUNCATED));
+        assert_eq!(
+            with_window(0, i32::MAX, 0),
+            Some("chunk is too small for its lines")
+        );
+        assert!(with_window(i32::MIN, i32::MAX, i32::MAX).is_some());
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = crate::test_dir("exr-write");
+        let path = dir.join("image.exr");
+        // 0.5, 1, 2, -2, 0, 65504 and the smallest subnormal
+        let halves = [
+            0x3800, 0x3c00, 0x4000, 0xc000, 0x0000, 0x7bff, 0x0001, 0x3c00,
+        ];
+        for names in [
+            &["R", "G", "B", "A"][..],
+            &["R", "G", "B"],
+            &["A", "B", "G", "R"],
+        ] {
+            let data: Vec<u16> = halves
+                .iter()
+                .copied()
+                .cycle()
+                .take(names.len() * 6)
+                .collect();
+            write(&path, 3, 2, names, &data).unwrap();
+            let image = decode(&fs::read(&path).unwrap()).unwrap();
+            assert_eq!((image.width, image.height), (3, 2));
+            for (pixel, values) in image.data.chunks(4).zip(data.chunks(names.len())) {
+                let mut expected = [0.0, 0.0, 0.0, 1.0];
+                for (name, &value) in names.iter().zip(values) {
+                    let channel = ["R", "G", "B", "A"].iter().position(|n| n == name).unwrap();
+                    expected[channel] = f16_to_f32(value);
+                }
+                assert_eq!(pixel, expected, "{:?}", names);
+            }
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
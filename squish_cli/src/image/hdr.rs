@@ -0,0 +1,292 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Reader for Radiance RGBE images (.hdr), flat or run-length encoded
+
+use std::fs;
+use std::path::Path;
+
+use super::FloatImage;
+
+pub fn read(path: &Path) -> Result<FloatImage, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    decode(&bytes).map_err(|e| format!("Failed to read Radiance HDR file: {}", e))
+}
+
+const TRUNCATED: &str = "image data is truncated";
+
+fn decode(bytes: &[u8]) -> Result<FloatImage, &'static str> {
+    let mut lines = bytes.split(|&b| b == b'\n');
+    let mut offset = 0;
+    let mut next_line = || {
+        let line = lines.next()?;
+        offset += line.len() + 1;
+        Some(line)
+    };
+
+    let magic = next_line().ok_or("file is empty")?;
+    if !magic.starts_with(b"#?RADIANCE") && !magic.starts_with(b"#?RGBE") {
+        return Err("missing #?RADIANCE signature");
+    }
+    // the header ends with an empty line
+    loop {
+        let line = next_line().ok_or("header is truncated")?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix(b"FORMAT=") {
+            if format != b"32-bit_rle_rgbe" {
+                return Err("only RGBE pixels are supported");
+            }
+        }
+    }
+
+    let resolution = next_line().ok_or("resolution is missing")?;
+    let resolution = std::str::from_utf8(resolution).map_err(|_| "invalid resolution")?;
+    let (bottom_up, height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        [y, height, "+X", width] if y == "-Y" || y == "+Y" => (y == "+Y", height, width),
+        _ => return Err("unsupported orientation"),
+    };
+    let width: usize = width.parse().map_err(|_| "invalid width")?;
+    let height: usize = height.parse().map_err(|_| "invalid height")?;
+    if width == 0 || height == 0 {
+        return Err("image is empty");
+    }
+
+    // each scanline takes at least one RGBE value, which bounds the height by the file size
+    let mut data = bytes.get(offset..).unwrap_or_default();
+    if data.len() / 4 < height {
+        return Err(TRUNCATED);
+    }
+    let size = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(4))
+        .ok_or("image is too large")?;
+    let mut pixels = vec![0.0; size];
+    let mut scanline = vec![[0u8; 4]; width];
+    for y in 0..height {
+        data = read_scanline(data, &mut scanline)?;
+        let row = if bottom_up { height - 1 - y } else { y };
+        let row = &mut pixels[row * width * 4..(row + 1) * width * 4];
+        for (pixel, rgbe) in row.chunks_mut(4).zip(&scanline) {
+            pixel.copy_from_slice(&rgbe_to_f32(*rgbe));
+        }
+    }
+
+    Ok(FloatImage {
+        width,
+        height,
+        data: pixels,
+    })
+}
+
+/// Reads one scanline and returns the data after it
+fn read_scanline<'a>(data: &'a [u8], scanline: &mut [[u8; 4]]) -> Result<&'a [u8], &'static str> {
+    let width = scanline.len();
+    // run-length encoded scanlines store each channel separately
+    if (8..0x8000).contains(&width) && data.len() >= 4 && data[..2] == [2, 2] {
+        let encoded_width = usize::from(u16::from_be_bytes([data[2], data[3]]));
+        if data[2] & 0x80 != 0 || encoded_width != width {
+            return Err("scanline width does not match the image");
+        }
+        let mut data = &data[4..];
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let (&count, rest) = data.split_first().ok_or(TRUNCATED)?;
+                if count > 128 {
+                    let count = usize::from(count - 128);
+                    let &value = rest.first().ok_or(TRUNCATED)?;
+                    for pixel in scanline.get_mut(x..x + count).ok_or("run is too long")? {
+                        pixel[channel] = value;
+                    }
+                    x += count;
+                    data = &rest[1..];
+                } else {
+                    let count = usize::from(count);
+                    if count == 0 {
+                        return Err("empty run");
+                    }
+                    let values = rest.get(..count).ok_or(TRUNCATED)?;
+                    let pixels = scanline.get_mut(x..x + count).ok_or("run is too long")?;
+                    for (pixel, &value) in pixels.iter_mut().zip(values) {
+                        pixel[channel] = value;
+                    }
+                    x += count;
+                    data = &rest[count..];
+                }
+            }
+        }
+        return Ok(data);
+    }
+
+    // flat pixels, where (1, 1, 1, n) repeats the previous pixel in the original encoding
+    let mut data = data;
+    let mut x = 0;
+    let mut shift = 0;
+    while x < width {
+        let (rgbe, rest) = data.split_first_chunk::<4>().ok_or(TRUNCATED)?;
+        data = rest;
+        if rgbe[..3] == [1, 1, 1] && x > 0 {
+            // consecutive runs add higher bytes of the count, of which there are only four
+            if rgbe[3] == 0 {
+                return Err("empty run");
+            }
+            if shift > 24 {
+                return Err("run is too long");
+            }
+            let count = usize::from(rgbe[3]) << shift;
+            let previous = scanline[x - 1];
+            let run = x..x.saturating_add(count);
+            for pixel in scanline.get_mut(run).ok_or("run is too long")? {
+                *pixel = previous;
+            }
+            x += count;
+            shift += 8;
+        } else {
+            scanline[x] = *rgbe;
+            x += 1;
+            shift = 0;
+        }
+    }
+    Ok(data)
+}
+
+fn rgbe_to_f32([r, g, b, e]: [u8; 4]) -> [f32; 4] {
+    if e == 0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+    let scale = (f32::from(e) - 136.0).exp2();
+    [
+        f32::from(r) * scale,
+        f32::from(g) * scale,
+        f32::from(b) * scale,
+        1.0,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(resolution: &str, data: &[u8]) -> Vec<u8> {
+        let header = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution);
+        [header.as_bytes(), data].concat()
+    }
+
+    /// Returns the red channel of each pixel
+    fn red(image: &FloatImage) -> Vec<f32> {
+        image.data.iter().step_by(4).copied().collect()
+    }
+
+    #[test]
+    fn test_flat_scanlines() {
+        // 128 with an exponent of 129 is 1.0, and 0, 0, 0, 0 is black
+        let data = [
+            [128, 64, 32, 129],
+            [0, 0, 0, 0],
+            [64, 128, 0, 130],
+            [128, 0, 0, 128],
+            [1, 1, 1, 1],
+            [32, 0, 0, 129],
+        ]
+        .concat();
+        let image_top_down = decode(&file("-Y 2 +X 3", &data)).unwrap();
+        assert_eq!((image_top_down.width, image_top_down.height), (3, 2));
+        assert_eq!(image_top_down.data[..4], [1.0, 0.5, 0.25, 1.0]);
+        assert_eq!(red(&image_top_down), [1.0, 0.0, 1.0, 0.5, 0.5, 0.25]);
+
+        // +Y stores the rows bottom to top
+        let image_bottom_up = decode(&file("+Y 2 +X 3", &data)).unwrap();
+        assert_eq!(red(&image_bottom_up), [0.5, 0.5, 0.25, 1.0, 0.0, 1.0]);
+
+        assert!(decode(&file("-Y 2 -X 3", &data)).is_err());
+        assert!(decode(&file("-Y 3 +X 3", &data)).is_err());
+
+        // the header alone cannot claim more scanlines than the data holds
+        assert_eq!(
+            decode(&file("-Y 2000000000 +X 2000000000", &[])).err(),
+            Some(TRUNCATED)
+        );
+    }
+
+    #[test]
+    fn test_flat_runs() {
+        // consecutive runs hold the next byte of the count, 2 + 1 * 256 here
+        let data = [
+            [128, 0, 0, 129],
+            [1, 1, 1, 2],
+            [1, 1, 1, 1],
+            [64, 0, 0, 129],
+        ]
+        .concat();
+        let image = decode(&file("-Y 1 +X 260", &data)).unwrap();
+        let red = red(&image);
+        assert!(red[..259].iter().all(|&r| r == 1.0));
+        assert_eq!(red[259], 0.5);
+
+        // empty runs and counts beyond four bytes are invalid
+        let data = [[128, 0, 0, 129], [1, 1, 1, 0]].concat();
+        assert_eq!(decode(&file("-Y 1 +X 2", &data)).err(), Some("empty run"));
+        let data = [[[128, 0, 0, 129]].as_slice(), &[[1, 1, 1, 1]; 5]]
+            .concat()
+            .concat();
+        assert_eq!(
+            decode(&file("-Y 1 +X 8", &data)).err(),
+            Some("run is too long")
+        );
+    }
+
+    #[test]
+    fn test_rle_scanlines() {
+        // each channel of the two scanlines in turn, as literals or runs of up to 127
+        let scanline = |red: &[u8]| -> Vec<u8> {
+            let mut data = vec![2, 2, 0, 8];
+            data.push(8);
+            data.extend_from_slice(red);
+            data.extend_from_slice(&[128 + 8, 0]);
+            data.extend_from_slice(&[3, 0, 0, 0, 128 + 5, 0]);
+            data.extend_from_slice(&[128 + 8, 129]);
+            data
+        };
+        let data = [
+            scanline(&[0, 16, 32, 48, 64, 80, 96, 112]),
+            scanline(&[128; 8]),
+        ]
+        .concat();
+        let image = decode(&file("-Y 2 +X 8", &data)).unwrap();
+        assert_eq!(
+            red(&image)[..8],
+            [0.0, 0.125, 0.25, 0.375, 0.5, 0.625, 0.75, 0.875]
+        );
+        assert!(red(&image)[8..].iter().all(|&r| r == 1.0));
+
+        let mut data = scanline(&[0; 8]);
+        data[3] = 9;
+        assert_eq!(
+            decode(&file("-Y 1 +X 8", &data)).err(),
+            Some("scanline width does not match the image")
+        );
+        let mut data = scanline(&[0; 8]);
+        data[4] = 9;
+        assert!(decode(&file("-Y 1 +X 8", &data)).is_err());
+    }
+}
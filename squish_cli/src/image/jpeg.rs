@@ -26,17 +26,21 @@ use jpeg_decoder::{Decoder, PixelFormat};
 
 use super::RawImage;
 
-pub fn read(path: &Path) -> RawImage {
-    let file = File::open(path).expect("Failed to open file");
+pub fn read(path: &Path) -> Result<RawImage, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
     let mut decoder = Decoder::new(file);
     decoder
         .read_info()
-        .expect("Failed to read JPEG header. Is this really a JPEG file?");
+        .map_err(|e| format!("Failed to read JPEG header: {}", e))?;
 
     // Decode the image
-    let info = decoder.info().unwrap();
+    let info = decoder
+        .info()
+        .ok_or("Failed to read JPEG header. Is this really a JPEG file?")?;
 
-    let mut buf = decoder.decode().unwrap();
+    let mut buf = decoder
+        .decode()
+        .map_err(|e| format!("Failed to read JPEG file: {}", e))?;
     buf = match info.pixel_format {
         PixelFormat::L8 => buf[..]
             .iter()
@@ -46,12 +50,13 @@ pub fn read(path: &Path) -> RawImage {
             .chunks(3)
             .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 255u8])
             .collect::<Vec<u8>>(),
-        PixelFormat::CMYK32 => panic!("CMYK images are not supported!"),
+        PixelFormat::CMYK32 => return Err("CMYK images are not supported".to_string()),
     };
 
-    RawImage {
+    Ok(RawImage {
         width: info.width as usize,
         height: info.height as usize,
         data: buf,
-    }
+        data16: None,
+    })
 }
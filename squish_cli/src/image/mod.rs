@@ -19,11 +19,134 @@
 // TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
 // SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+pub mod bmp;
+pub mod exr;
+pub mod hdr;
 pub mod jpeg;
 pub mod png;
+pub mod tga;
+
+/// A decoded image, with 8 bits per channel or floats for high dynamic range sources
+pub enum Image {
+    Raw(RawImage),
+    Float(FloatImage),
+}
 
 pub struct RawImage {
     pub width: usize,
     pub height: usize,
+    /// RGBA pixels with 8 bits per channel
     pub data: Vec<u8>,
+    /// RGBA pixels with 16 bits per channel, for sources with more than 8 bits
+    pub data16: Option<Vec<u16>>,
+}
+
+/// An image with linear RGBA float pixels
+pub struct FloatImage {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+}
+
+/// Defines how high dynamic range colours are mapped to [0, 1] for 8-bit formats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMap {
+    /// Clamp each channel to [0, 1]
+    Clamp,
+    /// Map each channel with x / (1 + x)
+    Reinhard,
+    /// Fit of the ACES filmic curve by Krzysztof Narkowicz
+    Aces,
+}
+
+impl Image {
+    pub fn size(&self) -> (usize, usize) {
+        match self {
+            Image::Raw(image) => (image.width, image.height),
+            Image::Float(image) => (image.width, image.height),
+        }
+    }
+}
+
+impl FloatImage {
+    /// Returns the RGB channels scaled by `exposure` stops
+    pub fn rgb(&self, exposure: f32) -> Vec<f32> {
+        let scale = exposure.exp2();
+        self.data
+            .chunks(4)
+            .flat_map(|rgba| [rgba[0] * scale, rgba[1] * scale, rgba[2] * scale])
+            .collect()
+    }
+
+    /// Converts to 8 bits per channel, scaling colours by `exposure` stops and mapping them
+    /// to [0, 1] with `tone_map`, encoded as sRGB if requested. Alpha is clamped.
+    pub fn to_rgba8(&self, tone_map: ToneMap, exposure: f32, srgb: bool) -> RawImage {
+        let scale = exposure.exp2();
+        let colour = |x: f32| {
+            let x = (x * scale).max(0.0);
+            let x = match tone_map {
+                ToneMap::Clamp => x,
+                ToneMap::Reinhard => x / (1.0 + x),
+                ToneMap::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            };
+            let x = x.min(1.0);
+            if srgb && x > 0.0031308 {
+                1.055 * x.powf(1.0 / 2.4) - 0.055
+            } else if srgb {
+                12.92 * x
+            } else {
+                x
+            }
+        };
+        let unorm8 = |x: f32| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let data = self
+            .data
+            .chunks(4)
+            .flat_map(|rgba| {
+                [
+                    unorm8(colour(rgba[0])),
+                    unorm8(colour(rgba[1])),
+                    unorm8(colour(rgba[2])),
+                    unorm8(rgba[3]),
+                ]
+            })
+            .collect();
+        RawImage {
+            width: self.width,
+            height: self.height,
+            data,
+            data16: None,
+        }
+    }
+}
+
+/// Converts the bit pattern of a half float to a float
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = u32::from(bits & 0x8000) << 16;
+    let exponent = u32::from(bits >> 10) & 0x1f;
+    let mantissa = u32::from(bits) & 0x3ff;
+    let magnitude = match exponent {
+        // zero and subnormals
+        0 => {
+            let value = mantissa as f32 / (1 << 24) as f32;
+            return if sign != 0 { -value } else { value };
+        }
+        // infinity and NaN
+        31 => 0x7f80_0000 | (mantissa << 13),
+        _ => ((exponent + 112) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(sign | magnitude)
+}
+
+/// Rounds 16-bit channels to the nearest 8-bit value
+pub fn to_u8(data: &[u16]) -> Vec<u8> {
+    data.iter()
+        .map(|&x| ((u32::from(x) * 255 + 32767) / 65535) as u8)
+        .collect()
+}
+
+/// Scales an unsigned value with `bits` bits to 8 bits, rounding to the nearest value
+fn scale_to_u8(value: u32, bits: u32) -> u8 {
+    let max = (1u64 << bits) - 1;
+    ((u64::from(value) * 255 + max / 2) / max) as u8
 }
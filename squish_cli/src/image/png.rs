@@ -27,59 +27,82 @@ use png::{BitDepth, ColorType, Transformations};
 
 use super::RawImage;
 
-pub fn read(path: &Path) -> RawImage {
-    let file = File::open(path).expect("Failed to open file");
+pub fn read(path: &Path) -> Result<RawImage, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
     let mut decoder = png::Decoder::new(file);
+    // palettes, low bit depths and transparency chunks expand to 8 bits per channel
     decoder.set_transformations(Transformations::EXPAND);
 
     let mut reader = decoder
         .read_info()
-        .expect("Failed to read PNG header. Is this really a PNG file?");
+        .map_err(|e| format!("Failed to read PNG header: {}", e))?;
 
     // Preallocate the output buffer.
     let mut buf = vec![0; reader.output_buffer_size()];
 
     // Read the next frame. Currently this function should only called once.
-    reader.next_frame(&mut buf).unwrap();
+    let info = reader
+        .next_frame(&mut buf)
+        .map_err(|e| format!("Failed to read PNG file: {}", e))?;
+    buf.truncate(info.buffer_size());
 
-    let info = reader.info();
-    if info.bit_depth != BitDepth::Eight {
-        panic!("Only images with 8 bits per channel are supported");
-    }
-
-    // expand to rgba
-    buf = match info.color_type {
-        ColorType::Grayscale => buf[..]
-            .iter()
-            .flat_map(|&r| vec![r, r, r, 255])
-            .collect::<Vec<u8>>(),
-        ColorType::GrayscaleAlpha => buf[..]
-            .chunks(2)
-            .flat_map(|rg| vec![rg[0], rg[0], rg[0], rg[1]])
-            .collect::<Vec<u8>>(),
-        ColorType::Rgb => buf[..]
-            .chunks(3)
-            .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 255])
-            .collect::<Vec<u8>>(),
-        ColorType::Rgba => buf,
+    let channels = info.color_type.samples();
+    let (data, data16) = match info.bit_depth {
+        BitDepth::Eight => (expand(&buf, channels, 255), None),
+        BitDepth::Sixteen => {
+            let samples: Vec<u16> = buf
+                .chunks(2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                .collect();
+            let data16 = expand(&samples, channels, u16::MAX);
+            (super::to_u8(&data16), Some(data16))
+        }
         _ => unreachable!(),
     };
 
-    RawImage {
+    Ok(RawImage {
         width: info.width as usize,
         height: info.height as usize,
-        data: buf,
-    }
+        data,
+        data16,
+    })
+}
+
+/// Expands grey, grey and alpha, or RGB samples to RGBA
+fn expand<T: Copy>(samples: &[T], channels: usize, opaque: T) -> Vec<T> {
+    samples
+        .chunks(channels)
+        .flat_map(|pixel| match *pixel {
+            [l] => [l, l, l, opaque],
+            [l, a] => [l, l, l, a],
+            [r, g, b] => [r, g, b, opaque],
+            [r, g, b, a] => [r, g, b, a],
+            _ => unreachable!(),
+        })
+        .collect()
 }
 
-pub fn write(path: &Path, width: u32, height: u32, data: &[u8]) {
-    let file = File::create(path).expect("Unable to create file");
+/// Writes an image with one to four channels, one and two as grey and grey with alpha
+pub fn write(
+    path: &Path,
+    width: u32,
+    height: u32,
+    channels: usize,
+    data: &[u8],
+) -> Result<(), String> {
+    let write_error = |e| format!("Failed to write PNG file: {}", e);
+    let file = File::create(path).map_err(|e| format!("Unable to create file: {}", e))?;
     let w = &mut BufWriter::new(file);
 
     let mut encoder = png::Encoder::new(w, width, height);
-    encoder.set_color(ColorType::Rgba);
+    encoder.set_color(match channels {
+        1 => ColorType::Grayscale,
+        2 => ColorType::GrayscaleAlpha,
+        3 => ColorType::Rgb,
+        _ => ColorType::Rgba,
+    });
     encoder.set_depth(BitDepth::Eight);
-    let mut writer = encoder.write_header().unwrap();
+    let mut writer = encoder.write_header().map_err(write_error)?;
 
-    writer.write_image_data(data).unwrap();
+    writer.write_image_data(data).map_err(write_error)
 }
@@ -0,0 +1,387 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Reader for Truevision TGA images, with colour-mapped, true colour and greyscale images
+//! in raw or run-length encoded form, and a writer for uncompressed ones
+
+use std::fs;
+use std::path::Path;
+
+use super::{scale_to_u8, RawImage};
+
+const HEADER_SIZE: usize = 18;
+
+pub fn read(path: &Path) -> Result<RawImage, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    decode(&bytes).map_err(|e| format!("Failed to read TGA file: {}", e))
+}
+
+/// Writes an uncompressed image with one to four channels, one and two as grey and grey
+/// with alpha
+pub fn write(
+    path: &Path,
+    width: usize,
+    height: usize,
+    channels: usize,
+    data: &[u8],
+) -> Result<(), String> {
+    let width = u16::try_from(width).map_err(|_| "TGA images are at most 65535 pixels wide")?;
+    let height = u16::try_from(height).map_err(|_| "TGA images are at most 65535 pixels high")?;
+    let (image_type, alpha_bits) = match channels {
+        1 => (3, 0),
+        2 => (3, 8),
+        3 => (2, 0),
+        _ => (2, 8),
+    };
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + data.len());
+    bytes.extend_from_slice(&[0, 0, image_type, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    bytes.extend_from_slice(&width.to_le_bytes());
+    bytes.extend_from_slice(&height.to_le_bytes());
+    // top to bottom rows
+    bytes.extend_from_slice(&[(channels * 8) as u8, 0x20 | alpha_bits]);
+    for pixel in data.chunks(channels) {
+        match *pixel {
+            [r, g, b] => bytes.extend_from_slice(&[b, g, r]),
+            [r, g, b, a] => bytes.extend_from_slice(&[b, g, r, a]),
+            _ => bytes.extend_from_slice(pixel),
+        }
+    }
+    fs::write(path, bytes).map_err(|e| format!("Failed to write TGA file: {}", e))
+}
+
+fn decode(bytes: &[u8]) -> Result<RawImage, &'static str> {
+    let header = bytes.get(..HEADER_SIZE).ok_or("file is too short")?;
+    let u16_at =
+        |offset: usize| usize::from(u16::from_le_bytes([header[offset], header[offset + 1]]));
+    let id_length = usize::from(header[0]);
+    let has_colour_map = header[1] == 1;
+    let image_type = header[2];
+    let first_entry = u16_at(3);
+    let map_length = u16_at(5);
+    let entry_depth = header[7];
+    let width = u16_at(12);
+    let height = u16_at(14);
+    let depth = header[16];
+    let descriptor = header[17];
+    let has_alpha = descriptor & 0x0f != 0;
+    let right_to_left = descriptor & 0x10 != 0;
+    let top_to_bottom = descriptor & 0x20 != 0;
+
+    let rle = image_type & 8 != 0;
+    let kind = match image_type & !8 {
+        1 if has_colour_map => Kind::ColourMapped,
+        2 => Kind::TrueColour,
+        3 => Kind::Greyscale,
+        _ => return Err("unsupported image type"),
+    };
+    if width == 0 || height == 0 {
+        return Err("image is empty");
+    }
+
+    let mut offset = HEADER_SIZE + id_length;
+    let mut palette = Vec::new();
+    if has_colour_map {
+        if !matches!(entry_depth, 15 | 16 | 24 | 32) {
+            return Err("unsupported colour depth");
+        }
+        let entry_size = usize::from(entry_depth).div_ceil(8);
+        let map = bytes
+            .get(offset..offset + map_length * entry_size)
+            .ok_or("colour map is truncated")?;
+        palette = map
+            .chunks(entry_size)
+            .map(|entry| true_colour(entry, entry_depth, has_alpha))
+            .collect::<Result<_, _>>()?;
+        offset += map.len();
+    }
+
+    let pixel_size = match (kind, depth) {
+        (Kind::ColourMapped, 8 | 16) | (Kind::Greyscale, 8 | 16) => usize::from(depth / 8),
+        (Kind::TrueColour, 15 | 16 | 24 | 32) => usize::from(depth).div_ceil(8),
+        _ => return Err("unsupported pixel depth"),
+    };
+    // u16 dimensions only overflow on 32-bit targets, and pixels are at most 4 bytes
+    let rgba_size = width
+        .checked_mul(height)
+        .and_then(|count| count.checked_mul(4))
+        .ok_or("image is too large")?;
+    let size = width * height * pixel_size;
+    let data = bytes.get(offset..).unwrap_or_default();
+    let raw = if rle {
+        decode_rle(data, size, pixel_size)?
+    } else {
+        data.get(..size).ok_or("image data is truncated")?.to_vec()
+    };
+
+    let mut pixels = vec![0; rgba_size];
+    for (i, pixel) in raw.chunks(pixel_size).enumerate() {
+        let rgba = match kind {
+            Kind::ColourMapped => {
+                let index = match *pixel {
+                    [i] => usize::from(i),
+                    [lo, hi] => usize::from(u16::from_le_bytes([lo, hi])),
+                    _ => unreachable!(),
+                };
+                *index
+                    .checked_sub(first_entry)
+                    .and_then(|index| palette.get(index))
+                    .ok_or("colour index is outside the colour map")?
+            }
+            Kind::TrueColour => true_colour(pixel, depth, has_alpha)?,
+            Kind::Greyscale => match *pixel {
+                [l] => [l, l, l, 255],
+                [l, a] => [l, l, l, a],
+                _ => unreachable!(),
+            },
+        };
+
+        // rows are stored bottom to top unless the descriptor says otherwise
+        let (mut x, mut y) = (i % width, i / width);
+        if right_to_left {
+            x = width - 1 - x;
+        }
+        if !top_to_bottom {
+            y = height - 1 - y;
+        }
+        let offset = (y * width + x) * 4;
+        pixels[offset..offset + 4].copy_from_slice(&rgba);
+    }
+
+    Ok(RawImage {
+        width,
+        height,
+        data: pixels,
+        data16: None,
+    })
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    ColourMapped,
+    TrueColour,
+    Greyscale,
+}
+
+/// Converts a BGR(A) pixel or colour map entry with `depth` bits to RGBA
+fn true_colour(pixel: &[u8], depth: u8, has_alpha: bool) -> Result<[u8; 4], &'static str> {
+    match (depth, pixel) {
+        (15 | 16, &[lo, hi]) => {
+            let x = u32::from(u16::from_le_bytes([lo, hi]));
+            let alpha = if depth == 16 && has_alpha && x & 0x8000 == 0 {
+                0
+            } else {
+                255
+            };
+            Ok([
+                scale_to_u8((x >> 10) & 31, 5),
+                scale_to_u8((x >> 5) & 31, 5),
+                scale_to_u8(x & 31, 5),
+                alpha,
+            ])
+        }
+        (24, &[b, g, r]) => Ok([r, g, b, 255]),
+        (32, &[b, g, r, a]) => Ok([r, g, b, if has_alpha { a } else { 255 }]),
+        _ => Err("unsupported colour depth"),
+    }
+}
+
+/// Expands run-length encoded packets to `size` bytes of pixels
+fn decode_rle(data: &[u8], size: usize, pixel_size: usize) -> Result<Vec<u8>, &'static str> {
+    // each packet of at least 1 + pixel_size bytes holds at most 128 pixels, so check that
+    // there is enough data before trusting the header with the allocation
+    let packets = data.len() / (1 + pixel_size);
+    if packets.saturating_mul(128 * pixel_size) < size {
+        return Err("image data is truncated");
+    }
+
+    let mut raw = Vec::with_capacity(size);
+    let mut offset = 0;
+    while raw.len() < size {
+        let packet = *data.get(offset).ok_or("image data is truncated")?;
+        let count = usize::from(packet & 0x7f) + 1;
+        let repeat = packet & 0x80 != 0;
+        let length = if repeat {
+            pixel_size
+        } else {
+            count * pixel_size
+        };
+        let bytes = data
+            .get(offset + 1..offset + 1 + length)
+            .ok_or("image data is truncated")?;
+        if repeat {
+            for _ in 0..count {
+                raw.extend_from_slice(bytes);
+            }
+        } else {
+            raw.extend_from_slice(bytes);
+        }
+        offset += 1 + length;
+    }
+    // packets may cross rows, and some writers let the last one overrun the image
+    raw.truncate(size);
+    Ok(raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a header without an image ID or colour map
+    fn header(image_type: u8, width: u16, height: u16, depth: u8, descriptor: u8) -> Vec<u8> {
+        let mut header = vec![0, 0, image_type, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        header.extend_from_slice(&width.to_le_bytes());
+        header.extend_from_slice(&height.to_le_bytes());
+        header.extend_from_slice(&[depth, descriptor]);
+        header
+    }
+
+    fn pixels(image: &RawImage) -> Vec<[u8; 4]> {
+        image
+            .data
+            .chunks(4)
+            .map(|p| [p[0], p[1], p[2], p[3]])
+            .collect()
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    #[test]
+    fn test_rle_packets_cross_rows() {
+        // a run of four red pixels crosses into the second row, and the last run of blue
+        // overruns the image
+        let mut bytes = header(10, 3, 2, 24, 0x20);
+        bytes.extend_from_slice(&[0x83, 0, 0, 255]);
+        bytes.extend_from_slice(&[0x00, 0, 255, 0]);
+        bytes.extend_from_slice(&[0x82, 255, 0, 0]);
+        let image = decode(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(pixels(&image), [RED, RED, RED, RED, GREEN, BLUE]);
+
+        bytes.truncate(bytes.len() - 4);
+        assert_eq!(decode(&bytes).err(), Some("image data is truncated"));
+    }
+
+    #[test]
+    fn test_row_order() {
+        let grey =
+            |values: &[u8]| -> Vec<[u8; 4]> { values.iter().map(|&l| [l, l, l, 255]).collect() };
+        for (descriptor, expected) in [
+            (0x00, [3, 4, 1, 2]),
+            (0x20, [1, 2, 3, 4]),
+            (0x10, [4, 3, 2, 1]),
+            (0x30, [2, 1, 4, 3]),
+        ] {
+            let mut bytes = header(3, 2, 2, 8, descriptor);
+            bytes.extend_from_slice(&[1, 2, 3, 4]);
+            assert_eq!(pixels(&decode(&bytes).unwrap()), grey(&expected));
+        }
+    }
+
+    #[test]
+    fn test_1555_alpha() {
+        let data = [0xfc00u16, 0x03e0, 0x001f, 0x8000]
+            .map(u16::to_le_bytes)
+            .concat();
+
+        // one attribute bit is alpha, which is only used with 16 bits per pixel
+        let mut bytes = header(2, 4, 1, 16, 0x21);
+        bytes.extend_from_slice(&data);
+        assert_eq!(
+            pixels(&decode(&bytes).unwrap()),
+            [RED, [0, 255, 0, 0], [0, 0, 255, 0], [0, 0, 0, 255]]
+        );
+
+        for (depth, descriptor) in [(16, 0x20), (15, 0x21)] {
+            let mut bytes = header(2, 4, 1, depth, descriptor);
+            bytes.extend_from_slice(&data);
+            assert_eq!(
+                pixels(&decode(&bytes).unwrap()),
+                [RED, GREEN, BLUE, [0, 0, 0, 255]]
+            );
+        }
+    }
+
+    #[test]
+    fn test_colour_map() {
+        // two 24-bit entries starting at index 1
+        let mut bytes = vec![0, 1, 1, 1, 0, 2, 0, 24, 0, 0, 0, 0, 2, 0, 1, 0, 8, 0x20];
+        bytes.extend_from_slice(&[0, 0, 255, 255, 0, 0]);
+        bytes.extend_from_slice(&[2, 1]);
+        assert_eq!(pixels(&decode(&bytes).unwrap()), [BLUE, RED]);
+
+        *bytes.last_mut().unwrap() = 0;
+        assert_eq!(
+            decode(&bytes).err(),
+            Some("colour index is outside the colour map")
+        );
+
+        // entries of zero bits would be chunked into nothing
+        let mut bytes = vec![0, 1, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 8, 0x20];
+        bytes.push(0);
+        assert_eq!(decode(&bytes).err(), Some("unsupported colour depth"));
+    }
+
+    #[test]
+    fn test_truncated_rle() {
+        // a few packets cannot fill the largest image the header allows
+        let mut bytes = header(10, 0xffff, 0xffff, 32, 0x28);
+        bytes.extend_from_slice(&[0xff, 1, 2, 3, 4].repeat(4));
+        assert_eq!(decode(&bytes).err(), Some("image data is truncated"));
+
+        // while a solid run of 128 pixels is accepted
+        let mut bytes = header(10, 16, 8, 24, 0x20);
+        bytes.extend_from_slice(&[0xff, 0, 0, 255]);
+        assert_eq!(pixels(&decode(&bytes).unwrap()), [RED; 128]);
+    }
+
+    #[test]
+    fn test_write() {
+        let dir = crate::test_dir("tga-write");
+        let path = dir.join("image.tga");
+        let rgba = [[10, 20, 30, 40], [50, 60, 70, 80], [90, 100, 110, 120]];
+        for channels in 1..=4 {
+            let data: Vec<u8> = rgba.iter().flat_map(|p| p[..channels].to_vec()).collect();
+            write(&path, 3, 1, channels, &data).unwrap();
+            let expected: Vec<[u8; 4]> = rgba
+                .iter()
+                .map(|&[r, g, b, a]| match channels {
+                    1 => [r, r, r, 255],
+                    2 => [r, r, r, g],
+                    3 => [r, g, b, 255],
+                    _ => [r, g, b, a],
+                })
+                .collect();
+            let image = decode(&fs::read(&path).unwrap()).unwrap();
+            assert_eq!((image.width, image.height), (3, 1));
+            assert_eq!(pixels(&image), expected, "{} channels", channels);
+        }
+
+        // rows are written top to bottom
+        write(&path, 1, 2, 3, &[255, 0, 0, 0, 0, 255]).unwrap();
+        assert_eq!(
+            pixels(&decode(&fs::read(&path).unwrap()).unwrap()),
+            [RED, BLUE]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
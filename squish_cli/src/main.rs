@@ -20,15 +20,29 @@
 // SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 use std::ffi::OsStr;
-use std::fs::File;
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::process;
 use std::str::FromStr;
+use std::thread;
 
-use ddsfile::{AlphaMode, D3D10ResourceDimension, D3DFormat, Dds, DxgiFormat};
-use squish::{Algorithm, Format, Params, COLOUR_WEIGHTS_PERCEPTUAL};
+use squish::dds::{AlphaMode, Dds, TextureKind};
+use squish::ktx::Ktx;
+use squish::ktx2::{Ktx2, Supercompression};
+use squish::metrics::{self, BlockError};
+use squish::mipmap::{self, EdgeMode, Filter, MipmapParams};
+use squish::{
+    num_blocks, Algorithm, Bc3Encoding, ColourWeights, Format, Params, COLOUR_WEIGHTS_PERCEPTUAL,
+};
 use structopt::StructOpt;
 
+use crate::cache::Cache;
+use crate::image::{Image, ToneMap};
+
+mod batch;
+mod cache;
 mod image;
+mod manifest;
 
 enum Profile {
     Speed,
@@ -37,20 +51,47 @@ enum Profile {
 }
 
 #[derive(StructOpt)]
-#[structopt(name = "squish", about = "A BC1/2/3 compressor and decompressor")]
+#[structopt(
+    name = "squish",
+    about = "A BC1/2/3/4/5/6H/7 compressor and decompressor"
+)]
 enum Opt {
-    /// Compress a PNG or JPEG file to DDS
+    /// Compress PNG, JPEG, TGA, BMP, OpenEXR or Radiance HDR files to DDS, KTX or KTX2
     #[structopt(name = "compress")]
     Compress {
-        /// Output file (DDS)
+        /// Output file (DDS, or KTX and KTX2 if the extension is .ktx or .ktx2)
         #[structopt(short = "o", long = "output", parse(from_os_str))]
         outfile: Option<PathBuf>,
 
-        /// Input file (PNG, JPG)
-        #[structopt(name = "INFILE", parse(from_os_str))]
-        infile: PathBuf,
+        /// Input files (PNG, JPG, TGA, BMP, EXR, HDR), directories or glob patterns. More than one
+        /// input, a directory or a pattern compresses all images in batch mode.
+        #[structopt(name = "INFILE", parse(from_os_str), required = true)]
+        infiles: Vec<PathBuf>,
+
+        /// Directory to write the outputs of batch mode to, mirroring the input directories.
+        /// Outputs are written next to the inputs without it.
+        #[structopt(short = "d", long = "output-dir", parse(from_os_str))]
+        output_dir: Option<PathBuf>,
+
+        /// Container for outputs without an output file (dds, ktx or ktx2).
+        #[structopt(long = "container", default_value = "dds", parse(try_from_str = parse_container))]
+        container: Container,
+
+        /// Number of files compressed at the same time in batch mode (defaults to the number
+        /// of CPUs).
+        #[structopt(short = "j", long = "jobs")]
+        jobs: Option<usize>,
+
+        /// Cache compressed outputs in this directory, copying them instead of compressing
+        /// again when the pixels and settings of an image are unchanged.
+        #[structopt(long = "cache-dir", parse(from_os_str))]
+        cache_dir: Option<PathBuf>,
 
-        /// Compression format (BC1, BC2 or BC3)
+        /// Compress all images again instead of using cached outputs.
+        #[structopt(long = "force")]
+        force: bool,
+
+        /// Compression format (BC1, BC2, BC3, BC4, BC4_SNORM, BC5, BC5_SNORM, BC6H, BC6H_SF16 or BC7)
         #[structopt(short = "f", long = "format", parse(try_from_str = parse_format))]
         format: Format,
 
@@ -62,22 +103,152 @@ enum Opt {
         #[structopt(long = "weigh-colour-by-alpha")]
         weigh_colour_by_alpha: bool,
 
+        /// Fit colours and filter mipmaps in linear light, treating the image as sRGB encoded,
+        /// and tag BC1, BC2, BC3 and BC7 colour output as sRGB.
+        #[structopt(long = "srgb")]
+        srgb: bool,
+
+        /// Treat the image as a normal map, storing X and Y only for BC3 (DXT5nm) and BC5.
+        #[structopt(long = "normal-map")]
+        normal_map: bool,
+
+        /// BC3 channel encoding (rgba, dxt5nm or ycocg).
+        #[structopt(long = "bc3-encoding", default_value = "rgba", parse(try_from_str = parse_bc3_encoding))]
+        bc3_encoding: Bc3Encoding,
+
         // TODO: replace with something nicer
         /// Colour weights to be used for matching colours during fitting.
         #[structopt(short = "w", long = "weights")]
         weights: Vec<f32>,
+
+        /// Write a PNG heatmap of the compression error of each block to this file.
+        #[structopt(long = "heatmap", parse(from_os_str))]
+        heatmap: Option<PathBuf>,
+
+        /// Generate a full mipmap chain.
+        #[structopt(long = "mipmaps")]
+        mipmaps: bool,
+
+        /// Mipmap filter (box, triangle, kaiser or lanczos).
+        #[structopt(long = "mip-filter", default_value = "box", parse(try_from_str = parse_filter))]
+        mip_filter: Filter,
+
+        /// Mipmap edge mode (clamp or wrap).
+        #[structopt(long = "mip-edge", default_value = "clamp", parse(try_from_str = parse_edge_mode))]
+        mip_edge: EdgeMode,
+
+        /// Preserve alpha-test coverage in mipmaps for this alpha reference value (e.g. 128 for BC1).
+        #[structopt(long = "alpha-coverage")]
+        alpha_coverage: Option<u8>,
+
+        /// Supercompress each mipmap level of KTX2 output with Zstandard.
+        #[structopt(long = "zstd")]
+        zstd: bool,
+
+        /// Rate-distortion optimise BC1-BC5 output for smaller archives, allowing this much
+        /// squared error per bit saved (e.g. 10 to 100).
+        #[structopt(long = "rdo", default_value = "0")]
+        rdo_lambda: f32,
+
+        /// Tone-mapping of HDR images compressed to 8-bit formats (clamp, reinhard or aces).
+        #[structopt(long = "tone-map", default_value = "clamp", parse(try_from_str = parse_tone_map))]
+        tone_map: ToneMap,
+
+        /// Exposure adjustment of HDR images in stops.
+        #[structopt(long = "exposure", default_value = "0", allow_hyphen_values = true)]
+        exposure: f32,
     },
 
-    /// Deompress a DDS file to PNG
+    /// Compress the images listed in a TOML manifest with the settings of its rules
+    #[structopt(name = "build")]
+    Build {
+        /// Manifest listing the inputs, the output directory and the settings
+        #[structopt(name = "MANIFEST", parse(from_os_str))]
+        manifest: PathBuf,
+
+        /// Number of files compressed at the same time (defaults to the number of CPUs).
+        #[structopt(short = "j", long = "jobs")]
+        jobs: Option<usize>,
+
+        /// Directory of the cache of compressed outputs (defaults to the `cache` of the
+        /// manifest, or .squish-cache next to it).
+        #[structopt(long = "cache-dir", parse(from_os_str))]
+        cache_dir: Option<PathBuf>,
+
+        /// Compress all images again instead of using cached outputs.
+        #[structopt(long = "force")]
+        force: bool,
+    },
+
+    /// Decompress a DDS, KTX or KTX2 file to PNG, TGA, raw RGBA8 or OpenEXR
     #[structopt(name = "decompress")]
     Decompress {
-        /// Output file (PNG)
+        /// Output file (PNG, or TGA, raw pixels and OpenEXR if the extension is .tga, .raw or
+        /// .rgba, and .exr)
         #[structopt(short = "o", long = "output", parse(from_os_str))]
         outfile: Option<PathBuf>,
 
-        /// Input file (DDS)
+        /// Input file (DDS, KTX, KTX2)
         #[structopt(name = "INFILE", parse(from_os_str))]
         infile: PathBuf,
+
+        /// Output format instead of the one of the output file (png, tga, raw or exr). Raw
+        /// output has 8 bits per channel, OpenEXR half floats and is only written for BC6H.
+        #[structopt(long = "output-format", parse(try_from_str = parse_output_format))]
+        output_format: Option<OutputFormat>,
+
+        /// Channels to write, in this order (e.g. rgb or a). PNG and TGA store two channels
+        /// as grey and alpha.
+        #[structopt(long = "channels", default_value = "rgba", parse(try_from_str = parse_channels))]
+        channels: Channels,
+
+        /// Mipmap level to decompress.
+        #[structopt(long = "level", default_value = "0")]
+        level: usize,
+
+        /// Array layer to decompress.
+        #[structopt(long = "layer", default_value = "0")]
+        layer: usize,
+
+        /// Cubemap face to decompress.
+        #[structopt(long = "face", default_value = "0")]
+        face: usize,
+
+        /// BC3 channel encoding to reconstruct the original pixels from (rgba, dxt5nm or ycocg).
+        #[structopt(long = "bc3-encoding", default_value = "rgba", parse(try_from_str = parse_bc3_encoding))]
+        bc3_encoding: Bc3Encoding,
+    },
+
+    /// Compare a DDS, KTX or KTX2 file against its source image
+    #[structopt(name = "compare")]
+    Compare {
+        /// Source image (PNG, JPG, TGA, BMP)
+        #[structopt(name = "SOURCE", parse(from_os_str))]
+        source: PathBuf,
+
+        /// Compressed image (DDS, KTX, KTX2)
+        #[structopt(name = "COMPRESSED", parse(from_os_str))]
+        compressed: PathBuf,
+
+        /// Colour weights to be used for the weighted metrics.
+        #[structopt(short = "w", long = "weights")]
+        weights: Vec<f32>,
+
+        /// Fail if the weighted PSNR is below this value (in dB).
+        #[structopt(long = "min-psnr")]
+        min_psnr: Option<f32>,
+
+        /// Fail if the weighted SSIM is below this value.
+        #[structopt(long = "min-ssim")]
+        min_ssim: Option<f32>,
+
+        /// Also report the angular error of a normal map.
+        #[structopt(long = "normal-map")]
+        normal_map: bool,
+
+        /// Fail if the mean angular error of a normal map is above this value (in degrees).
+        #[structopt(long = "max-angle")]
+        max_angle: Option<f32>,
     },
 }
 
@@ -85,105 +256,692 @@ fn main() {
     match Opt::from_args() {
         Opt::Compress {
             outfile,
-            infile,
+            infiles,
+            output_dir,
+            container,
+            jobs,
+            cache_dir,
+            force,
             format,
             profile,
             weigh_colour_by_alpha,
+            srgb,
+            normal_map,
+            bc3_encoding,
             weights,
+            heatmap,
+            mipmaps,
+            mip_filter,
+            mip_edge,
+            alpha_coverage,
+            zstd,
+            rdo_lambda,
+            tone_map,
+            exposure,
         } => {
-            let w;
-            if weights.is_empty() {
-                w = COLOUR_WEIGHTS_PERCEPTUAL;
-            } else if weights.len() == 3 {
-                w = [weights[0], weights[1], weights[2]];
-            } else {
-                panic!("Weights must have 3 values");
-            }
             let params = Params {
                 algorithm: profile.into(),
-                weights: w,
+                weights: parse_weights(&weights),
                 weigh_colour_by_alpha,
+                srgb,
+                normal_map,
+                bc3_encoding,
+                rdo_lambda,
+            };
+            let mipmaps = mipmaps.then_some(MipmapParams {
+                filter: mip_filter,
+                edge_mode: mip_edge,
+                srgb,
+                alpha_coverage,
+                normal_map,
+            });
+            let compression = Compression {
+                format,
+                params,
+                mipmaps,
+                zstd,
+                tone_map,
+                exposure,
             };
-            compress_file(outfile, &infile, format, params)
+            let cache = cache_dir.map(|dir| Cache { dir, force });
+
+            if !batch::is_batch(&infiles, output_dir.as_deref()) {
+                let infile = &infiles[0];
+                let outfile = outfile.unwrap_or_else(|| {
+                    PathBuf::new()
+                        .with_file_name(infile.file_name().unwrap_or_else(|| OsStr::new("output")))
+                        .with_extension(container.extension())
+                });
+                compress_file(
+                    &outfile,
+                    infile,
+                    &compression,
+                    heatmap.as_deref(),
+                    cache.as_ref(),
+                )
+                .unwrap_or_else(|e| fail(&e));
+                return;
+            }
+
+            if outfile.is_some() || heatmap.is_some() {
+                eprintln!("--output and --heatmap only work with a single input file");
+                process::exit(2);
+            }
+            let jobs_list = batch::jobs(&infiles, output_dir.as_deref(), container.extension())
+                .unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    process::exit(2);
+                });
+            let failures = batch::run(&jobs_list, threads(jobs), |job| {
+                compress_file(&job.output, &job.input, &compression, None, cache.as_ref())
+            });
+            if failures > 0 {
+                process::exit(1);
+            }
         }
-        Opt::Decompress { outfile, infile } => decompress_file(outfile, &infile),
+        Opt::Build {
+            manifest,
+            jobs,
+            cache_dir,
+            force,
+        } => {
+            let build = manifest::read(&manifest).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(2);
+            });
+            let cache = Cache {
+                dir: cache_dir.unwrap_or(build.cache_dir),
+                force,
+            };
+            let failures = batch::run(&build.entries, threads(jobs), |entry| {
+                compress_file(
+                    &entry.job.output,
+                    &entry.job.input,
+                    &entry.compression,
+                    None,
+                    Some(&cache),
+                )
+            });
+            if failures > 0 {
+                process::exit(1);
+            }
+        }
+        Opt::Decompress {
+            outfile,
+            infile,
+            output_format,
+            channels,
+            level,
+            layer,
+            face,
+            bc3_encoding,
+        } => decompress_file(
+            outfile,
+            &infile,
+            output_format,
+            &channels,
+            layer,
+            face,
+            level,
+            bc3_encoding,
+        )
+        .unwrap_or_else(|e| fail(&e)),
+        Opt::Compare {
+            source,
+            compressed,
+            weights,
+            min_psnr,
+            min_ssim,
+            normal_map,
+            max_angle,
+        } => compare_files(
+            &source,
+            &compressed,
+            parse_weights(&weights),
+            min_psnr,
+            min_ssim,
+            (normal_map || max_angle.is_some()).then_some(max_angle),
+        )
+        .unwrap_or_else(|e| fail(&e)),
     };
 }
 
-fn compress_file(outfile: Option<PathBuf>, infile: &Path, format: Format, params: Params) {
-    let outfile = outfile.unwrap_or_else(|| {
-        PathBuf::new()
-            .with_file_name(infile.file_name().unwrap_or_else(|| OsStr::new("output")))
-            .with_extension("dds")
-    });
+/// Prints an error and exits with a failure
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+/// Creates an empty directory for a test in the temporary directory
+#[cfg(test)]
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("squish-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Returns the number of files to compress at the same time, defaulting to the number of CPUs
+fn threads(jobs: Option<usize>) -> usize {
+    jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, |threads| threads.get()))
+}
+
+fn parse_weights(weights: &[f32]) -> ColourWeights {
+    match weights {
+        [] => COLOUR_WEIGHTS_PERCEPTUAL,
+        &[r, g, b] => [r, g, b],
+        _ => panic!("Weights must have 3 values"),
+    }
+}
+
+fn read_image(infile: &Path) -> Result<Image, String> {
     let in_ext = infile
         .extension()
-        .expect("Input filename has no extension, can't guess type")
+        .ok_or("Input filename has no extension, can't guess type")?
         .to_string_lossy()
-        .to_owned()
         .to_lowercase();
-    let image = match in_ext.as_str() {
-        "jpg" | "jpeg" => image::jpeg::read(infile),
-        "png" => image::png::read(infile),
-        _ => panic!("Unrecognized image format. Supported formats are PNG and JPEG"),
+    match in_ext.as_str() {
+        "jpg" | "jpeg" => image::jpeg::read(infile).map(Image::Raw),
+        "png" => image::png::read(infile).map(Image::Raw),
+        "tga" => image::tga::read(infile).map(Image::Raw),
+        "bmp" => image::bmp::read(infile).map(Image::Raw),
+        "exr" => image::exr::read(infile).map(Image::Float),
+        "hdr" => image::hdr::read(infile).map(Image::Float),
+        _ => Err(
+            "Unrecognized image format. Supported formats are PNG, JPEG, TGA, BMP, OpenEXR and Radiance HDR"
+                .to_string(),
+        ),
+    }
+}
+
+/// Settings for compressing an image
+#[derive(Clone, Copy, Debug)]
+struct Compression {
+    format: Format,
+    params: Params,
+    mipmaps: Option<MipmapParams>,
+    zstd: bool,
+    tone_map: ToneMap,
+    exposure: f32,
+}
+
+/// A compressed texture read from either container
+enum Texture {
+    Dds(Dds),
+    Ktx(Ktx),
+    Ktx2(Ktx2),
+}
+
+impl Texture {
+    fn format(&self) -> Format {
+        match self {
+            Texture::Dds(dds) => dds.format,
+            Texture::Ktx(ktx) => ktx.format,
+            Texture::Ktx2(ktx2) => ktx2.format,
+        }
+    }
+
+    /// Returns the width and height of a mipmap level
+    fn size(&self, level: usize) -> (usize, usize) {
+        let (width, height, _) = match self {
+            Texture::Dds(dds) => dds.level_size(level),
+            Texture::Ktx(ktx) => ktx.level_size(level),
+            Texture::Ktx2(ktx2) => ktx2.level_size(level),
+        };
+        (width, height)
+    }
+
+    /// Returns one mipmap level of one face of an array layer, if the texture has it
+    fn surface(&self, layer: usize, face: usize, level: usize) -> Option<&[u8]> {
+        match self {
+            Texture::Dds(dds) => dds.surface(layer, face, level),
+            Texture::Ktx(ktx) => ktx.surface(layer, face, level),
+            Texture::Ktx2(ktx2) => ktx2.surface(layer, face, level),
+        }
+    }
+}
+
+/// Defines the container of a compressed texture
+#[derive(Clone, Copy, PartialEq)]
+enum Container {
+    Dds,
+    Ktx,
+    Ktx2,
+}
+
+impl Container {
+    fn extension(self) -> &'static str {
+        match self {
+            Container::Dds => "dds",
+            Container::Ktx => "ktx",
+            Container::Ktx2 => "ktx2",
+        }
+    }
+}
+
+/// Picks the container from the file extension, defaulting to DDS
+fn container(path: &Path) -> Container {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    match ext.as_deref() {
+        Some("ktx") => Container::Ktx,
+        Some("ktx2") => Container::Ktx2,
+        _ => Container::Dds,
+    }
+}
+
+/// Reads a DDS, KTX or KTX2 file, depending on the extension
+fn read_texture(infile: &Path) -> Result<Texture, String> {
+    let bytes = fs::read(infile).map_err(|e| format!("Failed to read file: {}", e))?;
+    let (texture, kind) = match container(infile) {
+        Container::Dds => {
+            let dds = Dds::read(&bytes).map_err(|e| format!("Failed to read DDS file: {}", e))?;
+            let kind = dds.kind;
+            (Texture::Dds(dds), kind)
+        }
+        Container::Ktx => {
+            let ktx = Ktx::read(&bytes).map_err(|e| format!("Failed to read KTX file: {}", e))?;
+            let kind = ktx.kind;
+            (Texture::Ktx(ktx), kind)
+        }
+        Container::Ktx2 => {
+            let ktx2 =
+                Ktx2::read(&bytes).map_err(|e| format!("Failed to read KTX2 file: {}", e))?;
+            let kind = ktx2.kind;
+            (Texture::Ktx2(ktx2), kind)
+        }
     };
+    if kind == TextureKind::Texture3D {
+        return Err("Volume textures are not supported".to_string());
+    }
+    Ok(texture)
+}
 
-    let mut buf = vec![0u8; format.compressed_size(image.width, image.height)];
-    format.compress(&image.data, image.width, image.height, params, &mut buf);
+/// Compresses an image to `outfile`, or copies the output from the cache if it has one for
+/// the same pixels and settings. Returns whether the output came from the cache.
+fn compress_file(
+    outfile: &Path,
+    infile: &Path,
+    compression: &Compression,
+    heatmap: Option<&Path>,
+    cache: Option<&Cache>,
+) -> Result<bool, String> {
+    let Compression {
+        format,
+        mipmaps,
+        zstd,
+        tone_map,
+        exposure,
+        ..
+    } = *compression;
+    let source = read_image(infile)?;
 
-    let alphamode = if format == Format::Bc1 {
-        AlphaMode::PreMultiplied
-    } else {
-        AlphaMode::Straight
+    let container = container(outfile);
+    if zstd && container != Container::Ktx2 {
+        return Err("Zstandard supercompression requires KTX2 output".to_string());
+    }
+    let entry = cache.map(|cache| cache.entry(&source, compression, container));
+    // the heatmap needs the block errors, so it always compresses
+    if let (Some(cache), Some(entry), None) = (cache, &entry, heatmap) {
+        if let Some(bytes) = cache.load(entry) {
+            cache::write_if_changed(outfile, &bytes)
+                .map_err(|e| format!("Failed to write output file: {}", e))?;
+            return Ok(true);
+        }
+    }
+
+    // normal maps and the swizzled BC3 encodings are not colour, so never tag them as sRGB
+    let Params {
+        srgb,
+        normal_map,
+        bc3_encoding,
+        ..
+    } = compression.params;
+    let srgb = srgb
+        && !normal_map
+        && matches!(
+            format,
+            Format::Bc1 | Format::Bc2 | Format::Bc3 | Format::Bc7
+        )
+        && (format != Format::Bc3 || bc3_encoding == Bc3Encoding::Rgba);
+    let (width, height) = source.size();
+    let (buf, levels) = match source {
+        Image::Float(image) if format.is_hdr() => {
+            if heatmap.is_some() || mipmaps.is_some() {
+                return Err("Heatmaps and mipmaps of HDR images are not supported yet".to_string());
+            }
+            let mut buf = vec![0u8; format.compressed_size(width, height)];
+            let rgb = image.rgb(exposure);
+            format.compress_f32(&rgb, width, height, compression.params, &mut buf);
+            (buf, 1)
+        }
+        Image::Float(image) => {
+            let image = image.to_rgba8(tone_map, exposure, srgb);
+            compress_rgba8(&image, compression, heatmap)?
+        }
+        Image::Raw(image) => compress_rgba8(&image, compression, heatmap)?,
     };
-    let mut dds = Dds::new_dxgi(
-        image.height as u32,
-        image.width as u32,
-        None, // depth
-        format_to_dxgiformat(format),
-        None,  // mipmap_levels
-        None,  // array_layers
-        None,  // caps2
-        false, // is_cubemap
-        D3D10ResourceDimension::Texture2D,
-        alphamode,
-    )
-    .unwrap();
-    dds.data = buf;
 
-    let mut outfile = File::create(outfile).expect("Failed to create output file");
-    dds.write(&mut outfile).unwrap();
+    let premultiplied = format == Format::Bc1;
+
+    let writer = concat!("squish ", env!("CARGO_PKG_VERSION"), "\0");
+    let bytes = match container {
+        Container::Dds => {
+            let mut dds = Dds::new(format, width, height, levels);
+            dds.srgb = srgb;
+            dds.alpha_mode = if premultiplied {
+                AlphaMode::Premultiplied
+            } else {
+                AlphaMode::Straight
+            };
+            dds.data = buf;
+            dds.write()
+                .map_err(|e| format!("Failed to write DDS file: {}", e))?
+        }
+        Container::Ktx => {
+            let mut ktx = Ktx::new(format, width, height, levels);
+            ktx.srgb = srgb;
+            ktx.key_values = vec![
+                ("KTXwriter".to_string(), writer.into()),
+                ("KTXorientation".to_string(), b"S=r,T=d\0".to_vec()),
+            ];
+            ktx.data = buf;
+            ktx.write()
+                .map_err(|e| format!("Failed to write KTX file: {}", e))?
+        }
+        Container::Ktx2 => {
+            let mut ktx2 = Ktx2::new(format, width, height, levels);
+            ktx2.srgb = srgb;
+            ktx2.premultiplied = premultiplied;
+            if zstd {
+                ktx2.supercompression = Supercompression::Zstd;
+            }
+            ktx2.key_values = vec![
+                ("KTXwriter".to_string(), writer.into()),
+                ("KTXorientation".to_string(), b"rd\0".to_vec()),
+            ];
+            ktx2.data = buf;
+            ktx2.write()
+                .map_err(|e| format!("Failed to write KTX2 file: {}", e))?
+        }
+    };
+    if let Some(parent) = outfile.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create output directory: {}", e))?;
+    }
+    fs::write(outfile, &bytes).map_err(|e| format!("Failed to write output file: {}", e))?;
+
+    if let (Some(cache), Some(entry)) = (cache, &entry) {
+        // a failed cache only costs time on the next run
+        if let Err(e) = cache.store(entry, &bytes) {
+            eprintln!("Failed to store {} in the cache: {}", outfile.display(), e);
+        }
+    }
+    Ok(false)
+}
+
+/// Compresses an image with 8 bits per channel and its mipmaps, writing the error heatmap
+/// if requested. Returns the compressed levels and their count.
+fn compress_rgba8(
+    image: &image::RawImage,
+    compression: &Compression,
+    heatmap: Option<&Path>,
+) -> Result<(Vec<u8>, usize), String> {
+    let Compression {
+        format,
+        params,
+        mipmaps,
+        ..
+    } = *compression;
+    let mut buf = vec![0u8; format.compressed_size(image.width, image.height)];
+    if let Some(heatmap) = heatmap {
+        // the errors of swizzled blocks are measured on the decoded colours
+        let blocks = num_blocks(image.width) * num_blocks(image.height);
+        let mut errors = vec![BlockError::default(); blocks];
+        format
+            .try_compress_with_errors(
+                &image.data,
+                image.width,
+                image.height,
+                params,
+                &mut buf,
+                &mut errors,
+            )
+            .map_err(|e| format!("Failed to compress image: {}", e))?;
+        write_heatmap(heatmap, image.width, image.height, &errors)?;
+    } else {
+        format
+            .try_compress(&image.data, image.width, image.height, params, &mut buf)
+            .map_err(|e| format!("Failed to compress image: {}", e))?;
+    }
+
+    let mut levels = 1;
+    if let Some(mipmap_params) = mipmaps {
+        let chain = mipmap::generate(
+            &image.data,
+            image.width,
+            image.height,
+            usize::MAX,
+            &mipmap_params,
+        );
+        // the first level is compressed already
+        let compressed = mipmap::compress_chain(format, &chain[1..], params);
+        buf.extend_from_slice(&compressed.data);
+        levels = chain.len();
+    }
+    Ok((buf, levels))
+}
+
+/// Writes the root mean square error of each block as a black-red-yellow-white heatmap
+/// scaled to the worst block
+fn write_heatmap(
+    path: &Path,
+    width: usize,
+    height: usize,
+    errors: &[BlockError],
+) -> Result<(), String> {
+    let blocks_wide = num_blocks(width);
+    let rmse: Vec<f32> = errors
+        .iter()
+        .map(|error| (error.colour + error.alpha).sqrt())
+        .collect();
+    let (worst, &max) = rmse
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .unwrap();
+    println!(
+        "Worst block at ({}, {}) with an RMSE of {:.3}",
+        4 * (worst % blocks_wide),
+        4 * (worst / blocks_wide),
+        max
+    );
+
+    let mut data = vec![0u8; 4 * width * height];
+    for (i, pixel) in data.chunks_mut(4).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let t = if max > 0.0 {
+            rmse[blocks_wide * (y / 4) + x / 4] / max
+        } else {
+            0.0
+        };
+        let ramp = |offset: f32| ((3.0 * t - offset).clamp(0.0, 1.0) * 255.0).round() as u8;
+        pixel.copy_from_slice(&[ramp(0.0), ramp(1.0), ramp(2.0), 255]);
+    }
+
+    image::png::write(path, width as u32, height as u32, 4, &data)
 }
 
-fn decompress_file(outfile: Option<PathBuf>, infile: &Path) {
+/// Selected channels as indices into RGBA pixels, an alias so that structopt parses one
+/// value instead of a list
+type Channels = Vec<usize>;
+
+/// Defines the format of a decompressed image
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Png,
+    Tga,
+    Raw,
+    Exr,
+}
+
+/// Picks the output format from the file extension, defaulting to PNG
+fn output_format(path: &Path) -> OutputFormat {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    match ext.as_deref() {
+        Some("tga") => OutputFormat::Tga,
+        Some("raw" | "rgba") => OutputFormat::Raw,
+        Some("exr") => OutputFormat::Exr,
+        _ => OutputFormat::Png,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn decompress_file(
+    outfile: Option<PathBuf>,
+    infile: &Path,
+    output_format: Option<OutputFormat>,
+    channels: &[usize],
+    layer: usize,
+    face: usize,
+    level: usize,
+    bc3_encoding: Bc3Encoding,
+) -> Result<(), String> {
+    let output_format = output_format
+        .or_else(|| outfile.as_deref().map(self::output_format))
+        .unwrap_or(OutputFormat::Png);
     let outfile = outfile.unwrap_or_else(|| {
+        let extension = match output_format {
+            OutputFormat::Png => "png",
+            OutputFormat::Tga => "tga",
+            OutputFormat::Raw => "raw",
+            OutputFormat::Exr => "exr",
+        };
         PathBuf::new()
             .with_file_name(infile.file_name().unwrap_or_else(|| OsStr::new("output")))
-            .with_extension("png")
+            .with_extension(extension)
     });
 
-    let mut infile = File::open(&infile).expect("Failed to open file");
-    let dds = Dds::read(&mut infile).unwrap();
+    let texture = read_texture(infile)?;
+    let format = texture.format();
+    let (width, height) = texture.size(level);
+    let surface = texture.surface(layer, face, level).ok_or_else(|| {
+        format!(
+            "The texture has no mipmap level {} of face {} of array layer {}",
+            level, face, layer
+        )
+    })?;
 
-    let d3dformat = D3DFormat::try_from_pixel_format(&dds.header.spf);
-    let format;
-    if let Some(header10) = dds.header10 {
-        if header10.resource_dimension != D3D10ResourceDimension::Texture2D {
-            panic!("Only images with resource dimension Texture2D are supported");
+    if output_format == OutputFormat::Exr {
+        if !format.is_hdr() {
+            return Err("OpenEXR output is only supported for BC6H".to_string());
         }
-
-        format = dxgiformat_to_format(header10.dxgi_format)
-    } else {
-        format = d3dformat_to_format(d3dformat.unwrap());
+        let mut rgb = vec![0u16; 3 * width * height];
+        format.decompress_f16(surface, width, height, &mut rgb);
+        // BC6H is opaque, so alpha is a half float one
+        let rgba: Vec<u16> = rgb
+            .chunks(3)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0x3c00])
+            .collect();
+        let names: Vec<&str> = channels.iter().map(|&c| ["R", "G", "B", "A"][c]).collect();
+        let data = select_channels(&rgba, channels);
+        return image::exr::write(&outfile, width, height, &names, &data);
     }
 
-    let width = dds.header.width as usize;
-    let height = dds.header.height as usize;
     let mut decompressed = vec![0u8; 4 * width * height];
+    format
+        .try_decompress(surface, width, height, &mut decompressed)
+        .map_err(|e| format!("Failed to decompress image: {}", e))?;
+    if format == Format::Bc3 {
+        bc3_encoding.decode(&mut decompressed);
+    }
 
-    format.decompress(&dds.data, width, height, &mut decompressed);
+    let data = select_channels(&decompressed, channels);
+    match output_format {
+        OutputFormat::Png => {
+            image::png::write(&outfile, width as u32, height as u32, channels.len(), &data)
+        }
+        OutputFormat::Tga => image::tga::write(&outfile, width, height, channels.len(), &data),
+        OutputFormat::Raw => {
+            fs::write(&outfile, data).map_err(|e| format!("Failed to write output file: {}", e))
+        }
+        OutputFormat::Exr => unreachable!(),
+    }
+}
 
-    image::png::write(&outfile, width as u32, height as u32, &decompressed);
+/// Picks the selected channels of each RGBA pixel
+fn select_channels<T: Copy>(rgba: &[T], channels: &[usize]) -> Vec<T> {
+    rgba.chunks(4)
+        .flat_map(|pixel| channels.iter().map(|&c| pixel[c]))
+        .collect()
+}
+
+fn compare_files(
+    source: &Path,
+    compressed: &Path,
+    weights: ColourWeights,
+    min_psnr: Option<f32>,
+    min_ssim: Option<f32>,
+    normal_map: Option<Option<f32>>,
+) -> Result<(), String> {
+    let image = match read_image(source)? {
+        Image::Raw(image) => image,
+        Image::Float(_) => {
+            return Err("Comparing against HDR images is not supported yet".to_string())
+        }
+    };
+    let texture = read_texture(compressed)?;
+    let format = texture.format();
+    let data = texture.surface(0, 0, 0).ok_or("The texture has no data")?;
+    if texture.size(0) != (image.width, image.height) {
+        return Err(
+            "The compressed image does not have the same size as the source image".to_string(),
+        );
+    }
+
+    let result = metrics::compare(
+        format,
+        &image.data,
+        image.width,
+        image.height,
+        data,
+        weights,
+    )
+    .map_err(|e| format!("Failed to compare images: {}", e))?;
+
+    println!("channel   RMSE   PSNR (dB)   SSIM");
+    for (c, name) in ["R", "G", "B", "A"].iter().enumerate() {
+        println!(
+            "{:<8} {:>6.3} {:>11.3} {:>6.4}",
+            name, result.rmse[c], result.psnr[c], result.ssim[c]
+        );
+    }
+    println!(
+        "{:<8} {:>6.3} {:>11.3} {:>6.4}",
+        "weighted", result.weighted_rmse, result.weighted_psnr, result.weighted_ssim
+    );
+
+    let mut angle_ok = true;
+    if let Some(max_angle) = normal_map {
+        let angular =
+            metrics::compare_normals(format, &image.data, image.width, image.height, data)
+                .map_err(|e| format!("Failed to compare normals: {}", e))?;
+        println!(
+            "angular error: {:.3}° mean, {:.3}° max",
+            angular.mean, angular.max
+        );
+        angle_ok = max_angle.is_none_or(|max| angular.mean <= max);
+    }
+
+    let psnr_ok = min_psnr.is_none_or(|min| result.weighted_psnr >= min);
+    let ssim_ok = min_ssim.is_none_or(|min| result.weighted_ssim >= min);
+    if !psnr_ok || !ssim_ok || !angle_ok {
+        return Err("Image quality is below the required threshold".to_string());
+    }
+    Ok(())
 }
 
 impl FromStr for Profile {
@@ -199,9 +957,9 @@ impl FromStr for Profile {
     }
 }
 
-impl Into<Algorithm> for Profile {
-    fn into(self) -> Algorithm {
-        match self {
+impl From<Profile> for Algorithm {
+    fn from(profile: Profile) -> Self {
+        match profile {
             Profile::Speed => Algorithm::RangeFit,
             Profile::Balanced => Algorithm::ClusterFit,
             Profile::Quality => Algorithm::IterativeClusterFit,
@@ -209,43 +967,88 @@ impl Into<Algorithm> for Profile {
     }
 }
 
-fn format_to_dxgiformat(f: Format) -> DxgiFormat {
-    match f {
-        Format::Bc1 => DxgiFormat::BC1_UNorm_sRGB,
-        Format::Bc2 => DxgiFormat::BC2_UNorm_sRGB,
-        Format::Bc3 => DxgiFormat::BC3_UNorm_sRGB,
-        Format::Bc4 => DxgiFormat::BC4_UNorm,
-        Format::Bc5 => DxgiFormat::BC5_UNorm,
+fn parse_format(s: &str) -> Result<Format, &'static str> {
+    match s.to_lowercase().as_ref() {
+        "bc1" => Ok(Format::Bc1),
+        "bc2" => Ok(Format::Bc2),
+        "bc3" => Ok(Format::Bc3),
+        "bc4" => Ok(Format::Bc4),
+        "bc4_snorm" => Ok(Format::Bc4Snorm),
+        "bc5" => Ok(Format::Bc5),
+        "bc5_snorm" => Ok(Format::Bc5Snorm),
+        "bc6h" | "bc6h_uf16" => Ok(Format::Bc6hUf16),
+        "bc6h_sf16" => Ok(Format::Bc6hSf16),
+        "bc7" => Ok(Format::Bc7),
+        _ => Err("invalid compression format specifier"),
+    }
+}
+
+fn parse_container(s: &str) -> Result<Container, &'static str> {
+    match s.to_lowercase().as_ref() {
+        "dds" => Ok(Container::Dds),
+        "ktx" => Ok(Container::Ktx),
+        "ktx2" => Ok(Container::Ktx2),
+        _ => Err("invalid container specifier"),
+    }
+}
+
+fn parse_output_format(s: &str) -> Result<OutputFormat, &'static str> {
+    match s.to_lowercase().as_ref() {
+        "png" => Ok(OutputFormat::Png),
+        "tga" => Ok(OutputFormat::Tga),
+        "raw" => Ok(OutputFormat::Raw),
+        "exr" => Ok(OutputFormat::Exr),
+        _ => Err("invalid output format specifier"),
+    }
+}
+
+fn parse_channels(s: &str) -> Result<Channels, &'static str> {
+    let mut channels = Vec::new();
+    for c in s.to_lowercase().chars() {
+        let channel = "rgba".find(c).ok_or("channels must be r, g, b or a")?;
+        if channels.contains(&channel) {
+            return Err("each channel can only be selected once");
+        }
+        channels.push(channel);
+    }
+    if channels.is_empty() {
+        return Err("select at least one channel");
+    }
+    Ok(channels)
+}
+
+fn parse_filter(s: &str) -> Result<Filter, &'static str> {
+    match s.to_lowercase().as_ref() {
+        "box" => Ok(Filter::Box),
+        "triangle" => Ok(Filter::Triangle),
+        "kaiser" => Ok(Filter::Kaiser),
+        "lanczos" => Ok(Filter::Lanczos),
+        _ => Err("invalid mipmap filter specifier"),
     }
 }
 
-fn dxgiformat_to_format(d: DxgiFormat) -> Format {
-    match d {
-        DxgiFormat::BC1_UNorm_sRGB => Format::Bc1,
-        DxgiFormat::BC2_UNorm_sRGB => Format::Bc2,
-        DxgiFormat::BC3_UNorm_sRGB => Format::Bc3,
-        DxgiFormat::BC4_UNorm => Format::Bc4,
-        DxgiFormat::BC5_UNorm => Format::Bc5,
-        _ => panic!("Unsupported DXGI format!"),
+fn parse_bc3_encoding(s: &str) -> Result<Bc3Encoding, &'static str> {
+    match s.to_lowercase().as_ref() {
+        "rgba" => Ok(Bc3Encoding::Rgba),
+        "dxt5nm" => Ok(Bc3Encoding::Dxt5nm),
+        "ycocg" => Ok(Bc3Encoding::YCoCg),
+        _ => Err("invalid BC3 encoding specifier"),
     }
 }
 
-fn d3dformat_to_format(d: D3DFormat) -> Format {
-    match d {
-        D3DFormat::DXT1 => Format::Bc1,
-        D3DFormat::DXT3 => Format::Bc2,
-        D3DFormat::DXT5 => Format::Bc3,
-        _ => panic!("Unsupported D3D format!"),
+fn parse_tone_map(s: &str) -> Result<ToneMap, &'static str> {
+    match s.to_lowercase().as_ref() {
+        "clamp" => Ok(ToneMap::Clamp),
+        "reinhard" => Ok(ToneMap::Reinhard),
+        "aces" => Ok(ToneMap::Aces),
+        _ => Err("invalid tone-mapping specifier"),
     }
 }
 
-fn parse_format(s: &str) -> Result<Format, &'static str> {
+fn parse_edge_mode(s: &str) -> Result<EdgeMode, &'static str> {
     match s.to_lowercase().as_ref() {
-        "bc1" => Ok(Format::Bc1),
-        "bc2" => Ok(Format::Bc2),
-        "bc3" => Ok(Format::Bc3),
-        "bc4" => Ok(Format::Bc4),
-        "bc5" => Ok(Format::Bc5),
-        _ => Err("invalid compression format specifier"),
+        "clamp" => Ok(EdgeMode::Clamp),
+        "wrap" => Ok(EdgeMode::Wrap),
+        _ => Err("invalid mipmap edge mode specifier"),
     }
 }
@@ -0,0 +1,395 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Texture builds described by a TOML manifest
+//!
+//! A manifest lists the inputs to compress, the settings they share and rules that change
+//! the settings of the files they match. Paths are relative to the manifest:
+//!
+//! ```toml
+//! inputs = ["textures", "ui/**/*.png"]
+//! output = "build/textures"
+//! cache = "build/cache"
+//!
+//! [defaults]
+//! format = "bc1"
+//! profile = "quality"
+//! srgb = true
+//! mipmaps = true
+//! container = "ktx2"
+//!
+//! [[rules]]
+//! pattern = "*_n.png"
+//! format = "bc5"
+//! normal-map = true
+//! srgb = false
+//!
+//! [[rules]]
+//! pattern = "*_mask.png"
+//! format = "bc4"
+//! srgb = false
+//! ```
+//!
+//! Inputs are handled like in batch mode of the `compress` command, mirroring directories
+//! into `output`, or writing the outputs next to the inputs without it. Every rule whose
+//! pattern matches a file applies in order, so later rules override earlier ones. Patterns
+//! without a `/` match the file name, others the path relative to the manifest. Settings
+//! are named like the options of the `compress` command, plus `container`.
+//!
+//! Outputs are cached in the `cache` directory, `.squish-cache` next to the manifest by
+//! default, so only images whose pixels or settings changed are compressed again.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use glob::{MatchOptions, Pattern};
+use serde::Deserialize;
+use squish::mipmap::MipmapParams;
+use squish::{Params, COLOUR_WEIGHTS_PERCEPTUAL};
+
+use crate::batch::{self, Job};
+use crate::{
+    parse_bc3_encoding, parse_container, parse_edge_mode, parse_filter, parse_format,
+    parse_tone_map, Compression, Container, Profile,
+};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    cache: Option<PathBuf>,
+    #[serde(default)]
+    defaults: Settings,
+    #[serde(default)]
+    rules: Vec<toml::Table>,
+}
+
+/// Compression settings, unset ones are taken from the defaults or earlier rules
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Settings {
+    format: Option<String>,
+    profile: Option<String>,
+    weights: Option<[f32; 3]>,
+    weigh_colour_by_alpha: Option<bool>,
+    srgb: Option<bool>,
+    normal_map: Option<bool>,
+    bc3_encoding: Option<String>,
+    mipmaps: Option<bool>,
+    mip_filter: Option<String>,
+    mip_edge: Option<String>,
+    alpha_coverage: Option<u8>,
+    zstd: Option<bool>,
+    rdo: Option<f32>,
+    container: Option<String>,
+    tone_map: Option<String>,
+    exposure: Option<f32>,
+}
+
+struct Rule {
+    pattern: Pattern,
+    settings: Settings,
+}
+
+/// The inputs of a manifest and the cache directory to compress them with
+pub struct Build {
+    pub entries: Vec<Entry>,
+    pub cache_dir: PathBuf,
+}
+
+/// An input with the settings it is compressed with
+pub struct Entry {
+    pub job: Job,
+    pub compression: Compression,
+}
+
+impl AsRef<Job> for Entry {
+    fn as_ref(&self) -> &Job {
+        &self.job
+    }
+}
+
+impl Settings {
+    /// Replaces the settings that are set in `other`
+    fn apply(&mut self, other: &Settings) {
+        macro_rules! apply {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() {
+                    self.$field.clone_from(&other.$field);
+                })*
+            };
+        }
+        apply!(
+            format,
+            profile,
+            weights,
+            weigh_colour_by_alpha,
+            srgb,
+            normal_map,
+            bc3_encoding,
+            mipmaps,
+            mip_filter,
+            mip_edge,
+            alpha_coverage,
+            zstd,
+            rdo,
+            container,
+            tone_map,
+            exposure
+        );
+    }
+
+    fn entry(&self, mut job: Job) -> Result<Entry, String> {
+        let format = self.format.as_deref().ok_or("no format set")?;
+        let format = parse_format(format)?;
+        let profile = Profile::from_str(self.profile.as_deref().unwrap_or("balanced"))?;
+        let srgb = self.srgb.unwrap_or(false);
+        let normal_map = self.normal_map.unwrap_or(false);
+        let bc3_encoding = parse_bc3_encoding(self.bc3_encoding.as_deref().unwrap_or("rgba"))?;
+        let params = Params {
+            algorithm: profile.into(),
+            weights: self.weights.unwrap_or(COLOUR_WEIGHTS_PERCEPTUAL),
+            weigh_colour_by_alpha: self.weigh_colour_by_alpha.unwrap_or(false),
+            srgb,
+            normal_map,
+            bc3_encoding,
+            rdo_lambda: self.rdo.unwrap_or(0.0),
+        };
+
+        let mipmaps = if self.mipmaps.unwrap_or(false) {
+            Some(MipmapParams {
+                filter: parse_filter(self.mip_filter.as_deref().unwrap_or("box"))?,
+                edge_mode: parse_edge_mode(self.mip_edge.as_deref().unwrap_or("clamp"))?,
+                srgb,
+                alpha_coverage: self.alpha_coverage,
+                normal_map,
+            })
+        } else {
+            None
+        };
+
+        let container = parse_container(self.container.as_deref().unwrap_or("dds"))?;
+        let zstd = self.zstd.unwrap_or(false);
+        if zstd && container != Container::Ktx2 {
+            return Err("Zstandard supercompression requires KTX2 output".to_string());
+        }
+        job.output.set_extension(container.extension());
+
+        Ok(Entry {
+            job,
+            compression: Compression {
+                format,
+                params,
+                mipmaps,
+                zstd,
+                tone_map: parse_tone_map(self.tone_map.as_deref().unwrap_or("clamp"))?,
+                exposure: self.exposure.unwrap_or(0.0),
+            },
+        })
+    }
+}
+
+impl Rule {
+    fn new(mut table: toml::Table) -> Result<Rule, String> {
+        let pattern = match table.remove("pattern") {
+            Some(toml::Value::String(pattern)) => pattern,
+            _ => return Err("every rule needs a pattern".to_string()),
+        };
+        let pattern = Pattern::new(&pattern)
+            .map_err(|e| format!("invalid rule pattern {}: {}", pattern, e))?;
+        let settings = table
+            .try_into()
+            .map_err(|e| format!("invalid rule {}: {}", pattern, e))?;
+        Ok(Rule { pattern, settings })
+    }
+
+    /// Matches the file name, or the relative path for patterns with directories
+    fn matches(&self, relative: &Path) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        if self.pattern.as_str().contains('/') {
+            let path = relative.to_string_lossy().replace('\\', "/");
+            self.pattern.matches_with(&path, options)
+        } else {
+            let name = relative.file_name().unwrap_or_default().to_string_lossy();
+            self.pattern.matches_with(&name, options)
+        }
+    }
+}
+
+/// Reads a manifest and returns the inputs it describes with their settings
+pub fn read(path: &Path) -> Result<Build, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read manifest {}: {}", path.display(), e))?;
+    let manifest: Manifest = toml::from_str(&text)
+        .map_err(|e| format!("Failed to parse manifest {}: {}", path.display(), e))?;
+    let rules = manifest
+        .rules
+        .into_iter()
+        .map(Rule::new)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let root = path.parent().unwrap_or(Path::new(""));
+    let inputs: Vec<PathBuf> = manifest
+        .inputs
+        .iter()
+        .map(|input| root.join(input))
+        .collect();
+    let output_dir = manifest.output.map(|output| root.join(output));
+    let jobs = batch::jobs(&inputs, output_dir.as_deref(), "dds")?;
+
+    let entries = jobs
+        .into_iter()
+        .map(|job| {
+            let relative = job.input.strip_prefix(root).unwrap_or(&job.input);
+            let mut settings = manifest.defaults.clone();
+            for rule in rules.iter().filter(|rule| rule.matches(relative)) {
+                settings.apply(&rule.settings);
+            }
+            let input = job.input.clone();
+            settings
+                .entry(job)
+                .map_err(|e| format!("{}: {}", input.display(), e))
+        })
+        .collect::<Result<_, _>>()?;
+    let cache_dir = root.join(
+        manifest
+            .cache
+            .as_deref()
+            .unwrap_or(Path::new(".squish-cache")),
+    );
+    Ok(Build { entries, cache_dir })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use squish::{Bc3Encoding, Format};
+
+    fn rule(text: &str) -> Rule {
+        Rule::new(toml::from_str(text).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_rule_matches() {
+        // patterns without a slash match the file name in any directory
+        let normals = rule("pattern = \"*_n.png\"");
+        assert!(normals.matches(Path::new("wall_n.png")));
+        assert!(normals.matches(Path::new("textures/wall_n.png")));
+        assert!(!normals.matches(Path::new("textures/wall_n.tga")));
+        assert!(!normals.matches(Path::new("textures_n.png/wall.png")));
+
+        // patterns with a slash match the whole relative path, one directory per `*`
+        let ui = rule("pattern = \"ui/*.png\"");
+        assert!(ui.matches(Path::new("ui/icon.png")));
+        assert!(!ui.matches(Path::new("ui/small/icon.png")));
+        assert!(!ui.matches(Path::new("textures/ui/icon.png")));
+        assert!(rule("pattern = \"ui/**/*.png\"").matches(Path::new("ui/small/icon.png")));
+
+        assert!(Rule::new(toml::Table::new()).is_err());
+        assert!(Rule::new(toml::from_str("pattern = \"*\"\nformat = 1").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_settings_apply() {
+        let mut settings: Settings =
+            toml::from_str("format = \"bc1\"\nsrgb = true\nmipmaps = true").unwrap();
+        settings.apply(&rule("pattern = \"*\"\nformat = \"bc3\"\nsrgb = false").settings);
+        assert_eq!(settings.format.as_deref(), Some("bc3"));
+        assert_eq!(settings.srgb, Some(false));
+        assert_eq!(settings.mipmaps, Some(true));
+        assert_eq!(settings.container, None);
+
+        let entry = settings
+            .entry(Job {
+                input: PathBuf::from("a.png"),
+                output: PathBuf::from("a.dds"),
+            })
+            .unwrap();
+        assert_eq!(entry.compression.format, Format::Bc3);
+        assert!(!entry.compression.params.srgb);
+        assert!(entry.compression.mipmaps.is_some());
+    }
+
+    #[test]
+    fn test_read() {
+        let dir = crate::test_dir("manifest");
+        for file in ["tex/wall.png", "tex/wall_n.png", "ui/icon.png"] {
+            fs::create_dir_all(dir.join(file).parent().unwrap()).unwrap();
+            fs::write(dir.join(file), []).unwrap();
+        }
+        let manifest = dir.join("squish.toml");
+        fs::write(
+            &manifest,
+            r#"
+            inputs = ["tex", "ui/*.png"]
+            output = "build"
+
+            [defaults]
+            format = "bc1"
+
+            [[rules]]
+            pattern = "*.png"
+            format = "bc3"
+            container = "ktx2"
+
+            [[rules]]
+            pattern = "tex/*_n.png"
+            bc3-encoding = "dxt5nm"
+            container = "ktx"
+            "#,
+        )
+        .unwrap();
+
+        // every matching rule applies in order
+        let build = read(&manifest).unwrap();
+        assert_eq!(build.cache_dir, dir.join(".squish-cache"));
+        let entries: Vec<_> = build
+            .entries
+            .iter()
+            .map(|entry| {
+                let output = entry.job.output.strip_prefix(&dir).unwrap();
+                let params = entry.compression.params;
+                (
+                    output.to_path_buf(),
+                    entry.compression.format,
+                    params.bc3_encoding,
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("build/wall.ktx2".into(), Format::Bc3, Bc3Encoding::Rgba),
+                ("build/wall_n.ktx".into(), Format::Bc3, Bc3Encoding::Dxt5nm),
+                ("build/icon.ktx2".into(), Format::Bc3, Bc3Encoding::Rgba),
+            ]
+        );
+
+        fs::write(&manifest, "inputs = [\"tex\"]\n[defaults]\nzstd = true").unwrap();
+        assert!(read(&manifest).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
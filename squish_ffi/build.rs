@@ -0,0 +1,16 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    // the header in include/ is a copy of this one, which the tests keep in sync
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let header = PathBuf::from(env::var("OUT_DIR").unwrap()).join("squish.h");
+    cbindgen::generate(crate_dir)
+        .expect("Failed to generate the C header")
+        .write_to_file(header);
+
+    // the C test program is built for the same target as the tests
+    println!("cargo:rustc-env=TARGET={}", env::var("TARGET").unwrap());
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
@@ -0,0 +1,220 @@
+/* Generated by cbindgen from squish_ffi/src/lib.rs, do not edit by hand. */
+
+#ifndef SQUISH_H
+#define SQUISH_H
+
+#include <stdint.h>
+
+/*
+ Use DXT1 compression.
+ */
+#define kDxt1 (1 << 0)
+
+/*
+ Use DXT3 compression.
+ */
+#define kDxt3 (1 << 1)
+
+/*
+ Use DXT5 compression.
+ */
+#define kDxt5 (1 << 2)
+
+/*
+ Use BC4 compression.
+ */
+#define kBc4 (1 << 3)
+
+/*
+ Use BC5 compression.
+ */
+#define kBc5 (1 << 4)
+
+/*
+ Use a slow but high quality colour compressor (the default).
+ */
+#define kColourClusterFit (1 << 5)
+
+/*
+ Use a fast but low quality colour compressor.
+ */
+#define kColourRangeFit (1 << 6)
+
+/*
+ Weight the colour by alpha during cluster fit (disabled by default).
+ */
+#define kWeightColourByAlpha (1 << 7)
+
+/*
+ Use a very slow but very high quality colour compressor.
+ */
+#define kColourIterativeClusterFit (1 << 8)
+
+/*
+ Source is BGRA rather than RGBA.
+ */
+#define kSourceBGRA (1 << 9)
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Compresses a 4x4 block of pixels, masking out some pixels e.g. for padding the image to
+ a multiple of the block size
+
+ # Safety
+
+ `rgba` must point to 64 readable bytes, `block` to 8 or 16 writable bytes depending on
+ the format, and `metric` must be null or point to 3 floats.
+ */
+void CompressMasked(const uint8_t *rgba, int mask, void *block, int flags, const float *metric);
+
+/*
+ Compresses a 4x4 block of pixels
+
+ # Safety
+
+ See `CompressMasked`.
+ */
+void Compress(const uint8_t *rgba, void *block, int flags, const float *metric);
+
+/*
+ Decompresses a 4x4 block of pixels to RGBA
+
+ # Safety
+
+ `rgba` must point to 64 writable bytes and `block` to 8 or 16 readable bytes depending
+ on the format.
+ */
+void Decompress(uint8_t *rgba, const void *block, int flags);
+
+/*
+ Computes the amount of compressed storage required for an image
+ */
+int GetStorageRequirements(int width, int height, int flags);
+
+/*
+ Compresses an image with rows `pitch` bytes apart
+
+ # Safety
+
+ `rgba` must point to `height` rows of `4 * width` readable bytes each, `blocks` to
+ `GetStorageRequirements(width, height, flags)` writable bytes, and `metric` must be
+ null or point to 3 floats.
+ */
+void CompressImagePitch(const uint8_t *rgba, int width, int height, int pitch, void *blocks, int flags, const float *metric);
+
+/*
+ Compresses an image with tightly packed rows
+
+ # Safety
+
+ See `CompressImagePitch`.
+ */
+void CompressImage(const uint8_t *rgba, int width, int height, void *blocks, int flags, const float *metric);
+
+/*
+ Decompresses an image to RGBA with rows `pitch` bytes apart
+
+ # Safety
+
+ `rgba` must point to `height` rows of `4 * width` writable bytes each and `blocks` to
+ `GetStorageRequirements(width, height, flags)` readable bytes.
+ */
+void DecompressImagePitch(uint8_t *rgba, int width, int height, int pitch, const void *blocks, int flags);
+
+/*
+ Decompresses an image to tightly packed RGBA rows
+
+ # Safety
+
+ See `DecompressImagePitch`.
+ */
+void DecompressImage(uint8_t *rgba, int width, int height, const void *blocks, int flags);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SQUISH_H */
+
+#if defined(__cplusplus) && !defined(SQUISH_CPP_H)
+#define SQUISH_CPP_H
+/* libsquish's C++ interface with its overloads and default arguments */
+namespace squish {
+
+/* the flags move into the namespace, so the macros make way for enumerators */
+namespace detail {
+enum {
+    Dxt1 = kDxt1,
+    Dxt3 = kDxt3,
+    Dxt5 = kDxt5,
+    Bc4 = kBc4,
+    Bc5 = kBc5,
+    ColourClusterFit = kColourClusterFit,
+    ColourRangeFit = kColourRangeFit,
+    WeightColourByAlpha = kWeightColourByAlpha,
+    ColourIterativeClusterFit = kColourIterativeClusterFit,
+    SourceBGRA = kSourceBGRA,
+};
+}
+#undef kDxt1
+#undef kDxt3
+#undef kDxt5
+#undef kBc4
+#undef kBc5
+#undef kColourClusterFit
+#undef kColourRangeFit
+#undef kWeightColourByAlpha
+#undef kColourIterativeClusterFit
+#undef kSourceBGRA
+enum {
+    kDxt1 = detail::Dxt1,
+    kDxt3 = detail::Dxt3,
+    kDxt5 = detail::Dxt5,
+    kBc4 = detail::Bc4,
+    kBc5 = detail::Bc5,
+    kColourClusterFit = detail::ColourClusterFit,
+    kColourRangeFit = detail::ColourRangeFit,
+    kWeightColourByAlpha = detail::WeightColourByAlpha,
+    kColourIterativeClusterFit = detail::ColourIterativeClusterFit,
+    kSourceBGRA = detail::SourceBGRA,
+};
+
+typedef unsigned char u8;
+
+inline void CompressMasked(u8 const* rgba, int mask, void* block, int flags, float* metric = 0) {
+    ::CompressMasked(rgba, mask, block, flags, metric);
+}
+
+inline void Compress(u8 const* rgba, void* block, int flags, float* metric = 0) {
+    ::Compress(rgba, block, flags, metric);
+}
+
+inline void Decompress(u8* rgba, void const* block, int flags) {
+    ::Decompress(rgba, block, flags);
+}
+
+inline int GetStorageRequirements(int width, int height, int flags) {
+    return ::GetStorageRequirements(width, height, flags);
+}
+
+inline void CompressImage(u8 const* rgba, int width, int height, int pitch, void* blocks, int flags, float* metric = 0) {
+    ::CompressImagePitch(rgba, width, height, pitch, blocks, flags, metric);
+}
+
+inline void CompressImage(u8 const* rgba, int width, int height, void* blocks, int flags, float* metric = 0) {
+    ::CompressImage(rgba, width, height, blocks, flags, metric);
+}
+
+inline void DecompressImage(u8* rgba, int width, int height, int pitch, void const* blocks, int flags) {
+    ::DecompressImagePitch(rgba, width, height, pitch, blocks, flags);
+}
+
+inline void DecompressImage(u8* rgba, int width, int height, void const* blocks, int flags) {
+    ::DecompressImage(rgba, width, height, blocks, flags);
+}
+
+}
+#endif
@@ -0,0 +1,290 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! A C interface to **squish** that is compatible with Simon Brown's **libsquish**
+//!
+//! The functions and flags mirror libsquish, so C and C++ callers can link against this
+//! library instead. `include/squish.h` declares them for C and additionally provides the
+//! `squish` namespace with libsquish's overloads and default arguments for C++.
+//!
+//! Like libsquish, a null `metric` selects uniform colour weights and `kSourceBGRA` only
+//! affects compression.
+
+#![allow(non_upper_case_globals, non_snake_case)]
+
+use core::ffi::{c_int, c_void};
+use core::slice;
+
+use squish::{Algorithm, Format, Params, PixelLayout, COLOUR_WEIGHTS_UNIFORM};
+
+/// Use DXT1 compression.
+pub const kDxt1: c_int = 1 << 0;
+
+/// Use DXT3 compression.
+pub const kDxt3: c_int = 1 << 1;
+
+/// Use DXT5 compression.
+pub const kDxt5: c_int = 1 << 2;
+
+/// Use BC4 compression.
+pub const kBc4: c_int = 1 << 3;
+
+/// Use BC5 compression.
+pub const kBc5: c_int = 1 << 4;
+
+/// Use a slow but high quality colour compressor (the default).
+pub const kColourClusterFit: c_int = 1 << 5;
+
+/// Use a fast but low quality colour compressor.
+pub const kColourRangeFit: c_int = 1 << 6;
+
+/// Weight the colour by alpha during cluster fit (disabled by default).
+pub const kWeightColourByAlpha: c_int = 1 << 7;
+
+/// Use a very slow but very high quality colour compressor.
+pub const kColourIterativeClusterFit: c_int = 1 << 8;
+
+/// Source is BGRA rather than RGBA.
+pub const kSourceBGRA: c_int = 1 << 9;
+
+/// Picks the format from the flags, falling back to DXT1 like libsquish
+fn format(flags: c_int) -> Format {
+    match flags & (kDxt1 | kDxt3 | kDxt5 | kBc4 | kBc5) {
+        kDxt3 => Format::Bc2,
+        kDxt5 => Format::Bc3,
+        kBc4 => Format::Bc4,
+        kBc5 => Format::Bc5,
+        _ => Format::Bc1,
+    }
+}
+
+/// Builds the compressor parameters from the flags and an optional colour metric
+unsafe fn params(flags: c_int, metric: *const f32) -> Params {
+    let algorithm = match flags & (kColourClusterFit | kColourRangeFit | kColourIterativeClusterFit)
+    {
+        kColourRangeFit => Algorithm::RangeFit,
+        kColourIterativeClusterFit => Algorithm::IterativeClusterFit,
+        _ => Algorithm::ClusterFit,
+    };
+    let weights = if metric.is_null() {
+        COLOUR_WEIGHTS_UNIFORM
+    } else {
+        [*metric, *metric.add(1), *metric.add(2)]
+    };
+
+    Params {
+        algorithm,
+        weights,
+        weigh_colour_by_alpha: flags & kWeightColourByAlpha != 0,
+        ..Params::default()
+    }
+}
+
+fn layout(flags: c_int) -> PixelLayout {
+    if flags & kSourceBGRA != 0 {
+        PixelLayout::Bgra
+    } else {
+        PixelLayout::Rgba
+    }
+}
+
+/// Converts a dimension, treating negative values as empty
+fn dimension(value: c_int) -> usize {
+    usize::try_from(value).unwrap_or(0)
+}
+
+/// Compresses a 4x4 block of pixels, masking out some pixels e.g. for padding the image to
+/// a multiple of the block size
+///
+/// # Safety
+///
+/// `rgba` must point to 64 readable bytes, `block` to 8 or 16 writable bytes depending on
+/// the format, and `metric` must be null or point to 3 floats.
+#[no_mangle]
+pub unsafe extern "C" fn CompressMasked(
+    rgba: *const u8,
+    mask: c_int,
+    block: *mut c_void,
+    flags: c_int,
+    metric: *const f32,
+) {
+    let format = format(flags);
+    let order = if flags & kSourceBGRA != 0 {
+        [2, 1, 0, 3]
+    } else {
+        [0, 1, 2, 3]
+    };
+    let pixels = slice::from_raw_parts(rgba, 64);
+    let rgba: [[u8; 4]; 16] = core::array::from_fn(|i| order.map(|c| pixels[4 * i + c]));
+    let output = slice::from_raw_parts_mut(block as *mut u8, format.block_size());
+    format.compress_block_masked(rgba, mask as u32, params(flags, metric), output);
+}
+
+/// Compresses a 4x4 block of pixels
+///
+/// # Safety
+///
+/// See `CompressMasked`.
+#[no_mangle]
+pub unsafe extern "C" fn Compress(
+    rgba: *const u8,
+    block: *mut c_void,
+    flags: c_int,
+    metric: *const f32,
+) {
+    CompressMasked(rgba, 0xFFFF, block, flags, metric);
+}
+
+/// Decompresses a 4x4 block of pixels to RGBA
+///
+/// # Safety
+///
+/// `rgba` must point to 64 writable bytes and `block` to 8 or 16 readable bytes depending
+/// on the format.
+#[no_mangle]
+pub unsafe extern "C" fn Decompress(rgba: *mut u8, block: *const c_void, flags: c_int) {
+    let format = format(flags);
+    let block = slice::from_raw_parts(block as *const u8, format.block_size());
+    let output = slice::from_raw_parts_mut(rgba, 64);
+    for (pixel, decoded) in output.chunks_mut(4).zip(format.decompress_block(block)) {
+        pixel.copy_from_slice(&decoded);
+    }
+}
+
+/// Computes the amount of compressed storage required for an image
+#[no_mangle]
+pub extern "C" fn GetStorageRequirements(width: c_int, height: c_int, flags: c_int) -> c_int {
+    format(flags).compressed_size(dimension(width), dimension(height)) as c_int
+}
+
+/// Compresses an image with rows `pitch` bytes apart
+///
+/// # Safety
+///
+/// `rgba` must point to `height` rows of `4 * width` readable bytes each, `blocks` to
+/// `GetStorageRequirements(width, height, flags)` writable bytes, and `metric` must be
+/// null or point to 3 floats.
+#[no_mangle]
+pub unsafe extern "C" fn CompressImagePitch(
+    rgba: *const u8,
+    width: c_int,
+    height: c_int,
+    pitch: c_int,
+    blocks: *mut c_void,
+    flags: c_int,
+    metric: *const f32,
+) {
+    let (width, height, pitch) = (dimension(width), dimension(height), dimension(pitch));
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    let format = format(flags);
+    let pixels = slice::from_raw_parts(rgba, pitch * (height - 1) + 4 * width);
+    let size = format.compressed_size(width, height);
+    let output = slice::from_raw_parts_mut(blocks as *mut u8, size);
+    format.compress_pitched(
+        pixels,
+        layout(flags),
+        pitch,
+        width,
+        height,
+        params(flags, metric),
+        output,
+        format.compressed_row_size(width),
+    );
+}
+
+/// Compresses an image with tightly packed rows
+///
+/// # Safety
+///
+/// See `CompressImagePitch`.
+#[no_mangle]
+pub unsafe extern "C" fn CompressImage(
+    rgba: *const u8,
+    width: c_int,
+    height: c_int,
+    blocks: *mut c_void,
+    flags: c_int,
+    metric: *const f32,
+) {
+    CompressImagePitch(
+        rgba,
+        width,
+        height,
+        width.saturating_mul(4),
+        blocks,
+        flags,
+        metric,
+    );
+}
+
+/// Decompresses an image to RGBA with rows `pitch` bytes apart
+///
+/// # Safety
+///
+/// `rgba` must point to `height` rows of `4 * width` writable bytes each and `blocks` to
+/// `GetStorageRequirements(width, height, flags)` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn DecompressImagePitch(
+    rgba: *mut u8,
+    width: c_int,
+    height: c_int,
+    pitch: c_int,
+    blocks: *const c_void,
+    flags: c_int,
+) {
+    let (width, height, pitch) = (dimension(width), dimension(height), dimension(pitch));
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    let format = format(flags);
+    let size = format.compressed_size(width, height);
+    let data = slice::from_raw_parts(blocks as *const u8, size);
+    let output = slice::from_raw_parts_mut(rgba, pitch * (height - 1) + 4 * width);
+    format.decompress_pitched(
+        data,
+        format.compressed_row_size(width),
+        width,
+        height,
+        output,
+        PixelLayout::Rgba,
+        pitch,
+    );
+}
+
+/// Decompresses an image to tightly packed RGBA rows
+///
+/// # Safety
+///
+/// See `DecompressImagePitch`.
+#[no_mangle]
+pub unsafe extern "C" fn DecompressImage(
+    rgba: *mut u8,
+    width: c_int,
+    height: c_int,
+    blocks: *const c_void,
+    flags: c_int,
+) {
+    DecompressImagePitch(rgba, width, height, width.saturating_mul(4), blocks, flags);
+}
@@ -0,0 +1,124 @@
+/* Exercises the C interface and prints the compressed test image for comparison */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "squish.h"
+
+#define CHECK(condition)                                                    \
+    do {                                                                    \
+        if (!(condition)) {                                                 \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+                    #condition);                                            \
+            exit(1);                                                        \
+        }                                                                   \
+    } while (0)
+
+#define WIDTH 12
+#define HEIGHT 8
+
+static void fill_block(uint8_t* rgba, uint8_t r, uint8_t g, uint8_t b, uint8_t a) {
+    for (int i = 0; i < 16; ++i) {
+        rgba[4 * i + 0] = r;
+        rgba[4 * i + 1] = g;
+        rgba[4 * i + 2] = b;
+        rgba[4 * i + 3] = a;
+    }
+}
+
+static void test_storage_requirements(void) {
+    CHECK(GetStorageRequirements(5, 5, kDxt1) == 32);
+    CHECK(GetStorageRequirements(5, 5, kDxt3) == 64);
+    CHECK(GetStorageRequirements(5, 5, kDxt5) == 64);
+    CHECK(GetStorageRequirements(5, 5, kBc4) == 32);
+    CHECK(GetStorageRequirements(5, 5, kBc5) == 64);
+
+    /* missing or conflicting formats fall back to DXT1 */
+    CHECK(GetStorageRequirements(4, 4, 0) == 8);
+    CHECK(GetStorageRequirements(4, 4, kDxt3 | kDxt5) == 8);
+}
+
+static void test_blocks(void) {
+    uint8_t rgba[64];
+    uint8_t decoded[64];
+    uint8_t block[16];
+
+    /* pure red survives DXT1 exactly */
+    fill_block(rgba, 255, 0, 0, 255);
+    Compress(rgba, block, kDxt1, NULL);
+    Decompress(decoded, block, kDxt1);
+    CHECK(memcmp(rgba, decoded, sizeof(rgba)) == 0);
+
+    /* BGRA sources swap red and blue */
+    fill_block(rgba, 0, 0, 255, 255);
+    Compress(rgba, block, kDxt1 | kSourceBGRA, NULL);
+    Decompress(decoded, block, kDxt1);
+    CHECK(decoded[0] == 255 && decoded[1] == 0 && decoded[2] == 0);
+
+    /* masked pixels are ignored */
+    fill_block(rgba, 0, 255, 0, 255);
+    rgba[0] = 255;
+    rgba[1] = 0;
+    CompressMasked(rgba, 1, block, kDxt5 | kColourRangeFit, NULL);
+    Decompress(decoded, block, kDxt5);
+    CHECK(decoded[0] == 255 && decoded[1] == 0 && decoded[3] == 255);
+
+    /* alpha ends up in DXT3 and DXT5 */
+    fill_block(rgba, 0, 0, 0, 0x88);
+    Compress(rgba, block, kDxt3, NULL);
+    Decompress(decoded, block, kDxt3);
+    CHECK(decoded[3] == 0x88);
+}
+
+static void test_images(void) {
+    uint8_t rgba[4 * WIDTH * HEIGHT];
+    uint8_t padded[2 * 4 * WIDTH * HEIGHT];
+    uint8_t blocks[3 * 2 * 16];
+    uint8_t padded_blocks[3 * 2 * 16];
+    uint8_t decoded[4 * WIDTH * HEIGHT];
+    float metric[3] = {0.2126f, 0.7152f, 0.0722f};
+    int flags = kDxt5 | kColourIterativeClusterFit | kWeightColourByAlpha;
+
+    for (int i = 0; i < WIDTH * HEIGHT; ++i) {
+        int x = i % WIDTH;
+        int y = i / WIDTH;
+        rgba[4 * i + 0] = (uint8_t)(10 * (x + y));
+        rgba[4 * i + 1] = (uint8_t)(255 - 12 * (x + y));
+        rgba[4 * i + 2] = (uint8_t)(6 * (x + y));
+        rgba[4 * i + 3] = (uint8_t)(128 + 4 * x + 2 * y);
+    }
+    CompressImage(rgba, WIDTH, HEIGHT, blocks, flags, metric);
+
+    /* rows with padding compress the same */
+    memset(padded, 0xAB, sizeof(padded));
+    for (int y = 0; y < HEIGHT; ++y) {
+        memcpy(&padded[2 * 4 * WIDTH * y], &rgba[4 * WIDTH * y], 4 * WIDTH);
+    }
+    CompressImagePitch(padded, WIDTH, HEIGHT, 2 * 4 * WIDTH, padded_blocks, flags, metric);
+    CHECK(memcmp(blocks, padded_blocks, sizeof(blocks)) == 0);
+
+    /* decompression stays close to the source */
+    DecompressImage(decoded, WIDTH, HEIGHT, blocks, flags);
+    for (int i = 0; i < 4 * WIDTH * HEIGHT; ++i) {
+        CHECK(abs((int)decoded[i] - (int)rgba[i]) < 24);
+    }
+    memset(padded, 0xAB, sizeof(padded));
+    DecompressImagePitch(padded, WIDTH, HEIGHT, 2 * 4 * WIDTH, blocks, flags);
+    for (int y = 0; y < HEIGHT; ++y) {
+        CHECK(memcmp(&padded[2 * 4 * WIDTH * y], &decoded[4 * WIDTH * y], 4 * WIDTH) == 0);
+        CHECK(padded[2 * 4 * WIDTH * y + 4 * WIDTH] == 0xAB);
+    }
+
+    for (size_t i = 0; i < sizeof(blocks); ++i) {
+        printf("%02x", blocks[i]);
+    }
+    printf("\n");
+}
+
+int main(void) {
+    test_storage_requirements();
+    test_blocks();
+    test_images();
+    return 0;
+}
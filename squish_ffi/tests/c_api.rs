@@ -0,0 +1,105 @@
+// Copyright (c) 2018-2021 Jan Solanti <jhs@psonet.com>
+//
+// Permission is hereby granted, free of charge, to any person obtaining
+// a copy of this software and associated documentation files (the
+// "Software"), to	deal in the Software without restriction, including
+// without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to
+// permit persons to whom the Software is furnished to do so, subject to
+// the following conditions:
+//
+// The above copyright notice and this permission notice shall be included
+// in all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+// MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT.
+// IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY
+// CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT,
+// TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
+// SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Builds and runs the C test program against the library
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use squish::{Algorithm, Format, Params};
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+#[test]
+fn test_header_is_up_to_date() {
+    let generated = PathBuf::from(env!("OUT_DIR")).join("squish.h");
+    let generated = fs::read_to_string(generated).unwrap();
+    let committed = fs::read_to_string(manifest_dir().join("include/squish.h")).unwrap();
+    assert!(
+        generated == committed,
+        "include/squish.h is out of date, copy it from {}",
+        env!("OUT_DIR")
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn test_c_program() {
+    // the shared library is built next to the dependencies of this test
+    let exe = std::env::current_exe().unwrap();
+    let lib_dir = exe.parent().unwrap();
+    let program = Path::new(env!("CARGO_TARGET_TMPDIR")).join("squish_c_test");
+
+    let compiler = cc::Build::new()
+        .cargo_metadata(false)
+        .target(env!("TARGET"))
+        .host(env!("TARGET"))
+        .opt_level(0)
+        .try_get_compiler()
+        .unwrap();
+    let status = compiler
+        .to_command()
+        .arg(manifest_dir().join("tests/c/test.c"))
+        .arg("-I")
+        .arg(manifest_dir().join("include"))
+        .arg("-o")
+        .arg(&program)
+        .arg("-L")
+        .arg(lib_dir)
+        .arg("-lsquish_ffi")
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .status()
+        .unwrap();
+    assert!(status.success(), "failed to compile the C test program");
+
+    let output = Command::new(&program).output().unwrap();
+    assert!(
+        output.status.success(),
+        "C test program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // the C interface compresses exactly like the crate with the equivalent parameters
+    let rgba: Vec<u8> = (0..12 * 8)
+        .flat_map(|i: usize| {
+            let (x, y) = (i % 12, i / 12);
+            [
+                (10 * (x + y)) as u8,
+                (255 - 12 * (x + y)) as u8,
+                (6 * (x + y)) as u8,
+                (128 + 4 * x + 2 * y) as u8,
+            ]
+        })
+        .collect();
+    let params = Params {
+        algorithm: Algorithm::IterativeClusterFit,
+        weights: [0.2126, 0.7152, 0.0722],
+        weigh_colour_by_alpha: true,
+        ..Params::default()
+    };
+    let mut blocks = vec![0u8; Format::Bc3.compressed_size(12, 8)];
+    Format::Bc3.compress(&rgba, 12, 8, params, &mut blocks);
+    let expected: String = blocks.iter().map(|byte| format!("{:02x}", byte)).collect();
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), expected);
+}